@@ -1,12 +1,17 @@
+use crate::chart::{load_candles, CandleChart};
 use crate::routes::{
-    self, change_interval, change_pair, fetch_balance_sheet, fetch_last_kline_time, get_status,
-    plot_chart,
+    self, change_interval, change_pair, fetch_backtest_runs, fetch_balance_sheet,
+    fetch_last_kline_time, fetch_leaderboard, fetch_models, fetch_pnl, fetch_walk_forward_runs,
+    get_status, plot_equity,
 };
 use crate::store::Store;
-use crate::store_models::{BalanceSheetWithBalances, Chart, Interval, Meshetar, Pair, Status};
+use crate::store_models::{
+    BalanceSheetWithBalances, Environment, EquityPlot, Interval, Meshetar, Pair, Pnl,
+    SignalEvaluation, Status,
+};
 use crate::utils::{
-    console_log, date_string_to_integer, get_default_fetch_date, get_timestamp, readable_date,
-    to_fiat_format,
+    console_log, date_string_to_integer, get_default_fetch_date, get_performance_from,
+    get_timestamp, readable_date, to_fiat_format, to_percent_format, to_signed_fiat_format,
 };
 use gloo_timers::future::TimeoutFuture;
 use sycamore::futures::spawn_local_scoped;
@@ -15,11 +20,27 @@ use sycamore::reactive::{create_effect, create_rc_signal, create_signal, provide
 use sycamore::view::View;
 use sycamore::{component, view};
 
+/// Plot of the active model of the current pair and interval
+fn active_model_plot(store: &Store) -> Option<String> {
+    let interval = store.interval.get().parse::<Interval>().ok()?;
+    store
+        .models
+        .get()
+        .iter()
+        .find(|model| {
+            model.is_active
+                && model.symbol == *store.pair.get()
+                && model.interval == interval.kline_interval()
+        })
+        .map(|model| model.plot_path.clone())
+}
+
 fn sync_store(store: &Store, meshetar: Meshetar) {
     store.server_state.set(meshetar.status);
     store.pair.set(meshetar.pair.to_string());
     store.interval.set(meshetar.interval.to_string());
     store.mode.set(meshetar.status.to_string());
+    store.environment.set(meshetar.environment);
 }
 
 #[component]
@@ -31,6 +52,208 @@ pub fn Divider<'a, G: Html>(cx: Scope<'a>) -> View<G> {
     }
 }
 
+fn signal_evaluation_view<G: Html>(cx: Scope, evaluation: &SignalEvaluation) -> View<G> {
+    let summary = format!(
+        "{} signals for {} {}, accuracy against labels {}",
+        evaluation.signals,
+        evaluation.symbol,
+        evaluation.interval,
+        to_percent_format(evaluation.confusion_matrix.accuracy)
+    );
+    let hit_rate_rows = View::new_fragment(
+        evaluation
+            .horizons
+            .iter()
+            .map(|horizon| {
+                let horizon_label = format!("+{} klines", horizon.horizon);
+                let cells = View::new_fragment(
+                    horizon
+                        .classes
+                        .iter()
+                        .map(|class| {
+                            let hit_rate = format!(
+                                "{} of {} ({})",
+                                class.hits,
+                                class.count,
+                                to_percent_format(class.hit_rate)
+                            );
+                            let mean_return = to_percent_format(class.mean_return);
+                            view! { cx,
+                                td { (hit_rate) br {} small { (mean_return) } }
+                            }
+                        })
+                        .collect(),
+                );
+                view! { cx, tr { td { (horizon_label) } (cells) } }
+            })
+            .collect(),
+    );
+    let labels = evaluation.confusion_matrix.labels.clone();
+    // DOM nodes can only be placed once, so the header row is built per table
+    let label_headers = || {
+        View::new_fragment(
+            labels
+                .iter()
+                .map(|label| {
+                    let label = label.clone();
+                    view! { cx, th { (label) } }
+                })
+                .collect(),
+        )
+    };
+    let hit_rate_headers = label_headers();
+    let confusion_headers = label_headers();
+    let confusion_rows = View::new_fragment(
+        evaluation
+            .confusion_matrix
+            .counts
+            .iter()
+            .zip(labels.iter())
+            .map(|(counts, label)| {
+                let label = label.clone();
+                let cells = View::new_fragment(
+                    counts
+                        .iter()
+                        .map(|count| {
+                            let count = count.to_string();
+                            view! { cx, td { (count) } }
+                        })
+                        .collect(),
+                );
+                view! { cx, tr { th { (label) } (cells) } }
+            })
+            .collect(),
+    );
+    let calibration_rows = View::new_fragment(
+        evaluation
+            .calibration
+            .iter()
+            .map(|curve| {
+                let signal = curve.signal.clone();
+                let cells = View::new_fragment(
+                    curve
+                        .bins
+                        .iter()
+                        .map(|bin| {
+                            let observed = match bin.count {
+                                0 => String::from("-"),
+                                count => format!(
+                                    "{} ({})",
+                                    to_percent_format(bin.observed_frequency),
+                                    count
+                                ),
+                            };
+                            view! { cx, td { (observed) } }
+                        })
+                        .collect(),
+                );
+                view! { cx, tr { th { (signal) } (cells) } }
+            })
+            .collect(),
+    );
+    let bin_headers = View::new_fragment(
+        evaluation
+            .calibration
+            .first()
+            .map(|curve| {
+                curve
+                    .bins
+                    .iter()
+                    .map(|bin| {
+                        let range = format!("{:.0}-{:.0}%", bin.lower * 100.0, bin.upper * 100.0);
+                        view! { cx, th { small { (range) } } }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+    );
+    view! { cx,
+        p { (summary) }
+        h6 { "Hit rate (mean forward return)" }
+        table(class="signal-evaluation") {
+            thead { tr { th { "Horizon" } (hit_rate_headers) } }
+            tbody { (hit_rate_rows) }
+        }
+        h6 { "Signals (rows) against optimal labels (columns)" }
+        table(class="signal-evaluation") {
+            thead { tr { th {} (confusion_headers) } }
+            tbody { (confusion_rows) }
+        }
+        h6 { "Calibration: observed label frequency per probability bin" }
+        figure {
+            table(class="signal-evaluation") {
+                thead { tr { th {} (bin_headers) } }
+                tbody { (calibration_rows) }
+            }
+        }
+    }
+}
+
+fn performance_view<G: Html>(cx: Scope, equity: &EquityPlot, pnl: &Option<Pnl>) -> View<G> {
+    let summary = format!(
+        "Return {}, max drawdown {}",
+        to_percent_format(equity.curve.total_return),
+        to_percent_format(Some(equity.curve.max_drawdown))
+    );
+    let pnl_summary = match pnl {
+        Some(pnl) => {
+            let unrealized = match pnl.unrealized {
+                Some(unrealized) => to_signed_fiat_format(unrealized),
+                None => String::from("-"),
+            };
+            format!(
+                "{} over {} fills: realized {} {}, unrealized {} {}, commissions {} {}",
+                pnl.symbol,
+                pnl.fills,
+                to_signed_fiat_format(pnl.net_realized.unwrap_or(pnl.realized)),
+                pnl.quote_asset,
+                unrealized,
+                pnl.quote_asset,
+                to_fiat_format(pnl.commission),
+                pnl.quote_asset
+            )
+        }
+        None => String::new(),
+    };
+    // Newest days first
+    let daily_rows = View::new_fragment(
+        equity
+            .curve
+            .daily_returns
+            .iter()
+            .rev()
+            .take(14)
+            .map(|daily| {
+                let day = daily.day.clone();
+                let reporting_return = to_percent_format(daily.reporting_return);
+                let btc_return = to_percent_format(daily.btc_return);
+                view! { cx, tr { td { (day) } td { (reporting_return) } td { (btc_return) } } }
+            })
+            .collect(),
+    );
+    let plot_url = format!(
+        "http://localhost:8000/plot_equity?from={}&to={}&ver={}",
+        equity.curve.from,
+        equity.curve.to,
+        get_timestamp()
+    );
+    let currency = equity
+        .curve
+        .points
+        .last()
+        .map(|point| point.reporting_currency.clone())
+        .unwrap_or_default();
+    view! { cx,
+        p { (summary) br {} (pnl_summary) }
+        img(src=plot_url)
+        h6 { "Daily returns" }
+        table(class="daily-returns") {
+            thead { tr { th { "Day" } th { (currency) } th { "BTC" } } }
+            tbody { (daily_rows) }
+        }
+    }
+}
+
 #[component]
 pub fn App<G: Html>(cx: Scope) -> View<G> {
     let store = Store {
@@ -40,9 +263,18 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
         interval: create_rc_signal(String::from("Minutes1")),
         fetch_history_from: create_rc_signal(get_default_fetch_date()),
         server_state: create_rc_signal(Status::Idle),
+        environment: create_rc_signal(Environment::default()),
         last_kline_time: create_rc_signal(String::from("0")),
         balance_sheet: create_rc_signal(BalanceSheetWithBalances::default()),
-        chart: create_rc_signal(Chart::default()),
+        candles: create_rc_signal(None),
+        chart_range: create_rc_signal(None),
+        equity: create_rc_signal(None),
+        pnl: create_rc_signal(None),
+        models: create_rc_signal(Vec::new()),
+        signal_evaluation: create_rc_signal(None),
+        walk_forward_runs: create_rc_signal(Vec::new()),
+        leaderboard: create_rc_signal(Vec::new()),
+        backtest_runs: create_rc_signal(Vec::new()),
     };
     let store = provide_context(cx, store);
 
@@ -78,14 +310,38 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
                 }
                 _ => (),
             }
-            match plot_chart(store.chart.get().page).await {
-                Ok(chart) => store.chart.set(chart),
-                _ => (),
+            match load_candles(*store.chart_range.get()).await {
+                Ok(candles) => store.candles.set(Some(candles)),
+                Err(e) => console_log(&format!("Error fetching candles: {:?}", e)),
+            }
+            match plot_equity(get_performance_from()).await {
+                Ok(equity) => store.equity.set(Some(equity)),
+                Err(e) => console_log(&format!("Error fetching equity curve: {:?}", e)),
+            }
+            match fetch_pnl(get_performance_from()).await {
+                Ok(pnl) => store.pnl.set(Some(pnl)),
+                Err(e) => console_log(&format!("Error fetching PnL: {:?}", e)),
             }
             match fetch_balance_sheet().await {
                 Ok(balance_sheet) => store.balance_sheet.set(balance_sheet),
                 Err(e) => console_log(&format!("Error fetching sheet: {:?}", e)),
             }
+            match fetch_models().await {
+                Ok(models) => store.models.set(models),
+                Err(e) => console_log(&format!("Error fetching models: {:?}", e)),
+            }
+            match fetch_walk_forward_runs().await {
+                Ok(runs) => store.walk_forward_runs.set(runs),
+                Err(e) => console_log(&format!("Error fetching walk-forward runs: {:?}", e)),
+            }
+            match fetch_leaderboard().await {
+                Ok(trials) => store.leaderboard.set(trials),
+                Err(e) => console_log(&format!("Error fetching leaderboard: {:?}", e)),
+            }
+            match fetch_backtest_runs().await {
+                Ok(runs) => store.backtest_runs.set(runs),
+                Err(e) => console_log(&format!("Error fetching backtests: {:?}", e)),
+            }
             TimeoutFuture::new(3000).await;
         }
     });
@@ -96,9 +352,10 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
             Ok(pair) => {
                 spawn_local_scoped(cx, async move {
                     match change_pair(pair).await {
-                        Err(e) => console_log(&e.to_string()),
+                        Err(e) => store.message.set(e),
                         Ok(pair) => {
                             store.pair.set(pair.to_string());
+                            store.chart_range.set(None);
                         }
                     }
                 });
@@ -113,9 +370,10 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
             Ok(interval) => {
                 spawn_local_scoped(cx, async move {
                     match change_interval(interval).await {
-                        Err(e) => console_log(&e.to_string()),
+                        Err(e) => store.message.set(e),
                         Ok(interval) => {
                             store.interval.set(interval.to_string());
+                            store.chart_range.set(None);
                         }
                     }
                 });
@@ -128,7 +386,7 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
             let date = date_string_to_integer(&store.fetch_history_from.get());
             match routes::fetch_history(date).await {
                 Ok(meshetar) => sync_store(store, meshetar),
-                _ => (),
+                Err(e) => store.message.set(e),
             }
         });
     };
@@ -136,7 +394,7 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
         spawn_local_scoped(cx, async move {
             match routes::run().await {
                 Ok(meshetar) => sync_store(store, meshetar),
-                _ => (),
+                Err(e) => store.message.set(e),
             }
         });
     };
@@ -144,7 +402,7 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
         spawn_local_scoped(cx, async move {
             match routes::create_new_model().await {
                 Ok(meshetar) => sync_store(store, meshetar),
-                _ => (),
+                Err(e) => store.message.set(e),
             }
         });
     };
@@ -152,7 +410,7 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
         spawn_local_scoped(cx, async move {
             match routes::stop().await {
                 Ok(meshetar) => sync_store(store, meshetar),
-                _ => (),
+                Err(e) => store.message.set(e),
             }
         });
     };
@@ -160,29 +418,45 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
         spawn_local_scoped(cx, async move {
             match routes::clear_history().await {
                 Ok(meshetar) => sync_store(store, meshetar),
-                _ => (),
+                Err(e) => store.message.set(e),
             }
         });
     };
-    let chart_pagination_increase = move |_| {
-        store.chart.set(store.chart.get().set_is_loading(true));
+    let start_walk_forward = move |_| {
         spawn_local_scoped(cx, async move {
-            match plot_chart(store.chart.get().page + 1).await {
-                Ok(chart) => store.chart.set(chart),
-                _ => (),
+            match routes::start_walk_forward().await {
+                Ok(meshetar) => sync_store(store, meshetar),
+                Err(e) => store.message.set(e),
             }
         });
     };
-    let chart_pagination_decrease = move |_| {
-        store.chart.set(store.chart.get().set_is_loading(true));
+    let start_hyperparameter_search = move |_| {
         spawn_local_scoped(cx, async move {
-            match plot_chart(store.chart.get().page - 1).await {
-                Ok(chart) => store.chart.set(chart),
-                _ => (),
+            match routes::start_hyperparameter_search().await {
+                Ok(meshetar) => sync_store(store, meshetar),
+                Err(e) => store.message.set(e),
+            }
+        });
+    };
+    let start_backtest = move |_| {
+        spawn_local_scoped(cx, async move {
+            match routes::start_backtest().await {
+                Ok(()) => match fetch_backtest_runs().await {
+                    Ok(runs) => store.backtest_runs.set(runs),
+                    Err(e) => store.message.set(e),
+                },
+                Err(e) => store.message.set(e),
+            }
+        });
+    };
+    let evaluate_signals = move |_| {
+        spawn_local_scoped(cx, async move {
+            match routes::fetch_signal_evaluation().await {
+                Ok(evaluation) => store.signal_evaluation.set(Some(evaluation)),
+                Err(e) => store.message.set(e),
             }
         });
     };
-
     view! {cx,
         header(class=format!("container {}", *meshetar_state_style.get())) {
             h1 {
@@ -190,6 +464,9 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
                     "🫰"
                 }
                 " MESHETAR"
+                span(class=format!("environment-label environment-{}", store.environment.get())) {
+                    (store.environment.get().to_string().to_uppercase())
+                }
             }
             div(class="grid") {
                 p {
@@ -208,8 +485,18 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
                     }
                     " | "
                     strong(class="text-success") {
-                        (format!("{} BUSD", to_fiat_format(store.balance_sheet.get().sheet.busd_valuation)))
+                        (format!(
+                            "{} {}",
+                            to_fiat_format(store.balance_sheet.get().sheet.reporting_valuation),
+                            store.balance_sheet.get().sheet.reporting_currency
+                        ))
                     }
+                    (if store.balance_sheet.get().sheet.unpriced_assets.is_empty() {
+                        View::empty()
+                    } else {
+                        let unpriced = format!(" | unpriced: {}", store.balance_sheet.get().sheet.unpriced_assets);
+                        view! { cx, small { (unpriced) } }
+                    })
                 }
                 ul(class="asset-grid") {
                     Keyed(
@@ -220,7 +507,12 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
                                 br {}
                                 span { (balance.free) }
                                 br {}
-                                small { (format!("{:.8} ₿", balance.btc_valuation)) }
+                                (if balance.is_priced {
+                                    let valuation = format!("{:.8} ₿", balance.btc_valuation);
+                                    view! { cx, small { (valuation) } }
+                                } else {
+                                    view! { cx, small { "No price" } }
+                                })
                             }
                         },
                         key=|balance| balance.id
@@ -229,6 +521,15 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
             }
         }
         main(class=format!("container {}", *meshetar_state_style.get())) {
+            (if store.message.get().is_empty() {
+                View::empty()
+            } else {
+                view! { cx,
+                    p(class="error-message", on:click=move |_| store.message.set(String::new())) {
+                        (store.message.get())
+                    }
+                }
+            })
             article {
                 div(class="grid") {
                     select(bind:value=store.pair, on:change=handle_change_pair) {
@@ -238,6 +539,9 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
                         option {
                             "ETHBTC"
                         }
+                        option {
+                            "ETHUSDT"
+                        }
                     }
                     select(bind:value=store.interval, on:change=handle_change_interval) {
                         option {
@@ -261,6 +565,15 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
                     button(class="secondary", on:click=create_new_model, disabled=*is_normally_disabled.get()) {
                         "🪩 Create new model"
                     }
+                    button(class="secondary", on:click=start_walk_forward, disabled=*is_normally_disabled.get()) {
+                        "🚶 Walk-forward validation"
+                    }
+                    button(class="secondary", on:click=start_hyperparameter_search, disabled=*is_normally_disabled.get()) {
+                        "🎛️ Parameter search"
+                    }
+                    button(class="secondary", on:click=start_backtest, disabled=*is_normally_disabled.get()) {
+                        "🧾 Backtest"
+                    }
                 }
                 div(class="grid") {
                     button(on:click=run, disabled=*is_normally_disabled.get()) {
@@ -272,20 +585,199 @@ pub fn App<G: Html>(cx: Scope) -> View<G> {
                 }
                 Divider{}
                 div(class="chart-container") {
-                    div(class="chart-controls") {
-                        button(class="contrast", aria-loading=store.chart.get().is_loading, on:click=chart_pagination_increase, disabled=store.chart.get().prev_disabled()) {
-                            "◀️ Prev"
+                    CandleChart {}
+                    (match active_model_plot(store) {
+                        Some(plot_path) => view! { cx,
+                            img(src=format!("http://localhost:8000/{}?ver={}", plot_path, get_timestamp()))
+                        },
+                        None => View::empty(),
+                    })
+                }
+                Divider{}
+                (match store.equity.get().as_ref() {
+                    Some(equity) => performance_view(cx, equity, store.pnl.get().as_ref()),
+                    None => View::empty(),
+                })
+                Divider{}
+                table(class="model-list") {
+                    thead {
+                        tr {
+                            th { "Model" }
+                            th { "Created" }
+                            th { "Pair" }
+                            th { "Backend" }
+                            th { "Status" }
+                            th { "Accuracy" }
+                            th {}
+                        }
+                    }
+                    tbody {
+                        Keyed(
+                            iterable=store.models.map(cx, |models| models.clone()),
+                            view=move |cx, model| {
+                                let id = model.id;
+                                let activate = move |_| {
+                                    spawn_local_scoped(cx, async move {
+                                        match routes::activate_model(id).await {
+                                            Ok(_) => match fetch_models().await {
+                                                Ok(models) => store.models.set(models),
+                                                Err(e) => store.message.set(e),
+                                            },
+                                            Err(e) => store.message.set(e),
+                                        }
+                                    });
+                                };
+                                let action = if model.is_active {
+                                    view! { cx, strong { "Active" } }
+                                } else {
+                                    view! { cx,
+                                        button(class="secondary", on:click=activate, disabled=model.status != "trained") {
+                                            "Activate"
+                                        }
+                                    }
+                                };
+                                view! { cx,
+                                    tr {
+                                        td { (format!("#{}", model.id)) }
+                                        td { (model.created_at.clone()) }
+                                        td { (format!("{} {}", model.symbol, model.interval)) }
+                                        td { (model.backend.clone()) }
+                                        td { (model.status.clone()) }
+                                        td { (model.accuracy()) }
+                                        td { (action) }
+                                    }
+                                }
+                            },
+                            key=|model| (model.id, model.is_active)
+                        )
+                    }
+                }
+                Divider{}
+                table(class="walk-forward-list") {
+                    thead {
+                        tr {
+                            th { "Walk-forward" }
+                            th { "Created" }
+                            th { "Pair" }
+                            th { "Status" }
+                            th { "Folds" }
+                            th { "Mean accuracy" }
+                            th { "Pooled accuracy" }
                         }
-                        span {
-                            (store.chart.get().page) " / " (store.chart.get().total_pages)
+                    }
+                    tbody {
+                        Keyed(
+                            iterable=store.walk_forward_runs.map(cx, |runs| runs.clone()),
+                            view=|cx, run| {
+                                let summary = run.summary();
+                                let folds = format!("{} of {}", summary.completed_folds, run.folds.len());
+                                let mean_accuracy = format!(
+                                    "{} ± {}",
+                                    to_percent_format(summary.mean_accuracy),
+                                    to_percent_format(summary.std_accuracy)
+                                );
+                                let pooled_accuracy = to_percent_format(summary.pooled_accuracy);
+                                view! { cx,
+                                    tr {
+                                        td { (format!("#{}", run.id)) }
+                                        td { (run.created_at.clone()) }
+                                        td { (format!("{} {}", run.symbol, run.interval)) }
+                                        td { (run.status.clone()) }
+                                        td { (folds) }
+                                        td { (mean_accuracy) }
+                                        td { (pooled_accuracy) }
+                                    }
+                                }
+                            },
+                            key=|run| (run.id, run.status.clone(), run.folds.len())
+                        )
+                    }
+                }
+                Divider{}
+                table(class="backtest-list") {
+                    thead {
+                        tr {
+                            th { "Backtest" }
+                            th { "Created" }
+                            th { "Pair" }
+                            th { "Source" }
+                            th { "Return" }
+                            th { "Buy and hold" }
+                            th { "Max drawdown" }
+                            th { "Trades" }
+                            th { "Report" }
                         }
-                        button(class="contrast", aria-loading=store.chart.get().is_loading, on:click=chart_pagination_decrease, disabled=store.chart.get().next_disabled()) {
-                            "▶️ Next"
+                    }
+                    tbody {
+                        Keyed(
+                            iterable=store.backtest_runs.map(cx, |runs| runs.clone()),
+                            view=|cx, run| {
+                                let summary = run.summary();
+                                let report = format!("http://localhost:8000/backtest/{}", run.id);
+                                view! { cx,
+                                    tr {
+                                        td { (format!("#{}", run.id)) }
+                                        td { (run.created_at.clone()) }
+                                        td { (format!("{} {}", run.symbol, run.interval)) }
+                                        td { (run.source.clone()) }
+                                        td { (to_percent_format(Some(summary.total_return))) }
+                                        td { (to_percent_format(Some(summary.buy_and_hold_return))) }
+                                        td { (to_percent_format(Some(summary.max_drawdown))) }
+                                        td { (summary.trades) }
+                                        td {
+                                            a(href=format!("{}/report", report), target="_blank") { "📄 View" }
+                                            " "
+                                            a(href=format!("{}/bundle?format=svg", report)) { "SVG" }
+                                            " "
+                                            a(href=format!("{}/bundle?format=png", report)) { "PNG" }
+                                        }
+                                    }
+                                }
+                            },
+                            key=|run| run.id
+                        )
+                    }
+                }
+                Divider{}
+                table(class="leaderboard") {
+                    thead {
+                        tr {
+                            th { "Search" }
+                            th { "Trial" }
+                            th { "Parameters" }
+                            th { "Status" }
+                            th { "Accuracy" }
                         }
                     }
-                    img(src=format!("http://localhost:8000/{}?ver={}", store.chart.get().path, get_timestamp()))
-                    img(src=format!("http://localhost:8000/{}?ver={}", store.chart.get().model_path, get_timestamp()))
+                    tbody {
+                        Keyed(
+                            iterable=store.leaderboard.map(cx, |trials| trials.clone()),
+                            view=|cx, trial| {
+                                let score = to_percent_format(trial.score);
+                                view! { cx,
+                                    tr {
+                                        td { (format!("#{}", trial.search_id)) }
+                                        td { (trial.trial) }
+                                        td { code { (trial.parameters.clone()) } }
+                                        td { (trial.status.clone()) }
+                                        td { (score) }
+                                    }
+                                }
+                            },
+                            key=|trial| (trial.search_id, trial.trial, trial.status.clone())
+                        )
+                    }
+                }
+                Divider{}
+                div(class="grid") {
+                    button(class="secondary", on:click=evaluate_signals) {
+                        "🎯 Evaluate signals"
+                    }
                 }
+                (match store.signal_evaluation.get().as_ref() {
+                    Some(evaluation) => signal_evaluation_view(cx, evaluation),
+                    None => View::empty(),
+                })
             }
         }
     }
@@ -0,0 +1,493 @@
+use crate::routes::fetch_candles;
+use crate::store::Store;
+use crate::store_models::{Candle, CandleSeries, ChartRange, ChartSignal};
+use crate::utils::console_log;
+use chrono::{TimeZone, Utc};
+use sycamore::futures::spawn_local_scoped;
+use sycamore::prelude::{Html, Scope};
+use sycamore::reactive::{create_memo, create_signal, use_context};
+use sycamore::view::View;
+use sycamore::{component, view};
+use wasm_bindgen::JsCast;
+use web_sys::{Element, Event, MouseEvent, WheelEvent};
+
+/// Size of the chart in SVG units, it is scaled to the width of its container
+const WIDTH: f64 = 1024.0;
+const HEIGHT: f64 = 480.0;
+/// Plot area, the price axis is on the right and the time axis at the bottom
+const LEFT: f64 = 8.0;
+const RIGHT: f64 = WIDTH - 72.0;
+const TOP: f64 = 8.0;
+const BOTTOM: f64 = HEIGHT - 28.0;
+/// Candles the chart asks for per visible range, the server merges klines beyond that
+const CANDLES_PER_RANGE: i64 = 300;
+/// Fewest candles a zoom in may leave visible
+const MIN_VISIBLE_CANDLES: i64 = 10;
+const ZOOM_STEP: f64 = 1.25;
+
+const UP_COLOR: &str = "#26a69a";
+const DOWN_COLOR: &str = "#ef5350";
+const GRID_COLOR: &str = "rgba(128,128,128,0.2)";
+const TEXT_COLOR: &str = "#8a8a8a";
+
+/// Duration of a candle of the series, from the spacing of its candles
+fn candle_ms(series: &CandleSeries) -> i64 {
+    series
+        .candles
+        .windows(2)
+        .map(|pair| pair[1].open_time - pair[0].open_time)
+        .filter(|gap| *gap > 0)
+        .min()
+        .unwrap_or(series.candle_ms.unwrap_or(60000))
+}
+
+/// Range shown: the chosen one or the whole series when following the latest klines
+fn visible_range(range: Option<ChartRange>, series: &CandleSeries) -> ChartRange {
+    range.unwrap_or(ChartRange {
+        from: series.from,
+        to: series.to + candle_ms(series),
+    })
+}
+
+fn format_time(time: i64, span: i64) -> String {
+    let format = if span > 2 * 86400000 {
+        "%d.%m. %H:%M"
+    } else {
+        "%H:%M"
+    };
+    match Utc.timestamp_millis_opt(time).single() {
+        Some(time) => time.format(format).to_string(),
+        None => String::new(),
+    }
+}
+
+/// Decimals that tell prices of the range apart
+fn price_decimals(range: f64) -> usize {
+    match range {
+        range if range >= 100.0 => 0,
+        range if range >= 1.0 => 2,
+        range if range >= 0.01 => 4,
+        _ => 6,
+    }
+}
+
+/// Maps times and prices to SVG coordinates
+struct Scale {
+    range: ChartRange,
+    low: f64,
+    high: f64,
+}
+
+impl Scale {
+    fn x(&self, time: i64) -> f64 {
+        LEFT + (time - self.range.from) as f64 / self.range.span() as f64 * (RIGHT - LEFT)
+    }
+    fn time(&self, x: f64) -> i64 {
+        self.range.from + ((x - LEFT) / (RIGHT - LEFT) * self.range.span() as f64) as i64
+    }
+    fn y(&self, price: f64) -> f64 {
+        BOTTOM - (price - self.low) / (self.high - self.low) * (BOTTOM - TOP)
+    }
+    fn price(&self, y: f64) -> f64 {
+        self.low + (BOTTOM - y) / (BOTTOM - TOP) * (self.high - self.low)
+    }
+}
+
+fn visible_candles<'a>(
+    series: &'a CandleSeries,
+    range: &ChartRange,
+    candle_ms: i64,
+) -> Vec<&'a Candle> {
+    series
+        .candles
+        .iter()
+        .filter(|candle| candle.open_time + candle_ms > range.from && candle.open_time < range.to)
+        .collect()
+}
+
+/// Candle a time falls into
+fn candle_at<'a>(candles: &[&'a Candle], time: i64, candle_ms: i64) -> Option<&'a Candle> {
+    candles
+        .iter()
+        .find(|candle| candle.open_time <= time && time < candle.open_time + candle_ms)
+        .copied()
+}
+
+/// Buy and sell signals of a candle, the last one wins when several klines were merged
+fn candle_signal<'a>(
+    signals: &'a [ChartSignal],
+    candle: &Candle,
+    candle_ms: i64,
+) -> Option<&'a ChartSignal> {
+    signals
+        .iter()
+        .filter(|signal| signal.signal == "buy" || signal.signal == "sell")
+        .filter(|signal| {
+            candle.open_time <= signal.time && signal.time < candle.open_time + candle_ms
+        })
+        .last()
+}
+
+/// The chart as SVG markup: candles, buy and sell markers at the candle's price, axes and,
+/// while hovered, a crosshair with the candle under it
+fn render_svg(series: &CandleSeries, range: ChartRange, hover: Option<(f64, f64)>) -> String {
+    let candle_ms = candle_ms(series);
+    let candles = visible_candles(series, &range, candle_ms);
+    let mut svg = format!(
+        r#"<svg viewBox="0 0 {} {}" xmlns="http://www.w3.org/2000/svg" font-size="11" font-family="sans-serif">"#,
+        WIDTH, HEIGHT
+    );
+    if candles.is_empty() {
+        svg.push_str(&format!(
+            r#"<text x="{}" y="{}" fill="{}" text-anchor="middle">No klines in this range</text></svg>"#,
+            WIDTH / 2.0,
+            HEIGHT / 2.0,
+            TEXT_COLOR
+        ));
+        return svg;
+    }
+    let low = candles
+        .iter()
+        .map(|candle| candle.low)
+        .fold(f64::MAX, f64::min);
+    let high = candles
+        .iter()
+        .map(|candle| candle.high)
+        .fold(f64::MIN, f64::max);
+    let padding = ((high - low) * 0.08).max(high.abs() * 0.0005);
+    let scale = Scale {
+        range,
+        low: low - padding,
+        high: high + padding,
+    };
+    let decimals = price_decimals(scale.high - scale.low);
+
+    // Grid with price and time labels
+    for i in 0..=5 {
+        let price = scale.low + (scale.high - scale.low) * i as f64 / 5.0;
+        let y = scale.y(price);
+        svg.push_str(&format!(
+            r#"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke="{}"/><text x="{}" y="{:.1}" fill="{}">{:.*}</text>"#,
+            LEFT,
+            RIGHT,
+            GRID_COLOR,
+            RIGHT + 6.0,
+            y + 4.0,
+            TEXT_COLOR,
+            decimals,
+            price
+        ));
+    }
+    for i in 0..=6 {
+        let time = range.from + range.span() * i / 6;
+        let x = scale.x(time);
+        svg.push_str(&format!(
+            r#"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}" stroke="{}"/><text x="{x:.1}" y="{}" fill="{}" text-anchor="middle">{}</text>"#,
+            TOP,
+            BOTTOM,
+            GRID_COLOR,
+            BOTTOM + 18.0,
+            TEXT_COLOR,
+            format_time(time, range.span())
+        ));
+    }
+
+    // Candles, clipped to the plot area
+    svg.push_str(&format!(
+        r#"<clipPath id="plot-area"><rect x="{}" y="{}" width="{}" height="{}"/></clipPath><g clip-path="url(#plot-area)">"#,
+        LEFT,
+        TOP,
+        RIGHT - LEFT,
+        BOTTOM - TOP
+    ));
+    let body_width = (scale.x(range.from + candle_ms) - LEFT) * 0.7;
+    for candle in candles.iter() {
+        let color = if candle.close >= candle.open {
+            UP_COLOR
+        } else {
+            DOWN_COLOR
+        };
+        let x = scale.x(candle.open_time) + body_width / 0.7 / 2.0;
+        let top = scale.y(candle.open.max(candle.close));
+        let bottom = scale.y(candle.open.min(candle.close));
+        svg.push_str(&format!(
+            r#"<line x1="{x:.1}" y1="{:.1}" x2="{x:.1}" y2="{:.1}" stroke="{color}"/><rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{color}"/>"#,
+            scale.y(candle.high),
+            scale.y(candle.low),
+            x - body_width / 2.0,
+            top,
+            body_width.max(1.0),
+            (bottom - top).max(1.0),
+        ));
+        // Markers point at the candle, below its low for buys and above its high for sells
+        if let Some(signal) = candle_signal(&series.signals, candle, candle_ms) {
+            let size = body_width.clamp(4.0, 8.0);
+            let marker = if signal.signal == "buy" {
+                let y = scale.y(candle.low) + 4.0;
+                format!(
+                    r#"<path d="M{x:.1} {y:.1} l{size:.1} {:.1} h{:.1} z" fill="{}"/>"#,
+                    size * 1.5,
+                    -2.0 * size,
+                    UP_COLOR
+                )
+            } else {
+                let y = scale.y(candle.high) - 4.0;
+                format!(
+                    r#"<path d="M{x:.1} {y:.1} l{size:.1} {:.1} h{:.1} z" fill="{}"/>"#,
+                    -size * 1.5,
+                    -2.0 * size,
+                    DOWN_COLOR
+                )
+            };
+            svg.push_str(&marker);
+        }
+    }
+    svg.push_str("</g>");
+
+    if let Some((x, y)) =
+        hover.filter(|(x, y)| (LEFT..=RIGHT).contains(x) && (TOP..=BOTTOM).contains(y))
+    {
+        svg.push_str(&crosshair(
+            series, &scale, &candles, candle_ms, decimals, x, y,
+        ));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Crosshair lines, the price at the cursor and a tooltip of the candle under it
+fn crosshair(
+    series: &CandleSeries,
+    scale: &Scale,
+    candles: &[&Candle],
+    candle_ms: i64,
+    decimals: usize,
+    x: f64,
+    y: f64,
+) -> String {
+    let mut svg = format!(
+        r##"<g stroke="{}" stroke-dasharray="4 4"><line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}"/><line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}"/></g><rect x="{}" y="{:.1}" width="70" height="16" fill="#444"/><text x="{}" y="{:.1}" fill="#fff">{:.*}</text>"##,
+        TEXT_COLOR,
+        LEFT,
+        RIGHT,
+        TOP,
+        BOTTOM,
+        RIGHT + 1.0,
+        y - 8.0,
+        RIGHT + 6.0,
+        y + 4.0,
+        decimals,
+        scale.price(y)
+    );
+    let Some(candle) = candle_at(candles, scale.time(x), candle_ms) else {
+        return svg;
+    };
+    let mut lines = vec![
+        format_time(candle.open_time, 3 * 86400000),
+        format!(
+            "O {:.*}  H {:.*}",
+            decimals, candle.open, decimals, candle.high
+        ),
+        format!(
+            "L {:.*}  C {:.*}",
+            decimals, candle.low, decimals, candle.close
+        ),
+        format!("Volume {:.2}", candle.volume),
+    ];
+    if let Some(candle_ms) = series.candle_ms {
+        lines.push(format!("{} minute candle", candle_ms / 60000));
+    }
+    if let Some(signal) = candle_signal(&series.signals, candle, candle_ms) {
+        let name = match signal.count {
+            1 => signal.signal.to_uppercase(),
+            count => format!("{} x{}", signal.signal.to_uppercase(), count),
+        };
+        lines.push(match signal.confidence {
+            Some(confidence) => format!("{} at {:.0}%", name, confidence * 100.0),
+            None => name,
+        });
+    }
+    // The tooltip stays on the side of the cursor with more room
+    let width = 170.0;
+    let height = 8.0 + 15.0 * lines.len() as f64;
+    let left = if x + 12.0 + width > RIGHT {
+        x - 12.0 - width
+    } else {
+        x + 12.0
+    };
+    let top = (y + 12.0).min(BOTTOM - height);
+    svg.push_str(&format!(
+        r#"<rect x="{left:.1}" y="{top:.1}" width="{width}" height="{height}" rx="4" fill="rgba(20,20,20,0.85)"/>"#
+    ));
+    for (i, line) in lines.iter().enumerate() {
+        svg.push_str(&format!(
+            r##"<text x="{:.1}" y="{:.1}" fill="#eee">{}</text>"##,
+            left + 8.0,
+            top + 17.0 + 15.0 * i as f64,
+            line
+        ));
+    }
+    svg
+}
+
+/// Position of a mouse event in SVG units of the element it is handled on
+fn event_point(event: &Event) -> Option<(f64, f64)> {
+    let mouse = event.dyn_ref::<MouseEvent>()?;
+    let element = event.current_target()?.dyn_into::<Element>().ok()?;
+    let rect = element.get_bounding_client_rect();
+    if rect.width() <= 0.0 || rect.height() <= 0.0 {
+        return None;
+    }
+    Some((
+        (mouse.client_x() as f64 - rect.left()) / rect.width() * WIDTH,
+        (mouse.client_y() as f64 - rect.top()) / rect.height() * HEIGHT,
+    ))
+}
+
+/// Fetches the candles of the chosen range with half a range on either side, so a pan shows
+/// candles right away. Follows the latest klines without a range.
+pub async fn load_candles(range: Option<ChartRange>) -> Result<CandleSeries, String> {
+    match range {
+        Some(range) => {
+            let margin = range.span() / 2;
+            fetch_candles(
+                Some(range.from - margin),
+                Some(range.to + margin),
+                Some(CANDLES_PER_RANGE * 2),
+            )
+            .await
+        }
+        None => fetch_candles(None, None, None).await,
+    }
+}
+
+#[component]
+pub fn CandleChart<G: Html>(cx: Scope) -> View<G> {
+    let store = use_context::<Store>(cx);
+    let hover = create_signal(cx, None::<(f64, f64)>);
+    // Cursor position and range when a drag started
+    let drag = create_signal(cx, None::<(f64, ChartRange)>);
+    let svg = create_memo(cx, move || match store.candles.get().as_ref() {
+        Some(series) => render_svg(
+            series,
+            visible_range(*store.chart_range.get(), series),
+            *hover.get(),
+        ),
+        None => String::new(),
+    });
+
+    let reload = move || {
+        spawn_local_scoped(cx, async move {
+            match load_candles(*store.chart_range.get()).await {
+                Ok(series) => store.candles.set(Some(series)),
+                Err(e) => console_log(&format!("Error fetching candles: {:?}", e)),
+            }
+        });
+    };
+    let current_range = move || {
+        store
+            .candles
+            .get()
+            .as_ref()
+            .as_ref()
+            .map(|series| visible_range(*store.chart_range.get(), series))
+    };
+    // Zooms around a point given as its share of the plot width
+    let zoom = move |factor: f64, anchor: f64| {
+        let Some(range) = current_range() else {
+            return;
+        };
+        let min_span = store
+            .candles
+            .get()
+            .as_ref()
+            .as_ref()
+            .map_or(1, |series| MIN_VISIBLE_CANDLES * candle_ms(series));
+        let span = ((range.span() as f64 * factor) as i64).max(min_span);
+        let from = range.from + ((range.span() - span) as f64 * anchor) as i64;
+        store.chart_range.set(Some(ChartRange {
+            from,
+            to: from + span,
+        }));
+        reload();
+    };
+    // Moves the range by a share of its span, older for negative shares
+    let pan = move |share: f64| {
+        if let Some(range) = current_range() {
+            let shift = (range.span() as f64 * share) as i64;
+            store.chart_range.set(Some(ChartRange {
+                from: range.from + shift,
+                to: range.to + shift,
+            }));
+            reload();
+        }
+    };
+
+    let on_wheel = move |event: Event| {
+        event.prevent_default();
+        let Some(wheel) = event.dyn_ref::<WheelEvent>() else {
+            return;
+        };
+        let anchor =
+            event_point(&event).map_or(1.0, |(x, _)| ((x - LEFT) / (RIGHT - LEFT)).clamp(0.0, 1.0));
+        if wheel.delta_y() < 0.0 {
+            zoom(1.0 / ZOOM_STEP, anchor);
+        } else {
+            zoom(ZOOM_STEP, anchor);
+        }
+    };
+    let on_mouse_down = move |event: Event| {
+        if let (Some((x, _)), Some(range)) = (event_point(&event), current_range()) {
+            drag.set(Some((x, range)));
+        }
+    };
+    let on_mouse_move = move |event: Event| {
+        let point = event_point(&event);
+        hover.set(point);
+        if let (Some((start, range)), Some((x, _))) = (*drag.get(), point) {
+            let shift = ((start - x) / (RIGHT - LEFT) * range.span() as f64) as i64;
+            store.chart_range.set(Some(ChartRange {
+                from: range.from + shift,
+                to: range.to + shift,
+            }));
+        }
+    };
+    let on_mouse_up = move |_| {
+        if drag.get().is_some() {
+            drag.set(None);
+            reload();
+        }
+    };
+    let on_mouse_leave = move |_| {
+        hover.set(None);
+        if drag.get().is_some() {
+            drag.set(None);
+            reload();
+        }
+    };
+    let follow_latest = move |_| {
+        store.chart_range.set(None);
+        reload();
+    };
+
+    view! { cx,
+        div(class="chart-controls") {
+            button(class="contrast outline", title="Older", on:click=move |_| pan(-0.5)) { "◀️" }
+            button(class="contrast outline", title="Newer", on:click=move |_| pan(0.5)) { "▶️" }
+            button(class="contrast outline", title="Zoom out", on:click=move |_| zoom(ZOOM_STEP, 1.0)) { "➖" }
+            button(class="contrast outline", title="Zoom in", on:click=move |_| zoom(1.0 / ZOOM_STEP, 1.0)) { "➕" }
+            button(class="contrast", disabled=store.chart_range.get().is_none(), on:click=follow_latest) {
+                "⏭️ Latest"
+            }
+        }
+        div(
+            class=if drag.get().is_some() { "candle-chart dragging" } else { "candle-chart" },
+            on:wheel=on_wheel,
+            on:mousedown=on_mouse_down,
+            on:mousemove=on_mouse_move,
+            on:mouseup=on_mouse_up,
+            on:mouseleave=on_mouse_leave,
+            dangerously_set_inner_html=svg.get().to_string()
+        )
+    }
+}
@@ -1,4 +1,5 @@
 mod app;
+mod chart;
 mod routes;
 mod store;
 mod store_models;
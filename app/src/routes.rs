@@ -1,7 +1,23 @@
-use crate::store_models::{BalanceSheetWithBalances, Chart, Interval, Meshetar, Pair};
+use crate::store_models::{
+    BacktestRun, BalanceSheetWithBalances, CandleSeries, EquityPlot, ErrorResponse, Interval,
+    Meshetar, ModelRecord, Pair, Pnl, SearchTrial, SignalEvaluation, WalkForwardRun,
+};
 use reqwest::Response;
 
+/// Turns error responses of the server into their message
+async fn ensure_success(payload: Response) -> Result<Response, String> {
+    if payload.status().is_success() {
+        return Ok(payload);
+    }
+    let status = payload.status();
+    match payload.json::<ErrorResponse>().await {
+        Ok(error) => Err(error.message),
+        Err(_) => Err(format!("Server responded with {}", status)),
+    }
+}
+
 async fn parse_status(payload: Response) -> Result<Meshetar, String> {
+    let payload = ensure_success(payload).await?;
     match payload.text().await {
         Ok(meshetar) => match serde_json::from_str(&meshetar) {
             Ok(meshetar) => Ok(meshetar),
@@ -10,22 +26,15 @@ async fn parse_status(payload: Response) -> Result<Meshetar, String> {
         Err(e) => Err(e.to_string()),
     }
 }
-async fn parse_chart(payload: Response) -> Result<Chart, String> {
-    match payload.text().await {
-        Ok(chart) => match serde_json::from_str(&chart) {
-            Ok(chart) => Ok(chart),
-            Err(e) => Err(String::from(format!("Error parsing response {:?}", e))),
-        },
-        Err(e) => Err(e.to_string()),
-    }
-}
 async fn parse_response_string(payload: Response) -> Result<String, String> {
+    let payload = ensure_success(payload).await?;
     match payload.text().await {
         Ok(payload) => Ok(payload),
         Err(e) => Err(e.to_string()),
     }
 }
 async fn parse_balance_sheet(payload: Response) -> Result<BalanceSheetWithBalances, String> {
+    let payload = ensure_success(payload).await?;
     match payload.text().await {
         Ok(balance_sheet) => match serde_json::from_str(&balance_sheet) {
             Ok(balance_sheet) => Ok(balance_sheet),
@@ -60,10 +69,7 @@ pub async fn fetch_balance_sheet() -> Result<BalanceSheetWithBalances, String> {
 pub async fn fetch_last_kline_time() -> Result<String, String> {
     let resp = reqwest::get("http://localhost:8000/last_kline_time").await;
     match resp {
-        Ok(resp) => match resp.text().await {
-            Ok(last_kline_time) => Ok(last_kline_time),
-            Err(e) => Err(e.to_string()),
-        },
+        Ok(resp) => parse_response_string(resp).await,
         Err(e) => Err(e.to_string()),
     }
 }
@@ -124,21 +130,66 @@ pub async fn run() -> Result<Meshetar, String> {
     }
 }
 
-pub async fn plot_chart(page: i64) -> Result<Chart, String> {
-    let params = [("page", page.to_string())];
+/// Candles and signals of the current pair and interval between `from` and `to` (epoch ms),
+/// the latest ones without `from`
+pub async fn fetch_candles(
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+) -> Result<CandleSeries, String> {
+    let params: Vec<String> = [("from", from), ("to", to), ("limit", limit)]
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| format!("{}={}", name, value)))
+        .collect();
+    let resp = reqwest::get(format!(
+        "http://localhost:8000/candles?{}",
+        params.join("&")
+    ))
+    .await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<CandleSeries>().await {
+                Ok(candles) => Ok(candles),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Equity curve from `from` (epoch ms) until now
+pub async fn plot_equity(from: i64) -> Result<EquityPlot, String> {
+    let params = [("from", from)];
     let client = reqwest::Client::new();
     let resp = client
-        .post("http://localhost:8000/plot_chart")
+        .post("http://localhost:8000/plot_equity")
         .form(&params)
         .send()
         .await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<EquityPlot>().await {
+                Ok(equity) => Ok(equity),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
 
+pub async fn fetch_pnl(from: i64) -> Result<Pnl, String> {
+    let resp = reqwest::get(format!("http://localhost:8000/pnl?from={}", from)).await;
     match resp {
         Ok(resp) => {
-            let chart = parse_chart(resp).await?;
-            Ok(chart)
+            let payload = ensure_success(resp).await?;
+            match payload.json::<Pnl>().await {
+                Ok(pnl) => Ok(pnl),
+                Err(e) => Err(e.to_string()),
+            }
         }
-        Err(e) => Err(format!("{}", e)),
+        Err(e) => Err(e.to_string()),
     }
 }
 
@@ -166,7 +217,7 @@ pub async fn change_pair(pair: Pair) -> Result<Pair, String> {
         .send()
         .await;
     match resp {
-        Ok(resp) => match resp.text().await {
+        Ok(resp) => match parse_response_string(resp).await {
             Ok(pair) => {
                 let pair = pair.parse::<Pair>();
                 match pair {
@@ -174,7 +225,7 @@ pub async fn change_pair(pair: Pair) -> Result<Pair, String> {
                     Err(e) => Err(e.to_string()),
                 }
             }
-            Err(e) => Err(e.to_string()),
+            Err(e) => Err(e),
         },
         Err(e) => Err(e.to_string()),
     }
@@ -189,7 +240,7 @@ pub async fn change_interval(interval: Interval) -> Result<Interval, String> {
         .send()
         .await;
     match resp {
-        Ok(resp) => match resp.text().await {
+        Ok(resp) => match parse_response_string(resp).await {
             Ok(interval) => {
                 let interval = interval.parse::<Interval>();
                 match interval {
@@ -197,8 +248,145 @@ pub async fn change_interval(interval: Interval) -> Result<Interval, String> {
                     Err(e) => Err(e.to_string()),
                 }
             }
-            Err(e) => Err(e.to_string()),
+            Err(e) => Err(e),
         },
         Err(e) => Err(e.to_string()),
     }
 }
+
+pub async fn fetch_models() -> Result<Vec<ModelRecord>, String> {
+    let resp = reqwest::get("http://localhost:8000/models").await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<Vec<ModelRecord>>().await {
+                Ok(models) => Ok(models),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn activate_model(id: i64) -> Result<ModelRecord, String> {
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("http://localhost:8000/models/{}/activate", id))
+        .send()
+        .await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<ModelRecord>().await {
+                Ok(model) => Ok(model),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn fetch_signal_evaluation() -> Result<SignalEvaluation, String> {
+    let resp = reqwest::get("http://localhost:8000/signal_evaluation").await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<SignalEvaluation>().await {
+                Ok(evaluation) => Ok(evaluation),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn start_walk_forward() -> Result<Meshetar, String> {
+    let client = reqwest::Client::new();
+    let resp = client
+        .post("http://localhost:8000/walk_forward")
+        .send()
+        .await;
+    match resp {
+        Ok(resp) => {
+            let meshetar = parse_status(resp).await?;
+            Ok(meshetar)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn fetch_walk_forward_runs() -> Result<Vec<WalkForwardRun>, String> {
+    let resp = reqwest::get("http://localhost:8000/walk_forward").await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<Vec<WalkForwardRun>>().await {
+                Ok(runs) => Ok(runs),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn start_hyperparameter_search() -> Result<Meshetar, String> {
+    let client = reqwest::Client::new();
+    let resp = client
+        .post("http://localhost:8000/hyperparameter_search")
+        .send()
+        .await;
+    match resp {
+        Ok(resp) => {
+            let meshetar = parse_status(resp).await?;
+            Ok(meshetar)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn fetch_leaderboard() -> Result<Vec<SearchTrial>, String> {
+    let resp =
+        reqwest::get("http://localhost:8000/hyperparameter_search/leaderboard?limit=10").await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<Vec<SearchTrial>>().await {
+                Ok(trials) => Ok(trials),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Backtests the running model's signals over the last 30 days, a paper trading run
+pub async fn start_backtest() -> Result<(), String> {
+    let params = [("source", "signals")];
+    let client = reqwest::Client::new();
+    let resp = client
+        .post("http://localhost:8000/backtest")
+        .form(&params)
+        .send()
+        .await;
+    match resp {
+        Ok(resp) => {
+            ensure_success(resp).await?;
+            Ok(())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn fetch_backtest_runs() -> Result<Vec<BacktestRun>, String> {
+    let resp = reqwest::get("http://localhost:8000/backtest").await;
+    match resp {
+        Ok(resp) => {
+            let payload = ensure_success(resp).await?;
+            match payload.json::<Vec<BacktestRun>>().await {
+                Ok(runs) => Ok(runs),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -1,6 +1,9 @@
 use sycamore::reactive::RcSignal;
 
-use crate::store_models::{BalanceSheetWithBalances, Chart, Status};
+use crate::store_models::{
+    BacktestRun, BalanceSheetWithBalances, CandleSeries, ChartRange, Environment, EquityPlot,
+    ModelRecord, Pnl, SearchTrial, SignalEvaluation, Status, WalkForwardRun,
+};
 
 #[derive(Debug, Default, Clone)]
 pub struct Store {
@@ -10,7 +13,17 @@ pub struct Store {
     pub interval: RcSignal<String>,
     pub fetch_history_from: RcSignal<String>,
     pub server_state: RcSignal<Status>,
+    pub environment: RcSignal<Environment>,
     pub last_kline_time: RcSignal<String>,
     pub balance_sheet: RcSignal<BalanceSheetWithBalances>,
-    pub chart: RcSignal<Chart>,
+    pub candles: RcSignal<Option<CandleSeries>>,
+    /// None follows the latest klines
+    pub chart_range: RcSignal<Option<ChartRange>>,
+    pub equity: RcSignal<Option<EquityPlot>>,
+    pub pnl: RcSignal<Option<Pnl>>,
+    pub models: RcSignal<Vec<ModelRecord>>,
+    pub signal_evaluation: RcSignal<Option<SignalEvaluation>>,
+    pub walk_forward_runs: RcSignal<Vec<WalkForwardRun>>,
+    pub leaderboard: RcSignal<Vec<SearchTrial>>,
+    pub backtest_runs: RcSignal<Vec<BacktestRun>>,
 }
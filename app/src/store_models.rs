@@ -1,6 +1,6 @@
-use crate::utils::default_false;
 use chrono::NaiveDateTime;
 use serde::Deserialize;
+use std::collections::HashMap;
 use strum::{Display, EnumString};
 
 #[derive(Debug, Deserialize, Display, Default, Copy, Clone, Eq, PartialEq)]
@@ -13,10 +13,28 @@ pub enum Status {
     Running,
 }
 
+/// Body of every error response from the server
+#[derive(Debug, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    pub message: String,
+}
+
+#[derive(Debug, Deserialize, Display, Default, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Testnet,
+    Mainnet,
+    Mock,
+}
+
 #[derive(Deserialize, Display, EnumString)]
 pub enum Pair {
     BTCUSDT,
     ETHBTC,
+    ETHUSDT,
 }
 
 #[derive(Deserialize, Display, EnumString)]
@@ -24,12 +42,22 @@ pub enum Interval {
     Minutes1,
     Minutes3,
 }
+impl Interval {
+    /// Interval as the server stores it with klines and models
+    pub fn kline_interval(&self) -> &'static str {
+        match self {
+            Interval::Minutes1 => "1m",
+            Interval::Minutes3 => "3m",
+        }
+    }
+}
 
 #[derive(Deserialize)]
 pub struct Meshetar {
     pub pair: Pair,
     pub interval: Interval,
     pub status: Status,
+    pub environment: Environment,
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq)]
@@ -39,6 +67,8 @@ pub struct Balance {
     pub free: f64,
     pub locked: f64,
     pub btc_valuation: f64,
+    pub reporting_valuation: f64,
+    pub is_priced: bool,
     pub balance_sheet_id: i64,
 }
 
@@ -47,7 +77,9 @@ pub struct BalanceSheet {
     pub id: i64,
     pub timestamp: NaiveDateTime,
     pub btc_valuation: f64,
-    pub busd_valuation: f64,
+    pub reporting_valuation: f64,
+    pub reporting_currency: String,
+    pub unpriced_assets: String,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -63,47 +95,252 @@ impl Default for BalanceSheetWithBalances {
                 id: 0,
                 timestamp: NaiveDateTime::from_timestamp_millis(0).unwrap(),
                 btc_valuation: 0f64,
-                busd_valuation: 0f64,
+                reporting_valuation: 0f64,
+                reporting_currency: String::new(),
+                unpriced_assets: String::new(),
             },
             balances: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct Chart {
-    pub path: String,
-    pub model_path: String,
-    pub page: i64,
-    pub total_pages: i64,
-    #[serde(default = "default_false")]
-    pub is_loading: bool,
+#[derive(Deserialize, Debug, Clone)]
+pub struct EquityPoint {
+    pub time: i64,
+    pub btc_valuation: f64,
+    pub reporting_valuation: f64,
+    pub reporting_currency: String,
+    pub drawdown: f64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DailyReturn {
+    pub day: String,
+    pub btc_return: Option<f64>,
+    pub reporting_return: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EquityCurve {
+    pub from: i64,
+    pub to: i64,
+    pub points: Vec<EquityPoint>,
+    pub daily_returns: Vec<DailyReturn>,
+    pub max_drawdown: f64,
+    pub total_return: Option<f64>,
 }
-impl Chart {
-    pub fn next_disabled(&self) -> bool {
-        self.page <= 1 || self.is_loading
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EquityPlot {
+    pub curve: EquityCurve,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Pnl {
+    pub symbol: String,
+    pub quote_asset: String,
+    pub fills: usize,
+    pub position: f64,
+    pub average_cost: Option<f64>,
+    pub realized: f64,
+    /// Net of the fee of selling the position
+    pub unrealized: Option<f64>,
+    pub exit_fee: Option<f64>,
+    pub commission: f64,
+    pub other_commissions: HashMap<String, f64>,
+    /// Realized less the commissions paid in other assets
+    pub net_realized: Option<f64>,
+    pub last_price: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Candle {
+    pub open_time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ChartSignal {
+    pub time: i64,
+    /// "buy", "sell" or "hold"
+    pub signal: String,
+    pub confidence: Option<f64>,
+    /// Signals merged into this one when klines were merged into candles
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct CandleSeries {
+    pub symbol: String,
+    pub interval: String,
+    pub from: i64,
+    pub to: i64,
+    /// Time each candle spans when klines were merged, None for candles of single klines
+    pub candle_ms: Option<i64>,
+    pub candles: Vec<Candle>,
+    pub signals: Vec<ChartSignal>,
+}
+
+/// Time range the chart shows, epoch ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChartRange {
+    pub from: i64,
+    pub to: i64,
+}
+impl ChartRange {
+    pub fn span(&self) -> i64 {
+        (self.to - self.from).max(1)
     }
-    pub fn prev_disabled(&self) -> bool {
-        self.page >= self.total_pages || self.is_loading
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ModelRecord {
+    pub id: i64,
+    pub created_at: String,
+    pub symbol: String,
+    pub interval: String,
+    pub status: String,
+    /// JSON object of evaluation metrics
+    pub metrics: Option<String>,
+    pub plot_path: String,
+    pub is_active: bool,
+    /// "r" or "rust"
+    pub backend: String,
+}
+impl ModelRecord {
+    pub fn accuracy(&self) -> String {
+        self.metrics
+            .as_ref()
+            .and_then(|metrics| serde_json::from_str::<serde_json::Value>(metrics).ok())
+            .and_then(|metrics| metrics["accuracy"].as_f64())
+            .map(|accuracy| format!("{:.1}%", accuracy * 100.0))
+            .unwrap_or(String::from("-"))
     }
-    pub fn set_is_loading(&self, is_loading: bool) -> Chart {
-        Chart {
-            path: self.path.clone(),
-            model_path: self.path.clone(),
-            page: self.page.clone(),
-            total_pages: self.total_pages.clone(),
-            is_loading,
-        }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ClassHitRate {
+    pub signal: String,
+    pub count: i64,
+    pub hits: i64,
+    pub hit_rate: Option<f64>,
+    pub mean_return: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct HorizonEvaluation {
+    pub horizon: usize,
+    pub classes: Vec<ClassHitRate>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ConfusionMatrix {
+    pub labels: Vec<String>,
+    /// Rows are the signals, columns the optimal labels
+    pub counts: Vec<Vec<i64>>,
+    pub accuracy: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct CalibrationBin {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: i64,
+    pub mean_probability: Option<f64>,
+    pub observed_frequency: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct CalibrationCurve {
+    pub signal: String,
+    pub bins: Vec<CalibrationBin>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct SignalEvaluation {
+    pub symbol: String,
+    pub interval: String,
+    pub signals: i64,
+    pub horizons: Vec<HorizonEvaluation>,
+    pub confusion_matrix: ConfusionMatrix,
+    pub calibration: Vec<CalibrationCurve>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct WalkForwardFold {
+    pub fold: i64,
+    pub status: String,
+    pub test_from: i64,
+    pub test_to: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Default)]
+pub struct WalkForwardSummary {
+    pub folds: usize,
+    pub completed_folds: usize,
+    pub mean_accuracy: Option<f64>,
+    pub std_accuracy: Option<f64>,
+    pub pooled_accuracy: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct WalkForwardRun {
+    pub id: i64,
+    pub created_at: String,
+    pub symbol: String,
+    pub interval: String,
+    pub status: String,
+    /// JSON of the `WalkForwardSummary`, set once the run is over
+    pub summary: Option<String>,
+    pub folds: Vec<WalkForwardFold>,
+}
+impl WalkForwardRun {
+    pub fn summary(&self) -> WalkForwardSummary {
+        self.summary
+            .as_ref()
+            .and_then(|summary| serde_json::from_str(summary).ok())
+            .unwrap_or_default()
     }
 }
-impl Default for Chart {
-    fn default() -> Self {
-        Self {
-            page: 1,
-            total_pages: 1,
-            is_loading: true,
-            path: String::new(),
-            model_path: String::new(),
-        }
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Default)]
+pub struct BacktestSummary {
+    pub final_equity: f64,
+    pub total_return: f64,
+    pub buy_and_hold_return: f64,
+    pub max_drawdown: f64,
+    pub trades: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct BacktestRun {
+    pub id: i64,
+    pub created_at: String,
+    pub symbol: String,
+    pub interval: String,
+    /// "signals" of the model, a paper trading run, or the optimal "labels"
+    pub source: String,
+    pub from_time: i64,
+    pub to_time: i64,
+    /// JSON of the `BacktestSummary`
+    pub summary: String,
+}
+impl BacktestRun {
+    pub fn summary(&self) -> BacktestSummary {
+        serde_json::from_str(&self.summary).unwrap_or_default()
     }
 }
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct SearchTrial {
+    pub search_id: i64,
+    pub trial: i64,
+    pub status: String,
+    /// JSON object of the training parameters
+    pub parameters: String,
+    pub score: Option<f64>,
+}
@@ -40,8 +40,17 @@ pub fn to_fiat_format(value: f64) -> String {
     format!("{},{:02}", formatted_integer, fractional)
 }
 
-pub fn default_false() -> bool {
-    false
+/// Fiat format of profits and losses, with their sign
+pub fn to_signed_fiat_format(value: f64) -> String {
+    let sign = if value < 0.0 { "-" } else { "+" };
+    format!("{}{}", sign, to_fiat_format(value.abs()))
+}
+
+pub fn to_percent_format(value: Option<f64>) -> String {
+    match value {
+        Some(value) => format!("{:.1}%", value * 100.0),
+        None => String::from("-"),
+    }
 }
 
 pub fn date_string_to_integer(date: &String) -> i64 {
@@ -58,3 +67,8 @@ pub fn get_default_fetch_date() -> String {
     let date = Utc::now() - Duration::days(2);
     date.format("%Y-%m-%d").to_string()
 }
+
+/// Start of the equity curve and PnL, 30 days ago
+pub fn get_performance_from() -> i64 {
+    (Utc::now() - Duration::days(30)).timestamp_millis()
+}
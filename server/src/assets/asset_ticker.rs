@@ -1,5 +1,5 @@
 use crate::utils::{
-    binance_client::BINANCE_WSS_BASE_URL, database::DB_POOL, serde_utils::f64_from_string,
+    binance_client, database::DB_POOL, error::MeshetarError, serde_utils::f64_from_string,
 };
 use binance_spot_connector_rust::{
     market_stream::ticker::TickerStream, tokio_tungstenite::BinanceWebSocketClient,
@@ -52,9 +52,12 @@ pub struct TickerAsset {
     number_of_trades: i64, // Total number of trades
 }
 
-pub async fn insert_assets(assets: Vec<TickerAsset>) -> Result<(), String> {
+pub async fn insert_assets(assets: Vec<TickerAsset>) -> Result<(), MeshetarError> {
     let connection = DB_POOL.get().unwrap();
-    let mut tx = connection.begin().map_err(|e| format!("{:?}", e)).await?;
+    let mut tx = connection
+        .begin()
+        .map_err(|e| MeshetarError::Database(format!("{:?}", e)))
+        .await?;
     let query = "
         INSERT OR REPLACE INTO asset_ticker (
             symbol, 
@@ -97,19 +100,26 @@ pub async fn insert_assets(assets: Vec<TickerAsset>) -> Result<(), String> {
             .bind(&asset.total_traded_quote_volume)
             .bind(&asset.number_of_trades)
             .execute(tx.as_mut())
-            .map_err(|e| format!("Error inserting a asset (ticker) into Database. {:?}", e))
+            .map_err(|e| {
+                MeshetarError::Database(format!(
+                    "Error inserting a asset (ticker) into Database. {:?}",
+                    e
+                ))
+            })
             .await?;
     }
 
     tx.commit()
-        .map_err(|e| format!("Error on commiting TX on asset ticker: {:?}", e))
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error on commiting TX on asset ticker: {:?}", e))
+        })
         .await?;
 
     Ok(())
 }
 
-pub async fn subscribe() -> Result<(), String> {
-    let (mut conn, _) = BinanceWebSocketClient::connect_async(BINANCE_WSS_BASE_URL)
+pub async fn subscribe() -> Result<(), MeshetarError> {
+    let (mut conn, _) = BinanceWebSocketClient::connect_async(&binance_client::wss_base_url())
         .await
         .expect("Failed to connect");
 
@@ -1,9 +1,13 @@
 use crate::{
-    binance_client::{BINANCE_CLIENT, BINANCE_WSS_BASE_URL},
+    binance_client::{self, BINANCE_CLIENT},
     database::DB_POOL,
-    model::prediction_model::{self, TradeSignal},
+    model::{
+        feature_store,
+        prediction_model::{self, Prediction, TradeSignal},
+    },
+    plotting::plot,
     trading::meshetar::Meshetar,
-    utils::formatting::timestamp_to_string,
+    utils::{error::MeshetarError, formatting::timestamp_to_string, load_config},
     TaskControl,
 };
 use binance_spot_connector_rust::{
@@ -71,20 +75,29 @@ struct WebsocketResponse {
 pub async fn run(
     task_control: Arc<Mutex<TaskControl>>,
     meshetar: Arc<Mutex<Meshetar>>,
-) -> Result<(), String> {
+) -> Result<(), MeshetarError> {
     // Get params ready
     let meshetar = meshetar.lock().await;
     let pair = meshetar.pair.to_string();
     let interval = meshetar.interval.to_kline_interval();
     let interval_string = meshetar.interval.to_kline_interval().to_string();
+    let interval_ms = meshetar.interval.duration_ms();
     drop(meshetar);
 
-    let (mut conn, _) = BinanceWebSocketClient::connect_async(BINANCE_WSS_BASE_URL)
+    if binance_client::is_mock() {
+        return run_on_stored_klines(task_control, pair, interval_string, interval_ms).await;
+    }
+
+    let (mut conn, _) = BinanceWebSocketClient::connect_async(&binance_client::wss_base_url())
         .await
         .expect("Failed to connect");
 
-    conn.subscribe(vec![&KlineStream::new(&pair, interval).into()])
-        .await;
+    // Companion klines are stored for the features of the pair, only the pair's are predicted on
+    let companions = load_config::config().model.companions_of(&pair);
+    for symbol in std::iter::once(&pair).chain(companions.iter()) {
+        conn.subscribe(vec![&KlineStream::new(symbol, interval).into()])
+            .await;
+    }
 
     let mut receiver = task_control.lock().await.receiver.clone();
 
@@ -127,20 +140,9 @@ pub async fn run(
                                 let mut vec_kline: Vec<Kline> = Vec::new();
                                 vec_kline.push(kline);
                                 match insert_klines_to_database(vec_kline).await {
+                                    Ok(_) if symbol != pair => (),
                                     Ok(_) => {
-                                        let task_control2 = Arc::clone(&task_control);
-                                        match prediction_model::run_model(task_control2).await {
-                                            Ok(signal) => {
-                                                match insert_signal_to_database(signal, symbol.clone(), interval_string.clone(), time).await {
-                                                    Ok(_) => log::info!("New signal inserted."),
-                                                    Err(e) => log::warn!("{}", e)
-                                                };
-                                                log::info!("Kline analyzed: {:?}", signal)
-                                            },
-                                            Err(e) => {
-                                                log::warn!("{:?}", e)
-                                            }
-                                        }
+                                        analyze_kline(Arc::clone(&task_control), &symbol, &interval_string, time).await
                                     },
                                     Err(e) => {
                                         log::warn!("{}", e);
@@ -165,11 +167,87 @@ pub async fn run(
     Ok(())
 }
 
-async fn insert_klines_to_database(klines: Vec<Kline>) -> Result<(), String> {
+/// Stores the chart's indicators of a new kline of the pair, predicts on it and stores the
+/// signal
+async fn analyze_kline(
+    task_control: Arc<Mutex<TaskControl>>,
+    symbol: &str,
+    interval: &str,
+    time: i64,
+) {
+    let stored = match plot::chart_feature_sets() {
+        Ok(sets) => feature_store::materialize(symbol, interval, &sets).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = stored {
+        log::warn!("Error storing chart indicators: {}", e);
+    }
+    match prediction_model::run_model(task_control, symbol, interval).await {
+        Ok(prediction) => {
+            let min_confidence = load_config::config().trading.min_confidence;
+            let signal = prediction.signal_with_min_confidence(min_confidence);
+            match insert_signal_to_database(
+                signal,
+                &prediction,
+                symbol.to_string(),
+                interval.to_string(),
+                time,
+            )
+            .await
+            {
+                Ok(_) => log::info!("New signal inserted."),
+                Err(e) => log::warn!("{}", e),
+            };
+            log::info!(
+                "Kline analyzed: {:?} (model {:?} at {:.2})",
+                signal,
+                prediction.signal,
+                prediction.confidence()
+            )
+        }
+        Err(e) => {
+            log::warn!("{:?}", e)
+        }
+    }
+}
+
+/// The mock environment has no kline stream, the latest stored kline is checked once per
+/// interval instead and predicted on when it is new.
+async fn run_on_stored_klines(
+    task_control: Arc<Mutex<TaskControl>>,
+    pair: String,
+    interval: String,
+    interval_ms: i64,
+) -> Result<(), MeshetarError> {
+    log::info!("Mock environment, predicting on stored {} klines.", pair);
+    let mut receiver = task_control.lock().await.receiver.clone();
+    let mut last_analyzed = None;
+    loop {
+        if let Some(open_time) = latest_kline_open_time(&pair, &interval).await? {
+            if last_analyzed != Some(open_time) {
+                analyze_kline(Arc::clone(&task_control), &pair, &interval, open_time).await;
+                last_analyzed = Some(open_time);
+            }
+        }
+        tokio::select! {
+            _ = receiver.changed() => {
+                if *receiver.borrow() == false {
+                    break;
+                }
+            },
+            _ = sleep(Duration::from_millis(interval_ms as u64)) => (),
+        }
+    }
+    Ok(())
+}
+
+async fn insert_klines_to_database(klines: Vec<Kline>) -> Result<(), MeshetarError> {
     let connection = DB_POOL.get().unwrap();
     let mut tx = connection
         .begin()
-        .map_err(|e| format!("Error on creating transaction on klines: {:?}", e))
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error on creating transaction on klines: {:?}", e))
+        })
         .await?;
     for kline in klines {
         sqlx::query(
@@ -191,48 +269,69 @@ async fn insert_klines_to_database(klines: Vec<Kline>) -> Result<(), String> {
         .bind(kline.trades)
         .bind(kline.taker_buy_base_asset_volume)
         .bind(kline.taker_buy_quote_asset_volume)
-        .execute(tx.as_mut()).map_err(|e| format!("Error inserting a kline into Database. {:?}", e)).await?;
+        .execute(tx.as_mut()).map_err(|e| MeshetarError::Database(format!("Error inserting a kline into Database. {:?}", e))).await?;
     }
     tx.commit()
-        .map_err(|e| format!("Error committing new klines: {:?}", e))
+        .map_err(|e| MeshetarError::Database(format!("Error committing new klines: {:?}", e)))
         .await?;
     Ok(())
 }
 
 async fn insert_signal_to_database(
     signal: TradeSignal,
+    prediction: &Prediction,
     symbol: String,
     interval: String,
     time: i64,
-) -> Result<(), String> {
+) -> Result<(), MeshetarError> {
     let connection = DB_POOL.get().unwrap();
     sqlx::query(
         r#"
-        INSERT OR REPLACE INTO signals (symbol, interval, time, signal)
-        VALUES (?1, ?2, ?3, ?4)
+        INSERT OR REPLACE INTO signals (symbol, interval, time, signal, model_signal, probability_buy, probability_hold, probability_sell, model_id, latency_ms)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
         "#,
     )
     .bind(symbol)
     .bind(interval)
     .bind(time)
     .bind(signal.to_string())
+    .bind(prediction.signal.to_string())
+    .bind(prediction.probabilities.buy)
+    .bind(prediction.probabilities.hold)
+    .bind(prediction.probabilities.sell)
+    .bind(prediction.model_id)
+    .bind(prediction.latency_ms)
     .execute(connection)
-    .map_err(|e| format!("Error inserting a kline into Database. {:?}", e))
+    .map_err(|e| MeshetarError::Database(format!("Error inserting a kline into Database. {:?}", e)))
     .await?;
     Ok(())
 }
 
-pub async fn latest_kline_date() -> Result<i64, String> {
+/// Open time of the newest stored kline of a pair and interval, if there is any
+pub async fn latest_kline_open_time(
+    symbol: &str,
+    interval: &str,
+) -> Result<Option<i64>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_scalar("SELECT MAX(open_time) FROM klines WHERE symbol = ?1 AND interval = ?2")
+        .bind(symbol)
+        .bind(interval)
+        .fetch_one(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching last kline. {:?}", e)))
+        .await
+}
+
+pub async fn latest_kline_date() -> Result<i64, MeshetarError> {
     let connection = DB_POOL.get().unwrap();
     let row = sqlx::query("SELECT close_time FROM klines ORDER BY close_time DESC LIMIT 1")
         .fetch_one(connection)
-        .map_err(|e| format!("Error fetching last kline. {:?}", e))
+        .map_err(|e| MeshetarError::Database(format!("Error fetching last kline. {:?}", e)))
         .await?;
     let close_time: i64 = row.get("close_time");
     Ok(close_time)
 }
 
-pub async fn clear_history(symbol: String, interval: KlineInterval) -> Result<(), String> {
+pub async fn clear_history(symbol: String, interval: KlineInterval) -> Result<(), MeshetarError> {
     let connection = DB_POOL.get().unwrap();
     sqlx::query(
         r#"
@@ -243,7 +342,7 @@ pub async fn clear_history(symbol: String, interval: KlineInterval) -> Result<()
     .bind(interval.to_string())
     .bind(symbol)
     .execute(connection)
-    .map_err(|e| format!("Error deleting klines, {:?}", e))
+    .map_err(|e| MeshetarError::Database(format!("Error deleting klines, {:?}", e)))
     .await?;
     Ok(())
 }
@@ -285,16 +384,35 @@ fn parse_binance_klines(klines: &String, symbol: &String, interval: &KlineInterv
     new_klines
 }
 
+/// Fetches klines of the pair and of its companions from `start_time` (in seconds) until now.
+/// Nothing is fetched in the mock environment, it works with the klines already stored.
 pub async fn fetch_history(
     task_control: Arc<Mutex<TaskControl>>,
-    meshetar: Arc<Mutex<Meshetar>>,
+    symbol: String,
+    interval: KlineInterval,
     start_time: i64,
-) -> Result<(), String> {
+) -> Result<(), MeshetarError> {
+    if binance_client::is_mock() {
+        log::info!("Mock environment, using the stored {} history.", symbol);
+        return Ok(());
+    }
+    let companions = load_config::config().model.companions_of(&symbol);
+    for symbol in std::iter::once(symbol).chain(companions) {
+        fetch_symbol_history(Arc::clone(&task_control), symbol, interval, start_time).await?;
+        if *task_control.lock().await.receiver.borrow() == false {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_symbol_history(
+    task_control: Arc<Mutex<TaskControl>>,
+    symbol: String,
+    interval: KlineInterval,
+    start_time: i64,
+) -> Result<(), MeshetarError> {
     let mut receiver = task_control.lock().await.receiver.clone();
-    let meshetar = meshetar.lock().await;
-    let symbol = meshetar.pair.to_string();
-    let interval = meshetar.interval.to_kline_interval();
-    drop(meshetar);
     let mut start_time: i64 = start_time.clone() * 1000;
     let client = BINANCE_CLIENT.get().unwrap();
     log::info!("Fetching {} history.", symbol);
@@ -314,11 +432,11 @@ pub async fn fetch_history(
                 {
                     let data = client
                         .send(request)
-                        .map_err(|e| format!("Error sending binance request. {:?}", e))
+                        .map_err(|e| MeshetarError::Exchange(format!("Error sending binance request. {:?}", e)))
                         .await?;
                     klines = data
                         .into_body_str()
-                        .map_err(|e| format!("Failed parsing binance data. {:?}", e))
+                        .map_err(|e| MeshetarError::Exchange(format!("Failed parsing binance data. {:?}", e)))
                         .await?;
                 };
                 let new_klines = parse_binance_klines(&klines, &symbol, &interval);
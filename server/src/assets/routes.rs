@@ -1,6 +1,7 @@
 use super::book;
 use crate::{
     trading::meshetar::{Meshetar, MeshetarStatus},
+    utils::error::MeshetarError,
     TaskControl,
 };
 use rocket::{form::Form, response::status::Accepted, serde::json::Json, State};
@@ -18,11 +19,18 @@ pub async fn fetch_history(
     meshetar: &State<Arc<Mutex<Meshetar>>>,
     task_control: &State<Arc<Mutex<TaskControl>>>,
     data: Form<FetchHistoryPayload>,
-) -> Accepted<Json<Meshetar>> {
+) -> Result<Accepted<Json<Meshetar>>, MeshetarError> {
     // Change status
     let meshetar_clone = Arc::clone(&meshetar.inner());
-    meshetar_clone.lock().await.status = MeshetarStatus::FetchingHistory;
-    let meshetar_clone2 = Arc::clone(&meshetar.inner());
+    let (symbol, interval) = {
+        let mut meshetar = meshetar_clone.lock().await;
+        meshetar.ensure_idle()?;
+        meshetar.status = MeshetarStatus::FetchingHistory;
+        (
+            meshetar.pair.to_string(),
+            meshetar.interval.to_kline_interval(),
+        )
+    };
     let meshetar_clone3 = Arc::clone(&meshetar.inner());
     let meshetar_clone4 = Arc::clone(&meshetar.inner());
 
@@ -33,7 +41,7 @@ pub async fn fetch_history(
     log::warn!("{}", data.from.clone());
 
     tokio::spawn(async move {
-        match book::fetch_history(reciever, meshetar_clone2, data.from).await {
+        match book::fetch_history(reciever, symbol, interval, data.from).await {
             Ok(_) => log::info!("History fetching success."),
             Err(e) => log::info!("History fetching err: {:?}", e),
         };
@@ -41,21 +49,21 @@ pub async fn fetch_history(
         meshetar_clone.status = MeshetarStatus::Idle;
     });
     let summary = meshetar_clone4.lock().await.summerize_json();
-    Accepted(Some(summary))
+    Ok(Accepted(Some(summary)))
 }
 
 #[post("/clear_history")]
-pub async fn clear_history(meshetar: &State<Arc<Mutex<Meshetar>>>) -> Accepted<Json<Meshetar>> {
-    tokio::join!(async {
-        let m = meshetar.lock().await;
-        let pair = m.pair.to_string();
-        let interval = m.interval.to_kline_interval();
-        match book::clear_history(pair, interval).await {
-            Ok(_) => log::info!("History cleaning success."),
-            Err(e) => log::info!("History cleaning err: {:?}", e),
-        };
-    });
-    Accepted(Some(meshetar.lock().await.summerize_json()))
+pub async fn clear_history(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+) -> Result<Accepted<Json<Meshetar>>, MeshetarError> {
+    let m = meshetar.lock().await;
+    m.ensure_idle()?;
+    let pair = m.pair.to_string();
+    let interval = m.interval.to_kline_interval();
+    drop(m);
+    book::clear_history(pair, interval).await?;
+    log::info!("History cleaning success.");
+    Ok(Accepted(Some(meshetar.lock().await.summerize_json())))
 }
 
 #[get("/last_kline_time")]
@@ -1,7 +1,5 @@
 use serde::Deserialize;
-use yata::prelude::OHLCV;
-
-use super::book::Kline;
+use sqlx::FromRow;
 
 #[derive(Deserialize)]
 pub struct Indicators {
@@ -29,22 +27,456 @@ pub struct Indicators {
     pub wsma: f64,
 }
 
-fn klines_to_tuples(klines: Vec<Kline>) -> Vec<(f64, f64, f64, f64, f64)> {
-    /*klines
-        .into_iter()
-        .map(|kline| (kline.open, kline.high, kline.low, kline.close, kline.volume))
+/// Kline fields the indicators are computed from
+#[derive(FromRow, Debug, Clone)]
+pub struct Candle {
+    pub open_time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+/// Values of an indicator per candle, `None` while the indicator is warming up
+pub type Series = Vec<Option<f64>>;
+
+/// Indicator columns over a run of candles, named like the TTR columns the R scripts used
+pub struct TechnicalIndicators {
+    pub names: Vec<String>,
+    pub columns: Vec<Series>,
+}
+
+fn lag(values: &Series, periods: usize) -> Series {
+    (0..values.len())
+        .map(|i| {
+            if i >= periods {
+                values[i - periods]
+            } else {
+                None
+            }
+        })
         .collect()
-    */
-    todo!()
 }
 
-pub fn add_ta(klines: Vec<Kline>) -> Result<Indicators, String> {
-    let mut indicators: Vec<Indicators> = Vec::new();
-    if let Some(last_value) = klines.last() {
-        //let last_value = last_value.close;
-        //let mut adi = ADI::new(20, last_value);
-        todo!("TA todo")
+fn zip_with(a: &Series, b: &Series, f: impl Fn(f64, f64) -> f64) -> Series {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| Some(f((*a)?, (*b)?)))
+        .collect()
+}
+
+/// Applies `f` to every full window of `n` values
+fn rolling(values: &Series, n: usize, f: impl Fn(&[f64]) -> f64) -> Series {
+    (0..values.len())
+        .map(|i| {
+            if i + 1 < n {
+                return None;
+            }
+            let window: Option<Vec<f64>> = values[i + 1 - n..=i].iter().copied().collect();
+            window.map(|window| f(&window))
+        })
+        .collect()
+}
+
+fn sma(values: &Series, n: usize) -> Series {
+    rolling(values, n, |window| window.iter().sum::<f64>() / n as f64)
+}
+
+/// Population standard deviation, as TTR's `BBands` uses
+fn rolling_sd(values: &Series, n: usize) -> Series {
+    rolling(values, n, |window| {
+        let mean = window.iter().sum::<f64>() / n as f64;
+        (window
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f64>()
+            / n as f64)
+            .sqrt()
+    })
+}
+
+/// Exponential moving average seeded with the simple average of its first `n` values.
+/// Wilder's smoothing uses `1/n` as the ratio instead of `2/(n+1)`.
+fn ema(values: &Series, n: usize, wilder: bool) -> Series {
+    let ratio = if wilder {
+        1.0 / n as f64
     } else {
-        Err(String::from("Add_ta missing any value"))
+        2.0 / (n as f64 + 1.0)
+    };
+    let mut result = vec![None; values.len()];
+    let Some(start) = values.iter().position(|value| value.is_some()) else {
+        return result;
+    };
+    if start + n > values.len() {
+        return result;
+    }
+    let seed: Option<Vec<f64>> = values[start..start + n].iter().copied().collect();
+    let Some(seed) = seed else {
+        return result;
+    };
+    let mut previous = seed.iter().sum::<f64>() / n as f64;
+    result[start + n - 1] = Some(previous);
+    for i in start + n..values.len() {
+        let Some(value) = values[i] else {
+            break;
+        };
+        previous = value * ratio + previous * (1.0 - ratio);
+        result[i] = Some(previous);
+    }
+    result
+}
+
+/// Candles since the highest (or lowest) value of the window, 0 when it is the newest one
+fn periods_since_extreme(window: &[f64], is_better: impl Fn(f64, f64) -> bool) -> f64 {
+    let mut best = 0;
+    for (i, value) in window.iter().enumerate() {
+        if is_better(*value, window[best]) {
+            best = i;
+        }
+    }
+    (window.len() - 1 - best) as f64
+}
+
+/// Parabolic SAR of the highs and lows. The old `add_ta.R` handed `TTR::SAR` the highs and
+/// closes, the lows are what the indicator is defined on. It also starts from the first low
+/// instead of TTR's gap of one standard deviation below it, so values differ from TTR's while
+/// the first trend lasts.
+fn parabolic_sar(high: &[f64], low: &[f64], step: f64, max_step: f64) -> Series {
+    let mut result = vec![None; high.len()];
+    if high.len() < 2 {
+        return result;
+    }
+    let mut is_long = high[1] >= high[0];
+    let mut sar = if is_long { low[0] } else { high[0] };
+    let mut extreme = if is_long { high[0] } else { low[0] };
+    let mut acceleration = step;
+    result[0] = Some(sar);
+    for i in 1..high.len() {
+        sar += acceleration * (extreme - sar);
+        if is_long {
+            sar = sar.min(low[i - 1]);
+            if low[i] < sar {
+                is_long = false;
+                sar = extreme;
+                extreme = low[i];
+                acceleration = step;
+            } else if high[i] > extreme {
+                extreme = high[i];
+                acceleration = (acceleration + step).min(max_step);
+            }
+        } else {
+            sar = sar.max(high[i - 1]);
+            if high[i] > sar {
+                is_long = true;
+                sar = extreme;
+                extreme = high[i];
+                acceleration = step;
+            } else if low[i] < extreme {
+                extreme = low[i];
+                acceleration = (acceleration + step).min(max_step);
+            }
+        }
+        result[i] = Some(sar);
+    }
+    result
+}
+
+/// Computes the TTR indicator set the R scripts used, with TTR's default parameters
+pub fn add_ta(candles: &[Candle]) -> TechnicalIndicators {
+    let high: Series = candles.iter().map(|candle| Some(candle.high)).collect();
+    let low: Series = candles.iter().map(|candle| Some(candle.low)).collect();
+    let close: Series = candles.iter().map(|candle| Some(candle.close)).collect();
+    let volume: Series = candles.iter().map(|candle| Some(candle.volume)).collect();
+    let previous_close = lag(&close, 1);
+
+    // Bollinger bands
+    let bb_mavg = sma(&close, 20);
+    let bb_sd = rolling_sd(&close, 20);
+    let bb_up = zip_with(&bb_mavg, &bb_sd, |mavg, sd| mavg + 2.0 * sd);
+    let bb_dn = zip_with(&bb_mavg, &bb_sd, |mavg, sd| mavg - 2.0 * sd);
+    let bb_width = zip_with(&bb_up, &bb_dn, |up, dn| up - dn);
+    let bb_pct_b = zip_with(
+        &zip_with(&close, &bb_dn, |close, dn| close - dn),
+        &bb_width,
+        |distance, width| distance / width,
+    );
+
+    // MACD in percent
+    let macd = zip_with(
+        &ema(&close, 12, false),
+        &ema(&close, 26, false),
+        |fast, slow| 100.0 * (fast / slow - 1.0),
+    );
+    let macd_sig = ema(&macd, 9, false);
+
+    // RSI
+    let change = zip_with(&close, &previous_close, |close, previous| close - previous);
+    let gains: Series = change.iter().map(|c| c.map(|c| c.max(0.0))).collect();
+    let losses: Series = change.iter().map(|c| c.map(|c| (-c).max(0.0))).collect();
+    let rsi = zip_with(
+        &ema(&gains, 14, true),
+        &ema(&losses, 14, true),
+        |gain, loss| 100.0 * gain / (gain + loss),
+    );
+
+    // Average true range
+    let true_high = zip_with(&high, &previous_close, f64::max);
+    let true_low = zip_with(&low, &previous_close, f64::min);
+    let tr = zip_with(&true_high, &true_low, |high, low| high - low);
+    let atr = ema(&tr, 14, true);
+
+    // Stochastic momentum index
+    let highest = rolling(&high, 13, |window| {
+        window.iter().cloned().fold(f64::MIN, f64::max)
+    });
+    let lowest = rolling(&low, 13, |window| {
+        window.iter().cloned().fold(f64::MAX, f64::min)
+    });
+    let midpoint_distance = zip_with(
+        &close,
+        &zip_with(&highest, &lowest, |high, low| (high + low) / 2.0),
+        |close, midpoint| close - midpoint,
+    );
+    let range = zip_with(&highest, &lowest, |high, low| high - low);
+    let numerator = ema(&ema(&midpoint_distance, 25, false), 2, false);
+    let denominator = ema(&ema(&range, 25, false), 2, false);
+    let smi = zip_with(&numerator, &denominator, |numerator, denominator| {
+        100.0 * numerator / (denominator / 2.0)
+    });
+    let smi_signal = ema(&smi, 9, false);
+
+    // Directional movement
+    let up_move = zip_with(&high, &lag(&high, 1), |high, previous| high - previous);
+    let down_move = zip_with(&lag(&low, 1), &low, |previous, low| previous - low);
+    let plus_dm = zip_with(&up_move, &down_move, |up, down| {
+        if up > down && up > 0.0 {
+            up
+        } else {
+            0.0
+        }
+    });
+    let minus_dm = zip_with(&up_move, &down_move, |up, down| {
+        if down > up && down > 0.0 {
+            down
+        } else {
+            0.0
+        }
+    });
+    let adx_dip = zip_with(&ema(&plus_dm, 14, true), &atr, |dm, atr| 100.0 * dm / atr);
+    let adx_din = zip_with(&ema(&minus_dm, 14, true), &atr, |dm, atr| 100.0 * dm / atr);
+    let dx = zip_with(&adx_dip, &adx_din, |plus, minus| {
+        100.0 * (plus - minus).abs() / (plus + minus)
+    });
+    let adx = ema(&dx, 14, true);
+
+    // Aroon
+    let aroon_up = rolling(&high, 21, |window| {
+        100.0 * (20.0 - periods_since_extreme(window, |a, b| a >= b)) / 20.0
+    });
+    let aroon_dn = rolling(&low, 21, |window| {
+        100.0 * (20.0 - periods_since_extreme(window, |a, b| a <= b)) / 20.0
+    });
+    let aroon = zip_with(&aroon_up, &aroon_dn, |up, dn| up - dn);
+
+    // Change of the Chaikin volatility
+    let range_ema = ema(&zip_with(&high, &low, |high, low| high - low), 10, false);
+    let chaikin = zip_with(&range_ema, &lag(&range_ema, 10), |now, then| {
+        now / then - 1.0
+    });
+    let chaikin_volatility = zip_with(&chaikin, &lag(&chaikin, 1), |now, then| now / then - 1.0);
+
+    // Money flow index
+    let typical = zip_with(&zip_with(&high, &low, |h, l| h + l), &close, |hl, c| {
+        (hl + c) / 3.0
+    });
+    let money_flow = zip_with(&typical, &volume, |price, volume| price * volume);
+    let typical_change = zip_with(&typical, &lag(&typical, 1), |now, then| now - then);
+    let positive_flow = zip_with(&money_flow, &typical_change, |flow, change| {
+        if change > 0.0 {
+            flow
+        } else {
+            0.0
+        }
+    });
+    let negative_flow = zip_with(&money_flow, &typical_change, |flow, change| {
+        if change < 0.0 {
+            flow
+        } else {
+            0.0
+        }
+    });
+    let mfi = zip_with(
+        &rolling(&positive_flow, 14, |window| window.iter().sum()),
+        &rolling(&negative_flow, 14, |window| window.iter().sum()),
+        |positive, negative| 100.0 * positive / (positive + negative),
+    );
+
+    let sar = parabolic_sar(
+        &candles
+            .iter()
+            .map(|candle| candle.high)
+            .collect::<Vec<f64>>(),
+        &candles
+            .iter()
+            .map(|candle| candle.low)
+            .collect::<Vec<f64>>(),
+        0.02,
+        0.2,
+    );
+
+    let ma20 = sma(&close, 20);
+    let ma50 = sma(&close, 50);
+    let bullish = zip_with(
+        &ma20,
+        &ma50,
+        |ma20, ma50| if ma20 > ma50 { 1.0 } else { 0.0 },
+    );
+
+    let columns: Vec<(&str, Series)> = vec![
+        ("sma", sma(&close, 10)),
+        ("ema", ema(&close, 10, false)),
+        ("bb_dn", bb_dn),
+        ("bb_mavg", bb_mavg),
+        ("bb_up", bb_up),
+        ("bb_pct_b", bb_pct_b),
+        ("macd", macd),
+        ("macd_sig", macd_sig),
+        ("rsi", rsi),
+        ("tr", tr),
+        ("true_high", true_high),
+        ("true_low", true_low),
+        ("atr", atr),
+        ("smi", smi),
+        ("smi_signal", smi_signal),
+        ("adx", adx),
+        ("adx_dip", adx_dip),
+        ("adx_din", adx_din),
+        ("dx", dx),
+        ("aroon", aroon),
+        ("aroon_up", aroon_up),
+        ("aroon_dn", aroon_dn),
+        ("chaikin_volatility", chaikin_volatility),
+        ("mfi", mfi),
+        ("sar", sar),
+        ("ma20", ma20),
+        ("ma50", ma50),
+        ("bullish", bullish),
+        ("volume", volume),
+    ];
+    TechnicalIndicators {
+        names: columns.iter().map(|(name, _)| name.to_string()).collect(),
+        columns: columns.into_iter().map(|(_, column)| column).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_series(actual: &Series, expected: &[Option<f64>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            match (actual, expected) {
+                (Some(actual), Some(expected)) => assert!(
+                    (actual - expected).abs() < 1e-9,
+                    "{} is not {}",
+                    actual,
+                    expected
+                ),
+                _ => assert_eq!(actual, expected),
+            }
+        }
+    }
+
+    fn series(values: &[f64]) -> Series {
+        values.iter().map(|value| Some(*value)).collect()
+    }
+
+    #[test]
+    fn moving_averages_match_hand_computed_values() {
+        let values = series(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_series(
+            &sma(&values, 2),
+            &[None, Some(1.5), Some(2.5), Some(3.5), Some(4.5)],
+        );
+        assert_series(
+            &rolling_sd(&series(&[1.0, 3.0, 3.0]), 2),
+            &[None, Some(1.0), Some(0.0)],
+        );
+        // Seeded with the mean of 1, 2 and 3, then halfway to each new value
+        assert_series(
+            &ema(&values, 3, false),
+            &[None, None, Some(2.0), Some(3.0), Some(4.0)],
+        );
+        // A third of the way with Wilder's ratio
+        assert_series(
+            &ema(&values, 3, true),
+            &[None, None, Some(2.0), Some(8.0 / 3.0), Some(31.0 / 9.0)],
+        );
+        // Warming up values of an indicator are skipped before seeding
+        assert_series(
+            &ema(&lag(&values, 1), 2, false),
+            &[None, None, Some(1.5), Some(2.5), Some(3.5)],
+        );
+    }
+
+    #[test]
+    fn extremes_count_from_the_newest_value() {
+        assert_eq!(
+            periods_since_extreme(&[3.0, 5.0, 5.0, 1.0], |a, b| a >= b),
+            1.0
+        );
+        assert_eq!(
+            periods_since_extreme(&[3.0, 5.0, 5.0, 1.0], |a, b| a <= b),
+            0.0
+        );
+        assert_eq!(periods_since_extreme(&[1.0, 2.0, 3.0], |a, b| a <= b), 2.0);
+    }
+
+    #[test]
+    fn parabolic_sar_follows_the_trend_and_reverses() {
+        let high = [10.0, 11.0, 12.0, 11.0, 9.0];
+        let low = [9.0, 10.0, 11.0, 10.0, 8.0];
+        // Long from the first low, accelerating on the new highs of 11 and 12, then the low
+        // of 8 crosses it and the SAR jumps to the extreme of 12
+        assert_series(
+            &parabolic_sar(&high, &low, 0.02, 0.2),
+            &[Some(9.0), Some(9.0), Some(9.08), Some(9.2552), Some(12.0)],
+        );
+    }
+
+    #[test]
+    fn indicators_of_a_steady_rise() {
+        let candles: Vec<Candle> = (0..60)
+            .map(|i| {
+                let close = 100.0 + i as f64;
+                Candle {
+                    open_time: i * 60000,
+                    open: close,
+                    high: close + 1.0,
+                    low: close - 1.0,
+                    close,
+                    volume: 1.0,
+                }
+            })
+            .collect();
+        let indicators = add_ta(&candles);
+        assert_eq!(indicators.names.len(), indicators.columns.len());
+        let column = |name: &str| {
+            let index = indicators.names.iter().position(|n| n == name).unwrap();
+            &indicators.columns[index]
+        };
+        assert_eq!(column("rsi")[13], None);
+        assert_eq!(column("rsi")[14], Some(100.0));
+        // The previous close is the low, so the true range is the high-low range of 2
+        assert_eq!(column("tr")[1], Some(2.0));
+        assert_eq!(column("atr")[14], Some(2.0));
+        assert_eq!(column("bb_mavg")[19], Some(109.5));
+        assert_eq!(column("ma50")[48], None);
+        assert_eq!(column("ma50")[49], Some(124.5));
+        assert_eq!(column("bullish")[49], Some(1.0));
+        assert_eq!(column("aroon_up")[20], Some(100.0));
+        assert_eq!(column("aroon_dn")[20], Some(0.0));
     }
 }
@@ -2,6 +2,7 @@
 mod assets;
 mod model;
 mod plotting;
+mod sentiment;
 mod trading;
 mod utils;
 
@@ -11,8 +12,16 @@ use assets::{
 };
 use env_logger::Builder;
 use log::LevelFilter;
-use model::routes::create_new_model;
-use plotting::routes::plot_chart;
+use model::routes::{
+    activate_model, create_new_model, feature_sets, features, hyperparameter_leaderboard,
+    model_promotions, models, signal_evaluation, start_hyperparameter_search, start_walk_forward,
+    walk_forward_runs,
+};
+use model::scheduler;
+use plotting::routes::{
+    backtest_report, backtest_report_bundle, backtest_report_figure, chart_candles, plot_chart,
+    plot_equity, plot_equity_image,
+};
 use rocket::catch;
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::fs::FileServer;
@@ -21,12 +30,20 @@ use rocket::futures::TryFutureExt;
 use rocket::http::Header;
 use rocket::http::Status;
 use rocket::{Request, Response};
+use sentiment::{
+    ingestion,
+    routes::{fetch_sentiment, kline_sentiment},
+};
 use std::sync::Arc;
 use tokio::sync::watch;
 use tokio::sync::Mutex;
-use trading::routes::{interval_put, meshetar_status, pair_put, run, stop_all_operations};
+use trading::routes::{
+    backtest_run, backtest_runs, equity_curve, export_ledger, fee_rates, interval_put,
+    ledger_entries, ledger_lots, meshetar_status, order, pair_put, pnl, run, start_backtest,
+    stop_all_operations,
+};
 use trading::{meshetar::Meshetar, portfolio, routes::balance_sheet};
-use utils::{binance_client, database};
+use utils::{binance_client, database, error::MeshetarError, load_config, retention, secrets};
 
 pub struct CORS;
 
@@ -56,18 +73,22 @@ impl Fairing for CORS {
 extern crate rocket;
 
 #[catch(500)]
-fn internal_error() -> &'static str {
-    "Error 500; something is not clicking right."
+fn internal_error() -> MeshetarError {
+    MeshetarError::Internal(String::from("Error 500; something is not clicking right."))
 }
 
 #[catch(404)]
-fn not_found() -> &'static str {
-    "Error 404; nothing here fren."
+fn not_found() -> MeshetarError {
+    MeshetarError::NotFound(String::from("Error 404; nothing here fren."))
 }
 
+/// Keeps the status Rocket answered with, e.g. 422 of a form guard, with the JSON error body
 #[catch(default)]
-fn default(status: Status, req: &Request) -> String {
-    format!("{} ({})", status, req.uri())
+fn default(status: Status, req: &Request) -> (Status, MeshetarError) {
+    (
+        status,
+        MeshetarError::from_status(status, format!("{} ({})", status, req.uri())),
+    )
 }
 
 /// Catches all OPTION requests in order to get the CORS related Fairing triggered.
@@ -83,13 +104,24 @@ pub struct TaskControl {
 }
 
 #[rocket::main]
-async fn main() -> Result<(), String> {
+async fn main() -> Result<(), MeshetarError> {
     // Sets logging for sqlx to warn and above, info logs are too verbose
     let mut builder = Builder::new();
     builder.filter(None, LevelFilter::Info); // a default for other libs
     builder.filter(Some("sqlx"), LevelFilter::Warn);
     builder.init();
 
+    // Creating a keystore is a one-off command, the server is not started
+    if std::env::args().nth(1).as_deref() == Some("create-keystore") {
+        let path = std::env::args()
+            .nth(2)
+            .unwrap_or(String::from(secrets::DEFAULT_KEYSTORE_PATH));
+        return secrets::create_keystore(&path);
+    }
+
+    load_config::initialize()?;
+    let config = load_config::config();
+
     binance_client::initialize().await?;
 
     database::initialize().await?;
@@ -97,25 +129,50 @@ async fn main() -> Result<(), String> {
     let (sender, receiver) = watch::channel(false);
     let task_control = Arc::new(Mutex::new(TaskControl { sender, receiver }));
 
-    // Hook to assets ticker
-    tokio::spawn(async {
-        match asset_ticker::subscribe().await {
-            _ => log::warn!("Price fetching ended."),
-        }
-    });
-
-    // Periodically get account status
-    tokio::spawn(async {
-        loop {
-            match portfolio::fetch_account_data().await {
-                Err(e) => log::warn!("Error fetching balance: {:?}", e),
-                _ => (),
+    // The mock environment has no ticker or account to poll
+    if binance_client::is_mock() {
+        log::info!("Skipping ticker and account polling in the mock environment.");
+    } else {
+        // Hook to assets ticker
+        tokio::spawn(async {
+            match asset_ticker::subscribe().await {
+                _ => log::warn!("Price fetching ended."),
             }
-            std::thread::sleep(std::time::Duration::from_millis(5000));
-        }
-    });
+        });
+
+        // Periodically get account status
+        tokio::spawn(async {
+            loop {
+                match portfolio::fetch_account_data().await {
+                    Err(e) => log::warn!("Error fetching balance: {:?}", e),
+                    _ => (),
+                }
+                let interval = load_config::config().polling.balance_interval_ms;
+                std::thread::sleep(std::time::Duration::from_millis(interval));
+            }
+        });
+    }
+
+    // Scheduled model retraining
+    tokio::spawn(scheduler::run(
+        Arc::clone(&meshetar),
+        Arc::clone(&task_control),
+    ));
+
+    // Periodic sentiment fetching
+    tokio::spawn(ingestion::run());
+
+    // Compaction of old balance sheets and klines
+    tokio::spawn(retention::run());
 
-    match rocket::build()
+    // Reload non-critical config on change
+    tokio::spawn(load_config::watch());
+
+    let figment = rocket::Config::figment()
+        .merge(("address", config.server.address))
+        .merge(("port", config.server.port));
+
+    match rocket::custom(figment)
         .attach(CORS)
         .manage(meshetar)
         .manage(task_control)
@@ -132,14 +189,45 @@ async fn main() -> Result<(), String> {
                 last_kline_time,
                 run,
                 create_new_model,
+                models,
+                activate_model,
+                model_promotions,
+                signal_evaluation,
+                start_walk_forward,
+                walk_forward_runs,
+                start_hyperparameter_search,
+                hyperparameter_leaderboard,
+                feature_sets,
+                features,
+                fetch_sentiment,
+                kline_sentiment,
                 plot_chart,
-                balance_sheet
+                chart_candles,
+                plot_equity,
+                plot_equity_image,
+                balance_sheet,
+                equity_curve,
+                pnl,
+                ledger_entries,
+                ledger_lots,
+                export_ledger,
+                fee_rates,
+                start_backtest,
+                backtest_runs,
+                backtest_run,
+                backtest_report,
+                backtest_report_figure,
+                backtest_report_bundle,
+                order
             ],
         )
-        .mount("/", FileServer::new("static", Options::None).rank(1))
+        .mount(
+            "/",
+            FileServer::new(&config.storage.static_dir, Options::None).rank(1),
+        )
         .register("/", catchers![internal_error, not_found, default])
         .launch()
-        .map_err(|e| e.to_string())
+        .map_err(|e| MeshetarError::Internal(e.to_string()))
         .await
     {
         Ok(_) => Ok(()),
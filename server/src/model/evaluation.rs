@@ -0,0 +1,367 @@
+use crate::utils::{database::DB_POOL, error::MeshetarError, load_config};
+use futures::TryFutureExt;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashMap;
+
+const CLASSES: [&str; 3] = ["buy", "hold", "sell"];
+
+#[derive(FromRow)]
+struct EvaluatedSignal {
+    time: i64,
+    /// What the model said, before the confidence threshold
+    signal: String,
+    probability_buy: Option<f64>,
+    probability_hold: Option<f64>,
+    probability_sell: Option<f64>,
+    /// Optimal signal from the training labels, if the candle was labelled
+    label: Option<String>,
+}
+
+impl EvaluatedSignal {
+    fn probability(&self, class: &str) -> Option<f64> {
+        match class {
+            "buy" => self.probability_buy,
+            "hold" => self.probability_hold,
+            "sell" => self.probability_sell,
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ClassHitRate {
+    pub signal: String,
+    /// Signals with enough klines after them to evaluate
+    pub count: i64,
+    pub hits: i64,
+    pub hit_rate: Option<f64>,
+    pub mean_return: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HorizonEvaluation {
+    /// Number of klines after the signal the return is measured at
+    pub horizon: usize,
+    pub classes: Vec<ClassHitRate>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConfusionMatrix {
+    pub labels: Vec<String>,
+    /// Rows are the signals, columns the optimal labels
+    pub counts: Vec<Vec<i64>>,
+    pub accuracy: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CalibrationBin {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: i64,
+    pub mean_probability: Option<f64>,
+    /// Share of the signals in the bin whose label is the class
+    pub observed_frequency: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CalibrationCurve {
+    pub signal: String,
+    pub bins: Vec<CalibrationBin>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SignalEvaluation {
+    pub symbol: String,
+    pub interval: String,
+    pub signals: i64,
+    pub horizons: Vec<HorizonEvaluation>,
+    pub confusion_matrix: ConfusionMatrix,
+    pub calibration: Vec<CalibrationCurve>,
+}
+
+fn ratio(part: i64, total: i64) -> Option<f64> {
+    if total > 0 {
+        Some(part as f64 / total as f64)
+    } else {
+        None
+    }
+}
+
+/// Buys hit when price went up, sells when it went down and holds when it stayed within the band
+fn is_hit(class: &str, forward_return: f64, hold_band: f64) -> bool {
+    match class {
+        "buy" => forward_return > 0.0,
+        "sell" => forward_return < 0.0,
+        _ => forward_return.abs() <= hold_band,
+    }
+}
+
+fn hit_rates(
+    signals: &[EvaluatedSignal],
+    closes: &[f64],
+    positions: &HashMap<i64, usize>,
+    horizon: usize,
+    hold_band: f64,
+) -> HorizonEvaluation {
+    let classes = CLASSES
+        .iter()
+        .map(|class| {
+            let returns: Vec<f64> = signals
+                .iter()
+                .filter(|signal| signal.signal == *class)
+                .filter_map(|signal| positions.get(&signal.time))
+                .filter(|position| *position + horizon < closes.len())
+                .map(|position| closes[position + horizon] / closes[*position] - 1.0)
+                .collect();
+            let count = returns.len() as i64;
+            let hits = returns
+                .iter()
+                .filter(|forward_return| is_hit(class, **forward_return, hold_band))
+                .count() as i64;
+            ClassHitRate {
+                signal: class.to_string(),
+                count,
+                hits,
+                hit_rate: ratio(hits, count),
+                mean_return: if count > 0 {
+                    Some(returns.iter().sum::<f64>() / count as f64)
+                } else {
+                    None
+                },
+            }
+        })
+        .collect();
+    HorizonEvaluation { horizon, classes }
+}
+
+fn confusion_matrix(signals: &[EvaluatedSignal]) -> ConfusionMatrix {
+    let mut counts = vec![vec![0i64; CLASSES.len()]; CLASSES.len()];
+    for signal in signals {
+        let predicted = CLASSES.iter().position(|class| *class == signal.signal);
+        let actual = signal
+            .label
+            .as_ref()
+            .and_then(|label| CLASSES.iter().position(|class| class == label));
+        if let (Some(predicted), Some(actual)) = (predicted, actual) {
+            counts[predicted][actual] += 1;
+        }
+    }
+    let total: i64 = counts.iter().flatten().sum();
+    let correct: i64 = (0..CLASSES.len()).map(|i| counts[i][i]).sum();
+    ConfusionMatrix {
+        labels: CLASSES.iter().map(|class| class.to_string()).collect(),
+        counts,
+        accuracy: ratio(correct, total),
+    }
+}
+
+fn calibration_curve(signals: &[EvaluatedSignal], class: &str, bins: usize) -> CalibrationCurve {
+    let mut probability_sums = vec![0f64; bins];
+    let mut counts = vec![0i64; bins];
+    let mut observed = vec![0i64; bins];
+    for signal in signals {
+        let (Some(probability), Some(label)) = (signal.probability(class), &signal.label) else {
+            continue;
+        };
+        let bin = ((probability * bins as f64) as usize).min(bins - 1);
+        probability_sums[bin] += probability;
+        counts[bin] += 1;
+        if label == class {
+            observed[bin] += 1;
+        }
+    }
+    CalibrationCurve {
+        signal: class.to_string(),
+        bins: (0..bins)
+            .map(|bin| CalibrationBin {
+                lower: bin as f64 / bins as f64,
+                upper: (bin + 1) as f64 / bins as f64,
+                count: counts[bin],
+                mean_probability: if counts[bin] > 0 {
+                    Some(probability_sums[bin] / counts[bin] as f64)
+                } else {
+                    None
+                },
+                observed_frequency: ratio(observed[bin], counts[bin]),
+            })
+            .collect(),
+    }
+}
+
+/// Compares stored signals with what the price did after them and with the training labels
+pub async fn evaluate_signals(
+    symbol: &str,
+    interval: &str,
+) -> Result<SignalEvaluation, MeshetarError> {
+    let config = load_config::config().evaluation;
+    let connection = DB_POOL.get().unwrap();
+    let signals = sqlx::query_as::<_, EvaluatedSignal>(
+        "SELECT s.time, LOWER(COALESCE(s.model_signal, s.signal)) AS signal,
+            s.probability_buy, s.probability_hold, s.probability_sell, l.label
+        FROM signals s
+        LEFT JOIN labels l
+            ON l.symbol = s.symbol AND l.interval = s.interval AND l.open_time = s.time
+        WHERE s.symbol = ?1 AND s.interval = ?2
+        ORDER BY s.time ASC",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching signals to evaluate. {:?}", e)))
+    .await?;
+
+    let klines: Vec<(i64, f64)> = sqlx::query_as(
+        "SELECT open_time, close FROM klines
+        WHERE symbol = ?1 AND interval = ?2
+        ORDER BY open_time ASC",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching klines to evaluate. {:?}", e)))
+    .await?;
+    let positions: HashMap<i64, usize> = klines
+        .iter()
+        .enumerate()
+        .map(|(position, (open_time, _))| (*open_time, position))
+        .collect();
+    let closes: Vec<f64> = klines.iter().map(|(_, close)| *close).collect();
+
+    Ok(SignalEvaluation {
+        symbol: symbol.to_string(),
+        interval: interval.to_string(),
+        signals: signals.len() as i64,
+        horizons: config
+            .horizons
+            .iter()
+            .map(|horizon| hit_rates(&signals, &closes, &positions, *horizon, config.hold_band))
+            .collect(),
+        confusion_matrix: confusion_matrix(&signals),
+        calibration: CLASSES
+            .iter()
+            .map(|class| calibration_curve(&signals, class, config.calibration_bins))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(
+        time: i64,
+        signal: &str,
+        probabilities: Option<(f64, f64, f64)>,
+        label: Option<&str>,
+    ) -> EvaluatedSignal {
+        EvaluatedSignal {
+            time,
+            signal: signal.to_string(),
+            probability_buy: probabilities.map(|p| p.0),
+            probability_hold: probabilities.map(|p| p.1),
+            probability_sell: probabilities.map(|p| p.2),
+            label: label.map(String::from),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn hit_rates_measure_the_return_after_the_horizon() {
+        let closes = [100.0, 110.0, 99.0, 100.0];
+        let positions = HashMap::from([(0, 0), (1, 1), (2, 2), (3, 3)]);
+        let signals = [
+            signal(0, "buy", None, None),
+            signal(1, "buy", None, None),
+            signal(1, "sell", None, None),
+            signal(2, "hold", None, None),
+            // No kline after the horizon
+            signal(3, "buy", None, None),
+            // Not among the klines
+            signal(99, "sell", None, None),
+        ];
+        let evaluation = hit_rates(&signals, &closes, &positions, 1, 0.02);
+        assert_eq!(evaluation.horizon, 1);
+        let buy = &evaluation.classes[0];
+        assert_eq!((buy.signal.as_str(), buy.count, buy.hits), ("buy", 2, 1));
+        assert_close(buy.hit_rate.unwrap(), 0.5);
+        assert_close(buy.mean_return.unwrap(), (0.1 - 0.1) / 2.0);
+        let hold = &evaluation.classes[1];
+        assert_eq!((hold.count, hold.hits), (1, 1));
+        assert_close(hold.mean_return.unwrap(), 100.0 / 99.0 - 1.0);
+        let sell = &evaluation.classes[2];
+        assert_eq!((sell.count, sell.hits), (1, 1));
+        assert_close(sell.mean_return.unwrap(), -0.1);
+
+        let evaluation = hit_rates(&signals, &closes, &positions, 3, 0.02);
+        let sell = &evaluation.classes[2];
+        assert_eq!(
+            (sell.count, sell.hit_rate, sell.mean_return),
+            (0, None, None)
+        );
+    }
+
+    #[test]
+    fn confusion_matrix_counts_labelled_signals() {
+        let signals = [
+            signal(0, "buy", None, Some("buy")),
+            signal(1, "buy", None, Some("sell")),
+            signal(2, "hold", None, Some("hold")),
+            signal(3, "sell", None, None),
+            signal(4, "sell", None, Some("sell")),
+        ];
+        let matrix = confusion_matrix(&signals);
+        assert_eq!(matrix.labels, vec!["buy", "hold", "sell"]);
+        assert_eq!(
+            matrix.counts,
+            vec![vec![1, 0, 1], vec![0, 1, 0], vec![0, 0, 1]]
+        );
+        assert_close(matrix.accuracy.unwrap(), 0.75);
+        assert_eq!(confusion_matrix(&[]).accuracy, None);
+    }
+
+    #[test]
+    fn calibration_curve_bins_the_class_probability() {
+        let signals = [
+            signal(0, "buy", Some((0.2, 0.5, 0.3)), Some("buy")),
+            signal(1, "hold", Some((0.4, 0.5, 0.1)), Some("hold")),
+            signal(2, "buy", Some((0.9, 0.1, 0.0)), Some("buy")),
+            // A probability of one belongs to the last bin
+            signal(3, "buy", Some((1.0, 0.0, 0.0)), Some("sell")),
+            // Unlabelled and unscored signals are left out
+            signal(4, "buy", Some((0.7, 0.2, 0.1)), None),
+            signal(5, "buy", None, Some("buy")),
+        ];
+        let curve = calibration_curve(&signals, "buy", 4);
+        assert_eq!(curve.signal, "buy");
+        let bins: Vec<(f64, f64, i64)> = curve
+            .bins
+            .iter()
+            .map(|bin| (bin.lower, bin.upper, bin.count))
+            .collect();
+        assert_eq!(
+            bins,
+            vec![
+                (0.0, 0.25, 1),
+                (0.25, 0.5, 1),
+                (0.5, 0.75, 0),
+                (0.75, 1.0, 2)
+            ]
+        );
+        assert_close(curve.bins[0].mean_probability.unwrap(), 0.2);
+        assert_close(curve.bins[0].observed_frequency.unwrap(), 1.0);
+        assert_close(curve.bins[1].observed_frequency.unwrap(), 0.0);
+        assert_eq!(curve.bins[2].mean_probability, None);
+        assert_eq!(curve.bins[2].observed_frequency, None);
+        assert_close(curve.bins[3].mean_probability.unwrap(), 0.95);
+        assert_close(curve.bins[3].observed_frequency.unwrap(), 0.5);
+    }
+}
@@ -0,0 +1,750 @@
+use super::prediction_model::TrainingWindow;
+use crate::{
+    assets::technical_analysis::{self, Candle, Series},
+    sentiment::ingestion::{self, AlignedSentiment},
+    utils::{database::DB_POOL, error::MeshetarError, load_config},
+};
+use futures::TryFutureExt;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+
+/// Named group of features computed from klines. Its version has to be bumped whenever the
+/// way its features are computed changes, rows of every version are kept apart.
+pub struct FeatureSet {
+    pub name: &'static str,
+    pub version: i64,
+    /// Klines before the first new one its features are computed over when stored rows are
+    /// extended, enough for the recursive indicators to settle
+    lookback: i64,
+    compute: Compute,
+}
+
+enum Compute {
+    Klines(fn(&[Candle]) -> Vec<(String, Series)>),
+    /// From the pair's klines and the closes of a companion pair aligned with them, gets the
+    /// companion symbol to prefix the feature names with
+    Companion(fn(&[Candle], &Series, &str) -> Vec<(String, Series)>),
+    /// From the values of a sentiment source known at the pair's klines, gets the source name
+    /// to prefix the feature names with
+    Sentiment(fn(&[Option<AlignedSentiment>], &str) -> Vec<(String, Series)>),
+}
+
+pub const FEATURE_SETS: [FeatureSet; 2] = [
+    FeatureSet {
+        name: "technical",
+        version: 1,
+        lookback: 500,
+        compute: Compute::Klines(technical),
+    },
+    FeatureSet {
+        name: "derived",
+        version: 1,
+        lookback: 60,
+        compute: Compute::Klines(derived),
+    },
+];
+
+/// Cross-asset features, a set `companion:<symbol>` is stored per companion of a pair
+pub const COMPANION_SET: FeatureSet = FeatureSet {
+    name: "companion",
+    version: 1,
+    lookback: 60,
+    compute: Compute::Companion(companion),
+};
+
+const COMPANION_PREFIX: &str = "companion:";
+
+/// Sentiment features, a set `sentiment:<source>` is stored per configured source
+pub const SENTIMENT_SET: FeatureSet = FeatureSet {
+    name: "sentiment",
+    version: 1,
+    lookback: 1,
+    compute: Compute::Sentiment(sentiment),
+};
+
+pub const SENTIMENT_PREFIX: &str = "sentiment:";
+
+/// Which version of a feature set a model was trained on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeatureSetVersion {
+    pub name: String,
+    pub version: i64,
+}
+
+/// Feature set version that has been materialized
+#[derive(FromRow, Serialize, Debug)]
+pub struct StoredFeatureSet {
+    pub name: String,
+    pub version: i64,
+    /// JSON array of the feature names, in the order of the stored values
+    pub features: String,
+}
+
+/// Feature rows of one or more sets, joined on open time
+#[derive(Serialize, Debug)]
+pub struct FeatureRows {
+    pub names: Vec<String>,
+    /// Open time and the features of the kline, `None` while a feature is warming up
+    pub rows: Vec<(i64, Vec<Option<f64>>)>,
+}
+
+impl FeatureRows {
+    /// Rows every feature is known of
+    pub fn complete_rows(&self) -> Vec<(i64, Vec<f64>)> {
+        self.rows
+            .iter()
+            .filter_map(|(open_time, values)| {
+                values
+                    .iter()
+                    .copied()
+                    .collect::<Option<Vec<f64>>>()
+                    .map(|values| (*open_time, values))
+            })
+            .collect()
+    }
+}
+
+fn technical(candles: &[Candle]) -> Vec<(String, Series)> {
+    let indicators = technical_analysis::add_ta(candles);
+    indicators
+        .names
+        .into_iter()
+        .zip(indicators.columns)
+        .collect()
+}
+
+fn lagged(values: &[f64], i: usize, periods: usize) -> Option<f64> {
+    i.checked_sub(periods).map(|lagged| values[lagged])
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn std_dev(values: &[f64]) -> f64 {
+    let mean = mean(values);
+    (values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64)
+        .sqrt()
+}
+
+/// Returns, volatility, price change speed and the shape of the candles
+fn derived(candles: &[Candle]) -> Vec<(String, Series)> {
+    let close: Vec<f64> = candles.iter().map(|candle| candle.close).collect();
+    let volume: Vec<f64> = candles.iter().map(|candle| candle.volume).collect();
+    let returns_over = |periods: usize| -> Series {
+        (0..close.len())
+            .map(|i| lagged(&close, i, periods).map(|then| close[i] / then - 1.0))
+            .collect()
+    };
+    let one_bar_returns = returns_over(1);
+    let volatility_over = |periods: usize| -> Series {
+        (0..close.len())
+            .map(|i| {
+                if i < periods {
+                    return None;
+                }
+                let window: Option<Vec<f64>> = one_bar_returns[i + 1 - periods..=i]
+                    .iter()
+                    .copied()
+                    .collect();
+                window.map(|window| std_dev(&window))
+            })
+            .collect()
+    };
+
+    let return_5 = returns_over(5);
+    let volatility_30 = volatility_over(30);
+    // Move over 5 klines in units of the usual move over that many klines
+    let speed_5: Series = return_5
+        .iter()
+        .zip(volatility_30.iter())
+        .map(|(change, volatility)| Some(change.as_ref()? / (volatility.as_ref()? * 5f64.sqrt())))
+        .collect();
+    let acceleration_5: Series = (0..close.len())
+        .map(|i| Some(return_5[i]? - return_5[i.checked_sub(5)?]?))
+        .collect();
+
+    let range: Series = candles
+        .iter()
+        .map(|candle| Some((candle.high - candle.low) / candle.close))
+        .collect();
+    let range_mean_10: Series = (0..candles.len())
+        .map(|i| {
+            let from = (i + 1).checked_sub(10)?;
+            let window: Option<Vec<f64>> = range[from..=i].iter().copied().collect();
+            window.map(|window| mean(&window))
+        })
+        .collect();
+    let body: Series = candles
+        .iter()
+        .map(|candle| Some((candle.close - candle.open) / candle.open))
+        .collect();
+    let upper_wick: Series = candles
+        .iter()
+        .map(|candle| Some((candle.high - candle.open.max(candle.close)) / candle.close))
+        .collect();
+    let lower_wick: Series = candles
+        .iter()
+        .map(|candle| Some((candle.open.min(candle.close) - candle.low) / candle.close))
+        .collect();
+    let relative_volume: Series = (0..candles.len())
+        .map(|i| {
+            let from = (i + 1).checked_sub(20)?;
+            Some(volume[i] / mean(&volume[from..=i]))
+        })
+        .collect();
+
+    vec![
+        (String::from("return_1"), one_bar_returns.clone()),
+        (String::from("return_5"), return_5),
+        (String::from("return_15"), returns_over(15)),
+        (String::from("volatility_10"), volatility_over(10)),
+        (String::from("volatility_30"), volatility_30),
+        (String::from("speed_5"), speed_5),
+        (String::from("acceleration_5"), acceleration_5),
+        (String::from("range"), range),
+        (String::from("range_mean_10"), range_mean_10),
+        (String::from("body"), body),
+        (String::from("upper_wick"), upper_wick),
+        (String::from("lower_wick"), lower_wick),
+        (String::from("relative_volume"), relative_volume),
+    ]
+}
+
+/// Closes of the companion as of the open time of every candle, the latest companion close
+/// at or before it
+fn align(candles: &[Candle], companion_candles: &[Candle]) -> Series {
+    let mut next = 0;
+    candles
+        .iter()
+        .map(|candle| {
+            while next < companion_candles.len()
+                && companion_candles[next].open_time <= candle.open_time
+            {
+                next += 1;
+            }
+            next.checked_sub(1).map(|i| companion_candles[i].close)
+        })
+        .collect()
+}
+
+fn correlation(x: &[f64], y: &[f64]) -> Option<f64> {
+    let (x_mean, y_mean) = (mean(x), mean(y));
+    let covariance: f64 = x
+        .iter()
+        .zip(y.iter())
+        .map(|(x, y)| (x - x_mean) * (y - y_mean))
+        .sum();
+    let deviations = (x.iter().map(|x| (x - x_mean).powi(2)).sum::<f64>()
+        * y.iter().map(|y| (y - y_mean).powi(2)).sum::<f64>())
+    .sqrt();
+    (deviations > 0.0).then(|| covariance / deviations)
+}
+
+/// Lagged returns of the companion, how the pair moved relative to it and how closely
+fn companion(candles: &[Candle], companion_close: &Series, symbol: &str) -> Vec<(String, Series)> {
+    let close: Vec<f64> = candles.iter().map(|candle| candle.close).collect();
+    let companion_returns_over = |periods: usize| -> Series {
+        (0..companion_close.len())
+            .map(|i| Some(companion_close[i]? / companion_close[i.checked_sub(periods)?]? - 1.0))
+            .collect()
+    };
+    let companion_return_1 = companion_returns_over(1);
+    let companion_return_5 = companion_returns_over(5);
+    let lagged_by = |periods: usize| -> Series {
+        (0..companion_return_1.len())
+            .map(|i| companion_return_1[i.checked_sub(periods)?])
+            .collect()
+    };
+    let relative_return_5: Series = (0..close.len())
+        .map(|i| Some(close[i] / lagged(&close, i, 5)? - 1.0 - companion_return_5[i]?))
+        .collect();
+    let correlation_30: Series = (0..close.len())
+        .map(|i| {
+            let from = i.checked_sub(30)?;
+            let own: Vec<f64> = (from + 1..=i)
+                .map(|j| close[j] / close[j - 1] - 1.0)
+                .collect();
+            let other: Option<Vec<f64>> =
+                companion_return_1[from + 1..=i].iter().copied().collect();
+            correlation(&own, &other?)
+        })
+        .collect();
+
+    let prefix = symbol.to_lowercase();
+    vec![
+        (format!("{}_return_1", prefix), companion_return_1.clone()),
+        (format!("{}_return_5", prefix), companion_return_5),
+        (format!("{}_lagged_return_1", prefix), lagged_by(1)),
+        (format!("{}_lagged_return_2", prefix), lagged_by(2)),
+        (format!("{}_relative_return_5", prefix), relative_return_5),
+        (format!("{}_correlation_30", prefix), correlation_30),
+    ]
+}
+
+/// Level of the source, its last change and how long ago it was known
+fn sentiment(aligned: &[Option<AlignedSentiment>], source: &str) -> Vec<(String, Series)> {
+    let prefix = source.to_lowercase();
+    vec![
+        (
+            format!("{}_sentiment", prefix),
+            aligned
+                .iter()
+                .map(|known| known.map(|known| known.value))
+                .collect(),
+        ),
+        (
+            format!("{}_sentiment_change", prefix),
+            aligned
+                .iter()
+                .map(|known| known.and_then(|known| known.change))
+                .collect(),
+        ),
+        (
+            format!("{}_sentiment_age_hours", prefix),
+            aligned
+                .iter()
+                .map(|known| known.map(|known| known.age_ms as f64 / 3600000.0))
+                .collect(),
+        ),
+    ]
+}
+
+/// Name of the set of cross-asset features with a companion pair
+pub fn companion_set_name(symbol: &str) -> String {
+    format!("{}{}", COMPANION_PREFIX, symbol)
+}
+
+fn definition(name: &str) -> Option<&'static FeatureSet> {
+    if name.starts_with(COMPANION_PREFIX) {
+        return Some(&COMPANION_SET);
+    }
+    if name.starts_with(SENTIMENT_PREFIX) {
+        return Some(&SENTIMENT_SET);
+    }
+    FEATURE_SETS
+        .iter()
+        .find(|feature_set| feature_set.name == name)
+}
+
+fn find_set(set: &FeatureSetVersion) -> Result<&'static FeatureSet, MeshetarError> {
+    let current = definition(&set.name).ok_or(MeshetarError::NotFound(format!(
+        "Feature set {} does not exist.",
+        set.name
+    )))?;
+    if current.version != set.version {
+        return Err(MeshetarError::StateConflict(format!(
+            "Feature set {} version {} can not be computed anymore, the current version is {}. Retrain the model.",
+            set.name, set.version, current.version
+        )));
+    }
+    Ok(current)
+}
+
+/// Current versions of the named feature sets
+pub fn current_versions(names: &[String]) -> Result<Vec<FeatureSetVersion>, MeshetarError> {
+    names
+        .iter()
+        .map(|name| {
+            definition(name)
+                .map(|feature_set| FeatureSetVersion {
+                    name: name.clone(),
+                    version: feature_set.version,
+                })
+                .ok_or(MeshetarError::NotFound(format!(
+                    "Feature set {} does not exist.",
+                    name
+                )))
+        })
+        .collect()
+}
+
+/// Current versions of the configured feature sets and of the pair's companion sets, what new
+/// models of the pair are trained on
+pub fn configured_versions(symbol: &str) -> Result<Vec<FeatureSetVersion>, MeshetarError> {
+    let config = load_config::config().model;
+    let names: Vec<String> = config
+        .feature_sets
+        .iter()
+        .cloned()
+        .chain(
+            config
+                .companions_of(symbol)
+                .iter()
+                .map(|companion| companion_set_name(companion)),
+        )
+        .collect();
+    current_versions(&names)
+}
+
+pub async fn load_candles(
+    symbol: &str,
+    interval: &str,
+    window: Option<TrainingWindow>,
+) -> Result<Vec<Candle>, MeshetarError> {
+    candles_between(
+        symbol,
+        interval,
+        window.map_or(0, |window| window.train_from),
+        window.map_or(i64::MAX, |window| window.test_to),
+    )
+    .await
+}
+
+async fn candles_between(
+    symbol: &str,
+    interval: &str,
+    from: i64,
+    to: i64,
+) -> Result<Vec<Candle>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, Candle>(
+        "SELECT open_time, open, high, low, close, volume FROM klines
+        WHERE symbol = ?1 AND interval = ?2 AND open_time BETWEEN ?3 AND ?4
+        ORDER BY open_time ASC",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .bind(from)
+    .bind(to)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching klines. {:?}", e)))
+    .await
+}
+
+/// Names of the set's features, registered the first time the set is materialized
+async fn register_names(set: &FeatureSetVersion, names: &[String]) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let names_json = serde_json::json!(names).to_string();
+    sqlx::query("INSERT OR IGNORE INTO feature_sets (name, version, features) VALUES (?1, ?2, ?3)")
+        .bind(&set.name)
+        .bind(set.version)
+        .bind(&names_json)
+        .execute(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error registering feature set. {:?}", e)))
+        .await?;
+    let registered: (String,) =
+        sqlx::query_as("SELECT features FROM feature_sets WHERE name = ?1 AND version = ?2")
+            .bind(&set.name)
+            .bind(set.version)
+            .fetch_one(connection)
+            .map_err(|e| MeshetarError::Database(format!("Error fetching feature set. {:?}", e)))
+            .await?;
+    if registered.0 != names_json {
+        return Err(MeshetarError::Internal(format!(
+            "Features of set {} version {} changed without a version bump.",
+            set.name, set.version
+        )));
+    }
+    Ok(())
+}
+
+async fn stored_names(set: &FeatureSetVersion) -> Result<Vec<String>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let names: Option<(String,)> =
+        sqlx::query_as("SELECT features FROM feature_sets WHERE name = ?1 AND version = ?2")
+            .bind(&set.name)
+            .bind(set.version)
+            .fetch_optional(connection)
+            .map_err(|e| MeshetarError::Database(format!("Error fetching feature set. {:?}", e)))
+            .await?;
+    let names = names.ok_or(MeshetarError::NotFound(format!(
+        "Feature set {} version {} was never materialized.",
+        set.name, set.version
+    )))?;
+    serde_json::from_str(&names.0)
+        .map_err(|e| MeshetarError::Internal(format!("Error parsing feature names: {:?}", e)))
+}
+
+pub async fn list_sets() -> Result<Vec<StoredFeatureSet>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, StoredFeatureSet>(
+        "SELECT * FROM feature_sets ORDER BY name ASC, version DESC",
+    )
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching feature sets. {:?}", e)))
+    .await
+}
+
+/// Stores the features of the klines that have none stored yet. Features only look back, so
+/// stored rows never change and training and inference read the very same values. The newest
+/// stored row is replaced, its kline keeps changing until it closes. Only the lookback of the
+/// set before it is recomputed, unless older klines were fetched since, then the set is
+/// computed over all of them.
+pub async fn materialize(
+    symbol: &str,
+    interval: &str,
+    sets: &[FeatureSetVersion],
+) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let first_kline: (Option<i64>,) =
+        sqlx::query_as("SELECT MIN(open_time) FROM klines WHERE symbol = ?1 AND interval = ?2")
+            .bind(symbol)
+            .bind(interval)
+            .fetch_one(connection)
+            .map_err(|e| MeshetarError::Database(format!("Error fetching klines. {:?}", e)))
+            .await?;
+    let first_kline = match first_kline.0 {
+        Some(first_kline) => first_kline,
+        None => return Ok(()),
+    };
+    for set in sets {
+        let feature_set = find_set(set)?;
+        let stored: (Option<i64>, Option<i64>) = sqlx::query_as(
+            "SELECT MIN(open_time), MAX(open_time) FROM features
+            WHERE symbol = ?1 AND interval = ?2 AND feature_set = ?3 AND version = ?4",
+        )
+        .bind(symbol)
+        .bind(interval)
+        .bind(&set.name)
+        .bind(set.version)
+        .fetch_one(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching stored features. {:?}", e)))
+        .await?;
+        let last_stored = match stored {
+            (Some(first_stored), Some(last_stored)) if first_stored <= first_kline => {
+                Some(last_stored)
+            }
+            _ => None,
+        };
+        let context_from = match last_stored {
+            Some(last_stored) => {
+                let context: Option<(i64,)> = sqlx::query_as(
+                    "SELECT open_time FROM klines
+                    WHERE symbol = ?1 AND interval = ?2 AND open_time <= ?3
+                    ORDER BY open_time DESC
+                    LIMIT 1 OFFSET ?4",
+                )
+                .bind(symbol)
+                .bind(interval)
+                .bind(last_stored)
+                .bind(feature_set.lookback)
+                .fetch_optional(connection)
+                .map_err(|e| MeshetarError::Database(format!("Error fetching klines. {:?}", e)))
+                .await?;
+                context.map_or(0, |(open_time,)| open_time)
+            }
+            None => 0,
+        };
+        let candles = candles_between(symbol, interval, context_from, i64::MAX).await?;
+        let first_new = candles.partition_point(|candle| {
+            last_stored.map_or(false, |last_stored| candle.open_time < last_stored)
+        });
+        if first_new == candles.len() {
+            continue;
+        }
+
+        let columns = match feature_set.compute {
+            Compute::Klines(compute) => compute(&candles),
+            Compute::Companion(compute) => {
+                let companion = set.name.trim_start_matches(COMPANION_PREFIX);
+                let companion_candles =
+                    candles_between(companion, interval, context_from, i64::MAX).await?;
+                compute(&candles, &align(&candles, &companion_candles), companion)
+            }
+            Compute::Sentiment(compute) => {
+                let name = set.name.trim_start_matches(SENTIMENT_PREFIX);
+                let source = load_config::config()
+                    .sentiment
+                    .source(name)
+                    .cloned()
+                    .ok_or(MeshetarError::NotFound(format!(
+                        "Sentiment source {} is not configured.",
+                        name
+                    )))?;
+                let open_times: Vec<i64> = candles.iter().map(|candle| candle.open_time).collect();
+                compute(&ingestion::aligned(&source, &open_times).await?, name)
+            }
+        };
+        register_names(
+            set,
+            &columns
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<String>>(),
+        )
+        .await?;
+        let mut tx = connection
+            .begin()
+            .map_err(|e| {
+                MeshetarError::Database(format!(
+                    "Error on creating transaction on features: {:?}",
+                    e
+                ))
+            })
+            .await?;
+        for (i, candle) in candles.iter().enumerate().skip(first_new) {
+            let values: Vec<Option<f64>> = columns
+                .iter()
+                .map(|(_, column)| column[i].filter(|value| value.is_finite()))
+                .collect();
+            sqlx::query(
+                "INSERT OR REPLACE INTO features (symbol, interval, feature_set, version, open_time, feature_values)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .bind(symbol)
+            .bind(interval)
+            .bind(&set.name)
+            .bind(set.version)
+            .bind(candle.open_time)
+            .bind(serde_json::json!(values).to_string())
+            .execute(tx.as_mut())
+            .map_err(|e| MeshetarError::Database(format!("Error saving features. {:?}", e)))
+            .await?;
+        }
+        tx.commit()
+            .map_err(|e| MeshetarError::Database(format!("Error committing features: {:?}", e)))
+            .await?;
+        log::debug!(
+            "Materialized {} rows of {} v{} for {} {}.",
+            candles.len() - first_new,
+            set.name,
+            set.version,
+            symbol,
+            interval
+        );
+    }
+    Ok(())
+}
+
+/// Removes the rows of a set from `from` on, of every pair and version, the next materialize
+/// computes them again. For inputs that arrive late, e.g. sentiment values.
+pub async fn discard_from(set_name: &str, from: i64) -> Result<u64, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query("DELETE FROM features WHERE feature_set = ?1 AND open_time >= ?2")
+        .bind(set_name)
+        .bind(from)
+        .execute(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error removing features. {:?}", e)))
+        .await
+        .map(|result| result.rows_affected())
+}
+
+/// Stored feature rows of the sets between `from` and `to`, only open times all sets have a
+/// row for are returned. `limit` keeps the newest rows.
+pub async fn read(
+    symbol: &str,
+    interval: &str,
+    sets: &[FeatureSetVersion],
+    from: i64,
+    to: i64,
+    limit: Option<i64>,
+) -> Result<FeatureRows, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let mut names: Vec<String> = Vec::new();
+    let mut joined: Option<Vec<(i64, Vec<Option<f64>>)>> = None;
+    for set in sets {
+        names.extend(stored_names(set).await?);
+        let mut rows: Vec<(i64, String)> = sqlx::query_as(
+            "SELECT open_time, feature_values FROM features
+            WHERE symbol = ?1 AND interval = ?2 AND feature_set = ?3 AND version = ?4
+                AND open_time BETWEEN ?5 AND ?6
+            ORDER BY open_time DESC
+            LIMIT ?7",
+        )
+        .bind(symbol)
+        .bind(interval)
+        .bind(&set.name)
+        .bind(set.version)
+        .bind(from)
+        .bind(to)
+        .bind(limit.unwrap_or(-1))
+        .fetch_all(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching features. {:?}", e)))
+        .await?;
+        rows.reverse();
+        let parsed = rows
+            .into_iter()
+            .map(|(open_time, values)| {
+                serde_json::from_str::<Vec<Option<f64>>>(&values)
+                    .map(|values| (open_time, values))
+                    .map_err(|e| {
+                        MeshetarError::Internal(format!("Error parsing stored features: {:?}", e))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        joined = Some(match joined {
+            None => parsed,
+            Some(joined) => {
+                let mut by_time: HashMap<i64, Vec<Option<f64>>> = parsed.into_iter().collect();
+                joined
+                    .into_iter()
+                    .filter_map(|(open_time, mut values)| {
+                        values.extend(by_time.remove(&open_time)?);
+                        Some((open_time, values))
+                    })
+                    .collect()
+            }
+        });
+    }
+    Ok(FeatureRows {
+        names,
+        rows: joined.unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database;
+
+    const MINUTE_MS: i64 = 60000;
+
+    async fn store_kline(symbol: &str, open_time: i64, close: f64) {
+        sqlx::query(
+            "INSERT OR REPLACE INTO klines (symbol, interval, open_time, open, high, low, close,
+                volume, close_time, quote_asset_volume, number_of_trades,
+                taker_buy_base_asset_volume, taker_buy_quote_asset_volume)
+            VALUES (?1, '1m', ?2, ?3, ?4, ?5, ?3, 10.0, ?6, 0.0, 1, 0.0, 0.0)",
+        )
+        .bind(symbol)
+        .bind(open_time)
+        .bind(close)
+        .bind(close + 1.0)
+        .bind(close - 1.0)
+        .bind(open_time + MINUTE_MS - 1)
+        .execute(DB_POOL.get().unwrap())
+        .await
+        .unwrap();
+    }
+
+    async fn stored_row(symbol: &str, open_time: i64) -> String {
+        let row: (String,) = sqlx::query_as(
+            "SELECT feature_values FROM features
+            WHERE symbol = ?1 AND interval = '1m' AND feature_set = 'derived' AND open_time = ?2",
+        )
+        .bind(symbol)
+        .bind(open_time)
+        .fetch_one(DB_POOL.get().unwrap())
+        .await
+        .unwrap();
+        row.0
+    }
+
+    #[test]
+    fn replaces_the_newest_row_while_its_kline_changes() {
+        database::run_test(async {
+            let symbol = "MATERIALIZEUSDT";
+            let sets = current_versions(&[String::from("derived")]).unwrap();
+            for i in 0..40 {
+                store_kline(symbol, i * MINUTE_MS, 100.0 + (i % 7) as f64).await;
+            }
+            materialize(symbol, "1m", &sets).await.unwrap();
+            let newest = 39 * MINUTE_MS;
+            let before_newest = stored_row(symbol, newest - MINUTE_MS).await;
+            let newest_before = stored_row(symbol, newest).await;
+
+            // Same kline, a later close
+            store_kline(symbol, newest, 120.0).await;
+            materialize(symbol, "1m", &sets).await.unwrap();
+
+            assert_ne!(stored_row(symbol, newest).await, newest_before);
+            assert_eq!(stored_row(symbol, newest - MINUTE_MS).await, before_newest);
+        });
+    }
+}
@@ -0,0 +1,272 @@
+use super::{
+    feature_store,
+    labelling::{self, KlineLabels, LabelParameters},
+    prediction_model::{self, TrainingParameters},
+    registry::{self, TrainingReport},
+};
+use crate::{
+    utils::{
+        database::DB_POOL,
+        error::MeshetarError,
+        load_config::{self, SearchStrategy},
+    },
+    TaskControl,
+};
+use chrono::{DateTime, Utc};
+use futures::TryFutureExt;
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    task::JoinSet,
+};
+
+/// A trial of a search, as shown on the leaderboard
+#[derive(FromRow, Serialize, Clone, Debug)]
+pub struct SearchTrial {
+    pub search_id: i64,
+    pub trial: i64,
+    pub symbol: String,
+    pub interval: String,
+    pub status: String,
+    /// JSON object of the parameters the training script ran with
+    pub parameters: String,
+    pub metrics: Option<String>,
+    /// Test split accuracy, trials are ranked by it
+    pub score: Option<f64>,
+    pub artifact_path: String,
+}
+
+fn grid(space: &BTreeMap<String, Vec<serde_json::Value>>) -> Vec<TrainingParameters> {
+    space.iter().fold(
+        vec![TrainingParameters::new()],
+        |combinations, (name, values)| {
+            combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.insert(name.clone(), value.clone());
+                        combination
+                    })
+                })
+                .collect()
+        },
+    )
+}
+
+fn random(
+    space: &BTreeMap<String, Vec<serde_json::Value>>,
+    trials: usize,
+) -> Vec<TrainingParameters> {
+    let mut rng = rand::thread_rng();
+    (0..trials)
+        .map(|_| {
+            space
+                .iter()
+                .map(|(name, values)| (name.clone(), values.choose(&mut rng).unwrap().clone()))
+                .collect()
+        })
+        .collect()
+}
+
+async fn create_search(
+    symbol: &str,
+    interval: &str,
+    strategy: SearchStrategy,
+) -> Result<i64, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let timestamp: String = DateTime::to_rfc3339(&Utc::now());
+    let id: (i64,) = sqlx::query_as(
+        "INSERT INTO hyperparameter_searches (created_at, symbol, interval, strategy, status)
+        VALUES (?1, ?2, ?3, ?4, 'running') RETURNING id",
+    )
+    .bind(timestamp)
+    .bind(symbol)
+    .bind(interval)
+    .bind(strategy.to_string())
+    .fetch_one(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error creating parameter search. {:?}", e)))
+    .await?;
+    Ok(id.0)
+}
+
+async fn finish_search(search_id: i64, status: &str) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query("UPDATE hyperparameter_searches SET status = ?1 WHERE id = ?2")
+        .bind(status)
+        .bind(search_id)
+        .execute(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error finishing parameter search. {:?}", e)))
+        .await?;
+    Ok(())
+}
+
+async fn save_trial(
+    search_id: i64,
+    trial: i64,
+    parameters: &TrainingParameters,
+    artifact_path: &str,
+    report: Option<&TrainingReport>,
+) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let score = report.and_then(|report| report.metrics["accuracy"].as_f64());
+    sqlx::query(
+        "INSERT OR REPLACE INTO hyperparameter_trials
+            (search_id, trial, status, parameters, metrics, score, artifact_path)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )
+    .bind(search_id)
+    .bind(trial)
+    .bind(if report.is_some() {
+        "trained"
+    } else {
+        "failed"
+    })
+    .bind(serde_json::Value::Object(parameters.clone()).to_string())
+    .bind(report.map(|report| report.metrics.to_string()))
+    .bind(score)
+    .bind(artifact_path)
+    .execute(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error saving parameter trial. {:?}", e)))
+    .await?;
+    Ok(())
+}
+
+/// Best trials of all searches, for the pair and interval if given
+pub async fn leaderboard(
+    symbol: Option<&str>,
+    interval: Option<&str>,
+    limit: i64,
+) -> Result<Vec<SearchTrial>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, SearchTrial>(
+        "SELECT t.search_id, t.trial, s.symbol, s.interval, t.status, t.parameters, t.metrics,
+            t.score, t.artifact_path
+        FROM hyperparameter_trials t
+        JOIN hyperparameter_searches s ON s.id = t.search_id
+        WHERE (?1 IS NULL OR s.symbol = ?1) AND (?2 IS NULL OR s.interval = ?2)
+        ORDER BY t.score IS NULL, t.score DESC, t.search_id DESC, t.trial ASC
+        LIMIT ?3",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .bind(limit)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching leaderboard. {:?}", e)))
+    .await
+}
+
+/// Labels of the klines per distinct labelling parameters of the candidates. Candidates with
+/// invalid parameters are left out, their trials fail on them.
+async fn label_candidates(
+    symbol: &str,
+    interval: &str,
+    candidates: &[TrainingParameters],
+) -> Result<Vec<(LabelParameters, KlineLabels)>, MeshetarError> {
+    let mut labellings: Vec<(LabelParameters, KlineLabels)> = Vec::new();
+    for parameters in candidates {
+        let Ok(label_parameters) = LabelParameters::from_training_parameters(Some(parameters))
+        else {
+            continue;
+        };
+        if labellings
+            .iter()
+            .all(|(known, _)| *known != label_parameters)
+        {
+            let labels = labelling::label_klines(symbol, interval, None, &label_parameters).await?;
+            labellings.push((label_parameters, labels));
+        }
+    }
+    Ok(labellings)
+}
+
+/// Trains a model per parameter combination of the configured space, at most
+/// `max_concurrency` at a time, and stores every trial's result. Returns the search ID.
+/// Features are stored and klines labelled up front, so trials don't write concurrently.
+pub async fn run(
+    task_control: Arc<Mutex<TaskControl>>,
+    symbol: &str,
+    interval: &str,
+) -> Result<i64, MeshetarError> {
+    let config = load_config::config().hyperparameter_search;
+    let candidates = match config.strategy {
+        SearchStrategy::Grid => grid(&config.space),
+        SearchStrategy::Random => random(&config.space, config.trials),
+    };
+    let feature_sets = feature_store::configured_versions(symbol)?;
+    feature_store::materialize(symbol, interval, &feature_sets).await?;
+    let labellings = label_candidates(symbol, interval, &candidates).await?;
+    let search_id = create_search(symbol, interval, config.strategy).await?;
+    log::info!(
+        "Parameter search {} with {} trials, {} at a time.",
+        search_id,
+        candidates.len(),
+        config.max_concurrency
+    );
+
+    let semaphore = Arc::new(Semaphore::new(config.max_concurrency));
+    let mut trials = JoinSet::new();
+    let mut receiver = task_control.lock().await.receiver.clone();
+    for (trial, parameters) in candidates.into_iter().enumerate() {
+        let permit = tokio::select! {
+            permit = Arc::clone(&semaphore).acquire_owned() => permit.unwrap(),
+            _ = receiver.wait_for(|running| !running) => break,
+        };
+        let task_control = Arc::clone(&task_control);
+        let symbol = symbol.to_string();
+        let interval = interval.to_string();
+        let artifact_path =
+            registry::artifact_path(&format!("search_{}_trial_{}", search_id, trial));
+        let labels = LabelParameters::from_training_parameters(Some(&parameters))
+            .ok()
+            .and_then(|label_parameters| {
+                labellings
+                    .iter()
+                    .find(|(known, _)| *known == label_parameters)
+            })
+            .map(|(_, labels)| labels.clone());
+        trials.spawn(async move {
+            let _permit = permit;
+            let training = prediction_model::train_unregistered(
+                task_control,
+                &symbol,
+                &interval,
+                &artifact_path,
+                None,
+                Some(parameters.clone()),
+                labels,
+            )
+            .await;
+            if let Err(e) = &training {
+                log::warn!(
+                    "Parameter search {} trial {} failed: {}",
+                    search_id,
+                    trial,
+                    e
+                );
+            }
+            save_trial(
+                search_id,
+                trial as i64,
+                &parameters,
+                &artifact_path,
+                training.as_ref().ok(),
+            )
+            .await
+        });
+    }
+    while let Some(saved) = trials.join_next().await {
+        match saved {
+            Ok(Err(e)) => log::warn!("{}", e),
+            Err(e) => log::warn!("Parameter search trial panicked: {:?}", e),
+            Ok(Ok(_)) => (),
+        }
+    }
+
+    let is_stopped = *receiver.borrow() == false;
+    finish_search(search_id, if is_stopped { "stopped" } else { "completed" }).await?;
+    Ok(search_id)
+}
@@ -0,0 +1,391 @@
+use super::prediction_model::{TradeSignal, TrainingParameters, TrainingWindow};
+use crate::{
+    trading::fees,
+    utils::{database::DB_POOL, error::MeshetarError},
+};
+use futures::TryFutureExt;
+use serde::{Deserialize, Serialize};
+
+/// Training parameters the labels depend on, the training scripts' defaults
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LabelParameters {
+    /// Return over a hold that is worth a sell, on top of the fees
+    pub buy_threshold: f64,
+    /// Commission rate of each order of a hold, the account's market order rate if not set
+    pub fee_rate: Option<f64>,
+}
+
+impl Default for LabelParameters {
+    fn default() -> Self {
+        LabelParameters {
+            buy_threshold: 0.01,
+            fee_rate: None,
+        }
+    }
+}
+
+impl LabelParameters {
+    pub fn from_training_parameters(
+        parameters: Option<&TrainingParameters>,
+    ) -> Result<LabelParameters, MeshetarError> {
+        match parameters {
+            Some(parameters) => {
+                serde_json::from_value(serde_json::Value::Object(parameters.clone())).map_err(|e| {
+                    MeshetarError::Validation(format!("Invalid labelling parameters: {}", e))
+                })
+            }
+            None => Ok(LabelParameters::default()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Label {
+    pub open_time: i64,
+    pub label: TradeSignal,
+}
+
+/// Labels of stored klines, handed to the model backends to train on
+#[derive(Serialize, Debug, Clone)]
+pub struct KlineLabels {
+    pub labels: Vec<Label>,
+    pub holding_period: usize,
+    pub max_holding_period: usize,
+    /// Commission rate the labels were found with
+    pub fee_rate: f64,
+}
+
+/// Optimal signals of a run of candles, what the models are trained to predict
+pub struct OptimalSignals {
+    /// One signal per candle
+    pub signals: Vec<TradeSignal>,
+    /// Holding period the signals were found with
+    pub holding_period: usize,
+}
+
+/// R's `round`, halves go to the even number
+fn round_half_even(value: f64) -> usize {
+    let rounded = value.round();
+    if (value - value.trunc()).abs() == 0.5 && rounded % 2.0 != 0.0 {
+        (rounded - value.signum()) as usize
+    } else {
+        rounded as usize
+    }
+}
+
+/// Longest holding period tried, a quarter of the candles as in `default_create.R`
+pub fn max_holding_period(candles: usize) -> usize {
+    round_half_even(round_half_even(candles as f64 / 2.0) as f64 / 2.0).max(1)
+}
+
+/// Sells where the price change over `holding_period` candles beats the threshold and the
+/// fees of buying and selling, buys `holding_period` changes before them. Returns the signals
+/// per price change and the cumulative return after fees.
+fn signals_for_holding_period(
+    closes: &[f64],
+    changes: &[f64],
+    holding_period: usize,
+    min_return: f64,
+    fee_rate: f64,
+) -> (Vec<i8>, f64) {
+    // Prefix sums, so the sum of changes[a..=b] is sums[b + 1] - sums[a]
+    let mut sums = vec![0.0; changes.len() + 1];
+    for (i, change) in changes.iter().enumerate() {
+        sums[i + 1] = sums[i] + change;
+    }
+    let mut returns = vec![0.0; changes.len()];
+    // Fees of the buy and the sell of a hold, in the same price units as the returns
+    let mut fees = vec![0.0; changes.len()];
+    for i in holding_period - 1..changes.len() {
+        let from = if holding_period == 1 {
+            i
+        } else if i == holding_period - 1 {
+            // The first full window leaves out the very first change, as the R labelling does
+            1
+        } else {
+            i + 1 - holding_period
+        };
+        returns[i] = sums[i + 1] - sums[from.min(i + 1)];
+        fees[i] = fee_rate * (closes[from.min(i)] + closes[i + 1]);
+    }
+
+    let mut signals = vec![0i8; changes.len()];
+    for i in holding_period - 1..changes.len() {
+        if returns[i] > min_return + fees[i] {
+            signals[i] = -1;
+            if i >= holding_period {
+                signals[i - holding_period] = 1;
+            }
+        }
+    }
+    let cumulative_return = (0..changes.len())
+        .filter(|i| signals[*i] != 0)
+        .map(|i| returns[i] - fees[i])
+        .sum();
+    (signals, cumulative_return)
+}
+
+/// Candles that are the lowest of the `threshold` candles on either side of them
+fn local_minima(closes: &[f64], threshold: usize) -> Vec<usize> {
+    (threshold..closes.len().saturating_sub(threshold))
+        .filter(|i| {
+            closes[i - threshold..=i + threshold]
+                .iter()
+                .all(|close| closes[*i] <= *close)
+        })
+        .collect()
+}
+
+/// Finds the holding period with the highest return after fees, sells at the ends of its
+/// profitable holds and buys a candle after local price minima.
+///
+/// Tie-break: when several holding periods tie for the highest return the shortest one is
+/// used, e.g. when no hold is profitable at all. The R labelling stopped with an error there,
+/// labels of series without a tie are the same as its ones.
+pub fn optimal_trading_signal(
+    closes: &[f64],
+    buy_threshold: f64,
+    fee_rate: f64,
+    max_holding_period: usize,
+) -> Result<OptimalSignals, MeshetarError> {
+    if closes.len() < 3 {
+        return Err(MeshetarError::Validation(format!(
+            "{} klines are not enough to label.",
+            closes.len()
+        )));
+    }
+    let changes: Vec<f64> = closes.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    let max_holding_period = max_holding_period.clamp(1, changes.len());
+    let cumulative_returns: Vec<f64> = (1..=max_holding_period)
+        .map(|holding_period| {
+            signals_for_holding_period(closes, &changes, holding_period, buy_threshold, fee_rate).1
+        })
+        .collect();
+    let best = cumulative_returns.iter().cloned().fold(f64::MIN, f64::max);
+    let optimal: Vec<usize> = (1..=max_holding_period)
+        .filter(|holding_period| cumulative_returns[holding_period - 1] == best)
+        .collect();
+    if optimal.len() > 1 {
+        log::debug!(
+            "Holding periods {:?} tie for the best return {}, using the shortest.",
+            optimal,
+            best
+        );
+    }
+    let holding_period = optimal[0];
+
+    let (mut change_signals, _) =
+        signals_for_holding_period(closes, &changes, holding_period, buy_threshold, fee_rate);
+    for signal in change_signals.iter_mut().filter(|signal| **signal == 1) {
+        *signal = 0;
+    }
+    for minimum in local_minima(closes, holding_period) {
+        change_signals[minimum] = 1;
+    }
+
+    // The signal of a price change belongs to the candle that completes it
+    let signals = std::iter::once(0)
+        .chain(change_signals)
+        .map(|signal| match signal {
+            1 => TradeSignal::Buy,
+            -1 => TradeSignal::Sell,
+            _ => TradeSignal::Hold,
+        })
+        .collect();
+    Ok(OptimalSignals {
+        signals,
+        holding_period,
+    })
+}
+
+/// Labels the stored klines of the pair, within the window if given
+pub async fn label_klines(
+    symbol: &str,
+    interval: &str,
+    window: Option<TrainingWindow>,
+    parameters: &LabelParameters,
+) -> Result<KlineLabels, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let klines: Vec<(i64, f64)> = sqlx::query_as(
+        "SELECT open_time, close FROM klines
+        WHERE symbol = ?1 AND interval = ?2 AND open_time BETWEEN ?3 AND ?4
+        ORDER BY open_time ASC",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .bind(window.map_or(0, |window| window.train_from))
+    .bind(window.map_or(i64::MAX, |window| window.test_to))
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching klines to label. {:?}", e)))
+    .await?;
+    let closes: Vec<f64> = klines.iter().map(|(_, close)| *close).collect();
+    let max_holding_period = max_holding_period(closes.len());
+    let fee_rate = match parameters.fee_rate {
+        Some(fee_rate) => fee_rate,
+        None => fees::current().await?.market_order(),
+    };
+    let optimal = optimal_trading_signal(
+        &closes,
+        parameters.buy_threshold,
+        fee_rate,
+        max_holding_period,
+    )?;
+    Ok(KlineLabels {
+        labels: klines
+            .iter()
+            .zip(optimal.signals)
+            .map(|((open_time, _), label)| Label {
+                open_time: *open_time,
+                label,
+            })
+            .collect(),
+        holding_period: optimal.holding_period,
+        max_holding_period,
+        fee_rate,
+    })
+}
+
+/// Stores the labels signals are evaluated against, replacing the earlier ones of the klines
+pub async fn save_labels(
+    symbol: &str,
+    interval: &str,
+    labels: &[Label],
+    model_id: Option<i64>,
+) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let mut tx = connection
+        .begin()
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error on creating transaction on labels: {:?}", e))
+        })
+        .await?;
+    for label in labels {
+        sqlx::query(
+            "INSERT OR REPLACE INTO labels (symbol, interval, open_time, label, model_id)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .bind(symbol)
+        .bind(interval)
+        .bind(label.open_time)
+        .bind(label.label.to_string().to_lowercase())
+        .bind(model_id)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error saving labels. {:?}", e)))
+        .await?;
+    }
+    tx.commit()
+        .map_err(|e| MeshetarError::Database(format!("Error committing labels: {:?}", e)))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TradeSignal::{Buy as B, Hold as H, Sell as S};
+
+    fn label(closes: &[f64], buy_threshold: f64) -> OptimalSignals {
+        optimal_trading_signal(closes, buy_threshold, 0.0, max_holding_period(closes.len()))
+            .unwrap()
+    }
+
+    #[test]
+    fn rounds_halves_to_even_like_r() {
+        let rounded: Vec<usize> = [0.5, 1.5, 2.5, 3.5, 2.4, 2.6]
+            .into_iter()
+            .map(round_half_even)
+            .collect();
+        assert_eq!(rounded, vec![0, 2, 2, 4, 2, 3]);
+        let periods: Vec<usize> = [2, 3, 4, 6, 10, 12, 13, 14]
+            .into_iter()
+            .map(max_holding_period)
+            .collect();
+        assert_eq!(periods, vec![1, 1, 1, 2, 2, 3, 3, 4]);
+    }
+
+    // Expected labels are the ones of the R labelling (`optimal_trading_signal.R` with a fee
+    // rate of 0) on the same closes
+
+    #[test]
+    fn matches_r_labelling() {
+        let closes = [
+            100.0, 101.0, 103.0, 102.0, 106.0, 104.0, 103.0, 108.0, 110.0, 107.0, 105.0, 111.0,
+        ];
+        let optimal = label(&closes, 2.5);
+        assert_eq!(optimal.holding_period, 2);
+        assert_eq!(optimal.signals, vec![H, H, H, H, S, H, H, B, S, H, H, S]);
+
+        let closes = [
+            10.0, 9.0, 11.0, 12.0, 10.0, 13.0, 15.0, 14.0, 12.0, 16.0, 17.0, 15.0,
+        ];
+        let optimal = label(&closes, 1.5);
+        assert_eq!(optimal.holding_period, 3);
+        assert_eq!(optimal.signals, vec![H, H, H, H, H, S, S, H, H, B, S, S]);
+    }
+
+    #[test]
+    fn labels_a_holding_period_of_one() {
+        let optimal = label(&[10.0, 12.0, 10.0, 12.0, 10.0, 12.0], 1.0);
+        assert_eq!(optimal.holding_period, 1);
+        assert_eq!(optimal.signals, vec![H, S, H, B, H, B]);
+    }
+
+    #[test]
+    fn flat_series_only_buys_at_minima() {
+        let optimal = label(&[10.0; 6], 0.01);
+        assert_eq!(optimal.holding_period, 1);
+        assert_eq!(optimal.signals, vec![H, H, B, B, B, B]);
+    }
+
+    #[test]
+    fn ties_pick_the_shortest_holding_period() {
+        let closes = [10.0, 11.0, 12.0, 11.0, 10.0, 12.0, 14.0, 13.0, 12.0, 15.0];
+        let changes: Vec<f64> = closes.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let returns: Vec<f64> = (1..=2)
+            .map(|holding_period| {
+                signals_for_holding_period(&closes, &changes, holding_period, 0.5, 0.0).1
+            })
+            .collect();
+        assert_eq!(returns, vec![7.0, 7.0]);
+
+        let optimal = label(&closes, 0.5);
+        assert_eq!(optimal.holding_period, 1);
+        assert_eq!(optimal.signals, vec![H, H, S, H, H, B, S, H, H, B]);
+    }
+
+    #[test]
+    fn refuses_too_few_klines() {
+        assert!(optimal_trading_signal(&[1.0, 2.0], 0.01, 0.0, 1).is_err());
+    }
+
+    fn changes(closes: &[f64]) -> Vec<f64> {
+        closes.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    #[test]
+    fn charges_the_fee_rate_on_both_closes() {
+        let closes = [100.0, 102.0, 104.0, 103.0, 110.0];
+        let (signals, cumulative) =
+            signals_for_holding_period(&closes, &changes(&closes), 1, 0.0, 0.0);
+        assert_eq!(signals, vec![1, -1, 1, -1]);
+        assert!((cumulative - 10.0).abs() < 1e-9);
+
+        // The rises of 2 don't cover 0.01 * (100 + 102) and 0.01 * (102 + 104), the one of 7
+        // covers 0.01 * (103 + 110). The buy before it counts -1 - 0.01 * (104 + 103).
+        let (signals, cumulative) =
+            signals_for_holding_period(&closes, &changes(&closes), 1, 0.0, 0.01);
+        assert_eq!(signals, vec![0, 0, 1, -1]);
+        assert!((cumulative - (7.0 - 2.13 - 1.0 - 2.07)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn charges_the_fee_on_the_closes_a_window_spans() {
+        // The first window spans closes 1 to 3 like in R, returns 2, 5 and 1 against fees of
+        // 0.01 * (101 + 103), (101 + 106) and (103 + 104)
+        let closes = [100.0, 101.0, 103.0, 106.0, 104.0];
+        let (signals, cumulative) =
+            signals_for_holding_period(&closes, &changes(&closes), 2, 0.0, 0.01);
+        assert_eq!(signals, vec![1, 0, -1, 0]);
+        assert!((cumulative - (5.0 - 2.07)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,293 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Layer {
+    /// One row of input weights per neuron
+    weights: Vec<Vec<f64>>,
+    biases: Vec<f64>,
+}
+
+impl Layer {
+    /// Glorot uniform initialization
+    fn new(inputs: usize, outputs: usize, rng: &mut StdRng) -> Layer {
+        let limit = (6.0 / (inputs + outputs) as f64).sqrt();
+        Layer {
+            weights: (0..outputs)
+                .map(|_| (0..inputs).map(|_| rng.gen_range(-limit..limit)).collect())
+                .collect(),
+            biases: vec![0.0; outputs],
+        }
+    }
+
+    fn forward(&self, input: &[f64]) -> Vec<f64> {
+        self.weights
+            .iter()
+            .zip(self.biases.iter())
+            .map(|(weights, bias)| {
+                bias + weights
+                    .iter()
+                    .zip(input.iter())
+                    .map(|(weight, value)| weight * value)
+                    .sum::<f64>()
+            })
+            .collect()
+    }
+}
+
+fn logistic(value: f64) -> f64 {
+    1.0 / (1.0 + (-value).exp())
+}
+
+fn softmax(values: &[f64]) -> Vec<f64> {
+    let max = values.iter().cloned().fold(f64::MIN, f64::max);
+    let exps: Vec<f64> = values.iter().map(|value| (value - max).exp()).collect();
+    let sum: f64 = exps.iter().sum();
+    exps.iter().map(|value| value / sum).collect()
+}
+
+/// Training settings of the network
+pub struct MlpOptions {
+    pub epochs: usize,
+    pub learning_rate: f64,
+    pub batch_size: usize,
+    pub seed: u64,
+}
+
+/// Feed-forward network with logistic hidden layers and a softmax output,
+/// trained with mini-batch gradient descent on cross-entropy
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mlp {
+    layers: Vec<Layer>,
+}
+
+impl Mlp {
+    pub fn new(inputs: usize, hidden: &[usize], outputs: usize, seed: u64) -> Mlp {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let sizes: Vec<usize> = std::iter::once(inputs)
+            .chain(hidden.iter().copied())
+            .chain(std::iter::once(outputs))
+            .collect();
+        Mlp {
+            layers: sizes
+                .windows(2)
+                .map(|pair| Layer::new(pair[0], pair[1], &mut rng))
+                .collect(),
+        }
+    }
+
+    /// Activations of every layer, the first one is the input, the last one the class
+    /// probabilities
+    fn activations(&self, input: &[f64]) -> Vec<Vec<f64>> {
+        let mut activations = vec![input.to_vec()];
+        for (i, layer) in self.layers.iter().enumerate() {
+            let sums = layer.forward(activations.last().unwrap());
+            activations.push(if i + 1 == self.layers.len() {
+                softmax(&sums)
+            } else {
+                sums.into_iter().map(logistic).collect()
+            });
+        }
+        activations
+    }
+
+    pub fn predict(&self, input: &[f64]) -> Vec<f64> {
+        self.activations(input).pop().unwrap()
+    }
+
+    /// Takes one gradient step on the batch, returns its summed cross-entropy
+    fn train_batch(&mut self, inputs: &[&Vec<f64>], targets: &[usize], learning_rate: f64) -> f64 {
+        let mut weight_gradients: Vec<Vec<Vec<f64>>> = self
+            .layers
+            .iter()
+            .map(|layer| vec![vec![0.0; layer.weights[0].len()]; layer.weights.len()])
+            .collect();
+        let mut bias_gradients: Vec<Vec<f64>> = self
+            .layers
+            .iter()
+            .map(|layer| vec![0.0; layer.biases.len()])
+            .collect();
+        let mut loss = 0.0;
+
+        for (input, target) in inputs.iter().zip(targets.iter()) {
+            let activations = self.activations(input);
+            let output = activations.last().unwrap();
+            loss -= output[*target].max(f64::MIN_POSITIVE).ln();
+            // Softmax with cross-entropy has the error of the output sums as its gradient
+            let mut deltas: Vec<f64> = output
+                .iter()
+                .enumerate()
+                .map(|(class, probability)| probability - if class == *target { 1.0 } else { 0.0 })
+                .collect();
+            for layer_index in (0..self.layers.len()).rev() {
+                let layer_input = &activations[layer_index];
+                for (neuron, delta) in deltas.iter().enumerate() {
+                    bias_gradients[layer_index][neuron] += delta;
+                    for (weight, value) in layer_input.iter().enumerate() {
+                        weight_gradients[layer_index][neuron][weight] += delta * value;
+                    }
+                }
+                if layer_index > 0 {
+                    let weights = &self.layers[layer_index].weights;
+                    deltas = layer_input
+                        .iter()
+                        .enumerate()
+                        .map(|(input_neuron, activation)| {
+                            let error: f64 = deltas
+                                .iter()
+                                .enumerate()
+                                .map(|(neuron, delta)| delta * weights[neuron][input_neuron])
+                                .sum();
+                            error * activation * (1.0 - activation)
+                        })
+                        .collect();
+                }
+            }
+        }
+
+        let step = learning_rate / inputs.len() as f64;
+        for (layer_index, layer) in self.layers.iter_mut().enumerate() {
+            for (neuron, weights) in layer.weights.iter_mut().enumerate() {
+                for (weight, value) in weights.iter_mut().enumerate() {
+                    *value -= step * weight_gradients[layer_index][neuron][weight];
+                }
+                layer.biases[neuron] -= step * bias_gradients[layer_index][neuron];
+            }
+        }
+        loss
+    }
+
+    /// Trains on rows of inputs and their class indices. `should_stop` is asked after every
+    /// epoch, training ends early when it returns true. Returns the mean loss of the last epoch.
+    pub fn train(
+        &mut self,
+        inputs: &[Vec<f64>],
+        targets: &[usize],
+        options: &MlpOptions,
+        should_stop: impl Fn() -> bool,
+    ) -> f64 {
+        let mut rng = StdRng::seed_from_u64(options.seed);
+        let mut order: Vec<usize> = (0..inputs.len()).collect();
+        let mut mean_loss = f64::NAN;
+        for _ in 0..options.epochs {
+            order.shuffle(&mut rng);
+            let mut loss = 0.0;
+            for batch in order.chunks(options.batch_size.max(1)) {
+                let batch_inputs: Vec<&Vec<f64>> = batch.iter().map(|i| &inputs[*i]).collect();
+                let batch_targets: Vec<usize> = batch.iter().map(|i| targets[*i]).collect();
+                loss += self.train_batch(&batch_inputs, &batch_targets, options.learning_rate);
+            }
+            mean_loss = loss / inputs.len() as f64;
+            if should_stop() {
+                break;
+            }
+        }
+        mean_loss
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loss(network: &Mlp, input: &[f64], target: usize) -> f64 {
+        -network.predict(input)[target].ln()
+    }
+
+    #[test]
+    fn outputs_are_probabilities() {
+        let probabilities = softmax(&[1.0, 2.0, 3.0]);
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(probabilities[0] < probabilities[1] && probabilities[1] < probabilities[2]);
+        // Large sums don't overflow
+        assert!((softmax(&[1000.0, 1000.0])[0] - 0.5).abs() < 1e-12);
+
+        let network = Mlp::new(4, &[5, 3], 3, 7);
+        let output = network.predict(&[0.5, -1.0, 2.0, 0.0]);
+        assert_eq!(output.len(), 3);
+        assert!(output.iter().all(|probability| *probability > 0.0));
+        assert!((output.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn gradient_step_matches_numerical_gradient() {
+        let network = Mlp::new(3, &[4], 3, 11);
+        let input = vec![0.3, -0.7, 1.1];
+        let target = 1;
+        let learning_rate = 1e-3;
+        let mut stepped = network.clone();
+        stepped.train_batch(&[&input], &[target], learning_rate);
+
+        let epsilon = 1e-6;
+        for layer in 0..network.layers.len() {
+            for neuron in 0..network.layers[layer].weights.len() {
+                for weight in 0..network.layers[layer].weights[neuron].len() {
+                    let mut plus = network.clone();
+                    plus.layers[layer].weights[neuron][weight] += epsilon;
+                    let mut minus = network.clone();
+                    minus.layers[layer].weights[neuron][weight] -= epsilon;
+                    let numerical = (loss(&plus, &input, target) - loss(&minus, &input, target))
+                        / (2.0 * epsilon);
+                    let analytical = (network.layers[layer].weights[neuron][weight]
+                        - stepped.layers[layer].weights[neuron][weight])
+                        / learning_rate;
+                    assert!(
+                        (numerical - analytical).abs() < 1e-6,
+                        "weight {} {} {}: {} is not {}",
+                        layer,
+                        neuron,
+                        weight,
+                        analytical,
+                        numerical
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn learns_a_separable_set() {
+        // The sign of the first input decides the class, the second one is noise
+        let inputs: Vec<Vec<f64>> = (0..40)
+            .map(|i| {
+                let x = (i % 20) as f64 / 10.0 - 0.95;
+                vec![x, ((i * 7) % 11) as f64 / 10.0 - 0.5]
+            })
+            .collect();
+        let targets: Vec<usize> = inputs
+            .iter()
+            .map(|input| if input[0] > 0.0 { 0 } else { 2 })
+            .collect();
+        let mut network = Mlp::new(2, &[4], 3, 3);
+        let options = MlpOptions {
+            epochs: 300,
+            learning_rate: 0.5,
+            batch_size: 8,
+            seed: 3,
+        };
+        let first_loss = network.train(
+            &inputs,
+            &targets,
+            &MlpOptions {
+                epochs: 1,
+                ..options
+            },
+            || false,
+        );
+        let last_loss = network.train(&inputs, &targets, &options, || false);
+        assert!(last_loss < first_loss);
+        for (input, target) in inputs.iter().zip(targets.iter()) {
+            let output = network.predict(input);
+            let predicted = (0..3)
+                .reduce(|best, class| {
+                    if output[class] > output[best] {
+                        class
+                    } else {
+                        best
+                    }
+                })
+                .unwrap();
+            assert_eq!(predicted, *target, "{:?} was classed {:?}", input, output);
+        }
+    }
+}
@@ -1,2 +1,12 @@
+pub mod evaluation;
+pub mod feature_store;
+pub mod hyperparameter_search;
+pub mod labelling;
+pub mod mlp;
 pub mod prediction_model;
+pub mod promotion;
+pub mod registry;
 pub mod routes;
+pub mod rust_backend;
+pub mod scheduler;
+pub mod walk_forward;
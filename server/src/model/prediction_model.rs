@@ -1,35 +1,325 @@
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc, time::Instant};
 
+use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString};
 use tokio::sync::Mutex;
 
-use crate::{utils::rlang_runner, TaskControl};
+use super::{
+    feature_store::{self, FeatureSetVersion},
+    labelling::{self, KlineLabels, LabelParameters},
+    registry::{self, ModelRecord, TrainingReport},
+    rust_backend,
+};
+use crate::{
+    utils::{
+        error::MeshetarError,
+        load_config::{self, ModelBackend},
+        rlang_runner,
+    },
+    TaskControl,
+};
 
-#[derive(Debug, Copy, Clone, Display, EnumString)]
+#[derive(Debug, Copy, Clone, PartialEq, Display, EnumString, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TradeSignal {
     Hold,
     Buy,
     Sell,
 }
 
-pub async fn run_model(task_control: Arc<Mutex<TaskControl>>) -> Result<TradeSignal, String> {
-    match rlang_runner::run_script("models/default_run.R", task_control).await {
-        Ok(signal) => match signal.as_str() {
-            "buy" => Ok(TradeSignal::Buy),
-            "sell" => Ok(TradeSignal::Sell),
-            "hold" => Ok(TradeSignal::Hold),
-            _ => Err(format!(
-                "Model runner encountered unexpected signal: {:?}",
-                &signal
-            )),
-        },
-        Err(e) => Err(e),
+/// Input of the model scripts, written as JSON to their stdin. The Rust backend is handed
+/// the same.
+#[derive(Serialize)]
+pub(super) struct ScriptPayload {
+    pub symbol: String,
+    pub interval: String,
+    /// Registry model, walk-forward folds are not registered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_id: Option<i64>,
+    pub db_path: String,
+    pub artifact_path: String,
+    /// Number of most recent feature rows the run script reads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_size: Option<i64>,
+    /// Where the training script saves its plot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plot_path: Option<String>,
+    /// Explicit train/test window, otherwise the training script splits all history
+    #[serde(flatten)]
+    pub window: Option<TrainingWindow>,
+    /// Overrides of the training script's default parameters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<TrainingParameters>,
+    /// Optimal signals of the klines to train on, filled in by `train` unless given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labelling: Option<KlineLabels>,
+    /// Feature sets to read from the feature store, filled in by `train` and `run_model`
+    pub feature_sets: Vec<FeatureSetVersion>,
+}
+
+/// Labels the klines to train on, stores their features and trains with the configured
+/// backend. Labels of a registered model trained on all history are stored, signals are
+/// evaluated against them. Labels already in the payload are trained on as they are, their
+/// features have to be stored by the caller, so concurrent trainings share both.
+async fn train(
+    task_control: Arc<Mutex<TaskControl>>,
+    mut payload: ScriptPayload,
+) -> Result<TrainingReport, MeshetarError> {
+    let config = load_config::config().model;
+    let feature_sets = feature_store::configured_versions(&payload.symbol)?;
+    if payload.labelling.is_none() {
+        feature_store::materialize(&payload.symbol, &payload.interval, &feature_sets).await?;
+        let label_parameters =
+            LabelParameters::from_training_parameters(payload.parameters.as_ref())?;
+        let labels = labelling::label_klines(
+            &payload.symbol,
+            &payload.interval,
+            payload.window,
+            &label_parameters,
+        )
+        .await?;
+        if payload.window.is_none() && payload.model_id.is_some() {
+            labelling::save_labels(
+                &payload.symbol,
+                &payload.interval,
+                &labels.labels,
+                payload.model_id,
+            )
+            .await?;
+        }
+        payload.labelling = Some(labels);
     }
+    payload.feature_sets = feature_sets.clone();
+    let mut report: TrainingReport = match config.backend {
+        ModelBackend::R => {
+            rlang_runner::run_script(&config.create_script, &payload, task_control).await?
+        }
+        ModelBackend::Rust => rust_backend::train(task_control, payload).await?,
+    };
+    report.feature_sets = feature_sets;
+    Ok(report)
+}
+
+pub type TrainingParameters = serde_json::Map<String, serde_json::Value>;
+
+/// Klines with `train_from <= open_time < test_from` are trained on,
+/// the ones up to and including `test_to` are tested on
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct TrainingWindow {
+    pub train_from: i64,
+    pub test_from: i64,
+    pub test_to: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ClassProbabilities {
+    pub buy: f64,
+    pub hold: f64,
+    pub sell: f64,
 }
 
-pub async fn create_model(task_control: Arc<Mutex<TaskControl>>) -> Result<(), String> {
-    match rlang_runner::run_script("models/default_create.R", task_control).await {
-        Ok(_) => Ok(()),
-        Err(e) => Err(format!("{:?}", e)),
+/// Result of the run script
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Prediction {
+    pub signal: TradeSignal,
+    pub probabilities: ClassProbabilities,
+    pub model_id: i64,
+    /// Anything else the script wants to report, logged as is
+    #[serde(default)]
+    pub diagnostics: serde_json::Value,
+    /// Time the model took to answer, measured by the server
+    #[serde(skip_deserializing)]
+    pub latency_ms: i64,
+}
+
+impl Prediction {
+    /// Probability the model gave to its own signal
+    pub fn confidence(&self) -> f64 {
+        match self.signal {
+            TradeSignal::Buy => self.probabilities.buy,
+            TradeSignal::Hold => self.probabilities.hold,
+            TradeSignal::Sell => self.probabilities.sell,
+        }
+    }
+
+    /// Signal to trade on, buys and sells below `min_confidence` are not acted upon
+    pub fn signal_with_min_confidence(&self, min_confidence: f64) -> TradeSignal {
+        match self.signal {
+            TradeSignal::Buy | TradeSignal::Sell if self.confidence() < min_confidence => {
+                TradeSignal::Hold
+            }
+            signal => signal,
+        }
     }
 }
+
+/// Result of the run script when it is given a window
+#[derive(Deserialize)]
+struct WindowPredictions {
+    model_id: i64,
+    predictions: Vec<WindowPrediction>,
+}
+
+#[derive(Deserialize)]
+struct WindowPrediction {
+    open_time: i64,
+    signal: TradeSignal,
+}
+
+fn backend_of(model: &ModelRecord) -> Result<ModelBackend, MeshetarError> {
+    ModelBackend::from_str(&model.backend).map_err(|_| {
+        MeshetarError::ModelRunner(format!(
+            "Model {} has an unknown backend {}.",
+            model.id, model.backend
+        ))
+    })
+}
+
+/// Predicts the signal of every kline between `from` and `to` (epoch ms) with complete
+/// features, to score a model on klines it was not tested on. The run script is handed the
+/// window and answers with a signal per kline in it.
+pub async fn predict_between(
+    task_control: Arc<Mutex<TaskControl>>,
+    model: &ModelRecord,
+    from: i64,
+    to: i64,
+) -> Result<Vec<(i64, TradeSignal)>, MeshetarError> {
+    if backend_of(model)? == ModelBackend::Rust {
+        return rust_backend::predict_between(model, from, to).await;
+    }
+    let config = load_config::config();
+    let feature_sets = model.feature_set_versions()?;
+    feature_store::materialize(&model.symbol, &model.interval, &feature_sets).await?;
+    let payload = ScriptPayload {
+        symbol: model.symbol.clone(),
+        interval: model.interval.clone(),
+        model_id: Some(model.id),
+        db_path: config.storage.database_path,
+        artifact_path: model.artifact_path.clone(),
+        window_size: None,
+        plot_path: None,
+        window: Some(TrainingWindow {
+            train_from: from,
+            test_from: from,
+            test_to: to,
+        }),
+        parameters: None,
+        labelling: None,
+        feature_sets,
+    };
+    let result: WindowPredictions =
+        rlang_runner::run_script(&config.model.run_script, &payload, task_control).await?;
+    if result.model_id != model.id {
+        return Err(MeshetarError::ModelRunner(format!(
+            "Run script answered for model {}, expected model {}.",
+            result.model_id, model.id
+        )));
+    }
+    Ok(result
+        .predictions
+        .into_iter()
+        .map(|prediction| (prediction.open_time, prediction.signal))
+        .collect())
+}
+
+pub async fn run_model(
+    task_control: Arc<Mutex<TaskControl>>,
+    symbol: &str,
+    interval: &str,
+) -> Result<Prediction, MeshetarError> {
+    let config = load_config::config();
+    let model = registry::active_model(symbol, interval).await?;
+    let backend = backend_of(&model)?;
+    let feature_sets = model.feature_set_versions()?;
+    feature_store::materialize(symbol, interval, &feature_sets).await?;
+    let payload = ScriptPayload {
+        symbol: symbol.to_string(),
+        interval: interval.to_string(),
+        model_id: Some(model.id),
+        db_path: config.storage.database_path,
+        artifact_path: model.artifact_path.clone(),
+        window_size: Some(config.model.window_size),
+        plot_path: None,
+        window: None,
+        parameters: None,
+        labelling: None,
+        feature_sets,
+    };
+    let started_at = Instant::now();
+    let mut prediction: Prediction = match backend {
+        ModelBackend::R => {
+            rlang_runner::run_script(&config.model.run_script, &payload, task_control).await?
+        }
+        ModelBackend::Rust => rust_backend::predict(&model, &payload.feature_sets).await?,
+    };
+    prediction.latency_ms = started_at.elapsed().as_millis() as i64;
+    if prediction.model_id != model.id {
+        return Err(MeshetarError::ModelRunner(format!(
+            "Run script answered for model {}, expected model {}.",
+            prediction.model_id, model.id
+        )));
+    }
+    log::debug!("Prediction diagnostics: {}", prediction.diagnostics);
+    Ok(prediction)
+}
+
+/// Trains a new model version into the registry, it is not activated here.
+pub async fn create_model(
+    task_control: Arc<Mutex<TaskControl>>,
+    symbol: &str,
+    interval: &str,
+) -> Result<ModelRecord, MeshetarError> {
+    let config = load_config::config();
+    let model = registry::create_record(symbol, interval).await?;
+    let payload = ScriptPayload {
+        symbol: symbol.to_string(),
+        interval: interval.to_string(),
+        model_id: Some(model.id),
+        db_path: config.storage.database_path,
+        artifact_path: model.artifact_path.clone(),
+        window_size: None,
+        plot_path: Some(format!("{}/{}", config.storage.static_dir, model.plot_path)),
+        window: None,
+        parameters: None,
+        labelling: None,
+        feature_sets: Vec::new(),
+    };
+    match train(task_control, payload).await {
+        Ok(report) => {
+            registry::complete_record(model.id, &report).await?;
+            registry::get_model(model.id).await
+        }
+        Err(e) => {
+            registry::fail_record(model.id).await?;
+            Err(e)
+        }
+    }
+}
+
+/// Trains a model outside of the registry, used for walk-forward folds and parameter search.
+/// With `labelling` given the klines aren't labelled again and no features are stored.
+pub async fn train_unregistered(
+    task_control: Arc<Mutex<TaskControl>>,
+    symbol: &str,
+    interval: &str,
+    artifact_path: &str,
+    window: Option<TrainingWindow>,
+    parameters: Option<TrainingParameters>,
+    labelling: Option<KlineLabels>,
+) -> Result<TrainingReport, MeshetarError> {
+    let config = load_config::config();
+    let payload = ScriptPayload {
+        symbol: symbol.to_string(),
+        interval: interval.to_string(),
+        model_id: None,
+        db_path: config.storage.database_path,
+        artifact_path: artifact_path.to_string(),
+        window_size: None,
+        plot_path: None,
+        window,
+        parameters,
+        labelling,
+        feature_sets: Vec::new(),
+    };
+    train(task_control, payload).await
+}
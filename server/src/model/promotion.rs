@@ -0,0 +1,185 @@
+use super::{
+    prediction_model,
+    registry::{self, ModelRecord},
+};
+use crate::{
+    utils::{database::DB_POOL, error::MeshetarError},
+    TaskControl,
+};
+use chrono::{DateTime, Utc};
+use futures::TryFutureExt;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::Mutex;
+
+/// Whether a retrained model replaced the active one, and why
+#[derive(FromRow, Serialize, Clone, Debug)]
+pub struct PromotionDecision {
+    pub id: i64,
+    pub created_at: String,
+    pub symbol: String,
+    pub interval: String,
+    pub candidate_id: i64,
+    pub active_id: Option<i64>,
+    pub candidate_score: Option<f64>,
+    pub active_score: Option<f64>,
+    pub promoted: bool,
+    pub reason: String,
+}
+
+/// Test split accuracy the training script reported
+fn accuracy(model: &ModelRecord) -> Option<f64> {
+    model
+        .metrics
+        .as_ref()
+        .and_then(|metrics| serde_json::from_str::<serde_json::Value>(metrics).ok())
+        .and_then(|metrics| metrics["accuracy"].as_f64())
+}
+
+/// Accuracy of `model` on the klines between `from` and `to` against the stored labels, the
+/// ones the latest model was trained and tested on. The model predicts the klines again from
+/// their stored features, R models through their run script.
+async fn window_accuracy(
+    task_control: Arc<Mutex<TaskControl>>,
+    model: &ModelRecord,
+    from: i64,
+    to: i64,
+) -> Result<Option<f64>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let predictions: Vec<(i64, String)> =
+        prediction_model::predict_between(task_control, model, from, to)
+            .await?
+            .into_iter()
+            .map(|(open_time, signal)| (open_time, signal.to_string().to_lowercase()))
+            .collect();
+    let labels: HashMap<i64, String> = sqlx::query_as(
+        "SELECT open_time, label FROM labels
+        WHERE symbol = ?1 AND interval = ?2 AND open_time BETWEEN ?3 AND ?4",
+    )
+    .bind(&model.symbol)
+    .bind(&model.interval)
+    .bind(from)
+    .bind(to)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching labels. {:?}", e)))
+    .await?
+    .into_iter()
+    .collect();
+    let scored: Vec<bool> = predictions
+        .iter()
+        .filter_map(|(open_time, signal)| Some(labels.get(open_time)? == signal))
+        .collect();
+    if scored.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        scored.iter().filter(|correct| **correct).count() as f64 / scored.len() as f64,
+    ))
+}
+
+/// Activates `candidate` if there is no active model for its pair or it beats the active one
+/// by `min_improvement`. Both are scored on the candidate's test window, the candidate by the
+/// accuracy it reported and the active model by `window_accuracy`. Without a score of the
+/// active model the candidate is not promoted. The decision is recorded either way.
+pub async fn promote_if_better(
+    task_control: Arc<Mutex<TaskControl>>,
+    candidate: &ModelRecord,
+    min_improvement: f64,
+) -> Result<PromotionDecision, MeshetarError> {
+    let active = match registry::active_model(&candidate.symbol, &candidate.interval).await {
+        Ok(active) => Some(active),
+        Err(MeshetarError::NotFound(_)) => None,
+        Err(e) => return Err(e),
+    };
+    let candidate_score = accuracy(candidate);
+    let active_score = match (&active, candidate.test_from, candidate.test_to) {
+        // A model that can't be scored, e.g. a run script without window support, must not
+        // stop the candidate from being recorded
+        (Some(active), Some(test_from), Some(test_to)) => {
+            match window_accuracy(task_control, active, test_from, test_to).await {
+                Ok(score) => score,
+                Err(MeshetarError::ModelRunner(e)) => {
+                    log::warn!("Could not score active model {}: {}", active.id, e);
+                    None
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        _ => None,
+    };
+    let (promoted, reason) = match (&active, candidate_score, active_score) {
+        (None, _, _) => (true, String::from("No active model.")),
+        (Some(_), None, _) => (false, String::from("Candidate reported no accuracy.")),
+        (Some(_), Some(_), None) => (
+            false,
+            String::from("Active model could not be scored on the candidate's test window."),
+        ),
+        (Some(_), Some(candidate_score), Some(active_score)) => {
+            if candidate_score >= active_score + min_improvement {
+                (
+                    true,
+                    format!(
+                        "Accuracy {:.4} beats {:.4} of the active model by at least {:.4}.",
+                        candidate_score, active_score, min_improvement
+                    ),
+                )
+            } else {
+                (
+                    false,
+                    format!(
+                        "Accuracy {:.4} does not beat {:.4} of the active model by {:.4}.",
+                        candidate_score, active_score, min_improvement
+                    ),
+                )
+            }
+        }
+    };
+    if promoted {
+        registry::activate(candidate.id).await?;
+    }
+
+    let connection = DB_POOL.get().unwrap();
+    let timestamp: String = DateTime::to_rfc3339(&Utc::now());
+    let id: (i64,) = sqlx::query_as(
+        "INSERT INTO model_promotions
+            (created_at, symbol, interval, candidate_id, active_id, candidate_score, active_score, promoted, reason)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) RETURNING id",
+    )
+    .bind(timestamp)
+    .bind(&candidate.symbol)
+    .bind(&candidate.interval)
+    .bind(candidate.id)
+    .bind(active.as_ref().map(|active| active.id))
+    .bind(candidate_score)
+    .bind(active_score)
+    .bind(promoted)
+    .bind(&reason)
+    .fetch_one(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error recording model promotion. {:?}", e)))
+    .await?;
+    log::info!(
+        "Model {} {}: {}",
+        candidate.id,
+        if promoted { "promoted" } else { "not promoted" },
+        reason
+    );
+    get_decision(id.0).await
+}
+
+async fn get_decision(id: i64) -> Result<PromotionDecision, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, PromotionDecision>("SELECT * FROM model_promotions WHERE id = ?1")
+        .bind(id)
+        .fetch_one(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching model promotion. {:?}", e)))
+        .await
+}
+
+pub async fn list_decisions() -> Result<Vec<PromotionDecision>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, PromotionDecision>("SELECT * FROM model_promotions ORDER BY id DESC")
+        .fetch_all(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error listing model promotions. {:?}", e)))
+        .await
+}
@@ -0,0 +1,255 @@
+use super::feature_store::FeatureSetVersion;
+use crate::utils::{
+    database::DB_POOL,
+    error::MeshetarError,
+    load_config::{self, ModelBackend},
+};
+use chrono::{DateTime, Utc};
+use futures::TryFutureExt;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::path::Path;
+
+pub const ARTIFACTS_DIR: &str = "models/artifacts";
+/// Relative to the static dir, so the plots can be served
+const PLOTS_DIR: &str = "models";
+
+#[derive(FromRow, Serialize, Clone, Debug)]
+pub struct ModelRecord {
+    pub id: i64,
+    pub created_at: String,
+    pub symbol: String,
+    pub interval: String,
+    pub status: String,
+    pub train_from: Option<i64>,
+    pub train_to: Option<i64>,
+    pub test_from: Option<i64>,
+    pub test_to: Option<i64>,
+    /// JSON array of feature names
+    pub features: Option<String>,
+    /// JSON object of the training parameters
+    pub parameters: Option<String>,
+    /// JSON object of evaluation metrics on the test split
+    pub metrics: Option<String>,
+    pub artifact_path: String,
+    pub plot_path: String,
+    pub is_active: bool,
+    /// `ModelBackend` the model was trained with and runs on
+    pub backend: String,
+    /// JSON array of the feature set versions the model was trained on, models trained before
+    /// the feature store have none and were trained on the technical indicators
+    pub feature_sets: Option<String>,
+}
+
+impl ModelRecord {
+    pub fn feature_set_versions(&self) -> Result<Vec<FeatureSetVersion>, MeshetarError> {
+        match &self.feature_sets {
+            Some(feature_sets) => serde_json::from_str(feature_sets).map_err(|e| {
+                MeshetarError::Internal(format!(
+                    "Error parsing feature sets of model {}: {:?}",
+                    self.id, e
+                ))
+            }),
+            None => Ok(vec![FeatureSetVersion {
+                name: String::from("technical"),
+                version: 1,
+            }]),
+        }
+    }
+}
+
+/// What the training script reports back about a run
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TrainingReport {
+    pub train_from: i64,
+    pub train_to: i64,
+    pub test_from: i64,
+    pub test_to: i64,
+    pub features: serde_json::Value,
+    pub parameters: serde_json::Value,
+    pub metrics: serde_json::Value,
+    /// Filled in by the server, the backends are handed them in the payload
+    #[serde(default)]
+    pub feature_sets: Vec<FeatureSetVersion>,
+}
+
+pub enum ModelStatus {
+    Training,
+    Trained,
+    Failed,
+}
+
+impl ModelStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ModelStatus::Training => "training",
+            ModelStatus::Trained => "trained",
+            ModelStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Path of an artifact named `name`, with the extension of the configured backend
+pub fn artifact_path(name: &str) -> String {
+    let backend = load_config::config().model.backend;
+    format!(
+        "{}/{}.{}",
+        ARTIFACTS_DIR,
+        name,
+        backend.artifact_extension()
+    )
+}
+
+/// Reserves an ID for a new training run and the paths its outputs are written to
+pub async fn create_record(symbol: &str, interval: &str) -> Result<ModelRecord, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let config = load_config::config();
+    let static_dir = config.storage.static_dir;
+    let backend: ModelBackend = config.model.backend;
+    std::fs::create_dir_all(ARTIFACTS_DIR).map_err(|e| {
+        MeshetarError::Internal(format!("Error creating {}: {:?}", ARTIFACTS_DIR, e))
+    })?;
+    std::fs::create_dir_all(Path::new(&static_dir).join(PLOTS_DIR))
+        .map_err(|e| MeshetarError::Internal(format!("Error creating plot dir: {:?}", e)))?;
+
+    let timestamp: String = DateTime::to_rfc3339(&Utc::now());
+    let id: (i64,) = sqlx::query_as(
+        "INSERT INTO models (created_at, symbol, interval, status, artifact_path, plot_path, is_active, backend)
+        VALUES (?1, ?2, ?3, ?4, '', '', 0, ?5) RETURNING id",
+    )
+    .bind(timestamp)
+    .bind(symbol)
+    .bind(interval)
+    .bind(ModelStatus::Training.as_str())
+    .bind(backend.to_string())
+    .fetch_one(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error registering a model. {:?}", e)))
+    .await?;
+
+    let artifact_path = format!(
+        "{}/model_{}.{}",
+        ARTIFACTS_DIR,
+        id.0,
+        backend.artifact_extension()
+    );
+    let plot_path = format!("{}/model_{}.svg", PLOTS_DIR, id.0);
+    sqlx::query("UPDATE models SET artifact_path = ?1, plot_path = ?2 WHERE id = ?3")
+        .bind(&artifact_path)
+        .bind(&plot_path)
+        .bind(id.0)
+        .execute(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error registering a model. {:?}", e)))
+        .await?;
+    get_model(id.0).await
+}
+
+pub async fn complete_record(id: i64, report: &TrainingReport) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query(
+        "UPDATE models
+        SET status = ?1, train_from = ?2, train_to = ?3, test_from = ?4, test_to = ?5,
+            features = ?6, parameters = ?7, metrics = ?8, feature_sets = ?9
+        WHERE id = ?10",
+    )
+    .bind(ModelStatus::Trained.as_str())
+    .bind(report.train_from)
+    .bind(report.train_to)
+    .bind(report.test_from)
+    .bind(report.test_to)
+    .bind(report.features.to_string())
+    .bind(report.parameters.to_string())
+    .bind(report.metrics.to_string())
+    .bind(serde_json::json!(report.feature_sets).to_string())
+    .bind(id)
+    .execute(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error saving model {} results. {:?}", id, e)))
+    .await?;
+    Ok(())
+}
+
+pub async fn fail_record(id: i64) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query("UPDATE models SET status = ?1 WHERE id = ?2")
+        .bind(ModelStatus::Failed.as_str())
+        .bind(id)
+        .execute(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error marking model {} failed. {:?}", id, e)))
+        .await?;
+    Ok(())
+}
+
+pub async fn get_model(id: i64) -> Result<ModelRecord, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, ModelRecord>("SELECT * FROM models WHERE id = ?1")
+        .bind(id)
+        .fetch_optional(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching model {}. {:?}", id, e)))
+        .await?
+        .ok_or(MeshetarError::NotFound(format!(
+            "Model {} does not exist.",
+            id
+        )))
+}
+
+pub async fn list_models() -> Result<Vec<ModelRecord>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, ModelRecord>("SELECT * FROM models ORDER BY id DESC")
+        .fetch_all(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error listing models. {:?}", e)))
+        .await
+}
+
+pub async fn active_model(symbol: &str, interval: &str) -> Result<ModelRecord, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, ModelRecord>(
+        "SELECT * FROM models WHERE symbol = ?1 AND interval = ?2 AND is_active = 1",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .fetch_optional(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching active model. {:?}", e)))
+    .await?
+    .ok_or(MeshetarError::NotFound(format!(
+        "No active model for {} {}, create or activate one.",
+        symbol, interval
+    )))
+}
+
+/// Makes the model the one used for its symbol and interval, also how a rollback is done
+pub async fn activate(id: i64) -> Result<ModelRecord, MeshetarError> {
+    let model = get_model(id).await?;
+    if model.status != ModelStatus::Trained.as_str() {
+        return Err(MeshetarError::StateConflict(format!(
+            "Model {} is {}, only trained models can be activated.",
+            id, model.status
+        )));
+    }
+    let connection = DB_POOL.get().unwrap();
+    let mut tx = connection
+        .begin()
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error on creating transaction on models: {:?}", e))
+        })
+        .await?;
+    sqlx::query("UPDATE models SET is_active = 0 WHERE symbol = ?1 AND interval = ?2")
+        .bind(&model.symbol)
+        .bind(&model.interval)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error deactivating models. {:?}", e)))
+        .await?;
+    sqlx::query("UPDATE models SET is_active = 1 WHERE id = ?1")
+        .bind(id)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error activating model {}. {:?}", id, e)))
+        .await?;
+    tx.commit()
+        .map_err(|e| MeshetarError::Database(format!("Error committing model activation: {:?}", e)))
+        .await?;
+    log::info!(
+        "Model {} is now active for {} {}.",
+        id,
+        model.symbol,
+        model.interval
+    );
+    get_model(id).await
+}
@@ -4,19 +4,36 @@ use tokio::sync::Mutex;
 
 use crate::{
     trading::meshetar::{Meshetar, MeshetarStatus},
+    utils::error::MeshetarError,
     TaskControl,
 };
 
-use super::prediction_model;
+use super::{
+    evaluation::{self, SignalEvaluation},
+    feature_store::{self, FeatureRows, StoredFeatureSet},
+    hyperparameter_search::{self, SearchTrial},
+    prediction_model,
+    promotion::{self, PromotionDecision},
+    registry::{self, ModelRecord},
+    walk_forward::{self, WalkForwardRun},
+};
 
 #[post("/create_new_model")]
 pub async fn create_new_model(
     meshetar: &State<Arc<Mutex<Meshetar>>>,
     task_control: &State<Arc<Mutex<TaskControl>>>,
-) -> Accepted<Json<Meshetar>> {
+) -> Result<Accepted<Json<Meshetar>>, MeshetarError> {
     // Set state to running
     let meshetar_clone = Arc::clone(&meshetar.inner());
-    meshetar_clone.lock().await.status = MeshetarStatus::CreatingNewModel;
+    let (symbol, interval) = {
+        let mut meshetar = meshetar_clone.lock().await;
+        meshetar.ensure_idle()?;
+        meshetar.status = MeshetarStatus::CreatingNewModel;
+        (
+            meshetar.pair.to_string(),
+            meshetar.interval.to_kline_interval().to_string(),
+        )
+    };
     drop(meshetar_clone);
     // Set task control to running
     &task_control.lock().await.sender.send(true);
@@ -26,8 +43,12 @@ pub async fn create_new_model(
     let meshetar_clone3 = Arc::clone(&meshetar.inner());
     // Start running
     tokio::spawn(async move {
-        match prediction_model::create_model(reciever).await {
-            Ok(_) => log::warn!("Created model successfully"),
+        // Manually created models replace the active one, older versions stay for rollback
+        match prediction_model::create_model(reciever, &symbol, &interval).await {
+            Ok(model) => match registry::activate(model.id).await {
+                Ok(_) => log::warn!("Created model {} successfully", model.id),
+                Err(e) => log::error!("Activating model {} failed with error {}", model.id, e),
+            },
             Err(e) => log::error!("Creating model failed with error {}", e),
         };
         let mut meshetar_clone = meshetar_clone2.lock().await;
@@ -35,5 +56,143 @@ pub async fn create_new_model(
     });
 
     let summary = meshetar_clone3.lock().await.summerize_json();
-    Accepted(Some(summary))
+    Ok(Accepted(Some(summary)))
+}
+
+#[get("/models")]
+pub async fn models() -> Result<Json<Vec<ModelRecord>>, MeshetarError> {
+    Ok(Json(registry::list_models().await?))
+}
+
+#[post("/models/<id>/activate")]
+pub async fn activate_model(id: i64) -> Result<Json<ModelRecord>, MeshetarError> {
+    Ok(Json(registry::activate(id).await?))
+}
+
+#[get("/signal_evaluation")]
+pub async fn signal_evaluation(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+) -> Result<Json<SignalEvaluation>, MeshetarError> {
+    let meshetar = meshetar.lock().await;
+    let symbol = meshetar.pair.to_string();
+    let interval = meshetar.interval.to_kline_interval().to_string();
+    drop(meshetar);
+    Ok(Json(
+        evaluation::evaluate_signals(&symbol, &interval).await?,
+    ))
+}
+
+#[post("/walk_forward")]
+pub async fn start_walk_forward(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    task_control: &State<Arc<Mutex<TaskControl>>>,
+) -> Result<Accepted<Json<Meshetar>>, MeshetarError> {
+    let meshetar_clone = Arc::clone(&meshetar.inner());
+    let (symbol, interval) = {
+        let mut meshetar = meshetar_clone.lock().await;
+        meshetar.ensure_idle()?;
+        meshetar.status = MeshetarStatus::CreatingNewModel;
+        (
+            meshetar.pair.to_string(),
+            meshetar.interval.to_kline_interval().to_string(),
+        )
+    };
+    &task_control.lock().await.sender.send(true);
+    let reciever = Arc::clone(&task_control.inner());
+
+    let meshetar_clone2 = Arc::clone(&meshetar.inner());
+    tokio::spawn(async move {
+        match walk_forward::run(reciever, &symbol, &interval).await {
+            Ok(_) => log::warn!("Walk-forward validation finished"),
+            Err(e) => log::error!("Walk-forward validation failed with error {}", e),
+        };
+        let mut meshetar_clone = meshetar_clone2.lock().await;
+        meshetar_clone.status = MeshetarStatus::Idle;
+    });
+
+    let summary = meshetar_clone.lock().await.summerize_json();
+    Ok(Accepted(Some(summary)))
+}
+
+#[get("/walk_forward")]
+pub async fn walk_forward_runs() -> Result<Json<Vec<WalkForwardRun>>, MeshetarError> {
+    Ok(Json(walk_forward::list_runs().await?))
+}
+
+#[get("/model_promotions")]
+pub async fn model_promotions() -> Result<Json<Vec<PromotionDecision>>, MeshetarError> {
+    Ok(Json(promotion::list_decisions().await?))
+}
+
+#[post("/hyperparameter_search")]
+pub async fn start_hyperparameter_search(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    task_control: &State<Arc<Mutex<TaskControl>>>,
+) -> Result<Accepted<Json<Meshetar>>, MeshetarError> {
+    let meshetar_clone = Arc::clone(&meshetar.inner());
+    let (symbol, interval) = {
+        let mut meshetar = meshetar_clone.lock().await;
+        meshetar.ensure_idle()?;
+        meshetar.status = MeshetarStatus::CreatingNewModel;
+        (
+            meshetar.pair.to_string(),
+            meshetar.interval.to_kline_interval().to_string(),
+        )
+    };
+    &task_control.lock().await.sender.send(true);
+    let reciever = Arc::clone(&task_control.inner());
+
+    let meshetar_clone2 = Arc::clone(&meshetar.inner());
+    tokio::spawn(async move {
+        match hyperparameter_search::run(reciever, &symbol, &interval).await {
+            Ok(search_id) => log::warn!("Parameter search {} finished", search_id),
+            Err(e) => log::error!("Parameter search failed with error {}", e),
+        };
+        let mut meshetar_clone = meshetar_clone2.lock().await;
+        meshetar_clone.status = MeshetarStatus::Idle;
+    });
+
+    let summary = meshetar_clone.lock().await.summerize_json();
+    Ok(Accepted(Some(summary)))
+}
+
+#[get("/hyperparameter_search/leaderboard?<limit>")]
+pub async fn hyperparameter_leaderboard(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<SearchTrial>>, MeshetarError> {
+    let meshetar = meshetar.lock().await;
+    let symbol = meshetar.pair.to_string();
+    let interval = meshetar.interval.to_kline_interval().to_string();
+    drop(meshetar);
+    let limit = limit.unwrap_or(20);
+    Ok(Json(
+        hyperparameter_search::leaderboard(Some(&symbol), Some(&interval), limit).await?,
+    ))
+}
+
+#[get("/feature_sets")]
+pub async fn feature_sets() -> Result<Json<Vec<StoredFeatureSet>>, MeshetarError> {
+    Ok(Json(feature_store::list_sets().await?))
+}
+
+/// Newest stored features of the selected pair, of one set or of the ones new models train on
+#[get("/features?<set>&<limit>")]
+pub async fn features(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    set: Option<String>,
+    limit: Option<i64>,
+) -> Result<Json<FeatureRows>, MeshetarError> {
+    let meshetar = meshetar.lock().await;
+    let symbol = meshetar.pair.to_string();
+    let interval = meshetar.interval.to_kline_interval().to_string();
+    drop(meshetar);
+    let sets = match set {
+        Some(set) => feature_store::current_versions(&[set])?,
+        None => feature_store::configured_versions(&symbol)?,
+    };
+    let limit = limit.unwrap_or(100);
+    Ok(Json(
+        feature_store::read(&symbol, &interval, &sets, 0, i64::MAX, Some(limit)).await?,
+    ))
 }
@@ -0,0 +1,422 @@
+use super::{
+    feature_store::{self, FeatureSetVersion},
+    mlp::{Mlp, MlpOptions},
+    prediction_model::{ClassProbabilities, Prediction, ScriptPayload, TradeSignal},
+    registry::{ModelRecord, TrainingReport},
+};
+use crate::{
+    plotting::plot,
+    utils::{error::MeshetarError, formatting::timestamp_to_dt},
+    TaskControl,
+};
+use futures::TryFutureExt;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, sync::Arc};
+use tokio::sync::Mutex;
+
+const CLASSES: [TradeSignal; 3] = [TradeSignal::Buy, TradeSignal::Hold, TradeSignal::Sell];
+
+/// Training parameters of the Rust backend, the labelling ones match `default_create.R`. The
+/// fee rate is a labelling parameter, the labels come with the rate they were found with.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+struct Parameters {
+    buy_threshold: f64,
+    sell_threshold: f64,
+    /// Neurons per hidden layer, two layers of twice and once the number of features if empty
+    hidden: Vec<usize>,
+    epochs: usize,
+    learning_rate: f64,
+    batch_size: usize,
+    seed: u64,
+    train_share: f64,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Parameters {
+            buy_threshold: 0.01,
+            sell_threshold: -0.01,
+            hidden: Vec::new(),
+            epochs: 200,
+            learning_rate: 0.1,
+            batch_size: 32,
+            seed: 42,
+            train_share: 0.7,
+        }
+    }
+}
+
+/// What a trained model is saved as
+#[derive(Serialize, Deserialize)]
+struct Artifact {
+    feature_sets: Vec<FeatureSetVersion>,
+    features: Vec<String>,
+    /// Mean and standard deviation of every feature over the training split
+    means: Vec<f64>,
+    deviations: Vec<f64>,
+    network: Mlp,
+}
+
+impl Artifact {
+    fn normalize(&self, row: &[f64]) -> Vec<f64> {
+        row.iter()
+            .zip(self.means.iter().zip(self.deviations.iter()))
+            .map(|(value, (mean, deviation))| (value - mean) / deviation)
+            .collect()
+    }
+
+    fn probabilities(&self, row: &[f64]) -> Vec<f64> {
+        self.network.predict(&self.normalize(row))
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ClassMetrics {
+    precision: Option<f64>,
+    recall: Option<f64>,
+    support: usize,
+}
+
+fn class_index(signal: TradeSignal) -> usize {
+    CLASSES.iter().position(|class| *class == signal).unwrap()
+}
+
+fn most_probable(probabilities: &[f64]) -> usize {
+    (0..probabilities.len())
+        .reduce(|best, i| {
+            if probabilities[i] > probabilities[best] {
+                i
+            } else {
+                best
+            }
+        })
+        .unwrap_or(0)
+}
+
+/// Trains a network on the stored features and the labels of the payload, the same way
+/// `default_create.R` does with the same payload.
+pub(super) async fn train(
+    task_control: Arc<Mutex<TaskControl>>,
+    payload: ScriptPayload,
+) -> Result<TrainingReport, MeshetarError> {
+    let ScriptPayload {
+        symbol,
+        interval,
+        artifact_path,
+        plot_path,
+        window,
+        parameters,
+        labelling,
+        feature_sets,
+        ..
+    } = payload;
+    let labelling = labelling.ok_or(MeshetarError::Internal(String::from(
+        "Training payload has no labels.",
+    )))?;
+    let (symbol, interval, artifact_path) = (&symbol, &interval, &artifact_path);
+    let parameters: Parameters = serde_json::from_value(serde_json::Value::Object(
+        parameters.unwrap_or_default(),
+    ))
+    .map_err(|e| MeshetarError::Validation(format!("Invalid training parameters: {}", e)))?;
+    let candles = feature_store::load_candles(symbol, interval, window).await?;
+    let stored = feature_store::read(
+        symbol,
+        interval,
+        &feature_sets,
+        window.map_or(0, |window| window.train_from),
+        window.map_or(i64::MAX, |window| window.test_to),
+        None,
+    )
+    .await?;
+    let features_by_time: HashMap<i64, Vec<f64>> = stored.complete_rows().into_iter().collect();
+    let labels: HashMap<i64, TradeSignal> = labelling
+        .labels
+        .iter()
+        .map(|label| (label.open_time, label.label))
+        .collect();
+
+    // Labelled candles whose features have all warmed up
+    let modelled: Vec<(usize, Vec<f64>, TradeSignal)> = (0..candles.len())
+        .filter_map(|i| {
+            let label = labels.get(&candles[i].open_time)?;
+            let row = features_by_time.get(&candles[i].open_time)?;
+            Some((i, row.clone(), *label))
+        })
+        .collect();
+    let train_count = match window {
+        Some(window) => modelled
+            .iter()
+            .filter(|(i, _, _)| candles[*i].open_time < window.test_from)
+            .count(),
+        None => (parameters.train_share * modelled.len() as f64).round() as usize,
+    };
+    if train_count == 0 || train_count >= modelled.len() {
+        return Err(MeshetarError::Validation(format!(
+            "{} klines with features are not enough for a train and a test split.",
+            modelled.len()
+        )));
+    }
+    let (train, test) = modelled.split_at(train_count);
+
+    let features = stored.names.len();
+    let means: Vec<f64> = (0..features)
+        .map(|f| train.iter().map(|(_, row, _)| row[f]).sum::<f64>() / train.len() as f64)
+        .collect();
+    let deviations: Vec<f64> = (0..features)
+        .map(|f| {
+            let variance = train
+                .iter()
+                .map(|(_, row, _)| (row[f] - means[f]).powi(2))
+                .sum::<f64>()
+                / train.len() as f64;
+            // Constant features are left unscaled
+            if variance > 0.0 {
+                variance.sqrt()
+            } else {
+                1.0
+            }
+        })
+        .collect();
+    let hidden = if parameters.hidden.is_empty() {
+        vec![features * 2, features]
+    } else {
+        parameters.hidden.clone()
+    };
+    // Also catches the default layers of a feature set without features
+    if hidden.contains(&0) {
+        return Err(MeshetarError::Validation(format!(
+            "Hidden layers need at least one neuron, got {:?}.",
+            hidden
+        )));
+    }
+    let mut artifact = Artifact {
+        feature_sets,
+        features: stored.names.clone(),
+        means,
+        deviations,
+        network: Mlp::new(features, &hidden, CLASSES.len(), parameters.seed),
+    };
+
+    let inputs: Vec<Vec<f64>> = train
+        .iter()
+        .map(|(_, row, _)| artifact.normalize(row))
+        .collect();
+    let targets: Vec<usize> = train
+        .iter()
+        .map(|(_, _, label)| class_index(*label))
+        .collect();
+    let options = MlpOptions {
+        epochs: parameters.epochs,
+        learning_rate: parameters.learning_rate,
+        batch_size: parameters.batch_size,
+        seed: parameters.seed,
+    };
+    let receiver = task_control.lock().await.receiver.clone();
+    let (artifact, loss) = tokio::task::spawn_blocking(move || {
+        let loss = artifact
+            .network
+            .train(&inputs, &targets, &options, || *receiver.borrow() == false);
+        (artifact, loss)
+    })
+    .map_err(|e| MeshetarError::Internal(format!("Training task failed: {:?}", e)))
+    .await?;
+    if *task_control.lock().await.receiver.borrow() == false {
+        return Err(MeshetarError::ModelRunner(String::from(
+            "Training was stopped.",
+        )));
+    }
+
+    // Evaluate on the test split
+    let predicted: Vec<usize> = test
+        .iter()
+        .map(|(_, row, _)| most_probable(&artifact.probabilities(row)))
+        .collect();
+    let actual: Vec<usize> = test
+        .iter()
+        .map(|(_, _, label)| class_index(*label))
+        .collect();
+    let correct = predicted
+        .iter()
+        .zip(actual.iter())
+        .filter(|(p, a)| p == a)
+        .count();
+    let classes: serde_json::Map<String, serde_json::Value> = CLASSES
+        .iter()
+        .enumerate()
+        .map(|(class, signal)| {
+            let predicted_count = predicted.iter().filter(|p| **p == class).count();
+            let support = actual.iter().filter(|a| **a == class).count();
+            let true_positives = predicted
+                .iter()
+                .zip(actual.iter())
+                .filter(|(p, a)| **p == class && **a == class)
+                .count();
+            let metrics = ClassMetrics {
+                precision: (predicted_count > 0)
+                    .then(|| true_positives as f64 / predicted_count as f64),
+                recall: (support > 0).then(|| true_positives as f64 / support as f64),
+                support,
+            };
+            (
+                signal.to_string().to_lowercase(),
+                serde_json::json!(metrics),
+            )
+        })
+        .collect();
+
+    if let Some(parent) = Path::new(artifact_path).parent() {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            MeshetarError::Internal(format!("Error creating {:?}: {:?}", parent, e))
+        })?;
+    }
+    let serialized = serde_json::to_string(&artifact)
+        .map_err(|e| MeshetarError::Internal(format!("Error serializing model: {:?}", e)))?;
+    std::fs::write(artifact_path, serialized).map_err(|e| {
+        MeshetarError::Internal(format!("Error saving model to {}: {:?}", artifact_path, e))
+    })?;
+
+    let time_of = |i: usize| timestamp_to_dt(candles[i].open_time / 1000);
+    if let Some(plot_path) = plot_path {
+        plot::plot_training(
+            &plot_path,
+            &candles
+                .iter()
+                .enumerate()
+                .map(|(i, candle)| (time_of(i), candle.close as f32))
+                .collect::<Vec<_>>(),
+            &train
+                .iter()
+                .map(|(i, _, label)| (time_of(*i), *label))
+                .collect::<Vec<_>>(),
+            &test
+                .iter()
+                .zip(predicted.iter())
+                .map(|((i, _, _), class)| (time_of(*i), CLASSES[*class]))
+                .collect::<Vec<_>>(),
+            time_of(test[0].0),
+            labelling.holding_period,
+        )?;
+    }
+
+    Ok(TrainingReport {
+        train_from: candles[train[0].0].open_time,
+        train_to: candles[train[train.len() - 1].0].open_time,
+        test_from: candles[test[0].0].open_time,
+        test_to: candles[test[test.len() - 1].0].open_time,
+        features: serde_json::json!(stored.names),
+        parameters: serde_json::json!({
+            "buy_threshold": parameters.buy_threshold,
+            "sell_threshold": parameters.sell_threshold,
+            "fee_rate": labelling.fee_rate,
+            "hidden": hidden,
+            "epochs": parameters.epochs,
+            "learning_rate": parameters.learning_rate,
+            "batch_size": parameters.batch_size,
+            "seed": parameters.seed,
+            "max_holding_period": labelling.max_holding_period,
+            "opt_hold_period": labelling.holding_period,
+            "train_share": train.len() as f64 / modelled.len() as f64,
+            "final_loss": loss,
+        }),
+        metrics: serde_json::json!({
+            "accuracy": correct as f64 / test.len() as f64,
+            "classes": classes,
+        }),
+        feature_sets: Vec::new(),
+    })
+}
+
+fn load_artifact(model: &ModelRecord) -> Result<Artifact, MeshetarError> {
+    let serialized = std::fs::read_to_string(&model.artifact_path).map_err(|e| {
+        MeshetarError::ModelRunner(format!(
+            "Error reading model {} from {}: {:?}",
+            model.id, model.artifact_path, e
+        ))
+    })?;
+    serde_json::from_str(&serialized).map_err(|e| {
+        MeshetarError::ModelRunner(format!("Error parsing model {}: {:?}", model.id, e))
+    })
+}
+
+/// Predicts the signal of every kline between `from` and `to` (epoch ms) with complete
+/// features, to score a model on klines it was not tested on
+pub async fn predict_between(
+    model: &ModelRecord,
+    from: i64,
+    to: i64,
+) -> Result<Vec<(i64, TradeSignal)>, MeshetarError> {
+    let artifact = load_artifact(model)?;
+    feature_store::materialize(&model.symbol, &model.interval, &artifact.feature_sets).await?;
+    let rows = feature_store::read(
+        &model.symbol,
+        &model.interval,
+        &artifact.feature_sets,
+        from,
+        to,
+        None,
+    )
+    .await?;
+    if rows.names != artifact.features {
+        return Err(MeshetarError::ModelRunner(format!(
+            "Model {} was trained on different features.",
+            model.id
+        )));
+    }
+    Ok(rows
+        .complete_rows()
+        .into_iter()
+        .map(|(open_time, row)| {
+            (
+                open_time,
+                CLASSES[most_probable(&artifact.probabilities(&row))],
+            )
+        })
+        .collect())
+}
+
+/// Predicts the signal of the latest kline with a model trained by `train`, from the stored
+/// features of the sets the model was trained on
+pub async fn predict(
+    model: &ModelRecord,
+    feature_sets: &[FeatureSetVersion],
+) -> Result<Prediction, MeshetarError> {
+    let artifact = load_artifact(model)?;
+
+    let latest = feature_store::read(
+        &model.symbol,
+        &model.interval,
+        feature_sets,
+        0,
+        i64::MAX,
+        Some(1),
+    )
+    .await?;
+    if artifact.feature_sets != feature_sets || latest.names != artifact.features {
+        return Err(MeshetarError::ModelRunner(format!(
+            "Model {} was trained on different features.",
+            model.id
+        )));
+    }
+    let (open_time, row) = latest
+        .complete_rows()
+        .pop()
+        .ok_or(MeshetarError::Validation(String::from(
+            "The features of the latest kline are still warming up, fetch more klines.",
+        )))?;
+    let probabilities = artifact.probabilities(&row);
+    Ok(Prediction {
+        signal: CLASSES[most_probable(&probabilities)],
+        probabilities: ClassProbabilities {
+            buy: probabilities[0],
+            hold: probabilities[1],
+            sell: probabilities[2],
+        },
+        model_id: model.id,
+        diagnostics: serde_json::json!({
+            "feature_sets": feature_sets,
+            "last_open_time": open_time,
+        }),
+        latency_ms: 0,
+    })
+}
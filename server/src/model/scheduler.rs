@@ -0,0 +1,98 @@
+use super::{prediction_model, promotion};
+use crate::{
+    assets::book,
+    trading::meshetar::{Meshetar, MeshetarStatus},
+    utils::{
+        error::MeshetarError,
+        load_config::{self, RetrainJob},
+    },
+    TaskControl,
+};
+use chrono::{DateTime, Duration, Utc};
+use std::{str::FromStr, sync::Arc};
+use tokio::sync::Mutex;
+
+const SCHEDULE_CHECK_INTERVAL_MS: u64 = 10000;
+
+/// Whether the job's cron expression fired between `since` and `now`
+fn is_due(job: &RetrainJob, since: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+    match cron::Schedule::from_str(&job.cron) {
+        Ok(schedule) => schedule
+            .after(&since)
+            .next()
+            .map_or(false, |next| next <= now),
+        Err(_) => false,
+    }
+}
+
+/// Tops up the history of the job's pair, retrains and promotes the model if it is better.
+/// Expects meshetar to be reserved for the job already.
+async fn run_job(
+    job: &RetrainJob,
+    meshetar: &Arc<Mutex<Meshetar>>,
+    task_control: &Arc<Mutex<TaskControl>>,
+) -> Result<(), MeshetarError> {
+    let config = load_config::config().scheduler;
+    let symbol = job.pair.to_string();
+    let interval = job.interval.to_kline_interval();
+    let interval_string = interval.to_string();
+    task_control.lock().await.sender.send(true).ok();
+
+    let start_time = match book::latest_kline_open_time(&symbol, &interval_string).await? {
+        Some(open_time) => open_time / 1000,
+        None => (Utc::now() - Duration::days(config.history_days)).timestamp(),
+    };
+    book::fetch_history(
+        Arc::clone(task_control),
+        symbol.clone(),
+        interval,
+        start_time,
+    )
+    .await?;
+    if *task_control.lock().await.receiver.borrow() == false {
+        return Err(MeshetarError::StateConflict(String::from(
+            "Scheduled retraining was stopped.",
+        )));
+    }
+
+    meshetar.lock().await.status = MeshetarStatus::CreatingNewModel;
+    let candidate =
+        prediction_model::create_model(Arc::clone(task_control), &symbol, &interval_string).await?;
+    promotion::promote_if_better(Arc::clone(task_control), &candidate, config.min_improvement)
+        .await?;
+    Ok(())
+}
+
+/// Runs the retraining jobs of the config when their cron expressions fire. Jobs that fire
+/// while another operation is running are skipped.
+pub async fn run(meshetar: Arc<Mutex<Meshetar>>, task_control: Arc<Mutex<TaskControl>>) {
+    let mut last_check = Utc::now();
+    loop {
+        tokio::time::sleep(std::time::Duration::from_millis(SCHEDULE_CHECK_INTERVAL_MS)).await;
+        let now = Utc::now();
+        let config = load_config::config().scheduler;
+        if config.enabled {
+            for job in config
+                .jobs
+                .iter()
+                .filter(|job| is_due(job, last_check, now))
+            {
+                {
+                    let mut meshetar = meshetar.lock().await;
+                    if let Err(e) = meshetar.ensure_idle() {
+                        log::warn!("Scheduled retraining of {} skipped: {}", job.pair, e);
+                        continue;
+                    }
+                    meshetar.status = MeshetarStatus::FetchingHistory;
+                }
+                log::info!("Scheduled retraining of {} {:?}.", job.pair, job.interval);
+                match run_job(job, &meshetar, &task_control).await {
+                    Ok(_) => log::info!("Scheduled retraining of {} done.", job.pair),
+                    Err(e) => log::error!("Scheduled retraining failed: {}", e),
+                }
+                meshetar.lock().await.status = MeshetarStatus::Idle;
+            }
+        }
+        last_check = now;
+    }
+}
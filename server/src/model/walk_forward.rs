@@ -0,0 +1,389 @@
+use super::{
+    prediction_model::{self, TrainingWindow},
+    registry::{self, TrainingReport},
+};
+use crate::{
+    utils::{
+        database::DB_POOL,
+        error::MeshetarError,
+        load_config::{self, WalkForwardConfig},
+    },
+    TaskControl,
+};
+use chrono::{DateTime, Utc};
+use futures::TryFutureExt;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(FromRow, Serialize, Clone, Debug)]
+pub struct WalkForwardFold {
+    pub run_id: i64,
+    pub fold: i64,
+    pub status: String,
+    pub train_from: i64,
+    pub train_to: Option<i64>,
+    pub test_from: i64,
+    pub test_to: i64,
+    /// JSON object of the fold's out-of-sample metrics
+    pub metrics: Option<String>,
+}
+
+#[derive(FromRow, Serialize, Clone, Debug)]
+pub struct WalkForwardRun {
+    pub id: i64,
+    pub created_at: String,
+    pub symbol: String,
+    pub interval: String,
+    pub status: String,
+    pub train_size: i64,
+    pub test_size: i64,
+    pub step: i64,
+    /// JSON of the aggregated `WalkForwardSummary`
+    pub summary: Option<String>,
+    #[sqlx(skip)]
+    pub folds: Vec<WalkForwardFold>,
+}
+
+/// Out-of-sample performance over all completed folds
+#[derive(Serialize, Debug, Default)]
+pub struct WalkForwardSummary {
+    pub folds: usize,
+    pub completed_folds: usize,
+    pub mean_accuracy: Option<f64>,
+    pub std_accuracy: Option<f64>,
+    pub min_accuracy: Option<f64>,
+    pub max_accuracy: Option<f64>,
+    /// Accuracy over all test klines of all folds together
+    pub pooled_accuracy: Option<f64>,
+    pub test_klines: i64,
+}
+
+/// Rolling windows over the kline open times: `train_size` klines to train on, followed by
+/// `test_size` klines to test on, moved forward by `step` klines per fold.
+fn plan_folds(open_times: &[i64], config: &WalkForwardConfig) -> Vec<TrainingWindow> {
+    let mut folds = Vec::new();
+    let mut start = 0;
+    while start + config.train_size + config.test_size <= open_times.len()
+        && folds.len() < config.max_folds
+    {
+        let test_start = start + config.train_size;
+        folds.push(TrainingWindow {
+            train_from: open_times[start],
+            test_from: open_times[test_start],
+            test_to: open_times[test_start + config.test_size - 1],
+        });
+        start += config.step;
+    }
+    folds
+}
+
+/// Accuracy and number of tested klines reported for a fold
+fn fold_accuracy(metrics: &serde_json::Value) -> Option<(f64, i64)> {
+    let accuracy = metrics["accuracy"].as_f64()?;
+    let tested = metrics["classes"]
+        .as_object()?
+        .values()
+        .filter_map(|class| class["support"].as_i64())
+        .sum();
+    Some((accuracy, tested))
+}
+
+fn summarize(folds: &[WalkForwardFold]) -> WalkForwardSummary {
+    let results: Vec<(f64, i64)> = folds
+        .iter()
+        .filter_map(|fold| fold.metrics.as_ref())
+        .filter_map(|metrics| serde_json::from_str(metrics).ok())
+        .filter_map(|metrics| fold_accuracy(&metrics))
+        .collect();
+    let mut summary = WalkForwardSummary {
+        folds: folds.len(),
+        completed_folds: results.len(),
+        ..Default::default()
+    };
+    if results.is_empty() {
+        return summary;
+    }
+    let count = results.len() as f64;
+    let mean = results.iter().map(|(accuracy, _)| accuracy).sum::<f64>() / count;
+    let variance = results
+        .iter()
+        .map(|(accuracy, _)| (accuracy - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    let test_klines: i64 = results.iter().map(|(_, tested)| tested).sum();
+    let correct: f64 = results
+        .iter()
+        .map(|(accuracy, tested)| accuracy * *tested as f64)
+        .sum();
+    summary.mean_accuracy = Some(mean);
+    summary.std_accuracy = Some(variance.sqrt());
+    summary.min_accuracy = results
+        .iter()
+        .map(|(accuracy, _)| *accuracy)
+        .reduce(f64::min);
+    summary.max_accuracy = results
+        .iter()
+        .map(|(accuracy, _)| *accuracy)
+        .reduce(f64::max);
+    summary.pooled_accuracy = if test_klines > 0 {
+        Some(correct / test_klines as f64)
+    } else {
+        None
+    };
+    summary.test_klines = test_klines;
+    summary
+}
+
+async fn create_run(symbol: &str, interval: &str) -> Result<i64, MeshetarError> {
+    let config = load_config::config().walk_forward;
+    let connection = DB_POOL.get().unwrap();
+    let timestamp: String = DateTime::to_rfc3339(&Utc::now());
+    let id: (i64,) = sqlx::query_as(
+        "INSERT INTO walk_forward_runs (created_at, symbol, interval, status, train_size, test_size, step)
+        VALUES (?1, ?2, ?3, 'running', ?4, ?5, ?6) RETURNING id",
+    )
+    .bind(timestamp)
+    .bind(symbol)
+    .bind(interval)
+    .bind(config.train_size as i64)
+    .bind(config.test_size as i64)
+    .bind(config.step as i64)
+    .fetch_one(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error creating walk-forward run. {:?}", e)))
+    .await?;
+    Ok(id.0)
+}
+
+async fn finish_run(
+    run_id: i64,
+    status: &str,
+    summary: &WalkForwardSummary,
+) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let summary = serde_json::to_string(summary).map_err(|e| {
+        MeshetarError::Internal(format!("Error serializing walk-forward summary: {:?}", e))
+    })?;
+    sqlx::query("UPDATE walk_forward_runs SET status = ?1, summary = ?2 WHERE id = ?3")
+        .bind(status)
+        .bind(summary)
+        .bind(run_id)
+        .execute(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error finishing walk-forward run. {:?}", e)))
+        .await?;
+    Ok(())
+}
+
+async fn save_fold(
+    run_id: i64,
+    fold: i64,
+    window: &TrainingWindow,
+    report: Option<&TrainingReport>,
+) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query(
+        "INSERT OR REPLACE INTO walk_forward_folds
+            (run_id, fold, status, train_from, train_to, test_from, test_to, metrics)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )
+    .bind(run_id)
+    .bind(fold)
+    .bind(if report.is_some() {
+        "trained"
+    } else {
+        "failed"
+    })
+    .bind(window.train_from)
+    .bind(report.map(|report| report.train_to))
+    .bind(window.test_from)
+    .bind(window.test_to)
+    .bind(report.map(|report| report.metrics.to_string()))
+    .execute(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error saving walk-forward fold. {:?}", e)))
+    .await?;
+    Ok(())
+}
+
+async fn folds_of(run_id: i64) -> Result<Vec<WalkForwardFold>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, WalkForwardFold>(
+        "SELECT * FROM walk_forward_folds WHERE run_id = ?1 ORDER BY fold ASC",
+    )
+    .bind(run_id)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching walk-forward folds. {:?}", e)))
+    .await
+}
+
+pub async fn list_runs() -> Result<Vec<WalkForwardRun>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let mut runs =
+        sqlx::query_as::<_, WalkForwardRun>("SELECT * FROM walk_forward_runs ORDER BY id DESC")
+            .fetch_all(connection)
+            .map_err(|e| {
+                MeshetarError::Database(format!("Error listing walk-forward runs. {:?}", e))
+            })
+            .await?;
+    for run in runs.iter_mut() {
+        run.folds = folds_of(run.id).await?;
+    }
+    Ok(runs)
+}
+
+/// Trains and tests a model per fold over the kline history and stores the per-fold and
+/// aggregated out-of-sample metrics. A failed fold is recorded and skipped.
+pub async fn run(
+    task_control: Arc<Mutex<TaskControl>>,
+    symbol: &str,
+    interval: &str,
+) -> Result<WalkForwardSummary, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let open_times: Vec<i64> = sqlx::query_scalar(
+        "SELECT open_time FROM klines WHERE symbol = ?1 AND interval = ?2 ORDER BY open_time ASC",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching kline times. {:?}", e)))
+    .await?;
+    let windows = plan_folds(&open_times, &load_config::config().walk_forward);
+    if windows.is_empty() {
+        return Err(MeshetarError::Validation(format!(
+            "{} klines of {} {} are not enough for a single walk-forward fold.",
+            open_times.len(),
+            symbol,
+            interval
+        )));
+    }
+
+    let run_id = create_run(symbol, interval).await?;
+    log::info!("Walk-forward run {} with {} folds.", run_id, windows.len());
+    for (fold, window) in windows.iter().enumerate() {
+        let artifact_path =
+            registry::artifact_path(&format!("walk_forward_{}_fold_{}", run_id, fold));
+        let training = prediction_model::train_unregistered(
+            Arc::clone(&task_control),
+            symbol,
+            interval,
+            &artifact_path,
+            Some(*window),
+            None,
+            None,
+        )
+        .await;
+        let is_stopped = *task_control.lock().await.receiver.borrow() == false;
+        if is_stopped {
+            finish_run(run_id, "stopped", &summarize(&folds_of(run_id).await?)).await?;
+            return Err(MeshetarError::StateConflict(format!(
+                "Walk-forward run {} was stopped.",
+                run_id
+            )));
+        }
+        match training {
+            Ok(report) => save_fold(run_id, fold as i64, window, Some(&report)).await?,
+            Err(e) => {
+                log::warn!("Walk-forward run {} fold {} failed: {}", run_id, fold, e);
+                save_fold(run_id, fold as i64, window, None).await?
+            }
+        }
+    }
+
+    let summary = summarize(&folds_of(run_id).await?);
+    finish_run(run_id, "completed", &summary).await?;
+    log::info!(
+        "Walk-forward run {} done, mean out-of-sample accuracy {:?}.",
+        run_id,
+        summary.mean_accuracy
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    fn windows(open_times: &[i64], max_folds: usize) -> Vec<(i64, i64, i64)> {
+        let config = WalkForwardConfig {
+            train_size: 3,
+            test_size: 2,
+            step: 2,
+            max_folds,
+        };
+        plan_folds(open_times, &config)
+            .iter()
+            .map(|window| (window.train_from, window.test_from, window.test_to))
+            .collect()
+    }
+
+    #[test]
+    fn folds_roll_forward_by_the_step() {
+        let open_times: Vec<i64> = (0..10).map(|kline| kline * 60000).collect();
+        assert_eq!(
+            windows(&open_times, 10),
+            vec![
+                (0, 180000, 240000),
+                (120000, 300000, 360000),
+                (240000, 420000, 480000)
+            ]
+        );
+        assert_eq!(windows(&open_times, 2).len(), 2);
+        assert_eq!(windows(&open_times[..5], 10), vec![(0, 180000, 240000)]);
+        assert!(windows(&open_times[..4], 10).is_empty());
+    }
+
+    fn fold(fold: i64, metrics: Option<&str>) -> WalkForwardFold {
+        WalkForwardFold {
+            run_id: 1,
+            fold,
+            status: String::from("done"),
+            train_from: 0,
+            train_to: None,
+            test_from: 0,
+            test_to: 0,
+            metrics: metrics.map(String::from),
+        }
+    }
+
+    #[test]
+    fn summary_pools_the_completed_folds() {
+        let folds = [
+            fold(
+                0,
+                Some(
+                    r#"{"accuracy": 0.5, "classes": {"buy": {"support": 10}, "sell": {"support": 10}}}"#,
+                ),
+            ),
+            fold(
+                1,
+                Some(
+                    r#"{"accuracy": 0.8, "classes": {"buy": {"support": 5}, "hold": {"support": 5}}}"#,
+                ),
+            ),
+            fold(2, None),
+            fold(3, Some("{")),
+        ];
+        let summary = summarize(&folds);
+        assert_eq!((summary.folds, summary.completed_folds), (4, 2));
+        assert_close(summary.mean_accuracy.unwrap(), 0.65);
+        assert_close(summary.std_accuracy.unwrap(), 0.15);
+        assert_close(summary.min_accuracy.unwrap(), 0.5);
+        assert_close(summary.max_accuracy.unwrap(), 0.8);
+        assert_close(summary.pooled_accuracy.unwrap(), 18.0 / 30.0);
+        assert_eq!(summary.test_klines, 30);
+
+        let summary = summarize(&folds[2..]);
+        assert_eq!((summary.folds, summary.completed_folds), (2, 0));
+        assert_eq!(summary.mean_accuracy, None);
+        assert_eq!(summary.pooled_accuracy, None);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::utils::{database::DB_POOL, error::MeshetarError, load_config};
+use futures::TryFutureExt;
+use serde::Serialize;
+use sqlx::FromRow;
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct Candle {
+    pub open_time: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct ChartSignal {
+    pub time: i64,
+    /// Signal after the confidence threshold, what would have been traded
+    pub signal: String,
+    /// Probability the model gave to its own signal, the average of merged signals
+    pub confidence: Option<f64>,
+    /// Signals merged into this one, the ones of a merged candle with the same signal
+    pub count: i64,
+}
+
+/// Candles and signals of a pair and interval over a time range, for the app's chart
+#[derive(Serialize, Debug)]
+pub struct CandleSeries {
+    pub symbol: String,
+    pub interval: String,
+    /// Open time of the first and last candle, 0 if there are none
+    pub from: i64,
+    pub to: i64,
+    /// Time each candle spans when the range had more klines than the limit and they were
+    /// merged, `None` when the candles are the stored klines
+    pub candle_ms: Option<i64>,
+    pub candles: Vec<Candle>,
+    pub signals: Vec<ChartSignal>,
+}
+
+/// Klines are merged into candles of whole minutes
+const MINUTE_MS: i64 = 60000;
+
+/// Candles between `from` and `to` (epoch ms), at most `limit` of them: longer ranges are
+/// merged into candles spanning the same time each. Without `from` the last `limit` klines up
+/// to `to` are returned, `to` defaults to the latest kline.
+pub async fn candles(
+    symbol: &str,
+    interval: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+) -> Result<CandleSeries, MeshetarError> {
+    let config = load_config::config().plot;
+    let limit = limit
+        .unwrap_or(config.points_per_page)
+        .clamp(1, config.max_candles);
+    series(symbol, interval, from, to.unwrap_or(i64::MAX), limit).await
+}
+
+async fn series(
+    symbol: &str,
+    interval: &str,
+    from: Option<i64>,
+    to: i64,
+    limit: i64,
+) -> Result<CandleSeries, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let (first, last, candle_ms, candles): (i64, i64, Option<i64>, Vec<Candle>) = match from {
+        Some(from) => {
+            let (klines, first, last): (i64, Option<i64>, Option<i64>) = sqlx::query_as(
+                "SELECT COUNT(*), MIN(open_time), MAX(open_time) FROM klines
+                WHERE symbol = ?1 AND interval = ?2 AND open_time BETWEEN ?3 AND ?4",
+            )
+            .bind(symbol)
+            .bind(interval)
+            .bind(from)
+            .bind(to)
+            .fetch_one(connection)
+            .map_err(|e| MeshetarError::Database(format!("Error counting candles. {:?}", e)))
+            .await?;
+            let (Some(first), Some(last)) = (first, last) else {
+                return Ok(CandleSeries {
+                    symbol: symbol.to_string(),
+                    interval: interval.to_string(),
+                    from: 0,
+                    to: 0,
+                    candle_ms: None,
+                    candles: Vec::new(),
+                    signals: Vec::new(),
+                });
+            };
+            // Buckets from the first kline on, few enough to fit the limit
+            let candle_ms = (klines > limit).then(|| {
+                let bucket_ms = (last - first + MINUTE_MS + limit - 1) / limit;
+                (bucket_ms + MINUTE_MS - 1) / MINUTE_MS * MINUTE_MS
+            });
+            let candles = sqlx::query_as(
+                "WITH buckets AS (
+                    SELECT
+                        (open_time - ?3) / ?5 AS bucket,
+                        MIN(open_time) AS first_time,
+                        MAX(open_time) AS last_time,
+                        MAX(high) AS high,
+                        MIN(low) AS low,
+                        SUM(volume) AS volume
+                    FROM klines
+                    WHERE symbol = ?1 AND interval = ?2 AND open_time BETWEEN ?3 AND ?4
+                    GROUP BY bucket
+                )
+                SELECT
+                    ?3 + buckets.bucket * ?5 AS open_time,
+                    first.open,
+                    buckets.high,
+                    buckets.low,
+                    last.close,
+                    buckets.volume
+                FROM buckets
+                JOIN klines first ON first.symbol = ?1 AND first.interval = ?2
+                    AND first.open_time = buckets.first_time
+                JOIN klines last ON last.symbol = ?1 AND last.interval = ?2
+                    AND last.open_time = buckets.last_time
+                ORDER BY open_time ASC",
+            )
+            .bind(symbol)
+            .bind(interval)
+            .bind(first)
+            .bind(last)
+            // Every kline its own bucket when nothing is merged
+            .bind(candle_ms.unwrap_or(1))
+            .fetch_all(connection)
+            .map_err(|e| MeshetarError::Database(format!("Error fetching candles. {:?}", e)))
+            .await?;
+            (first, last, candle_ms, candles)
+        }
+        None => {
+            let mut candles: Vec<Candle> = sqlx::query_as(
+                "SELECT open_time, open, high, low, close, volume FROM klines
+                WHERE symbol = ?1 AND interval = ?2 AND open_time <= ?3
+                ORDER BY open_time DESC
+                LIMIT ?4",
+            )
+            .bind(symbol)
+            .bind(interval)
+            .bind(to)
+            .bind(limit)
+            .fetch_all(connection)
+            .map_err(|e| MeshetarError::Database(format!("Error fetching candles. {:?}", e)))
+            .await?;
+            candles.reverse();
+            let first = candles.first().map_or(0, |candle| candle.open_time);
+            let last = candles.last().map_or(0, |candle| candle.open_time);
+            (first, last, None, candles)
+        }
+    };
+
+    // Signals of a merged candle are merged the same way, one per signal in the order they
+    // last occurred
+    let signals: Vec<ChartSignal> = sqlx::query_as(
+        "SELECT
+            ?3 + (s.time - ?3) / ?5 * ?5 AS time,
+            LOWER(signal) AS signal,
+            AVG(
+                CASE LOWER(COALESCE(model_signal, signal))
+                    WHEN 'buy' THEN probability_buy
+                    WHEN 'sell' THEN probability_sell
+                    ELSE probability_hold
+                END
+            ) AS confidence,
+            COUNT(*) AS count
+        FROM signals s
+        WHERE symbol = ?1 AND interval = ?2 AND s.time BETWEEN ?3 AND ?4
+        GROUP BY 1, 2
+        ORDER BY 1 ASC, MAX(s.time) ASC",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .bind(first)
+    .bind(last)
+    .bind(candle_ms.unwrap_or(1))
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching chart signals. {:?}", e)))
+    .await?;
+
+    Ok(CandleSeries {
+        symbol: symbol.to_string(),
+        interval: interval.to_string(),
+        from: first,
+        to: last,
+        candle_ms,
+        candles,
+        signals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database;
+
+    #[test]
+    fn merges_klines_and_signals_into_time_buckets() {
+        database::run_test(async {
+            let symbol = "CANDLESUSDT";
+            let connection = DB_POOL.get().unwrap();
+            for i in 0..10 {
+                let close = 100.0 + i as f64;
+                sqlx::query(
+                    "INSERT INTO klines (symbol, interval, open_time, open, high, low, close,
+                        volume, close_time, quote_asset_volume, number_of_trades,
+                        taker_buy_base_asset_volume, taker_buy_quote_asset_volume)
+                    VALUES (?1, '1m', ?2, ?3 - 0.5, ?3 + 1, ?3 - 1, ?3, 1.0, ?2 + 59999, 0.0, 1, 0.0, 0.0)",
+                )
+                .bind(symbol)
+                .bind(i * MINUTE_MS)
+                .bind(close)
+                .execute(connection)
+                .await
+                .unwrap();
+            }
+            for (minute, signal, probability) in [
+                (1, "Buy", 0.6),
+                (2, "Sell", 0.8),
+                (4, "Buy", 0.7),
+                (5, "Buy", 0.9),
+            ] {
+                sqlx::query(
+                    "INSERT INTO signals (symbol, interval, time, signal, probability_buy,
+                        probability_sell)
+                    VALUES (?1, '1m', ?2, ?3, ?4, ?4)",
+                )
+                .bind(symbol)
+                .bind(minute * MINUTE_MS)
+                .bind(signal)
+                .bind(probability)
+                .execute(connection)
+                .await
+                .unwrap();
+            }
+
+            let all = series(symbol, "1m", Some(0), i64::MAX, 10).await.unwrap();
+            assert_eq!(all.candle_ms, None);
+            assert_eq!(all.candles.len(), 10);
+            assert_eq!(all.signals.len(), 4);
+
+            // 10 minutes in at most 4 candles of whole minutes
+            let merged = series(symbol, "1m", Some(0), i64::MAX, 4).await.unwrap();
+            assert_eq!(merged.candle_ms, Some(3 * MINUTE_MS));
+            assert_eq!((merged.from, merged.to), (0, 9 * MINUTE_MS));
+            let candles: Vec<(i64, f64, f64, f64, f64, f64)> = merged
+                .candles
+                .iter()
+                .map(|c| (c.open_time, c.open, c.high, c.low, c.close, c.volume))
+                .collect();
+            assert_eq!(
+                candles,
+                vec![
+                    (0, 99.5, 103.0, 99.0, 102.0, 3.0),
+                    (3 * MINUTE_MS, 102.5, 106.0, 102.0, 105.0, 3.0),
+                    (6 * MINUTE_MS, 105.5, 109.0, 105.0, 108.0, 3.0),
+                    (9 * MINUTE_MS, 108.5, 110.0, 108.0, 109.0, 1.0),
+                ]
+            );
+            let signals: Vec<(i64, &str, i64)> = merged
+                .signals
+                .iter()
+                .map(|s| (s.time, s.signal.as_str(), s.count))
+                .collect();
+            assert_eq!(
+                signals,
+                vec![(0, "buy", 1), (0, "sell", 1), (3 * MINUTE_MS, "buy", 2)]
+            );
+            assert!((merged.signals[2].confidence.unwrap() - 0.8).abs() < 1e-9);
+        });
+    }
+}
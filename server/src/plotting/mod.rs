@@ -1,2 +1,5 @@
+pub mod candles;
 pub mod plot;
+pub mod render;
+pub mod report;
 pub mod routes;
@@ -1,36 +1,224 @@
+use super::render::{Drawing, Theme};
 use crate::{
-    model::prediction_model::TradeSignal,
+    model::{
+        feature_store::{self, FeatureSetVersion},
+        prediction_model::TradeSignal,
+    },
+    sentiment::ingestion,
+    trading::performance::EquityPoint,
     utils::{
         database::DB_POOL,
+        error::MeshetarError,
         formatting::{dt_to_readable, timestamp_to_dt},
+        load_config::{self, ChartOverlay, ChartPanel},
     },
 };
 use chrono::{DateTime, Duration, Utc};
 use futures::TryFutureExt;
-use plotters::{prelude::*, style::full_palette::PINK};
-use std::str::FromStr;
+use plotters::{
+    coord::Shift,
+    prelude::*,
+    style::full_palette::{self, PINK},
+};
+use std::{collections::HashMap, str::FromStr};
+
+/// Default size of the chart, every panel below the candles adds to its height
+const CHART_WIDTH: u32 = 1024;
+const PRICE_HEIGHT: u32 = 480;
+const PANEL_HEIGHT: u32 = 160;
+
+/// Size of the chart with the configured panels
+pub fn default_chart_size() -> (u32, u32) {
+    let panels = load_config::config().plot.panels.len() as u32;
+    (CHART_WIDTH, PRICE_HEIGHT + PANEL_HEIGHT * panels)
+}
+
+fn plot_chart_error<E: std::fmt::Debug>(e: E) -> MeshetarError {
+    MeshetarError::Internal(format!("Error plotting chart: {:?}", e))
+}
+
+/// Stored indicators an overlay draws, with the legend of its first line
+fn overlay_lines(overlay: ChartOverlay) -> Vec<(&'static str, Option<&'static str>, RGBColor)> {
+    match overlay {
+        ChartOverlay::Sma => vec![("sma", Some("SMA 10"), full_palette::ORANGE)],
+        ChartOverlay::Ema => vec![("ema", Some("EMA 10"), full_palette::YELLOW)],
+        ChartOverlay::Bollinger => vec![
+            ("bb_up", Some("Bollinger 20"), full_palette::PURPLE_200),
+            ("bb_mavg", None, full_palette::PURPLE_200),
+            ("bb_dn", None, full_palette::PURPLE_200),
+        ],
+    }
+}
+
+/// Range of the values with a 5% margin, `fallback` when there are none
+fn value_range(values: impl Iterator<Item = f32>, fallback: (f32, f32)) -> std::ops::Range<f32> {
+    let (min, max) = values.fold((f32::MAX, f32::MIN), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    let (min, max) = if min > max { fallback } else { (min, max) };
+    let margin = ((max - min) * 0.05).max(f32::EPSILON);
+    min - margin..max + margin
+}
+
+/// Volume, RSI or MACD of the plotted klines on its own scale below the candles
+fn draw_panel<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    panel: ChartPanel,
+    data: &ChartPlotData,
+    time_range: std::ops::Range<DateTime<Utc>>,
+    show_time: bool,
+    foreground: RGBColor,
+) -> Result<(), MeshetarError> {
+    let text_style = TextStyle::from(("sans-serif", 16).into_font()).color(&foreground);
+    let y_range = match panel {
+        ChartPanel::Volume => {
+            let max = data
+                .volumes
+                .iter()
+                .map(|(_, volume)| *volume)
+                .fold(0.0, f32::max);
+            0.0..(max * 1.1).max(f32::EPSILON)
+        }
+        ChartPanel::Rsi => 0.0..100.0,
+        ChartPanel::Macd => value_range(
+            data.indicator("macd")
+                .iter()
+                .chain(data.indicator("macd_sig"))
+                .map(|(_, value)| *value)
+                .chain(std::iter::once(0.0)),
+            (-1.0, 1.0),
+        ),
+    };
+    // Same label areas as the candles, so the time axes line up
+    let mut chart = ChartBuilder::on(area)
+        .margin(0)
+        .x_label_area_size(if show_time { 50 } else { 0 })
+        .y_label_area_size(120)
+        .right_y_label_area_size(if data.sentiment.is_empty() { 0 } else { 80 })
+        .build_cartesian_2d(time_range.clone(), y_range)
+        .map_err(plot_chart_error)?;
+    chart
+        .configure_mesh()
+        .label_style(text_style)
+        .light_line_style(foreground.mix(0.05))
+        .bold_line_style(foreground.mix(0.2))
+        .axis_style(foreground)
+        .y_desc(match panel {
+            ChartPanel::Volume => "Volume",
+            ChartPanel::Rsi => "RSI 14",
+            ChartPanel::Macd => "MACD %",
+        })
+        .x_labels(9)
+        .x_label_formatter(&|x| dt_to_readable(*x))
+        .y_labels(4)
+        .y_label_formatter(&|y| format!("{:.2}", y))
+        .draw()
+        .map_err(plot_chart_error)?;
+
+    match panel {
+        ChartPanel::Volume => {
+            // Bars colored like their candle
+            let width = data.kline_width();
+            chart
+                .draw_series(data.klines.iter().zip(data.volumes.iter()).map(
+                    |((time, (open, _, _, close)), (_, volume))| {
+                        let color = if close >= open { GREEN } else { RED };
+                        Rectangle::new(
+                            [(*time, 0.0), (*time + width, *volume)],
+                            color.mix(0.5).filled(),
+                        )
+                    },
+                ))
+                .map_err(plot_chart_error)?;
+        }
+        ChartPanel::Rsi => {
+            for level in [30.0, 70.0] {
+                chart
+                    .draw_series(std::iter::once(PathElement::new(
+                        vec![(time_range.start, level), (time_range.end, level)],
+                        foreground.mix(0.4),
+                    )))
+                    .map_err(plot_chart_error)?;
+            }
+            chart
+                .draw_series(LineSeries::new(
+                    data.indicator("rsi").iter().cloned(),
+                    full_palette::AMBER.stroke_width(2),
+                ))
+                .map_err(plot_chart_error)?;
+        }
+        ChartPanel::Macd => {
+            let width = data.kline_width();
+            let signal_at: HashMap<DateTime<Utc>, f32> =
+                data.indicator("macd_sig").iter().cloned().collect();
+            chart
+                .draw_series(data.indicator("macd").iter().filter_map(|(time, macd)| {
+                    let histogram = macd - signal_at.get(time)?;
+                    let color = if histogram >= 0.0 { GREEN } else { RED };
+                    Some(Rectangle::new(
+                        [(*time, 0.0), (*time + width, histogram)],
+                        color.mix(0.5).filled(),
+                    ))
+                }))
+                .map_err(plot_chart_error)?;
+            chart
+                .draw_series(LineSeries::new(
+                    data.indicator("macd").iter().cloned(),
+                    full_palette::LIGHTBLUE.stroke_width(2),
+                ))
+                .map_err(plot_chart_error)?;
+            chart
+                .draw_series(LineSeries::new(
+                    data.indicator("macd_sig").iter().cloned(),
+                    full_palette::ORANGE.stroke_width(1),
+                ))
+                .map_err(plot_chart_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Candles with the configured indicator overlays, buy and sell markers at the close they
+/// were signalled on and the configured panels below
+pub struct Chart<'a> {
+    pub data: &'a ChartPlotData,
+    pub theme: Theme,
+}
 
-static PLOT_PATH: &str = "static/plot.svg";
-static PLOT_PATH_CONSUMER: &str = "plot.svg";
+impl Drawing for Chart<'_> {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root_area: &DrawingArea<DB, Shift>,
+    ) -> Result<(), MeshetarError> {
+        draw_chart(root_area, self.data, self.theme)
+    }
+}
 
-pub async fn plot_chart(
-    data: Vec<(DateTime<Utc>, (f32, f32, f32, f32))>,
-    signals: Vec<(DateTime<Utc>, TradeSignal)>,
-) -> Result<String, String> {
+fn draw_chart<DB: DrawingBackend>(
+    root_area: &DrawingArea<DB, Shift>,
+    data: &ChartPlotData,
+    theme: Theme,
+) -> Result<(), MeshetarError> {
+    if data.klines.is_empty() {
+        return Err(MeshetarError::NotFound(String::from(
+            "No klines to plot the chart with.",
+        )));
+    }
+    let foreground = theme.foreground();
     let font = ("sans-serif", 20).into_font();
-    let text_style = TextStyle::from(font).color(&WHITE);
+    let text_style = TextStyle::from(font).color(&foreground);
     let axis_style = ShapeStyle {
-        color: WHITE.mix(1f64),
+        color: foreground.mix(1f64),
         filled: true,
         stroke_width: 2,
     };
     let thin_guide_style = ShapeStyle {
-        color: WHITE.mix(0.05),
+        color: foreground.mix(0.05),
         filled: true,
         stroke_width: 1,
     };
     let guide_style = ShapeStyle {
-        color: WHITE.mix(0.2),
+        color: foreground.mix(0.2),
         filled: true,
         stroke_width: 1,
     };
@@ -45,11 +233,6 @@ pub async fn plot_chart(
         stroke_width: 1,
     };
 
-    let hold_indicator = ShapeStyle {
-        color: WHITE.mix(0.5),
-        filled: false,
-        stroke_width: 1,
-    };
     let buy_indicator = ShapeStyle {
         color: GREEN.mix(0.8f64),
         filled: true,
@@ -57,7 +240,7 @@ pub async fn plot_chart(
     };
     let sell_indicator = ShapeStyle {
         color: RED.mix(0.8f64),
-        filled: false,
+        filled: true,
         stroke_width: 1,
     };
     let last_value = ShapeStyle {
@@ -66,31 +249,51 @@ pub async fn plot_chart(
         stroke_width: 1,
     };
 
+    let config = load_config::config();
+    let overlays: Vec<(&str, Option<&str>, RGBColor)> = config
+        .plot
+        .overlays
+        .iter()
+        .flat_map(|overlay| overlay_lines(*overlay))
+        .collect();
+
     let mut global_min = f32::MAX;
     let mut global_max = f32::MIN;
 
-    for (_, ohlc) in data.iter() {
+    for (_, ohlc) in data.klines.iter() {
         global_min = global_min.min(ohlc.0).min(ohlc.1).min(ohlc.2).min(ohlc.3);
         global_max = global_max.max(ohlc.0).max(ohlc.1).max(ohlc.2).max(ohlc.3);
     }
+    for (name, _, _) in overlays.iter() {
+        for (_, value) in data.indicator(name) {
+            global_min = global_min.min(*value);
+            global_max = global_max.max(*value);
+        }
+    }
 
     global_min = global_min * 0.95;
     global_max = global_max * 1.05;
 
-    let root_area = SVGBackend::new(PLOT_PATH, (1024, 480)).into_drawing_area();
-    root_area.fill(&RGBColor(20, 30, 38)).unwrap();
+    // The candles get the height of three panels
+    let panels = config.plot.panels.len() as u32;
+    let (_, height) = root_area.dim_in_pixel();
+    root_area
+        .fill(&theme.background())
+        .map_err(plot_chart_error)?;
+    let (price_area, panels_area) = root_area.split_vertically(height * 3 / (3 + panels));
     let (from_date, to_date) = (
-        *&data[0].0 - Duration::minutes(1),
-        *&data[*&data.len() - 1].0 + Duration::minutes(1),
+        data.klines[0].0 - Duration::minutes(1),
+        data.klines[data.klines.len() - 1].0 + Duration::minutes(1),
     );
 
-    let mut chart = ChartBuilder::on(&root_area)
+    let mut chart = ChartBuilder::on(&price_area)
         .caption("Signals", text_style.clone())
         .margin(0)
-        .x_label_area_size(50)
+        .x_label_area_size(if config.plot.panels.is_empty() { 50 } else { 0 })
         .y_label_area_size(120)
+        .right_y_label_area_size(if data.sentiment.is_empty() { 0 } else { 80 })
         .build_cartesian_2d(from_date..to_date, global_min..global_max)
-        .unwrap();
+        .map_err(plot_chart_error)?;
 
     chart
         .configure_mesh()
@@ -108,33 +311,66 @@ pub async fn plot_chart(
         .y_labels(10)
         .y_label_formatter(&|y| format!("{:.4}", y))
         .draw()
-        .unwrap();
+        .map_err(plot_chart_error)?;
 
     // Candlesticks
     chart
-        .draw_series(data.iter().map(|(x, (o, h, l, c))| {
+        .draw_series(data.klines.iter().map(|(x, (o, h, l, c))| {
             CandleStick::new(*x, *o, *h, *l, *c, gain_style, lose_style, 3)
         }))
-        .unwrap();
-
-    // Trade signals
-    for (x, signal) in signals.iter() {
-        let style = match signal {
-            TradeSignal::Buy => buy_indicator,
-            TradeSignal::Sell => sell_indicator,
-            TradeSignal::Hold => hold_indicator,
-        };
+        .map_err(plot_chart_error)?;
+
+    // Indicator overlays
+    for (name, label, color) in overlays.iter().cloned() {
+        let series = chart
+            .draw_series(LineSeries::new(
+                data.indicator(name).iter().cloned(),
+                color.stroke_width(2),
+            ))
+            .map_err(plot_chart_error)?;
+        if let Some(label) = label {
+            series.label(label).legend(move |(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+            });
+        }
+    }
+    if !overlays.is_empty() {
         chart
-            .draw_series(std::iter::once(Circle::new(
-                (*x, global_max),
-                4,
-                style.clone(),
-            )))
-            .unwrap();
+            .configure_series_labels()
+            .label_font(text_style.clone())
+            .background_style(theme.background().mix(0.8))
+            .border_style(foreground.mix(0.2))
+            .position(SeriesLabelPosition::UpperLeft)
+            .draw()
+            .map_err(plot_chart_error)?;
     }
 
+    // Trade signals, pointing at the close of the kline they were given on
+    let close_at: HashMap<DateTime<Utc>, f32> = data
+        .klines
+        .iter()
+        .map(|(time, (_, _, _, close))| (*time, *close))
+        .collect();
+    chart
+        .draw_series(data.signals.iter().filter_map(|(time, signal)| {
+            let (direction, style) = match signal {
+                TradeSignal::Buy => (1, buy_indicator),
+                TradeSignal::Sell => (-1, sell_indicator),
+                TradeSignal::Hold => return None,
+            };
+            let price = *close_at.get(time)?;
+            Some(
+                EmptyElement::at((*time, price))
+                    + Polygon::new(
+                        vec![(0, 0), (-6, 12 * direction), (6, 12 * direction)],
+                        style,
+                    ),
+            )
+        }))
+        .map_err(plot_chart_error)?;
+
     // Last price
-    if let Some(last_kline) = data.last() {
+    if let Some(last_kline) = data.klines.last() {
         let (x, (_o, _h, _l, c)) = *last_kline;
         chart
             .draw_series(std::iter::once(Rectangle::new(
@@ -144,11 +380,269 @@ pub async fn plot_chart(
                 ],
                 last_value,
             )))
-            .unwrap();
+            .map_err(plot_chart_error)?;
+    }
+
+    // Sentiment, on its own scale on the right
+    if !data.sentiment.is_empty() {
+        let (sentiment_min, sentiment_max) = data
+            .sentiment
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), (_, value)| {
+                (min.min(*value), max.max(*value))
+            });
+        let margin = ((sentiment_max - sentiment_min) * 0.05).max(f32::EPSILON);
+        let mut chart = chart.set_secondary_coord(
+            from_date..to_date,
+            sentiment_min - margin..sentiment_max + margin,
+        );
+        chart
+            .configure_secondary_axes()
+            .label_style(text_style.clone())
+            .axis_style(axis_style)
+            .y_desc("Sentiment")
+            .y_labels(5)
+            .y_label_formatter(&|y| format!("{:.2}", y))
+            .draw()
+            .map_err(plot_chart_error)?;
+        chart
+            .draw_secondary_series(LineSeries::new(
+                data.sentiment.iter().cloned(),
+                full_palette::LIGHTBLUE.stroke_width(2),
+            ))
+            .map_err(plot_chart_error)?;
+    }
+
+    if !config.plot.panels.is_empty() {
+        let panel_areas = panels_area.split_evenly((config.plot.panels.len(), 1));
+        for (i, (panel, area)) in config
+            .plot
+            .panels
+            .iter()
+            .zip(panel_areas.iter())
+            .enumerate()
+        {
+            draw_panel(
+                area,
+                *panel,
+                data,
+                from_date..to_date,
+                i + 1 == config.plot.panels.len(),
+                foreground,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn plot_error<E: std::fmt::Debug>(e: E) -> MeshetarError {
+    MeshetarError::Internal(format!("Error plotting training run: {:?}", e))
+}
+
+/// Close prices of a training run with the optimal signals of its train split and the
+/// predictions of its test split, the split is marked with a vertical line
+pub fn plot_training(
+    plot_path: &str,
+    closes: &[(DateTime<Utc>, f32)],
+    labels: &[(DateTime<Utc>, TradeSignal)],
+    predictions: &[(DateTime<Utc>, TradeSignal)],
+    split: DateTime<Utc>,
+    holding_period: usize,
+) -> Result<(), MeshetarError> {
+    let (Some(first), Some(last)) = (closes.first(), closes.last()) else {
+        return Err(MeshetarError::Validation(String::from(
+            "No klines to plot the training run with.",
+        )));
+    };
+    let min = closes
+        .iter()
+        .map(|(_, close)| *close)
+        .fold(f32::MAX, f32::min)
+        * 0.99;
+    let max = closes
+        .iter()
+        .map(|(_, close)| *close)
+        .fold(f32::MIN, f32::max)
+        * 1.01;
+    let price_at: std::collections::HashMap<DateTime<Utc>, f32> = closes.iter().cloned().collect();
+
+    let text_style = TextStyle::from(("sans-serif", 20).into_font()).color(&WHITE);
+    let root_area = SVGBackend::new(plot_path, (1024, 480)).into_drawing_area();
+    root_area.fill(&RGBColor(20, 30, 38)).map_err(plot_error)?;
+    let mut chart = ChartBuilder::on(&root_area)
+        .caption(
+            format!("Buy/Sell signals, holding period {}", holding_period),
+            text_style.clone(),
+        )
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(120)
+        .build_cartesian_2d(first.0..last.0, min..max)
+        .map_err(plot_error)?;
+    chart
+        .configure_mesh()
+        .label_style(text_style)
+        .light_line_style(WHITE.mix(0.05))
+        .bold_line_style(WHITE.mix(0.2))
+        .axis_style(WHITE)
+        .x_labels(9)
+        .x_label_formatter(&|x| dt_to_readable(*x))
+        .y_label_formatter(&|y| format!("{:.4}", y))
+        .draw()
+        .map_err(plot_error)?;
+
+    chart
+        .draw_series(LineSeries::new(closes.iter().cloned(), &WHITE))
+        .map_err(plot_error)?;
+    let markers = [
+        (labels, TradeSignal::Buy, GREEN.mix(1.0)),
+        (labels, TradeSignal::Sell, RED.mix(1.0)),
+        (predictions, TradeSignal::Buy, GREEN.mix(0.5)),
+        (
+            predictions,
+            TradeSignal::Sell,
+            full_palette::ORANGE.mix(1.0),
+        ),
+    ];
+    for (signals, kind, color) in markers {
+        chart
+            .draw_series(
+                signals
+                    .iter()
+                    .filter(|(_, signal)| *signal == kind)
+                    .filter_map(|(time, _)| price_at.get(time).map(|price| (*time, *price)))
+                    .map(|point| Circle::new(point, 4, color.filled())),
+            )
+            .map_err(plot_error)?;
     }
+    chart
+        .draw_series(std::iter::once(PathElement::new(
+            vec![(split, min), (split, max)],
+            RED.stroke_width(2),
+        )))
+        .map_err(plot_error)?;
+
+    root_area.present().map_err(plot_error)
+}
 
-    root_area.present().unwrap();
-    Ok(PLOT_PATH_CONSUMER.to_string())
+fn plot_equity_error<E: std::fmt::Debug>(e: E) -> MeshetarError {
+    MeshetarError::Internal(format!("Error plotting equity curve: {:?}", e))
+}
+
+/// Default size of the equity curve
+pub const EQUITY_SIZE: (u32, u32) = (1024, 480);
+
+/// Reporting valuation of the equity curve with its drawdown below
+pub struct Equity<'a> {
+    pub points: &'a [EquityPoint],
+    pub theme: Theme,
+}
+
+impl Drawing for Equity<'_> {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        root_area: &DrawingArea<DB, Shift>,
+    ) -> Result<(), MeshetarError> {
+        draw_equity(root_area, self.points, self.theme)
+    }
+}
+
+fn draw_equity<DB: DrawingBackend>(
+    root_area: &DrawingArea<DB, Shift>,
+    points: &[EquityPoint],
+    theme: Theme,
+) -> Result<(), MeshetarError> {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Err(MeshetarError::NotFound(String::from(
+            "No balance sheets to plot the equity curve with.",
+        )));
+    };
+    let series: Vec<(DateTime<Utc>, f64, f64)> = points
+        .iter()
+        .map(|point| {
+            (
+                timestamp_to_dt(point.time / 1000),
+                point.reporting_valuation,
+                point.drawdown * 100.0,
+            )
+        })
+        .collect();
+    let (from_date, to_date) = (
+        timestamp_to_dt(first.time / 1000),
+        timestamp_to_dt(last.time / 1000) + Duration::minutes(1),
+    );
+    let (min, max) = series
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), (_, value, _)| {
+            (min.min(*value), max.max(*value))
+        });
+    let margin = ((max - min) * 0.05).max(f64::EPSILON);
+    let max_drawdown = series
+        .iter()
+        .map(|(_, _, drawdown)| *drawdown)
+        .fold(0.0, f64::min)
+        .min(-1.0);
+
+    let foreground = theme.foreground();
+    let text_style = TextStyle::from(("sans-serif", 20).into_font()).color(&foreground);
+    root_area
+        .fill(&theme.background())
+        .map_err(plot_equity_error)?;
+    let (_, height) = root_area.dim_in_pixel();
+    let (equity_area, drawdown_area) = root_area.split_vertically(height * 2 / 3);
+
+    let mut equity_chart = ChartBuilder::on(&equity_area)
+        .caption("Equity", text_style.clone())
+        .margin(10)
+        .x_label_area_size(0)
+        .y_label_area_size(120)
+        .build_cartesian_2d(from_date..to_date, min - margin..max + margin)
+        .map_err(plot_equity_error)?;
+    equity_chart
+        .configure_mesh()
+        .label_style(text_style.clone())
+        .light_line_style(foreground.mix(0.05))
+        .bold_line_style(foreground.mix(0.2))
+        .axis_style(foreground)
+        .y_desc(last.reporting_currency.as_str())
+        .y_label_formatter(&|y| format!("{:.2}", y))
+        .draw()
+        .map_err(plot_equity_error)?;
+    equity_chart
+        .draw_series(LineSeries::new(
+            series.iter().map(|(time, value, _)| (*time, *value)),
+            full_palette::LIGHTGREEN.stroke_width(2),
+        ))
+        .map_err(plot_equity_error)?;
+
+    let mut drawdown_chart = ChartBuilder::on(&drawdown_area)
+        .margin(10)
+        .x_label_area_size(50)
+        .y_label_area_size(120)
+        .build_cartesian_2d(from_date..to_date, max_drawdown * 1.05..0.0)
+        .map_err(plot_equity_error)?;
+    drawdown_chart
+        .configure_mesh()
+        .label_style(text_style)
+        .light_line_style(foreground.mix(0.05))
+        .bold_line_style(foreground.mix(0.2))
+        .axis_style(foreground)
+        .y_desc("Drawdown %")
+        .x_labels(9)
+        .x_label_formatter(&|x| dt_to_readable(*x))
+        .y_labels(4)
+        .y_label_formatter(&|y| format!("{:.1}", y))
+        .draw()
+        .map_err(plot_equity_error)?;
+    drawdown_chart
+        .draw_series(AreaSeries::new(
+            series.iter().map(|(time, _, drawdown)| (*time, *drawdown)),
+            0.0,
+            RED.mix(0.4),
+        ))
+        .map_err(plot_equity_error)?;
+
+    Ok(())
 }
 
 #[derive(sqlx::FromRow)]
@@ -158,6 +652,7 @@ struct SimpleKline {
     high: f32,
     low: f32,
     close: f32,
+    volume: f32,
 }
 
 #[derive(sqlx::FromRow, Debug)]
@@ -169,49 +664,139 @@ struct SimpleSignal {
 pub struct ChartPlotData {
     pub page: i64,
     pub total_pages: i64,
+    /// Open time of the newest plotted kline, 0 without klines
+    pub last_open_time: i64,
     pub klines: Vec<(DateTime<Utc>, (f32, f32, f32, f32))>,
+    pub volumes: Vec<(DateTime<Utc>, f32)>,
     pub signals: Vec<(DateTime<Utc>, TradeSignal)>,
+    /// Values of the plotted sentiment source known at the klines
+    pub sentiment: Vec<(DateTime<Utc>, f32)>,
+    /// Technical indicators of the klines by name, where they are known
+    pub indicators: HashMap<String, Vec<(DateTime<Utc>, f32)>>,
 }
+
+impl ChartPlotData {
+    /// Bars of the panels fill most of the time between two klines
+    fn kline_width(&self) -> Duration {
+        match self.klines.as_slice() {
+            [first, second, ..] => (second.0 - first.0) * 4 / 5,
+            _ => Duration::seconds(48),
+        }
+    }
+    fn indicator(&self, name: &str) -> &[(DateTime<Utc>, f32)] {
+        self.indicators
+            .get(name)
+            .map_or(&[], |values| values.as_slice())
+    }
+}
+
+/// Whether the configured overlays and panels draw stored indicators, volume comes with the
+/// klines
+fn draws_indicators() -> bool {
+    let config = load_config::config().plot;
+    !config.overlays.is_empty()
+        || config
+            .panels
+            .iter()
+            .any(|panel| *panel != ChartPanel::Volume)
+}
+
+/// Technical indicators of the klines between `from` and `to` from the feature store,
+/// extended to the newest klines first
+/// Feature sets the chart's indicators are read from, the book loop keeps them materialized
+pub fn chart_feature_sets() -> Result<Vec<FeatureSetVersion>, MeshetarError> {
+    feature_store::current_versions(&[String::from("technical")])
+}
+
+/// Stored indicators of the klines, plotting only reads them. A pair whose indicators were
+/// never materialized is plotted without them.
+async fn chart_indicators(
+    pair: &str,
+    interval: &str,
+    from: i64,
+    to: i64,
+) -> Result<HashMap<String, Vec<(DateTime<Utc>, f32)>>, MeshetarError> {
+    let sets = chart_feature_sets()?;
+    let rows = match feature_store::read(pair, interval, &sets, from, to, None).await {
+        Ok(rows) => rows,
+        Err(MeshetarError::NotFound(_)) => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(rows
+        .names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let values = rows
+                .rows
+                .iter()
+                .filter_map(|(open_time, values)| {
+                    Some((timestamp_to_dt(open_time / 1000), values[i]? as f32))
+                })
+                .collect();
+            (name.clone(), values)
+        })
+        .collect())
+}
+
+/// A page of the klines between `from` and `to` (epoch ms), pages count back from the newest
+/// klines of the range
 pub async fn generate_plot_data(
     pair: String,
     interval: String,
     page: i64,
-) -> Result<ChartPlotData, String> {
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<ChartPlotData, MeshetarError> {
+    let (from, to) = (from.unwrap_or(0), to.unwrap_or(i64::MAX));
     let klines: Vec<SimpleKline>;
     let signals: Vec<SimpleSignal>;
+    let indicators: HashMap<String, Vec<(DateTime<Utc>, f32)>>;
     let page_to_go: i64;
     let total_pages: i64;
-    let points_per_page: i64 = 180;
+    let max_time: i64;
+    let points_per_page: i64 = load_config::config().plot.points_per_page;
     {
         let connection = DB_POOL.get().unwrap();
         let pages_row: (i64,) =
-            sqlx::query_as("SELECT CAST((COUNT(*) + ?1 - 1) / ?1 AS INTEGER) AS pages FROM klines WHERE symbol = ?2")
+            sqlx::query_as("SELECT CAST((COUNT(*) + ?1 - 1) / ?1 AS INTEGER) AS pages FROM klines WHERE symbol = ?2 AND interval = ?3 AND open_time BETWEEN ?4 AND ?5")
                 .bind(&points_per_page)
                 .bind(&pair)
+                .bind(&interval)
+                .bind(from)
+                .bind(to)
                 .fetch_one(connection)
-                .map_err(|e| format!("Error getting total number of pages for klines, {:?}", e))
+                .map_err(|e| MeshetarError::Database(format!("Error getting total number of pages for klines, {:?}", e)))
                 .await?;
         total_pages = pages_row.0;
-        page_to_go = page.clamp(1, total_pages);
+        page_to_go = page.clamp(1, total_pages.max(1));
         klines = sqlx::query_as::<_, SimpleKline>(
-            "SELECT open_time, open, high, low, close 
+            "SELECT open_time, open, high, low, close, volume
             FROM klines 
-            WHERE interval = ?1 AND symbol = ?2 
+            WHERE interval = ?1 AND symbol = ?2 AND open_time BETWEEN ?3 AND ?4
             ORDER BY open_time DESC 
-            LIMIT ?3
-            OFFSET ?4",
+            LIMIT ?5
+            OFFSET ?6",
         )
         .bind(interval.clone())
         .bind(pair.clone())
+        .bind(from)
+        .bind(to)
         .bind(points_per_page)
         .bind(points_per_page * (page_to_go - 1))
         .fetch_all(connection)
         .await
-        .map_err(|e| format!("Error fetching last kline. {:?}", e))?;
+        .map_err(|e| MeshetarError::Database(format!("Error fetching last kline. {:?}", e)))?;
 
-        let max_time: i64 = klines.first().map(|i| i.open_time).unwrap_or(0);
+        max_time = klines.first().map(|i| i.open_time).unwrap_or(0);
         let min_time: i64 = klines.last().map(|i| i.open_time).unwrap_or(0);
 
+        indicators = if draws_indicators() {
+            chart_indicators(&pair, &interval, min_time, max_time).await?
+        } else {
+            HashMap::new()
+        };
+
         signals = sqlx::query_as::<_, SimpleSignal>(
             "SELECT signal, time 
             FROM signals 
@@ -224,9 +809,34 @@ pub async fn generate_plot_data(
         .bind(max_time)
         .fetch_all(connection)
         .await
-        .map_err(|e| format!("Error fetching last kline. {:?}", e))?;
+        .map_err(|e| MeshetarError::Database(format!("Error fetching last kline. {:?}", e)))?;
     }
 
+    let config = load_config::config();
+    let sentiment_source = config
+        .plot
+        .sentiment_source
+        .as_ref()
+        .and_then(|name| config.sentiment.source(name));
+    let sentiment_rows: Vec<(DateTime<Utc>, f32)> = match sentiment_source {
+        Some(source) => {
+            let open_times: Vec<i64> = klines.iter().rev().map(|kline| kline.open_time).collect();
+            open_times
+                .iter()
+                .zip(ingestion::aligned(source, &open_times).await?)
+                .filter_map(|(open_time, known)| {
+                    Some((timestamp_to_dt(open_time / 1000), known?.value as f32))
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    let volumes: Vec<(DateTime<Utc>, f32)> = klines
+        .iter()
+        .rev()
+        .map(|kline| (timestamp_to_dt(kline.open_time / 1000), kline.volume))
+        .collect();
     let mut rows: Vec<(DateTime<Utc>, (f32, f32, f32, f32))> = klines
         .into_iter()
         .map(|kline| {
@@ -251,8 +861,12 @@ pub async fn generate_plot_data(
 
     Ok(ChartPlotData {
         klines: rows,
+        volumes,
         signals: signal_rows,
+        sentiment: sentiment_rows,
+        indicators,
         page: page_to_go,
         total_pages,
+        last_open_time: max_time,
     })
 }
@@ -0,0 +1,100 @@
+use crate::utils::error::MeshetarError;
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+use plotters::{coord::Shift, prelude::*};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+/// Smallest and largest width or height an image is rendered with
+const MIN_SIZE: u32 = 200;
+const MAX_SIZE: u32 = 4096;
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Hash, Default, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Hash, Default, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub fn background(&self) -> RGBColor {
+        match self {
+            Theme::Dark => RGBColor(20, 30, 38),
+            Theme::Light => WHITE,
+        }
+    }
+    /// Color of text, axes and guides
+    pub fn foreground(&self) -> RGBColor {
+        match self {
+            Theme::Dark => WHITE,
+            Theme::Light => RGBColor(33, 37, 41),
+        }
+    }
+}
+
+/// A plot that can be drawn on any plotters backend
+pub trait Drawing {
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> Result<(), MeshetarError>;
+}
+
+fn render_error<E: std::fmt::Debug>(e: E) -> MeshetarError {
+    MeshetarError::Internal(format!("Error rendering plot: {:?}", e))
+}
+
+pub fn validate_size(width: u32, height: u32) -> Result<(), MeshetarError> {
+    if !(MIN_SIZE..=MAX_SIZE).contains(&width) || !(MIN_SIZE..=MAX_SIZE).contains(&height) {
+        return Err(MeshetarError::Validation(format!(
+            "Plots are {} to {} pixels wide and high, {}x{} was asked for.",
+            MIN_SIZE, MAX_SIZE, width, height
+        )));
+    }
+    Ok(())
+}
+
+/// Renders the drawing in memory, the bytes of the SVG document or PNG image
+pub fn render(
+    drawing: &impl Drawing,
+    format: ImageFormat,
+    (width, height): (u32, u32),
+) -> Result<Vec<u8>, MeshetarError> {
+    validate_size(width, height)?;
+    match format {
+        ImageFormat::Svg => {
+            let mut svg = String::new();
+            {
+                let root = SVGBackend::with_string(&mut svg, (width, height)).into_drawing_area();
+                drawing.draw(&root)?;
+                root.present().map_err(render_error)?;
+            }
+            Ok(svg.into_bytes())
+        }
+        ImageFormat::Png => {
+            let mut pixels = vec![0u8; width as usize * height as usize * 3];
+            {
+                let root =
+                    BitMapBackend::with_buffer(&mut pixels, (width, height)).into_drawing_area();
+                drawing.draw(&root)?;
+                root.present().map_err(render_error)?;
+            }
+            let mut png = Vec::new();
+            PngEncoder::new(&mut png)
+                .write_image(&pixels, width, height, ColorType::Rgb8)
+                .map_err(render_error)?;
+            Ok(png)
+        }
+    }
+}
@@ -0,0 +1,874 @@
+use super::{
+    candles::{self, CandleSeries},
+    render::{self, Drawing, ImageFormat, Theme},
+};
+use crate::{
+    trading::{
+        backtesting::{self, BacktestDetail, BacktestPoint, BacktestSummary},
+        meshetar::Interval,
+    },
+    utils::{
+        error::MeshetarError,
+        formatting::{dt_to_readable, timestamp_to_dt},
+        load_config,
+    },
+};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use plotters::{
+    coord::Shift,
+    prelude::*,
+    style::{
+        full_palette,
+        text_anchor::{HPos, Pos, VPos},
+    },
+};
+use serde::Serialize;
+use std::io::Write;
+use strum::{Display, EnumIter, EnumString, IntoEnumIterator};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Return of the run and of buying and holding over a calendar month, from the equity at the
+/// end of the month before or the initial capital
+#[derive(Serialize, Debug, Clone)]
+pub struct MonthlyReturn {
+    pub year: i32,
+    /// 1 to 12
+    pub month: u32,
+    pub strategy: f64,
+    pub buy_and_hold: f64,
+}
+
+/// A backtest or paper trading run with what its report is drawn from
+pub struct BacktestReport {
+    pub detail: BacktestDetail,
+    pub summary: BacktestSummary,
+    /// Klines of the run's range, merged down to `plot.max_candles`
+    pub candles: CandleSeries,
+    pub monthly_returns: Vec<MonthlyReturn>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumIter)]
+#[strum(serialize_all = "snake_case")]
+pub enum ReportFigure {
+    /// Equity of the run against buying and holding
+    Equity,
+    Drawdown,
+    /// Candles with the run's buys and sells
+    Trades,
+    /// Heatmap of the run's monthly returns
+    MonthlyReturns,
+}
+
+impl ReportFigure {
+    pub fn size(&self, report: &BacktestReport) -> (u32, u32) {
+        match self {
+            ReportFigure::Equity => (1024, 400),
+            ReportFigure::Drawdown => (1024, 240),
+            ReportFigure::Trades => (1024, 480),
+            ReportFigure::MonthlyReturns => {
+                let years = report_years(report).len() as u32;
+                (1024, (90 + 48 * years).max(200))
+            }
+        }
+    }
+}
+
+fn report_error<E: std::fmt::Debug>(e: E) -> MeshetarError {
+    MeshetarError::Internal(format!("Error plotting backtest report: {:?}", e))
+}
+
+fn bundle_error<E: std::fmt::Debug>(e: E) -> MeshetarError {
+    MeshetarError::Internal(format!("Error bundling backtest report: {:?}", e))
+}
+
+/// Splits the equity into calendar months, a month ends with its last point
+fn monthly_returns(equity: &[BacktestPoint], initial_capital: f64) -> Vec<MonthlyReturn> {
+    let month_of = |point: &BacktestPoint| {
+        let time = timestamp_to_dt(point.time / 1000);
+        (time.year(), time.month())
+    };
+    let mut months = Vec::new();
+    let (mut start_equity, mut start_hold) = (initial_capital, initial_capital);
+    for (i, point) in equity.iter().enumerate() {
+        let (year, month) = month_of(point);
+        let is_month_end = equity
+            .get(i + 1)
+            .is_none_or(|next| month_of(next) != (year, month));
+        if is_month_end {
+            months.push(MonthlyReturn {
+                year,
+                month,
+                strategy: point.equity / start_equity - 1.0,
+                buy_and_hold: point.buy_and_hold / start_hold - 1.0,
+            });
+            start_equity = point.equity;
+            start_hold = point.buy_and_hold;
+        }
+    }
+    months
+}
+
+/// Fall of every value from the highest one before it, as a negative share
+fn drawdowns(values: impl Iterator<Item = f64>) -> Vec<f64> {
+    let mut peak = f64::MIN;
+    values
+        .map(|value| {
+            peak = peak.max(value);
+            if peak > 0.0 {
+                value / peak - 1.0
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+fn report_years(report: &BacktestReport) -> Vec<i32> {
+    let mut years: Vec<i32> = report
+        .monthly_returns
+        .iter()
+        .map(|month| month.year)
+        .collect();
+    years.dedup();
+    years
+}
+
+pub async fn load(run_id: i64) -> Result<BacktestReport, MeshetarError> {
+    let detail = backtesting::get_run(run_id).await?;
+    let summary: BacktestSummary = serde_json::from_str(&detail.run.summary)
+        .map_err(|e| MeshetarError::Internal(format!("Error parsing backtest summary: {:?}", e)))?;
+    let candles = candles::candles(
+        &detail.run.symbol,
+        &detail.run.interval,
+        Some(detail.run.from_time),
+        Some(detail.run.to_time),
+        Some(load_config::config().plot.max_candles),
+    )
+    .await?;
+    let monthly_returns = monthly_returns(&detail.equity, detail.run.initial_capital);
+    Ok(BacktestReport {
+        detail,
+        summary,
+        candles,
+        monthly_returns,
+    })
+}
+
+fn percent(value: f64) -> String {
+    format!("{:.2}%", value * 100.0)
+}
+
+/// Rows of the report's stats table
+pub fn stats(report: &BacktestReport) -> Vec<(&'static str, String)> {
+    let run = &report.detail.run;
+    let summary = &report.summary;
+    let quote = |value: f64| format!("{:.2}", value);
+    let hold_drawdown = drawdowns(report.detail.equity.iter().map(|point| point.buy_and_hold))
+        .into_iter()
+        .fold(0.0, f64::min);
+    let best_month = report
+        .monthly_returns
+        .iter()
+        .max_by(|a, b| a.strategy.total_cmp(&b.strategy));
+    let worst_month = report
+        .monthly_returns
+        .iter()
+        .min_by(|a, b| a.strategy.total_cmp(&b.strategy));
+    let month_label = |month: Option<&MonthlyReturn>| match month {
+        Some(month) => format!(
+            "{} {} ({})",
+            MONTHS[month.month as usize - 1],
+            month.year,
+            percent(month.strategy)
+        ),
+        None => String::from("-"),
+    };
+    let positive_months = report
+        .monthly_returns
+        .iter()
+        .filter(|month| month.strategy > 0.0)
+        .count();
+    vec![
+        ("Pair", format!("{} {}", run.symbol, run.interval)),
+        ("Source", run.source.clone()),
+        (
+            "Period",
+            format!(
+                "{} to {} UTC",
+                timestamp_to_dt(run.from_time / 1000).format("%Y-%m-%d %H:%M"),
+                timestamp_to_dt(run.to_time / 1000).format("%Y-%m-%d %H:%M")
+            ),
+        ),
+        ("Klines", summary.klines.to_string()),
+        ("Initial capital", quote(run.initial_capital)),
+        ("Final equity", quote(summary.final_equity)),
+        ("Total return", percent(summary.total_return)),
+        ("Buy and hold return", percent(summary.buy_and_hold_return)),
+        (
+            "Excess return",
+            percent(summary.total_return - summary.buy_and_hold_return),
+        ),
+        ("Max drawdown", percent(-summary.max_drawdown)),
+        ("Buy and hold max drawdown", percent(hold_drawdown)),
+        ("Trades", summary.trades.to_string()),
+        (
+            "Round trips won",
+            format!("{} of {}", summary.winning_round_trips, summary.round_trips),
+        ),
+        (
+            "Win rate",
+            summary.win_rate.map_or(String::from("-"), percent),
+        ),
+        ("Fee rate", percent(run.fee_rate)),
+        ("Fees paid", quote(summary.fees_paid)),
+        ("Best month", month_label(best_month)),
+        ("Worst month", month_label(worst_month)),
+        (
+            "Positive months",
+            format!("{} of {}", positive_months, report.monthly_returns.len()),
+        ),
+    ]
+}
+
+/// One figure of a report
+pub struct ReportPlot<'a> {
+    pub report: &'a BacktestReport,
+    pub figure: ReportFigure,
+    pub theme: Theme,
+}
+
+impl Drawing for ReportPlot<'_> {
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> Result<(), MeshetarError> {
+        root.fill(&self.theme.background()).map_err(report_error)?;
+        match self.figure {
+            ReportFigure::Equity => draw_equity(root, self.report, self.theme),
+            ReportFigure::Drawdown => draw_drawdown(root, self.report, self.theme),
+            ReportFigure::Trades => draw_trades(root, self.report, self.theme),
+            ReportFigure::MonthlyReturns => draw_monthly_returns(root, self.report, self.theme),
+        }
+    }
+}
+
+/// Time axis of the equity, a minute long at least
+fn equity_range(report: &BacktestReport) -> Result<std::ops::Range<DateTime<Utc>>, MeshetarError> {
+    let equity = &report.detail.equity;
+    let (Some(first), Some(last)) = (equity.first(), equity.last()) else {
+        return Err(MeshetarError::NotFound(format!(
+            "Backtest run {} has no equity to plot.",
+            report.detail.run.id
+        )));
+    };
+    let from = timestamp_to_dt(first.time / 1000);
+    let to = timestamp_to_dt(last.time / 1000).max(from + Duration::minutes(1));
+    Ok(from..to)
+}
+
+fn draw_equity<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    report: &BacktestReport,
+    theme: Theme,
+) -> Result<(), MeshetarError> {
+    let foreground = theme.foreground();
+    let text_style = TextStyle::from(("sans-serif", 18).into_font()).color(&foreground);
+    let equity = &report.detail.equity;
+    let time_range = equity_range(report)?;
+    let (min, max) = equity
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), point| {
+            (
+                min.min(point.equity).min(point.buy_and_hold),
+                max.max(point.equity).max(point.buy_and_hold),
+            )
+        });
+    let margin = ((max - min) * 0.05).max(f64::EPSILON);
+    let mut chart = ChartBuilder::on(root)
+        .caption("Equity against buy and hold", text_style.clone())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(100)
+        .build_cartesian_2d(time_range, min - margin..max + margin)
+        .map_err(report_error)?;
+    chart
+        .configure_mesh()
+        .label_style(text_style.clone())
+        .light_line_style(foreground.mix(0.05))
+        .bold_line_style(foreground.mix(0.2))
+        .axis_style(foreground)
+        .x_labels(7)
+        .x_label_formatter(&|x| dt_to_readable(*x))
+        .y_label_formatter(&|y| format!("{:.2}", y))
+        .draw()
+        .map_err(report_error)?;
+    let lines = [
+        (
+            "Backtest",
+            full_palette::LIGHTGREEN,
+            equity
+                .iter()
+                .map(|point| (timestamp_to_dt(point.time / 1000), point.equity))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "Buy and hold",
+            full_palette::GREY,
+            equity
+                .iter()
+                .map(|point| (timestamp_to_dt(point.time / 1000), point.buy_and_hold))
+                .collect::<Vec<_>>(),
+        ),
+    ];
+    for (label, color, points) in lines {
+        chart
+            .draw_series(LineSeries::new(points, color.stroke_width(2)))
+            .map_err(report_error)?
+            .label(label)
+            .legend(move |(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+            });
+    }
+    chart
+        .configure_series_labels()
+        .label_font(text_style)
+        .background_style(theme.background().mix(0.8))
+        .border_style(foreground.mix(0.2))
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()
+        .map_err(report_error)
+}
+
+fn draw_drawdown<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    report: &BacktestReport,
+    theme: Theme,
+) -> Result<(), MeshetarError> {
+    let foreground = theme.foreground();
+    let text_style = TextStyle::from(("sans-serif", 18).into_font()).color(&foreground);
+    let equity = &report.detail.equity;
+    let time_range = equity_range(report)?;
+    let times: Vec<DateTime<Utc>> = equity
+        .iter()
+        .map(|point| timestamp_to_dt(point.time / 1000))
+        .collect();
+    let strategy = drawdowns(equity.iter().map(|point| point.equity));
+    let hold = drawdowns(equity.iter().map(|point| point.buy_and_hold));
+    let deepest = strategy
+        .iter()
+        .chain(hold.iter())
+        .fold(0.0, |deepest: f64, drawdown| deepest.min(*drawdown))
+        .min(-0.01)
+        * 100.0;
+    let mut chart = ChartBuilder::on(root)
+        .caption("Drawdown %", text_style.clone())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(100)
+        .build_cartesian_2d(time_range, deepest * 1.05..0.0)
+        .map_err(report_error)?;
+    chart
+        .configure_mesh()
+        .label_style(text_style)
+        .light_line_style(foreground.mix(0.05))
+        .bold_line_style(foreground.mix(0.2))
+        .axis_style(foreground)
+        .x_labels(7)
+        .x_label_formatter(&|x| dt_to_readable(*x))
+        .y_labels(4)
+        .y_label_formatter(&|y| format!("{:.1}", y))
+        .draw()
+        .map_err(report_error)?;
+    chart
+        .draw_series(AreaSeries::new(
+            times
+                .iter()
+                .zip(strategy.iter())
+                .map(|(time, drawdown)| (*time, drawdown * 100.0)),
+            0.0,
+            RED.mix(0.4),
+        ))
+        .map_err(report_error)?;
+    chart
+        .draw_series(LineSeries::new(
+            times
+                .iter()
+                .zip(hold.iter())
+                .map(|(time, drawdown)| (*time, drawdown * 100.0)),
+            full_palette::GREY.stroke_width(1),
+        ))
+        .map_err(report_error)?;
+    Ok(())
+}
+
+fn draw_trades<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    report: &BacktestReport,
+    theme: Theme,
+) -> Result<(), MeshetarError> {
+    let foreground = theme.foreground();
+    let text_style = TextStyle::from(("sans-serif", 18).into_font()).color(&foreground);
+    let candles = &report.candles.candles;
+    // Klines of old runs may be compacted away, the rest of the report is still drawn
+    let (Some(first), Some(last)) = (candles.first(), candles.last()) else {
+        let message = format!(
+            "No klines left of backtest run {} to plot its trades on.",
+            report.detail.run.id
+        );
+        let (width, height) = root.dim_in_pixel();
+        return root
+            .draw(&Text::new(
+                message,
+                (width as i32 / 2, height as i32 / 2),
+                text_style.pos(Pos::new(HPos::Center, VPos::Center)),
+            ))
+            .map_err(report_error);
+    };
+    // A candle of padding on both sides
+    let candle_ms = report.candles.candle_ms.unwrap_or_else(|| {
+        Interval::from_kline_interval(&report.detail.run.interval)
+            .map_or(60000, |interval| interval.duration_ms())
+    });
+    let from = timestamp_to_dt(first.open_time / 1000) - Duration::milliseconds(candle_ms);
+    let to = timestamp_to_dt(last.open_time / 1000) + Duration::milliseconds(candle_ms);
+    let (min, max) = candles
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), candle| {
+            (min.min(candle.low), max.max(candle.high))
+        });
+    let margin = ((max - min) * 0.05).max(f64::EPSILON);
+    let caption = format!(
+        "Trades on {} {}{}",
+        report.detail.run.symbol,
+        report.detail.run.interval,
+        match report.candles.candle_ms {
+            Some(candle_ms) => format!(", {} minute candles", candle_ms / 60000),
+            None => String::new(),
+        }
+    );
+    let mut chart = ChartBuilder::on(root)
+        .caption(caption, text_style.clone())
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(100)
+        .build_cartesian_2d(from..to, min - margin..max + margin)
+        .map_err(report_error)?;
+    chart
+        .configure_mesh()
+        .label_style(text_style)
+        .light_line_style(foreground.mix(0.05))
+        .bold_line_style(foreground.mix(0.2))
+        .axis_style(foreground)
+        .x_labels(7)
+        .x_label_formatter(&|x| dt_to_readable(*x))
+        .y_label_formatter(&|y| format!("{:.4}", y))
+        .draw()
+        .map_err(report_error)?;
+    let candle_width = (900 / candles.len().max(1) as u32).clamp(1, 8);
+    chart
+        .draw_series(candles.iter().map(|candle| {
+            CandleStick::new(
+                timestamp_to_dt(candle.open_time / 1000),
+                candle.open,
+                candle.high,
+                candle.low,
+                candle.close,
+                GREEN.mix(0.6).filled(),
+                RED.mix(0.6),
+                candle_width,
+            )
+        }))
+        .map_err(report_error)?;
+    // Buys point up at their price from below, sells down from above
+    chart
+        .draw_series(report.detail.trades.iter().map(|trade| {
+            let (direction, color) = if trade.side == "BUY" {
+                (1, GREEN)
+            } else {
+                (-1, RED)
+            };
+            EmptyElement::at((timestamp_to_dt(trade.time / 1000), trade.price))
+                + Polygon::new(
+                    vec![(0, 0), (-6, 12 * direction), (6, 12 * direction)],
+                    color.filled(),
+                )
+        }))
+        .map_err(report_error)?;
+    Ok(())
+}
+
+/// Green for gains and red for losses, stronger the further the return is from 0
+fn heat_color(value: f64, largest: f64, background: RGBColor) -> RGBColor {
+    let target = if value >= 0.0 {
+        RGBColor(46, 160, 67)
+    } else {
+        RGBColor(218, 54, 51)
+    };
+    let share = (value.abs() / largest).clamp(0.0, 1.0) * 0.85 + 0.15;
+    let blend = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * share) as u8;
+    RGBColor(
+        blend(background.0, target.0),
+        blend(background.1, target.1),
+        blend(background.2, target.2),
+    )
+}
+
+fn draw_monthly_returns<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    report: &BacktestReport,
+    theme: Theme,
+) -> Result<(), MeshetarError> {
+    let foreground = theme.foreground();
+    let text_style = TextStyle::from(("sans-serif", 16).into_font()).color(&foreground);
+    let area = root
+        .titled(
+            "Monthly returns",
+            ("sans-serif", 18).into_font().color(&foreground),
+        )
+        .map_err(report_error)?;
+    let years = report_years(report);
+    if years.is_empty() {
+        return Ok(());
+    }
+    let (width, height) = area.dim_in_pixel();
+    let (left, top) = (70, 30);
+    let cell_width = (width as i32 - left - 10) / 12;
+    let cell_height = ((height as i32 - top - 10) / years.len() as i32).min(48);
+    let largest = report
+        .monthly_returns
+        .iter()
+        .map(|month| month.strategy.abs())
+        .fold(f64::EPSILON, f64::max);
+    let centered = text_style.pos(Pos::new(HPos::Center, VPos::Center));
+
+    for (column, month) in MONTHS.iter().enumerate() {
+        let x = left + cell_width * column as i32 + cell_width / 2;
+        area.draw(&Text::new(*month, (x, top / 2), centered.clone()))
+            .map_err(report_error)?;
+    }
+    for (row, year) in years.iter().enumerate() {
+        let y = top + cell_height * row as i32;
+        area.draw(&Text::new(
+            year.to_string(),
+            (left / 2, y + cell_height / 2),
+            centered.clone(),
+        ))
+        .map_err(report_error)?;
+        for month in report
+            .monthly_returns
+            .iter()
+            .filter(|month| month.year == *year)
+        {
+            let x = left + cell_width * (month.month as i32 - 1);
+            area.draw(&Rectangle::new(
+                [(x + 1, y + 1), (x + cell_width - 1, y + cell_height - 1)],
+                heat_color(month.strategy, largest, theme.background()).filled(),
+            ))
+            .map_err(report_error)?;
+            area.draw(&Text::new(
+                format!("{:.1}%", month.strategy * 100.0),
+                (x + cell_width / 2, y + cell_height / 2),
+                centered.clone(),
+            ))
+            .map_err(report_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// A figure of the report as SVG or PNG, sized to fit it
+pub fn render_figure(
+    report: &BacktestReport,
+    figure: ReportFigure,
+    format: ImageFormat,
+    theme: Theme,
+) -> Result<Vec<u8>, MeshetarError> {
+    render::render(
+        &ReportPlot {
+            report,
+            figure,
+            theme,
+        },
+        format,
+        figure.size(report),
+    )
+}
+
+fn hex(color: RGBColor) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2)
+}
+
+/// The report as a single HTML page, the stats table with the figures inlined as SVG
+pub fn html(report: &BacktestReport, theme: Theme) -> Result<String, MeshetarError> {
+    let run = &report.detail.run;
+    let rows: String = stats(report)
+        .iter()
+        .map(|(name, value)| format!("<tr><th>{}</th><td>{}</td></tr>", name, value))
+        .collect();
+    let mut figures = String::new();
+    for figure in ReportFigure::iter() {
+        let svg = render_figure(report, figure, ImageFormat::Svg, theme)?;
+        figures.push_str(&format!(
+            "<figure>{}</figure>",
+            String::from_utf8_lossy(&svg)
+        ));
+    }
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Backtest {id} {symbol} {interval}</title>
+<style>
+body {{ background: {background}; color: {foreground}; font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+th, td {{ padding: 0.3em 1em; border-bottom: 1px solid {foreground}22; text-align: left; }}
+td {{ text-align: right; }}
+figure {{ margin: 0 0 1.5em 0; }}
+figure svg {{ max-width: 100%; height: auto; }}
+</style>
+</head>
+<body>
+<h1>Backtest {id}: {symbol} {interval}</h1>
+<p>Created {created_at}</p>
+<table>{rows}</table>
+{figures}
+</body>
+</html>
+"#,
+        id = run.id,
+        symbol = run.symbol,
+        interval = run.interval,
+        created_at = run.created_at,
+        background = hex(theme.background()),
+        foreground = hex(theme.foreground()),
+        rows = rows,
+        figures = figures,
+    ))
+}
+
+/// Stats table as CSV
+fn stats_csv(report: &BacktestReport) -> String {
+    let mut csv = String::from("stat,value\n");
+    for (name, value) in stats(report) {
+        csv.push_str(&format!("{},\"{}\"\n", name, value));
+    }
+    csv
+}
+
+/// Zip of the HTML report, the stats as CSV and every figure in the format
+pub fn bundle(
+    report: &BacktestReport,
+    format: ImageFormat,
+    theme: Theme,
+) -> Result<Vec<u8>, MeshetarError> {
+    let mut files = vec![
+        (
+            String::from("report.html"),
+            html(report, theme)?.into_bytes(),
+        ),
+        (String::from("stats.csv"), stats_csv(report).into_bytes()),
+    ];
+    for figure in ReportFigure::iter() {
+        files.push((
+            format!("{}.{}", figure, format),
+            render_figure(report, figure, format, theme)?,
+        ));
+    }
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, bytes) in files {
+        // PNGs are compressed already
+        let method = if name.ends_with(".png") {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        zip.start_file(
+            format!("backtest_{}/{}", report.detail.run.id, name),
+            FileOptions::default().compression_method(method),
+        )
+        .map_err(bundle_error)?;
+        zip.write_all(&bytes).map_err(bundle_error)?;
+    }
+    let zip = zip.finish().map_err(bundle_error)?;
+    Ok(zip.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trading::backtesting::{BacktestRun, BacktestTrade};
+    use chrono::TimeZone;
+    use std::io::Read;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    fn point(
+        (year, month, day, hour, minute): (i32, u32, u32, u32, u32),
+        equity: f64,
+        buy_and_hold: f64,
+    ) -> BacktestPoint {
+        BacktestPoint {
+            time: Utc
+                .with_ymd_and_hms(year, month, day, hour, minute, 0)
+                .unwrap()
+                .timestamp_millis(),
+            equity,
+            buy_and_hold,
+        }
+    }
+
+    /// Two points in January, the last a minute before February, and one in February and March
+    fn equity() -> Vec<BacktestPoint> {
+        vec![
+            point((2024, 1, 15, 12, 0), 1100.0, 1050.0),
+            point((2024, 1, 31, 23, 59), 1200.0, 1100.0),
+            point((2024, 2, 1, 0, 0), 1140.0, 990.0),
+            point((2024, 3, 10, 8, 30), 1254.0, 1089.0),
+        ]
+    }
+
+    fn report(candles: Vec<candles::Candle>) -> BacktestReport {
+        let equity = equity();
+        let monthly_returns = monthly_returns(&equity, 1000.0);
+        BacktestReport {
+            detail: BacktestDetail {
+                run: BacktestRun {
+                    id: 7,
+                    created_at: String::from("2024-03-11T00:00:00+00:00"),
+                    symbol: String::from("BTCUSDT"),
+                    interval: String::from("3m"),
+                    source: String::from("backtest"),
+                    from_time: equity[0].time,
+                    to_time: equity[3].time,
+                    fee_rate: 0.001,
+                    initial_capital: 1000.0,
+                    summary: String::new(),
+                },
+                trades: vec![BacktestTrade {
+                    time: equity[0].time,
+                    side: String::from("BUY"),
+                    price: 100.0,
+                    quantity: 9.99,
+                    fee: 1.0,
+                }],
+                equity,
+            },
+            summary: BacktestSummary {
+                klines: 4,
+                final_equity: 1254.0,
+                total_return: 0.254,
+                buy_and_hold_return: 0.089,
+                max_drawdown: 0.05,
+                trades: 2,
+                round_trips: 2,
+                winning_round_trips: 1,
+                win_rate: Some(0.5),
+                fees_paid: 2.5,
+            },
+            candles: CandleSeries {
+                symbol: String::from("BTCUSDT"),
+                interval: String::from("3m"),
+                from: 0,
+                to: 0,
+                candle_ms: None,
+                candles,
+                signals: Vec::new(),
+            },
+            monthly_returns,
+        }
+    }
+
+    #[test]
+    fn months_start_from_the_end_of_the_month_before() {
+        let months = monthly_returns(&equity(), 1000.0);
+        let periods: Vec<(i32, u32)> = months
+            .iter()
+            .map(|month| (month.year, month.month))
+            .collect();
+        assert_eq!(periods, vec![(2024, 1), (2024, 2), (2024, 3)]);
+        // January from the initial capital
+        assert_close(months[0].strategy, 0.2);
+        assert_close(months[0].buy_and_hold, 0.1);
+        // February from January's last point, a minute before it
+        assert_close(months[1].strategy, 1140.0 / 1200.0 - 1.0);
+        assert_close(months[1].buy_and_hold, -0.1);
+        assert_close(months[2].strategy, 0.1);
+        assert_close(months[2].buy_and_hold, 0.1);
+        assert!(monthly_returns(&[], 1000.0).is_empty());
+    }
+
+    #[test]
+    fn drawdowns_fall_from_the_highest_value_before() {
+        let falls = drawdowns([100.0, 120.0, 90.0, 130.0, 65.0].into_iter());
+        for (actual, expected) in falls.iter().zip([0.0, 0.0, -0.25, 0.0, -0.5]) {
+            assert_close(*actual, expected);
+        }
+        // Nothing to fall from without a positive peak
+        assert_eq!(drawdowns([0.0, 0.0].into_iter()), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn stats_of_a_run() {
+        let stats = stats(&report(Vec::new()));
+        let stat = |name: &str| {
+            stats
+                .iter()
+                .find(|(stat, _)| *stat == name)
+                .map(|(_, value)| value.as_str())
+                .unwrap()
+        };
+        assert_eq!(stat("Pair"), "BTCUSDT 3m");
+        assert_eq!(stat("Period"), "2024-01-15 12:00 to 2024-03-10 08:30 UTC");
+        assert_eq!(stat("Final equity"), "1254.00");
+        assert_eq!(stat("Total return"), "25.40%");
+        assert_eq!(stat("Excess return"), "16.50%");
+        assert_eq!(stat("Max drawdown"), "-5.00%");
+        // 990 after the buy and hold peak of 1100
+        assert_eq!(stat("Buy and hold max drawdown"), "-10.00%");
+        assert_eq!(stat("Round trips won"), "1 of 2");
+        assert_eq!(stat("Win rate"), "50.00%");
+        assert_eq!(stat("Best month"), "Jan 2024 (20.00%)");
+        assert_eq!(stat("Worst month"), "Feb 2024 (-5.00%)");
+        assert_eq!(stat("Positive months"), "2 of 3");
+    }
+
+    #[test]
+    fn bundles_a_run_without_klines_left() {
+        let zip = bundle(&report(Vec::new()), ImageFormat::Svg, Theme::Light).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zip)).unwrap();
+        let mut names: Vec<String> = archive.file_names().map(String::from).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "backtest_7/drawdown.svg",
+                "backtest_7/equity.svg",
+                "backtest_7/monthly_returns.svg",
+                "backtest_7/report.html",
+                "backtest_7/stats.csv",
+                "backtest_7/trades.svg",
+            ]
+        );
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        assert!(read("backtest_7/trades.svg").contains("No klines left of backtest run 7"));
+        assert!(read("backtest_7/stats.csv").contains("Total return,\"25.40%\"\n"));
+        assert!(read("backtest_7/report.html").contains("<h1>Backtest 7: BTCUSDT 3m</h1>"));
+    }
+}
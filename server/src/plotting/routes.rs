@@ -1,46 +1,327 @@
-use super::plot;
-use crate::trading::meshetar::Meshetar;
-use rocket::{form::Form, serde::json::Json, State};
+use super::{
+    candles::{self, CandleSeries},
+    plot::{self, ChartPlotData},
+    render::{self, ImageFormat, Theme},
+    report::{self, ReportFigure},
+};
+use crate::{
+    trading::{
+        meshetar::{Interval, Meshetar, Pair},
+        performance::{self, EquityCurve},
+    },
+    utils::{error::MeshetarError, load_config},
+};
+use rocket::{
+    form::Form,
+    http::{ContentType, Header},
+    request::{self, FromRequest, Request},
+    response::content::RawHtml,
+    serde::json::Json,
+    Responder, State,
+};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::hash_map::DefaultHasher,
+    convert::Infallible,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::Arc,
+};
 use tokio::sync::Mutex;
 
-#[derive(FromForm, Deserialize)]
-pub struct PlotChartPayload<'r> {
-    page: &'r str,
+#[derive(FromForm)]
+pub struct PlotChartQuery {
+    /// The selected pair and interval unless given
+    pair: Option<String>,
+    interval: Option<String>,
+    /// Range of the klines (epoch ms), pages count back from its newest klines
+    from: Option<i64>,
+    to: Option<i64>,
+    page: Option<i64>,
+    width: Option<u32>,
+    height: Option<u32>,
+    /// "svg" or "png"
+    format: Option<String>,
+    /// "dark" or "light"
+    theme: Option<String>,
 }
-#[derive(Serialize)]
-pub struct ChartPlotWithPagination {
-    path: String,
-    model_path: String,
-    page: i64,
-    total_pages: i64,
+
+/// Value of the request's `If-None-Match` header
+pub struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        request::Outcome::Success(IfNoneMatch(
+            request.headers().get_one("If-None-Match").map(String::from),
+        ))
+    }
+}
+
+impl IfNoneMatch {
+    fn matches(&self, etag: &str) -> bool {
+        self.0.as_ref().map_or(false, |tags| {
+            tags.split(',')
+                .map(|tag| tag.trim())
+                .any(|tag| tag == etag || tag == "*")
+        })
+    }
+}
+
+#[derive(Responder)]
+pub enum ChartImage {
+    Rendered(
+        Vec<u8>,
+        ContentType,
+        Header<'static>,
+        Header<'static>,
+        Header<'static>,
+    ),
+    #[response(status = 304)]
+    NotModified((), Header<'static>),
 }
-#[post("/plot_chart", data = "<data>")]
+
+fn parse_param<T: FromStr>(value: Option<&str>, name: &str) -> Result<Option<T>, MeshetarError> {
+    value
+        .map(|value| {
+            T::from_str(value).map_err(|_| {
+                MeshetarError::Validation(format!("Couldnt parse {} {}.", name, value))
+            })
+        })
+        .transpose()
+}
+
+/// Changes with the newest plotted kline, its prices while it is open, its signal, the plotted
+/// sentiment and anything that changes the drawing
+fn chart_etag(
+    pair: Pair,
+    interval: Interval,
+    format: ImageFormat,
+    theme: Theme,
+    size: (u32, u32),
+    query: &PlotChartQuery,
+    data: &ChartPlotData,
+) -> String {
+    let config = load_config::config().plot;
+    let mut hasher = DefaultHasher::new();
+    (
+        pair, interval, format, theme, size, query.from, query.to, data.page,
+    )
+        .hash(&mut hasher);
+    format!(
+        "{:?}{:?}{:?}",
+        config.overlays, config.panels, config.sentiment_source
+    )
+    .hash(&mut hasher);
+    data.signals.len().hash(&mut hasher);
+    // Sentiment of past klines changes when a source is fetched again
+    for (time, value) in data.sentiment.iter() {
+        (time.timestamp_millis(), value.to_bits()).hash(&mut hasher);
+    }
+    if let (Some((_, (open, high, low, close))), Some((_, volume))) =
+        (data.klines.last(), data.volumes.last())
+    {
+        [open, high, low, close, volume]
+            .map(|value| value.to_bits())
+            .hash(&mut hasher);
+    }
+    format!("\"{}-{:x}\"", data.last_open_time, hasher.finish())
+}
+
+/// Renders a page of candles in memory, of the selected pair and interval unless others are
+/// given. Answers 304 while the `If-None-Match` ETag still matches the newest kline.
+#[get("/plot_chart?<query..>")]
 pub async fn plot_chart(
     meshetar: &State<Arc<Mutex<Meshetar>>>,
-    data: Form<PlotChartPayload<'_>>,
-) -> Result<Json<ChartPlotWithPagination>, ()> {
-    let page = match data.page.parse::<i64>() {
-        Ok(page) => page,
-        Err(_) => 0,
+    if_none_match: IfNoneMatch,
+    query: PlotChartQuery,
+) -> Result<ChartImage, MeshetarError> {
+    let (selected_pair, selected_interval) = {
+        let meshetar = meshetar.lock().await;
+        (meshetar.pair, meshetar.interval)
+    };
+    let pair = parse_param::<Pair>(query.pair.as_deref(), "pair")?.unwrap_or(selected_pair);
+    let interval = parse_param::<Interval>(query.interval.as_deref(), "interval")?
+        .unwrap_or(selected_interval);
+    let format = parse_param::<ImageFormat>(query.format.as_deref(), "format")?.unwrap_or_default();
+    let theme = parse_param::<Theme>(query.theme.as_deref(), "theme")?.unwrap_or_default();
+    let (default_width, default_height) = plot::default_chart_size();
+    let size = (
+        query.width.unwrap_or(default_width),
+        query.height.unwrap_or(default_height),
+    );
+    render::validate_size(size.0, size.1)?;
+
+    let data = plot::generate_plot_data(
+        pair.to_string(),
+        interval.to_kline_interval().to_string(),
+        query.page.unwrap_or(1),
+        query.from,
+        query.to,
+    )
+    .await?;
+    let etag = chart_etag(pair, interval, format, theme, size, &query, &data);
+    if if_none_match.matches(&etag) {
+        return Ok(ChartImage::NotModified((), Header::new("ETag", etag)));
+    }
+    let (page, total_pages) = (data.page, data.total_pages);
+    let image = tokio::task::spawn_blocking(move || {
+        render::render(&plot::Chart { data: &data, theme }, format, size)
+    })
+    .await
+    .map_err(|e| MeshetarError::Internal(format!("Error rendering chart: {:?}", e)))??;
+    Ok(ChartImage::Rendered(
+        image,
+        image_content_type(format),
+        Header::new("ETag", etag),
+        Header::new("Cache-Control", "no-cache"),
+        Header::new("X-Pages", format!("{}/{}", page, total_pages)),
+    ))
+}
+
+/// Candles and signals of the selected pair and interval for the app's interactive chart.
+/// Times are epoch ms, the last `points_per_page` klines are returned without a range.
+#[get("/candles?<from>&<to>&<limit>")]
+pub async fn chart_candles(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Json<CandleSeries>, MeshetarError> {
+    let (pair, interval) = {
+        let meshetar = meshetar.lock().await;
+        (
+            meshetar.pair.to_string(),
+            meshetar.interval.to_kline_interval().to_string(),
+        )
     };
-    let meshetar = meshetar.lock().await;
-    let pair = meshetar.pair.to_string();
-    let interval = meshetar.interval.to_kline_interval().to_string();
-    drop(meshetar);
-    match plot::generate_plot_data(pair, interval, page).await {
-        Ok(chart_plot_data) => {
-            match plot::plot_chart(chart_plot_data.klines, chart_plot_data.signals).await {
-                Ok(path) => Ok(Json(ChartPlotWithPagination {
-                    path,
-                    model_path: "historical_trading_signals_model.svg".to_string(),
-                    page: chart_plot_data.page,
-                    total_pages: chart_plot_data.total_pages,
-                })),
-                Err(e) => Err(log::warn!("Error plotting chart. {e}")),
-            }
-        }
-        Err(e) => Err(log::warn!("Error plotting chart. {e}")),
+    Ok(Json(
+        candles::candles(&pair, &interval, from, to, limit).await?,
+    ))
+}
+
+fn image_content_type(format: ImageFormat) -> ContentType {
+    match format {
+        ImageFormat::Svg => ContentType::SVG,
+        ImageFormat::Png => ContentType::PNG,
     }
 }
+
+/// Runs the plotting of a report off the async workers
+async fn render_report<T: Send + 'static>(
+    run_id: i64,
+    draw: impl FnOnce(&report::BacktestReport) -> Result<T, MeshetarError> + Send + 'static,
+) -> Result<T, MeshetarError> {
+    let report = report::load(run_id).await?;
+    tokio::task::spawn_blocking(move || draw(&report))
+        .await
+        .map_err(|e| MeshetarError::Internal(format!("Error rendering backtest report: {:?}", e)))?
+}
+
+/// Report of a backtest or paper trading run as a page: stats and every figure
+#[get("/backtest/<run_id>/report?<theme>")]
+pub async fn backtest_report(
+    run_id: i64,
+    theme: Option<String>,
+) -> Result<RawHtml<String>, MeshetarError> {
+    let theme = parse_param::<Theme>(theme.as_deref(), "theme")?.unwrap_or_default();
+    Ok(RawHtml(
+        render_report(run_id, move |report| report::html(report, theme)).await?,
+    ))
+}
+
+/// One figure of a run's report: equity, drawdown, trades or monthly_returns
+#[get("/backtest/<run_id>/report/<figure>?<format>&<theme>")]
+pub async fn backtest_report_figure(
+    run_id: i64,
+    figure: &str,
+    format: Option<String>,
+    theme: Option<String>,
+) -> Result<(ContentType, Vec<u8>), MeshetarError> {
+    let figure = ReportFigure::from_str(figure).map_err(|_| {
+        MeshetarError::NotFound(format!("Backtest reports have no figure {}.", figure))
+    })?;
+    let format = parse_param::<ImageFormat>(format.as_deref(), "format")?.unwrap_or_default();
+    let theme = parse_param::<Theme>(theme.as_deref(), "theme")?.unwrap_or_default();
+    let image = render_report(run_id, move |report| {
+        report::render_figure(report, figure, format, theme)
+    })
+    .await?;
+    Ok((image_content_type(format), image))
+}
+
+#[derive(Responder)]
+#[response(content_type = "application/zip")]
+pub struct ReportBundle {
+    zip: Vec<u8>,
+    disposition: Header<'static>,
+}
+
+/// Zip of a run's report page, its stats as CSV and its figures as SVG or PNG
+#[get("/backtest/<run_id>/bundle?<format>&<theme>")]
+pub async fn backtest_report_bundle(
+    run_id: i64,
+    format: Option<String>,
+    theme: Option<String>,
+) -> Result<ReportBundle, MeshetarError> {
+    let format = parse_param::<ImageFormat>(format.as_deref(), "format")?.unwrap_or_default();
+    let theme = parse_param::<Theme>(theme.as_deref(), "theme")?.unwrap_or_default();
+    let zip = render_report(run_id, move |report| report::bundle(report, format, theme)).await?;
+    Ok(ReportBundle {
+        zip,
+        disposition: Header::new(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"backtest_{}_{}.zip\"",
+                run_id, format
+            ),
+        ),
+    })
+}
+
+#[derive(FromForm, Deserialize)]
+pub struct PlotEquityPayload {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+#[derive(Serialize)]
+pub struct EquityPlot {
+    curve: EquityCurve,
+}
+/// Equity curve over a range (epoch ms), the last 30 days by default. Its plot is served by
+/// `plot_equity_image` over the resolved range.
+#[post("/plot_equity", data = "<data>")]
+pub async fn plot_equity(data: Form<PlotEquityPayload>) -> Result<Json<EquityPlot>, MeshetarError> {
+    let (from, to) = performance::resolve_range(data.from, data.to);
+    let curve = performance::equity_curve(from, to, performance::DEFAULT_POINTS).await?;
+    Ok(Json(EquityPlot { curve }))
+}
+
+/// Renders the equity curve over a range (epoch ms) in memory, the last 30 days by default
+#[get("/plot_equity?<from>&<to>&<format>&<theme>")]
+pub async fn plot_equity_image(
+    from: Option<i64>,
+    to: Option<i64>,
+    format: Option<String>,
+    theme: Option<String>,
+) -> Result<(ContentType, Vec<u8>), MeshetarError> {
+    let format = parse_param::<ImageFormat>(format.as_deref(), "format")?.unwrap_or_default();
+    let theme = parse_param::<Theme>(theme.as_deref(), "theme")?.unwrap_or_default();
+    let (from, to) = performance::resolve_range(from, to);
+    let curve = performance::equity_curve(from, to, performance::DEFAULT_POINTS).await?;
+    let image = tokio::task::spawn_blocking(move || {
+        render::render(
+            &plot::Equity {
+                points: &curve.points,
+                theme,
+            },
+            format,
+            plot::EQUITY_SIZE,
+        )
+    })
+    .await
+    .map_err(|e| MeshetarError::Internal(format!("Error rendering equity curve: {:?}", e)))??;
+    Ok((image_content_type(format), image))
+}
@@ -0,0 +1,338 @@
+use super::provider::{self, SentimentPoint};
+use crate::{
+    model::feature_store,
+    utils::{
+        database::DB_POOL,
+        error::MeshetarError,
+        load_config::{self, SentimentSource},
+    },
+};
+use futures::TryFutureExt;
+use serde::Serialize;
+
+/// Sentiment known at a kline, `None` before the source's first value
+#[derive(Serialize, Debug)]
+pub struct KlineSentiment {
+    pub open_time: i64,
+    pub sentiment: Option<AlignedSentiment>,
+}
+
+/// Sentiment value known at a kline's open time
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct AlignedSentiment {
+    pub value: f64,
+    /// Change from the source's value before it
+    pub change: Option<f64>,
+    /// Time since the value became known
+    pub age_ms: i64,
+}
+
+async fn latest_time(source: &str) -> Result<Option<i64>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_scalar("SELECT MAX(time) FROM sentiment WHERE source = ?1")
+        .bind(source)
+        .fetch_one(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching sentiment. {:?}", e)))
+        .await
+}
+
+async fn save_points(source: &str, points: &[SentimentPoint]) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let mut tx = connection
+        .begin()
+        .map_err(|e| {
+            MeshetarError::Database(format!(
+                "Error on creating transaction on sentiment: {:?}",
+                e
+            ))
+        })
+        .await?;
+    for point in points {
+        sqlx::query("INSERT OR REPLACE INTO sentiment (source, time, value) VALUES (?1, ?2, ?3)")
+            .bind(source)
+            .bind(point.time)
+            .bind(point.value)
+            .execute(tx.as_mut())
+            .map_err(|e| MeshetarError::Database(format!("Error saving sentiment. {:?}", e)))
+            .await?;
+    }
+    tx.commit()
+        .map_err(|e| MeshetarError::Database(format!("Error committing sentiment: {:?}", e)))
+        .await
+}
+
+/// Stores the source's values from its newest stored one on, that one may have been revised.
+/// Feature rows of klines that knew the values since are removed, they were stored without
+/// them. Returns the number of values stored.
+pub async fn fetch_source(source: &SentimentSource) -> Result<usize, MeshetarError> {
+    let since = latest_time(&source.name).await?;
+    let points: Vec<SentimentPoint> = provider::for_source(source)
+        .fetch(since)
+        .await?
+        .into_iter()
+        .filter(|point| since.map_or(true, |since| point.time >= since))
+        .collect();
+    save_points(&source.name, &points).await?;
+    if let Some(first) = points.iter().map(|point| point.time).min() {
+        let set_name = format!("{}{}", feature_store::SENTIMENT_PREFIX, source.name);
+        let discarded = feature_store::discard_from(&set_name, first + source.lag_ms).await?;
+        log::debug!("Discarded {} feature rows of {}.", discarded, set_name);
+    }
+    log::info!(
+        "Stored {} values of sentiment source {}.",
+        points.len(),
+        source.name
+    );
+    Ok(points.len())
+}
+
+/// Fetches every configured source, a failing source does not stop the others
+pub async fn fetch_all() -> Vec<(String, Result<usize, MeshetarError>)> {
+    let mut results = Vec::new();
+    for source in load_config::config().sentiment.sources {
+        let result = fetch_source(&source).await;
+        results.push((source.name, result));
+    }
+    results
+}
+
+/// Fetches the sources periodically while sentiment is enabled
+pub async fn run() {
+    loop {
+        let config = load_config::config().sentiment;
+        if config.enabled {
+            for (source, result) in fetch_all().await {
+                if let Err(e) = result {
+                    log::warn!("Error fetching sentiment source {}: {}", source, e);
+                }
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(config.fetch_interval_ms)).await;
+    }
+}
+
+/// Values of the source known at the open times, the newest one whose time plus the source's
+/// lag is not after the open time. `open_times` have to be ascending.
+pub async fn aligned(
+    source: &SentimentSource,
+    open_times: &[i64],
+) -> Result<Vec<Option<AlignedSentiment>>, MeshetarError> {
+    let (Some(first), Some(last)) = (open_times.first(), open_times.last()) else {
+        return Ok(Vec::new());
+    };
+    let connection = DB_POOL.get().unwrap();
+    // From the value before the one known at the first open time, for its change
+    let points: Vec<(i64, f64)> = sqlx::query_as(
+        "SELECT time, value FROM sentiment
+        WHERE source = ?1 AND time <= ?3 AND time >= COALESCE(
+            (SELECT time FROM sentiment WHERE source = ?1 AND time <= ?2
+            ORDER BY time DESC LIMIT 1 OFFSET 1), 0)
+        ORDER BY time ASC",
+    )
+    .bind(&source.name)
+    .bind(first - source.lag_ms)
+    .bind(last - source.lag_ms)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching sentiment. {:?}", e)))
+    .await?;
+
+    let mut next = 0;
+    Ok(open_times
+        .iter()
+        .map(|open_time| {
+            while next < points.len() && points[next].0 + source.lag_ms <= *open_time {
+                next += 1;
+            }
+            let known = next.checked_sub(1)?;
+            let (time, value) = points[known];
+            Some(AlignedSentiment {
+                value,
+                change: known.checked_sub(1).map(|before| value - points[before].1),
+                age_ms: open_time - time - source.lag_ms,
+            })
+        })
+        .collect())
+}
+
+/// Values of the source known at the newest `limit` klines of the pair, oldest first
+pub async fn for_latest_klines(
+    source: &SentimentSource,
+    symbol: &str,
+    interval: &str,
+    limit: i64,
+) -> Result<Vec<KlineSentiment>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let mut open_times: Vec<i64> = sqlx::query_scalar(
+        "SELECT open_time FROM klines
+        WHERE symbol = ?1 AND interval = ?2
+        ORDER BY open_time DESC
+        LIMIT ?3",
+    )
+    .bind(symbol)
+    .bind(interval)
+    .bind(limit)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching klines. {:?}", e)))
+    .await?;
+    open_times.reverse();
+    Ok(open_times
+        .iter()
+        .zip(aligned(source, &open_times).await?)
+        .map(|(open_time, sentiment)| KlineSentiment {
+            open_time: *open_time,
+            sentiment,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{
+        database,
+        load_config::{SentimentProviderKind, TimeFormat},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    const DAY_MS: i64 = 86400000;
+    /// 2023-05-01 00:00 UTC
+    const MAY_1: i64 = 1682899200000;
+
+    /// Answers every request with the body and sends on the request line
+    async fn serve_json(body: &'static str) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let request = String::from_utf8_lossy(&request);
+                sender
+                    .send(request.lines().next().unwrap_or_default().to_string())
+                    .unwrap();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (format!("http://{}", address), receiver)
+    }
+
+    fn source(name: &str, url: String) -> SentimentSource {
+        SentimentSource {
+            name: name.to_string(),
+            provider: SentimentProviderKind::HttpJson,
+            url,
+            items_pointer: String::from("/data"),
+            time_field: String::from("date"),
+            value_field: String::from("mean"),
+            time_format: TimeFormat::Date,
+            lag_ms: DAY_MS,
+        }
+    }
+
+    async fn stored(name: &str) -> Vec<(i64, f64)> {
+        sqlx::query_as("SELECT time, value FROM sentiment WHERE source = ?1 ORDER BY time ASC")
+            .bind(name)
+            .fetch_all(DB_POOL.get().unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn fetches_http_json_and_aligns_with_the_lag() {
+        database::run_test(async {
+            let (url, mut requests) = serve_json(
+                r#"{"data": [
+                    {"date": "2023-05-01", "mean": "0.5"},
+                    {"date": "2023-05-02", "mean": 0.25},
+                    {"date": "not a date", "mean": 1.0},
+                    {"date": "2023-05-03"}
+                ]}"#,
+            )
+            .await;
+            let source = source("stubsentiment", format!("{}/values?since={{since}}", url));
+
+            assert_eq!(fetch_source(&source).await.unwrap(), 2);
+            assert_eq!(
+                requests.recv().await.unwrap(),
+                "GET /values?since=0 HTTP/1.1"
+            );
+            assert_eq!(
+                stored(&source.name).await,
+                vec![(MAY_1, 0.5), (MAY_1 + DAY_MS, 0.25)]
+            );
+
+            // Values are known a day after their date
+            let open_times = [
+                MAY_1 + DAY_MS / 2,
+                MAY_1 + DAY_MS,
+                MAY_1 + 2 * DAY_MS + DAY_MS / 4,
+            ];
+            let aligned = aligned(&source, &open_times).await.unwrap();
+            assert!(aligned[0].is_none());
+            let first = aligned[1].unwrap();
+            assert_eq!((first.value, first.change, first.age_ms), (0.5, None, 0));
+            let second = aligned[2].unwrap();
+            assert_eq!(
+                (second.value, second.change, second.age_ms),
+                (0.25, Some(-0.25), DAY_MS / 4)
+            );
+
+            // The next fetch asks from the newest stored value on
+            fetch_source(&source).await.unwrap();
+            assert_eq!(
+                requests.recv().await.unwrap(),
+                format!("GET /values?since={} HTTP/1.1", MAY_1 + DAY_MS)
+            );
+        });
+    }
+
+    #[test]
+    fn discards_feature_rows_stored_before_the_values_arrived() {
+        database::run_test(async {
+            let (url, _requests) =
+                serve_json(r#"{"data": [{"date": "2023-05-02", "mean": 0.75}]}"#).await;
+            let source = source("latesentiment", url);
+            let set_name = format!("{}{}", feature_store::SENTIMENT_PREFIX, source.name);
+            for open_time in [MAY_1 + DAY_MS, MAY_1 + 2 * DAY_MS, MAY_1 + 3 * DAY_MS] {
+                sqlx::query(
+                    "INSERT INTO features (symbol, interval, feature_set, version, open_time, feature_values)
+                    VALUES ('SENTIMENTUSDT', '1h', ?1, 1, ?2, '[null,null,null]')",
+                )
+                .bind(&set_name)
+                .bind(open_time)
+                .execute(DB_POOL.get().unwrap())
+                .await
+                .unwrap();
+            }
+
+            fetch_source(&source).await.unwrap();
+
+            let remaining: Vec<i64> = sqlx::query_scalar(
+                "SELECT open_time FROM features WHERE feature_set = ?1 ORDER BY open_time ASC",
+            )
+            .bind(&set_name)
+            .fetch_all(DB_POOL.get().unwrap())
+            .await
+            .unwrap();
+            assert_eq!(remaining, vec![MAY_1 + DAY_MS]);
+        });
+    }
+}
@@ -0,0 +1,3 @@
+pub mod ingestion;
+pub mod provider;
+pub mod routes;
@@ -0,0 +1,132 @@
+use crate::utils::{
+    error::MeshetarError,
+    load_config::{SentimentProviderKind, SentimentSource, TimeFormat},
+};
+use chrono::{DateTime, NaiveDate};
+use futures::TryFutureExt;
+use hyper_tls::HttpsConnector;
+use std::{future::Future, pin::Pin};
+
+/// Sentiment value at a time (epoch ms)
+#[derive(Debug, Clone, Copy)]
+pub struct SentimentPoint {
+    pub time: i64,
+    pub value: f64,
+}
+
+pub type ProviderFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<SentimentPoint>, MeshetarError>> + Send + 'a>>;
+
+/// Source of sentiment values, e.g. a fear and greed index
+pub trait SentimentProvider: Send + Sync {
+    /// Values of the source, at least the ones after `since` (epoch ms) when given
+    fn fetch(&self, since: Option<i64>) -> ProviderFuture<'_>;
+}
+
+pub fn for_source(source: &SentimentSource) -> Box<dyn SentimentProvider> {
+    match source.provider {
+        SentimentProviderKind::HttpJson => Box::new(HttpJsonProvider {
+            source: source.clone(),
+        }),
+    }
+}
+
+/// Fetches a JSON array of objects with a time and a value field. `{since}` in the url is
+/// replaced with the time of the newest stored value.
+pub struct HttpJsonProvider {
+    source: SentimentSource,
+}
+
+impl HttpJsonProvider {
+    fn provider_error<E: std::fmt::Debug>(&self, what: &str, e: E) -> MeshetarError {
+        MeshetarError::Provider(format!("{} {}: {:?}", what, self.source.name, e))
+    }
+
+    async fn fetch_points(&self, since: Option<i64>) -> Result<Vec<SentimentPoint>, MeshetarError> {
+        let url = self
+            .source
+            .url
+            .replace("{since}", &since.unwrap_or(0).to_string());
+        let uri: hyper::Uri = url
+            .parse()
+            .map_err(|e| self.provider_error("Invalid url of sentiment source", e))?;
+        let client = hyper::Client::builder().build::<_, hyper::Body>(HttpsConnector::new());
+        let response = client
+            .get(uri)
+            .map_err(|e| self.provider_error("Error requesting sentiment source", e))
+            .await?;
+        if !response.status().is_success() {
+            return Err(MeshetarError::Provider(format!(
+                "Sentiment source {} answered {}.",
+                self.source.name,
+                response.status()
+            )));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .map_err(|e| self.provider_error("Error reading sentiment source", e))
+            .await?;
+        let json: serde_json::Value = serde_json::from_slice(&body)
+            .map_err(|e| self.provider_error("Invalid JSON from sentiment source", e))?;
+        self.parse(&json)
+    }
+
+    fn parse(&self, json: &serde_json::Value) -> Result<Vec<SentimentPoint>, MeshetarError> {
+        let items = json
+            .pointer(&self.source.items_pointer)
+            .and_then(|items| items.as_array())
+            .ok_or(MeshetarError::Provider(format!(
+                "Sentiment source {} has no array at '{}'.",
+                self.source.name, self.source.items_pointer
+            )))?;
+        let points: Vec<SentimentPoint> = items
+            .iter()
+            .filter_map(|item| {
+                Some(SentimentPoint {
+                    time: parse_time(item.get(&self.source.time_field)?, self.source.time_format)?,
+                    value: parse_number(item.get(&self.source.value_field)?)?,
+                })
+            })
+            .collect();
+        if points.len() < items.len() {
+            log::warn!(
+                "Skipped {} of {} values of sentiment source {} without a valid {} or {}.",
+                items.len() - points.len(),
+                items.len(),
+                self.source.name,
+                self.source.time_field,
+                self.source.value_field
+            );
+        }
+        Ok(points)
+    }
+}
+
+impl SentimentProvider for HttpJsonProvider {
+    fn fetch(&self, since: Option<i64>) -> ProviderFuture<'_> {
+        Box::pin(self.fetch_points(since))
+    }
+}
+
+/// Numbers may come as JSON numbers or as strings
+fn parse_number(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+    .filter(|number| number.is_finite())
+}
+
+fn parse_time(value: &serde_json::Value, format: TimeFormat) -> Option<i64> {
+    match format {
+        TimeFormat::EpochMs => parse_number(value).map(|ms| ms as i64),
+        TimeFormat::EpochS => parse_number(value).map(|s| (s * 1000.0) as i64),
+        TimeFormat::Date => NaiveDate::parse_from_str(value.as_str()?, "%Y-%m-%d")
+            .ok()?
+            .and_hms_opt(0, 0, 0)
+            .map(|time| time.timestamp_millis()),
+        TimeFormat::Rfc3339 => DateTime::parse_from_rfc3339(value.as_str()?)
+            .ok()
+            .map(|time| time.timestamp_millis()),
+    }
+}
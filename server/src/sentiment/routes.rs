@@ -0,0 +1,68 @@
+use super::ingestion::{self, KlineSentiment};
+use crate::{
+    trading::meshetar::Meshetar,
+    utils::{error::MeshetarError, load_config},
+};
+use rocket::{serde::json::Json, State};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Serialize)]
+pub struct SourceFetch {
+    source: String,
+    stored: Option<usize>,
+    error: Option<String>,
+}
+
+/// Fetches every configured source now, also while the periodic fetching is disabled
+#[post("/sentiment/fetch")]
+pub async fn fetch_sentiment() -> Json<Vec<SourceFetch>> {
+    Json(
+        ingestion::fetch_all()
+            .await
+            .into_iter()
+            .map(|(source, result)| SourceFetch {
+                source,
+                stored: result.as_ref().ok().copied(),
+                error: result.err().map(|e| e.to_string()),
+            })
+            .collect(),
+    )
+}
+
+/// Sentiment known at the newest klines of the selected pair, of the plotted source unless
+/// another one is given
+#[get("/sentiment?<source>&<limit>")]
+pub async fn kline_sentiment(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    source: Option<String>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<KlineSentiment>>, MeshetarError> {
+    let meshetar = meshetar.lock().await;
+    let symbol = meshetar.pair.to_string();
+    let interval = meshetar.interval.to_kline_interval().to_string();
+    drop(meshetar);
+    let config = load_config::config();
+    let name = source
+        .or(config.plot.sentiment_source.clone())
+        .or(config
+            .sentiment
+            .sources
+            .first()
+            .map(|source| source.name.clone()))
+        .ok_or(MeshetarError::NotFound(String::from(
+            "No sentiment source is configured.",
+        )))?;
+    let source = config
+        .sentiment
+        .source(&name)
+        .ok_or(MeshetarError::NotFound(format!(
+            "Sentiment source {} is not configured.",
+            name
+        )))?;
+    let limit = limit.unwrap_or(100);
+    Ok(Json(
+        ingestion::for_latest_klines(source, &symbol, &interval, limit).await?,
+    ))
+}
@@ -1,3 +1,454 @@
-pub fn test() -> Result<(), String> {
-    todo!();
+use super::{fees, trade::OrderSide};
+use crate::utils::{database::DB_POOL, error::MeshetarError, load_config};
+use chrono::{DateTime, Utc};
+use futures::TryFutureExt;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use strum::{Display, EnumString};
+
+/// Signals a backtest trades on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum BacktestSource {
+    /// Signals the running model stored, a paper trading run of the model
+    Signals,
+    /// Optimal signals of the labelling, the most a model could make of the klines
+    Labels,
+}
+
+impl BacktestSource {
+    /// Signal of each kline in the source's table, lowercase
+    fn join(&self) -> &'static str {
+        match self {
+            BacktestSource::Signals => {
+                "LEFT JOIN signals s
+                ON s.symbol = k.symbol AND s.interval = k.interval AND s.time = k.open_time"
+            }
+            BacktestSource::Labels => {
+                "LEFT JOIN (
+                    SELECT symbol, interval, open_time AS time, label AS signal FROM labels
+                ) s ON s.symbol = k.symbol AND s.interval = k.interval AND s.time = k.open_time"
+            }
+        }
+    }
+}
+
+/// Close of a kline and the signal it got, if any
+#[derive(FromRow, Debug)]
+struct SignalCandle {
+    time: i64,
+    close: f64,
+    signal: Option<String>,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct BacktestTrade {
+    pub time: i64,
+    pub side: String,
+    pub price: f64,
+    /// Base asset bought or sold, a buy's fee is taken from it
+    pub quantity: f64,
+    /// In the quote asset
+    pub fee: f64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct BacktestPoint {
+    pub time: i64,
+    /// Quote asset plus the position at the kline's close
+    pub equity: f64,
+    /// Equity of buying at the first close and holding, after the fee of the buy
+    pub buy_and_hold: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BacktestSummary {
+    pub klines: usize,
+    pub final_equity: f64,
+    pub total_return: f64,
+    pub buy_and_hold_return: f64,
+    /// Largest fall of the equity from its highest point before, as a share of it
+    pub max_drawdown: f64,
+    pub trades: usize,
+    /// Buys followed by a sell
+    pub round_trips: usize,
+    pub winning_round_trips: usize,
+    pub win_rate: Option<f64>,
+    /// In the quote asset
+    pub fees_paid: f64,
+}
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct BacktestRun {
+    pub id: i64,
+    pub created_at: String,
+    pub symbol: String,
+    pub interval: String,
+    pub source: String,
+    pub from_time: i64,
+    pub to_time: i64,
+    /// Commission rate of every trade
+    pub fee_rate: f64,
+    pub initial_capital: f64,
+    /// JSON of the `BacktestSummary`
+    pub summary: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BacktestDetail {
+    pub run: BacktestRun,
+    pub trades: Vec<BacktestTrade>,
+    pub equity: Vec<BacktestPoint>,
+}
+
+struct Simulation {
+    fee_rate: f64,
+    initial_capital: f64,
+    trades: Vec<BacktestTrade>,
+    equity: Vec<BacktestPoint>,
+    summary: BacktestSummary,
+}
+
+/// Trades the signals at the close of their klines: a buy spends all of the quote asset and a
+/// sell sells the whole position, both paying `fee_rate`. Signals that can't be followed, a buy
+/// while holding or a sell while not, are skipped.
+fn simulate(candles: &[SignalCandle], fee_rate: f64, initial_capital: f64) -> Simulation {
+    let mut quote = initial_capital;
+    let mut base = 0.0;
+    // Quote asset spent on the open position, fee included
+    let mut entry_cost = 0.0;
+    let mut trades = Vec::new();
+    let mut equity = Vec::with_capacity(candles.len());
+    let mut summary = BacktestSummary {
+        klines: candles.len(),
+        ..Default::default()
+    };
+    let held = candles.first().map_or(0.0, |first| {
+        initial_capital * (1.0 - fee_rate) / first.close
+    });
+    let mut peak = initial_capital;
+
+    for candle in candles {
+        match candle.signal.as_deref() {
+            Some("buy") if base == 0.0 && quote > 0.0 => {
+                let fee = quote * fee_rate;
+                base = (quote - fee) / candle.close;
+                entry_cost = quote;
+                quote = 0.0;
+                summary.fees_paid += fee;
+                trades.push(BacktestTrade {
+                    time: candle.time,
+                    side: OrderSide::Buy.to_string(),
+                    price: candle.close,
+                    quantity: base,
+                    fee,
+                });
+            }
+            Some("sell") if base > 0.0 => {
+                let proceeds = base * candle.close;
+                let fee = proceeds * fee_rate;
+                quote = proceeds - fee;
+                summary.fees_paid += fee;
+                summary.round_trips += 1;
+                if quote > entry_cost {
+                    summary.winning_round_trips += 1;
+                }
+                trades.push(BacktestTrade {
+                    time: candle.time,
+                    side: OrderSide::Sell.to_string(),
+                    price: candle.close,
+                    quantity: base,
+                    fee,
+                });
+                base = 0.0;
+            }
+            _ => (),
+        }
+        let value = quote + base * candle.close;
+        peak = peak.max(value);
+        summary.max_drawdown = summary.max_drawdown.max((peak - value) / peak);
+        equity.push(BacktestPoint {
+            time: candle.time,
+            equity: value,
+            buy_and_hold: held * candle.close,
+        });
+    }
+
+    summary.trades = trades.len();
+    summary.win_rate = (summary.round_trips > 0)
+        .then(|| summary.winning_round_trips as f64 / summary.round_trips as f64);
+    summary.final_equity = equity.last().map_or(initial_capital, |point| point.equity);
+    summary.total_return = summary.final_equity / initial_capital - 1.0;
+    summary.buy_and_hold_return = equity
+        .last()
+        .map_or(0.0, |point| point.buy_and_hold / initial_capital - 1.0);
+    Simulation {
+        fee_rate,
+        initial_capital,
+        trades,
+        equity,
+        summary,
+    }
+}
+
+async fn signal_candles(
+    symbol: &str,
+    interval: &str,
+    source: BacktestSource,
+    from: i64,
+    to: i64,
+) -> Result<Vec<SignalCandle>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as(&format!(
+        "SELECT k.open_time AS time, k.close, LOWER(s.signal) AS signal
+        FROM klines k
+        {}
+        WHERE k.symbol = ?1 AND k.interval = ?2 AND k.open_time BETWEEN ?3 AND ?4
+        ORDER BY k.open_time ASC",
+        source.join()
+    ))
+    .bind(symbol)
+    .bind(interval)
+    .bind(from)
+    .bind(to)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching klines to backtest. {:?}", e)))
+    .await
+}
+
+async fn save_run(
+    symbol: &str,
+    interval: &str,
+    source: BacktestSource,
+    from: i64,
+    to: i64,
+    simulation: &Simulation,
+) -> Result<i64, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let summary = serde_json::to_string(&simulation.summary).map_err(|e| {
+        MeshetarError::Internal(format!("Error serializing backtest summary: {:?}", e))
+    })?;
+    let mut tx = connection
+        .begin()
+        .map_err(|e| {
+            MeshetarError::Database(format!(
+                "Error on creating transaction on backtests: {:?}",
+                e
+            ))
+        })
+        .await?;
+    let run_id: i64 = sqlx::query_scalar(
+        "INSERT INTO backtest_runs
+            (created_at, symbol, interval, source, from_time, to_time, fee_rate, initial_capital, summary)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) RETURNING id",
+    )
+    .bind(DateTime::to_rfc3339(&Utc::now()))
+    .bind(symbol)
+    .bind(interval)
+    .bind(source.to_string())
+    .bind(from)
+    .bind(to)
+    .bind(simulation.fee_rate)
+    .bind(simulation.initial_capital)
+    .bind(summary)
+    .fetch_one(tx.as_mut())
+    .map_err(|e| MeshetarError::Database(format!("Error creating backtest run. {:?}", e)))
+    .await?;
+    for trade in &simulation.trades {
+        sqlx::query(
+            "INSERT INTO backtest_trades (run_id, time, side, price, quantity, fee)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )
+        .bind(run_id)
+        .bind(trade.time)
+        .bind(&trade.side)
+        .bind(trade.price)
+        .bind(trade.quantity)
+        .bind(trade.fee)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error saving backtest trade. {:?}", e)))
+        .await?;
+    }
+    for point in &simulation.equity {
+        sqlx::query(
+            "INSERT INTO backtest_equity (run_id, time, equity, buy_and_hold)
+            VALUES (?1, ?2, ?3, ?4)",
+        )
+        .bind(run_id)
+        .bind(point.time)
+        .bind(point.equity)
+        .bind(point.buy_and_hold)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error saving backtest equity. {:?}", e)))
+        .await?;
+    }
+    tx.commit()
+        .map_err(|e| MeshetarError::Database(format!("Error committing backtest run: {:?}", e)))
+        .await?;
+    Ok(run_id)
+}
+
+/// Backtests the source's signals over the stored klines between `from` and `to` (epoch ms)
+/// and stores the run. Trades pay `fee_rate`, the account's market order rate by default.
+pub async fn run(
+    symbol: &str,
+    interval: &str,
+    source: BacktestSource,
+    from: i64,
+    to: i64,
+    fee_rate: Option<f64>,
+) -> Result<BacktestDetail, MeshetarError> {
+    let fee_rate = match fee_rate {
+        Some(fee_rate) if (0.0..1.0).contains(&fee_rate) => fee_rate,
+        Some(fee_rate) => {
+            return Err(MeshetarError::Validation(format!(
+                "Fee rate {} must be at least 0 and below 1.",
+                fee_rate
+            )))
+        }
+        None => fees::current().await?.market_order(),
+    };
+    let candles = signal_candles(symbol, interval, source, from, to).await?;
+    if candles.is_empty() {
+        return Err(MeshetarError::Validation(format!(
+            "No {} {} klines to backtest in the range.",
+            symbol, interval
+        )));
+    }
+    let initial_capital = load_config::config().backtest.initial_capital;
+    let simulation = simulate(&candles, fee_rate, initial_capital);
+    log::info!(
+        "Backtested {} {} on {}: return {:.4}, buy and hold {:.4}, {} trades.",
+        symbol,
+        interval,
+        source,
+        simulation.summary.total_return,
+        simulation.summary.buy_and_hold_return,
+        simulation.summary.trades
+    );
+    let run_id = save_run(symbol, interval, source, from, to, &simulation).await?;
+    get_run(run_id).await
+}
+
+pub async fn list_runs() -> Result<Vec<BacktestRun>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as::<_, BacktestRun>("SELECT * FROM backtest_runs ORDER BY id DESC")
+        .fetch_all(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error listing backtest runs. {:?}", e)))
+        .await
+}
+
+/// A stored run with its trades and equity
+pub async fn get_run(run_id: i64) -> Result<BacktestDetail, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let run = sqlx::query_as::<_, BacktestRun>("SELECT * FROM backtest_runs WHERE id = ?1")
+        .bind(run_id)
+        .fetch_optional(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching backtest run. {:?}", e)))
+        .await?
+        .ok_or(MeshetarError::NotFound(format!(
+            "Backtest run {} not found.",
+            run_id
+        )))?;
+    let trades = sqlx::query_as::<_, BacktestTrade>(
+        "SELECT time, side, price, quantity, fee FROM backtest_trades
+        WHERE run_id = ?1 ORDER BY time ASC",
+    )
+    .bind(run_id)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching backtest trades. {:?}", e)))
+    .await?;
+    let equity = sqlx::query_as::<_, BacktestPoint>(
+        "SELECT time, equity, buy_and_hold FROM backtest_equity
+        WHERE run_id = ?1 ORDER BY time ASC",
+    )
+    .bind(run_id)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching backtest equity. {:?}", e)))
+    .await?;
+    Ok(BacktestDetail {
+        run,
+        trades,
+        equity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn trades_the_signals_after_fees() {
+        let candles: Vec<SignalCandle> = [
+            (100.0, Some("buy")),
+            (110.0, None),
+            (121.0, Some("sell")),
+            // Nothing to sell
+            (110.0, Some("sell")),
+            (100.0, Some("buy")),
+            (90.0, Some("hold")),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, (close, signal))| SignalCandle {
+            time: i as i64,
+            close: *close,
+            signal: signal.map(String::from),
+        })
+        .collect();
+        let simulation = simulate(&candles, 0.001, 1000.0);
+
+        // 999 of quote buy 9.99 at 100, sold at 121 for 1208.79 less 1.20879, which buys
+        // 12.0637362879 at 100 after a fee of 1.20758121
+        let trades: Vec<(i64, &str)> = simulation
+            .trades
+            .iter()
+            .map(|trade| (trade.time, trade.side.as_str()))
+            .collect();
+        assert_eq!(trades, vec![(0, "BUY"), (2, "SELL"), (4, "BUY")]);
+        assert_close(simulation.trades[0].quantity, 9.99);
+        assert_close(simulation.trades[1].fee, 1.20879);
+        assert_close(simulation.trades[2].quantity, 12.0637362879);
+        let equity: Vec<f64> = simulation.equity.iter().map(|point| point.equity).collect();
+        for (actual, expected) in equity.iter().zip([
+            999.0,
+            1098.9,
+            1207.58121,
+            1207.58121,
+            1206.37362879,
+            1085.736265911,
+        ]) {
+            assert_close(*actual, expected);
+        }
+        assert_close(simulation.equity[5].buy_and_hold, 899.1);
+
+        let summary = &simulation.summary;
+        assert_eq!((summary.klines, summary.trades), (6, 3));
+        assert_eq!((summary.round_trips, summary.winning_round_trips), (1, 1));
+        assert_eq!(summary.win_rate, Some(1.0));
+        assert_close(summary.fees_paid, 3.41637121);
+        assert_close(summary.final_equity, 1085.736265911);
+        assert_close(summary.total_return, 0.085736265911);
+        assert_close(summary.buy_and_hold_return, -0.1009);
+        // From 1207.58121 down to 1085.736265911
+        assert_close(summary.max_drawdown, 0.1009);
+    }
+
+    #[test]
+    fn keeps_the_capital_without_klines() {
+        let simulation = simulate(&[], 0.001, 1000.0);
+        assert!(simulation.trades.is_empty());
+        assert_eq!(simulation.summary.final_equity, 1000.0);
+        assert_eq!(simulation.summary.total_return, 0.0);
+    }
 }
@@ -0,0 +1,68 @@
+use crate::utils::{database::DB_POOL, error::MeshetarError, load_config};
+use futures::TryFutureExt;
+use serde::Serialize;
+
+/// The account endpoint states commissions in hundredths of a percent
+const COMMISSION_UNIT: f64 = 10000.0;
+
+/// Commission rates as shares of the traded amount
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct FeeRates {
+    pub maker: f64,
+    pub taker: f64,
+    /// False while the account hasn't been fetched, `fees.default_rate` is used then
+    pub from_account: bool,
+    /// Whether the BNB discount is applied to the rates
+    pub bnb_discount: bool,
+}
+
+impl FeeRates {
+    /// Rate of the market orders the server places
+    pub fn market_order(&self) -> f64 {
+        self.taker
+    }
+}
+
+/// Rates of the stored account, discounted when fees are paid in BNB and the last balance
+/// sheet holds BNB to pay them with
+pub async fn current() -> Result<FeeRates, MeshetarError> {
+    let config = load_config::config().fees;
+    let connection = DB_POOL.get().unwrap();
+    let account: Option<(i64, i64)> = sqlx::query_as(
+        "SELECT maker_commission, taker_commission FROM account
+        ORDER BY update_time DESC LIMIT 1",
+    )
+    .fetch_optional(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching commission rates. {:?}", e)))
+    .await?;
+    let (maker, taker) = match account {
+        Some((maker, taker)) => (
+            maker as f64 / COMMISSION_UNIT,
+            taker as f64 / COMMISSION_UNIT,
+        ),
+        None => (config.default_rate, config.default_rate),
+    };
+    let holds_bnb = if config.pay_with_bnb {
+        let bnb: Option<f64> = sqlx::query_scalar(
+            "SELECT free FROM balances
+            WHERE asset = 'BNB' AND balance_sheet_id = (SELECT MAX(id) FROM balance_sheets)",
+        )
+        .fetch_optional(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error fetching BNB balance. {:?}", e)))
+        .await?;
+        bnb.map_or(false, |free| free > 0.0)
+    } else {
+        false
+    };
+    let discount = if holds_bnb {
+        1.0 - config.bnb_discount
+    } else {
+        1.0
+    };
+    Ok(FeeRates {
+        maker: maker * discount,
+        taker: taker * discount,
+        from_account: account.is_some(),
+        bnb_discount: holds_bnb,
+    })
+}
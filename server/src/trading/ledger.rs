@@ -0,0 +1,399 @@
+use super::{meshetar::Pair, trade::OrderSide};
+use crate::utils::{database::DB_POOL, error::MeshetarError, load_config::CostBasisMethod};
+use chrono::{TimeZone, Utc};
+use futures::TryFutureExt;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::{collections::VecDeque, str::FromStr};
+
+/// Lots smaller than this are what float rounding leaves of a closed lot
+const DUST: f64 = 1e-12;
+
+/// A fill of an order placed by the server, the fee stays in the asset it was paid in
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct Fill {
+    pub symbol: String,
+    pub trade_id: i64,
+    pub order_id: i64,
+    pub side: String,
+    pub time: i64,
+    pub price: f64,
+    pub quantity: f64,
+    pub commission: f64,
+    pub commission_asset: String,
+}
+
+/// A fill with what it changed in the balances of its pair's assets, fees not included
+#[derive(Serialize, Debug)]
+pub struct LedgerEntry {
+    #[serde(flatten)]
+    pub fill: Fill,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub base_change: f64,
+    pub quote_change: f64,
+}
+
+/// Base asset bought and not sold yet, its cost in the quote asset includes the fees
+#[derive(Serialize, Debug, Clone)]
+pub struct Lot {
+    pub symbol: String,
+    pub asset: String,
+    pub quote_asset: String,
+    pub opened_at: i64,
+    pub quantity: f64,
+    pub cost: f64,
+}
+
+/// Part of a lot closed by a sell. Amounts are in the quote asset, fees paid in the base or
+/// quote asset are part of the cost and proceeds.
+#[derive(Serialize, Debug, Clone)]
+pub struct ClosedLot {
+    pub symbol: String,
+    pub asset: String,
+    pub quote_asset: String,
+    /// Not known at average cost, every lot is closed at once
+    pub opened_at: Option<i64>,
+    pub closed_at: i64,
+    pub trade_id: i64,
+    pub quantity: f64,
+    pub cost: f64,
+    pub proceeds: f64,
+    pub gain: f64,
+    /// Share of the sell's fee paid in another asset (e.g. BNB), not part of the gain
+    pub other_fee: f64,
+    pub other_fee_asset: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Book {
+    pub method: String,
+    pub open_lots: Vec<Lot>,
+    pub closed_lots: Vec<ClosedLot>,
+}
+
+fn format_time(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map_or(String::new(), |time| time.to_rfc3339())
+}
+
+fn pair_of(symbol: &str) -> Result<Pair, MeshetarError> {
+    Pair::from_str(symbol).map_err(|_| {
+        MeshetarError::Internal(format!("Stored fills have an unknown pair {}.", symbol))
+    })
+}
+
+/// Fills up to `to` (epoch ms), of one pair or of all, oldest first
+pub async fn fills(symbol: Option<&str>, to: i64) -> Result<Vec<Fill>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    sqlx::query_as(
+        "SELECT * FROM fills
+        WHERE (?1 IS NULL OR symbol = ?1) AND time <= ?2
+        ORDER BY time ASC, trade_id ASC",
+    )
+    .bind(symbol)
+    .bind(to)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching fills. {:?}", e)))
+    .await
+}
+
+/// Fills between `from` and `to` (epoch ms) with their balance changes
+pub async fn entries(
+    symbol: Option<&str>,
+    from: i64,
+    to: i64,
+) -> Result<Vec<LedgerEntry>, MeshetarError> {
+    fills(symbol, to)
+        .await?
+        .into_iter()
+        .filter(|fill| fill.time >= from)
+        .map(|fill| {
+            let (base_asset, quote_asset) = pair_of(&fill.symbol)?.assets();
+            let side = OrderSide::from_str(&fill.side).map_err(|_| {
+                MeshetarError::Internal(format!("Stored fill has an unknown side {}.", fill.side))
+            })?;
+            let sign = match side {
+                OrderSide::Buy => 1.0,
+                OrderSide::Sell => -1.0,
+            };
+            Ok(LedgerEntry {
+                base_asset: base_asset.to_string(),
+                quote_asset: quote_asset.to_string(),
+                base_change: sign * fill.quantity,
+                quote_change: -sign * fill.quantity * fill.price,
+                fill,
+            })
+        })
+        .collect()
+}
+
+/// Books the fills of one pair, oldest first, into open and closed lots. Sells beyond the
+/// bought quantity sold what was bought outside of the server and are left out, along with
+/// their share of the sell's fee.
+pub fn book_pair(
+    pair: Pair,
+    fills: &[Fill],
+    method: CostBasisMethod,
+) -> Result<(Vec<Lot>, Vec<ClosedLot>), MeshetarError> {
+    let symbol = pair.to_string();
+    let (base_asset, quote_asset) = pair.assets();
+    let mut lots: VecDeque<Lot> = VecDeque::new();
+    let mut closed_lots = Vec::new();
+    for fill in fills.iter().filter(|fill| fill.symbol == symbol) {
+        let side = OrderSide::from_str(&fill.side).map_err(|_| {
+            MeshetarError::Internal(format!("Stored fill has an unknown side {}.", fill.side))
+        })?;
+        let (quote_fee, base_fee, other_fee) = if fill.commission_asset == quote_asset {
+            (fill.commission, 0.0, 0.0)
+        } else if fill.commission_asset == base_asset {
+            (0.0, fill.commission, 0.0)
+        } else {
+            (0.0, 0.0, fill.commission)
+        };
+        match side {
+            OrderSide::Buy => lots.push_back(Lot {
+                symbol: symbol.clone(),
+                asset: base_asset.to_string(),
+                quote_asset: quote_asset.to_string(),
+                opened_at: fill.time,
+                // A fee in the base asset is taken from what was bought
+                quantity: fill.quantity - base_fee,
+                cost: fill.quantity * fill.price + quote_fee,
+            }),
+            OrderSide::Sell => {
+                let held: f64 = lots.iter().map(|lot| lot.quantity).sum();
+                let matched = fill.quantity.min(held);
+                if matched <= DUST {
+                    continue;
+                }
+                let proceeds = fill.quantity * fill.price - quote_fee - base_fee * fill.price;
+                let close = |opened_at: Option<i64>, quantity: f64, cost: f64| {
+                    let share = quantity / fill.quantity;
+                    ClosedLot {
+                        symbol: symbol.clone(),
+                        asset: base_asset.to_string(),
+                        quote_asset: quote_asset.to_string(),
+                        opened_at,
+                        closed_at: fill.time,
+                        trade_id: fill.trade_id,
+                        quantity,
+                        cost,
+                        proceeds: proceeds * share,
+                        gain: proceeds * share - cost,
+                        other_fee: other_fee * share,
+                        other_fee_asset: (other_fee > 0.0).then(|| fill.commission_asset.clone()),
+                    }
+                };
+                match method {
+                    CostBasisMethod::Fifo => {
+                        let mut remaining = matched;
+                        while remaining > DUST {
+                            let Some(lot) = lots.front_mut() else {
+                                break;
+                            };
+                            let quantity = remaining.min(lot.quantity);
+                            let cost = lot.cost * quantity / lot.quantity;
+                            closed_lots.push(close(Some(lot.opened_at), quantity, cost));
+                            lot.quantity -= quantity;
+                            lot.cost -= cost;
+                            remaining -= quantity;
+                            if lot.quantity <= DUST {
+                                lots.pop_front();
+                            }
+                        }
+                    }
+                    CostBasisMethod::Average => {
+                        let cost: f64 =
+                            lots.iter().map(|lot| lot.cost).sum::<f64>() * matched / held;
+                        closed_lots.push(close(None, matched, cost));
+                        // Every lot keeps the same share
+                        let kept = 1.0 - matched / held;
+                        for lot in lots.iter_mut() {
+                            lot.quantity *= kept;
+                            lot.cost *= kept;
+                        }
+                        lots.retain(|lot| lot.quantity > DUST);
+                    }
+                }
+            }
+        }
+    }
+    Ok((lots.into(), closed_lots))
+}
+
+/// Lots open at `to` and the ones closed between `from` and `to` (epoch ms), of one pair or
+/// of all
+pub async fn book(
+    symbol: Option<&str>,
+    from: i64,
+    to: i64,
+    method: CostBasisMethod,
+) -> Result<Book, MeshetarError> {
+    let fills = fills(symbol, to).await?;
+    let mut pairs: Vec<&str> = fills.iter().map(|fill| fill.symbol.as_str()).collect();
+    pairs.sort();
+    pairs.dedup();
+    let mut book = Book {
+        method: method.to_string(),
+        ..Default::default()
+    };
+    for pair in pairs {
+        let (open_lots, closed_lots) = book_pair(pair_of(pair)?, &fills, method)?;
+        book.open_lots.extend(open_lots);
+        book.closed_lots.extend(
+            closed_lots
+                .into_iter()
+                .filter(|closed| closed.closed_at >= from),
+        );
+    }
+    book.closed_lots
+        .sort_by_key(|closed| (closed.closed_at, closed.trade_id));
+    Ok(book)
+}
+
+/// Closed lots as CSV, one row per disposal
+pub fn closed_lots_csv(closed_lots: &[ClosedLot]) -> String {
+    let mut csv = String::from(
+        "closed_at,opened_at,symbol,asset,quantity,quote_asset,cost,proceeds,gain,other_fee,other_fee_asset,trade_id\n",
+    );
+    for closed in closed_lots {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            format_time(closed.closed_at),
+            closed.opened_at.map_or(String::new(), format_time),
+            closed.symbol,
+            closed.asset,
+            closed.quantity,
+            closed.quote_asset,
+            closed.cost,
+            closed.proceeds,
+            closed.gain,
+            closed.other_fee,
+            closed.other_fee_asset.clone().unwrap_or_default(),
+            closed.trade_id
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(trade_id: i64, side: OrderSide, price: f64, quantity: f64, fee: (f64, &str)) -> Fill {
+        Fill {
+            symbol: Pair::BTCUSDT.to_string(),
+            trade_id,
+            order_id: trade_id,
+            side: side.to_string(),
+            time: trade_id * 1000,
+            price,
+            quantity,
+            commission: fee.0,
+            commission_asset: fee.1.to_string(),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    fn two_buys_and_a_sell() -> Vec<Fill> {
+        vec![
+            fill(1, OrderSide::Buy, 100.0, 1.0, (0.1, "USDT")),
+            fill(2, OrderSide::Buy, 200.0, 1.0, (0.2, "USDT")),
+            fill(3, OrderSide::Sell, 300.0, 1.0, (0.3, "USDT")),
+        ]
+    }
+
+    #[test]
+    fn fifo_closes_the_oldest_lot() {
+        let (open, closed) =
+            book_pair(Pair::BTCUSDT, &two_buys_and_a_sell(), CostBasisMethod::Fifo).unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].opened_at, Some(1000));
+        assert_close(closed[0].cost, 100.1);
+        assert_close(closed[0].proceeds, 299.7);
+        assert_close(closed[0].gain, 199.6);
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].opened_at, 2000);
+        assert_close(open[0].quantity, 1.0);
+        assert_close(open[0].cost, 200.2);
+    }
+
+    #[test]
+    fn average_closes_every_lot_at_its_share() {
+        let (open, closed) = book_pair(
+            Pair::BTCUSDT,
+            &two_buys_and_a_sell(),
+            CostBasisMethod::Average,
+        )
+        .unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].opened_at, None);
+        assert_close(closed[0].cost, 150.15);
+        assert_close(closed[0].gain, 149.55);
+        assert_eq!(open.len(), 2);
+        assert_close(open[0].quantity, 0.5);
+        assert_close(open[0].cost, 50.05);
+        assert_close(open[1].quantity, 0.5);
+        assert_close(open[1].cost, 100.1);
+    }
+
+    #[test]
+    fn base_asset_fees_shrink_the_lot_and_the_proceeds() {
+        let fills = vec![
+            fill(1, OrderSide::Buy, 100.0, 2.0, (0.002, "BTC")),
+            fill(2, OrderSide::Sell, 110.0, 1.998, (0.001998, "BTC")),
+        ];
+        let (open, closed) = book_pair(Pair::BTCUSDT, &fills, CostBasisMethod::Fifo).unwrap();
+        assert!(open.is_empty());
+        assert_close(closed[0].quantity, 1.998);
+        assert_close(closed[0].cost, 200.0);
+        // 1.998 * 110 less the fee of 0.001998 * 110
+        assert_close(closed[0].proceeds, 219.56022);
+        assert_close(closed[0].gain, 19.56022);
+    }
+
+    #[test]
+    fn bnb_fees_stay_out_of_the_gain() {
+        let fills = vec![
+            fill(1, OrderSide::Buy, 100.0, 1.0, (0.01, "BNB")),
+            fill(2, OrderSide::Sell, 120.0, 1.0, (0.012, "BNB")),
+        ];
+        for method in [CostBasisMethod::Fifo, CostBasisMethod::Average] {
+            let (open, closed) = book_pair(Pair::BTCUSDT, &fills, method).unwrap();
+            assert!(open.is_empty());
+            assert_close(closed[0].cost, 100.0);
+            assert_close(closed[0].gain, 20.0);
+            assert_close(closed[0].other_fee, 0.012);
+            assert_eq!(closed[0].other_fee_asset.as_deref(), Some("BNB"));
+        }
+    }
+
+    #[test]
+    fn oversells_count_only_the_held_quantity_and_its_fee_share() {
+        let fills = vec![
+            fill(1, OrderSide::Buy, 100.0, 1.0, (0.1, "USDT")),
+            fill(2, OrderSide::Sell, 150.0, 2.0, (0.3, "USDT")),
+            // Nothing is held anymore
+            fill(3, OrderSide::Sell, 150.0, 1.0, (0.15, "USDT")),
+        ];
+        for method in [CostBasisMethod::Fifo, CostBasisMethod::Average] {
+            let (open, closed) = book_pair(Pair::BTCUSDT, &fills, method).unwrap();
+            assert!(open.is_empty());
+            assert_eq!(closed.len(), 1);
+            assert_close(closed[0].quantity, 1.0);
+            // Half of 300 less half of the fee
+            assert_close(closed[0].proceeds, 149.85);
+            assert_close(closed[0].gain, 49.75);
+        }
+    }
+}
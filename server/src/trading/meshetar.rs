@@ -1,5 +1,9 @@
+use crate::utils::{
+    error::MeshetarError,
+    load_config::{self, Environment},
+};
 use binance_spot_connector_rust::market::klines::KlineInterval;
-use rocket::serde::{json::Json, Serialize};
+use rocket::serde::{json::Json, Deserialize, Serialize};
 use strum::{Display, EnumString};
 
 #[derive(Copy, Clone, Debug, Serialize, PartialEq)]
@@ -11,13 +15,25 @@ pub enum MeshetarStatus {
     Running,
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Display, EnumString)]
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize, Display, EnumString)]
 pub enum Pair {
     BTCUSDT,
     ETHBTC,
+    ETHUSDT,
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Display, EnumString)]
+impl Pair {
+    /// Base and quote asset of the pair
+    pub fn assets(&self) -> (&'static str, &'static str) {
+        match self {
+            Pair::BTCUSDT => ("BTC", "USDT"),
+            Pair::ETHBTC => ("ETH", "BTC"),
+            Pair::ETHUSDT => ("ETH", "USDT"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Hash, Serialize, Deserialize, Display, EnumString)]
 pub enum Interval {
     Minutes1,
     Minutes3,
@@ -30,6 +46,18 @@ impl Interval {
             Interval::Minutes3 => KlineInterval::Minutes3,
         }
     }
+    /// The interval klines are stored with, e.g. "1m"
+    pub fn from_kline_interval(interval: &str) -> Option<Interval> {
+        [Interval::Minutes1, Interval::Minutes3]
+            .into_iter()
+            .find(|known| known.to_kline_interval().to_string() == interval)
+    }
+    pub fn duration_ms(&self) -> i64 {
+        match self {
+            Interval::Minutes1 => 60000,
+            Interval::Minutes3 => 180000,
+        }
+    }
 }
 
 // Core struct
@@ -40,27 +68,44 @@ pub struct Meshetar {
     pub pair: Pair,
     pub interval: Interval,
     pub status: MeshetarStatus,
+    pub environment: Environment,
 }
 
 impl Meshetar {
     pub fn new() -> Self {
+        let config = load_config::config();
         Meshetar {
-            interval: Interval::Minutes1,
-            pair: Pair::BTCUSDT,
+            interval: config.trading.default_interval,
+            pair: config.trading.default_pair,
             status: MeshetarStatus::Idle,
+            environment: config.exchange.environment,
+        }
+    }
+    pub fn ensure_idle(&self) -> Result<(), MeshetarError> {
+        if self.status != MeshetarStatus::Idle {
+            Err(MeshetarError::StateConflict(format!(
+                "Meshetar is busy ({:?}), stop it first.",
+                self.status
+            )))
+        } else {
+            Ok(())
         }
     }
-    pub fn change_pair(&mut self, pair: Pair) -> Result<&mut Self, String> {
-        if self.status == MeshetarStatus::Idle {
-            Err(String::from("Cant change pair while working."))
+    pub fn change_pair(&mut self, pair: Pair) -> Result<&mut Self, MeshetarError> {
+        if self.status != MeshetarStatus::Idle {
+            Err(MeshetarError::StateConflict(String::from(
+                "Cant change pair while working.",
+            )))
         } else {
             self.pair = pair;
             Ok(self)
         }
     }
-    pub fn change_interval(&mut self, interval: Interval) -> Result<&mut Self, String> {
-        if self.status == MeshetarStatus::Idle {
-            Err(String::from("Cant change interval while working."))
+    pub fn change_interval(&mut self, interval: Interval) -> Result<&mut Self, MeshetarError> {
+        if self.status != MeshetarStatus::Idle {
+            Err(MeshetarError::StateConflict(String::from(
+                "Cant change interval while working.",
+            )))
         } else {
             self.interval = interval;
             Ok(self)
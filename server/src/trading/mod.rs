@@ -1,5 +1,9 @@
 pub mod backtesting;
+pub mod fees;
+pub mod ledger;
 pub mod meshetar;
+pub mod performance;
 pub mod portfolio;
 pub mod routes;
 pub mod trade;
+pub mod valuation;
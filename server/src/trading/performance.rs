@@ -0,0 +1,266 @@
+use super::{fees, ledger, meshetar::Pair, portfolio::SHEET_TIME, valuation::Prices};
+use crate::utils::{
+    database::DB_POOL,
+    error::MeshetarError,
+    load_config::{self, CostBasisMethod},
+};
+use chrono::{Duration, Utc};
+use futures::TryFutureExt;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashMap;
+
+#[derive(FromRow, Serialize, Debug, Clone)]
+pub struct EquityPoint {
+    pub time: i64,
+    pub btc_valuation: f64,
+    pub reporting_valuation: f64,
+    /// Currency of the reporting valuation at the time
+    pub reporting_currency: String,
+    /// Fall of the reporting valuation from its highest point before, 0 at a new high
+    #[sqlx(default)]
+    pub drawdown: f64,
+}
+
+/// Change of the valuations from the last balance sheet of the day before
+#[derive(Serialize, Debug)]
+pub struct DailyReturn {
+    pub day: String,
+    pub btc_return: Option<f64>,
+    pub reporting_return: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EquityCurve {
+    pub from: i64,
+    pub to: i64,
+    /// Last balance sheet of every time bucket, at most the requested number of points
+    pub points: Vec<EquityPoint>,
+    pub daily_returns: Vec<DailyReturn>,
+    /// Deepest drawdown of the points
+    pub max_drawdown: f64,
+    /// Change of the reporting valuation from the first to the last point, if both are in the
+    /// same currency
+    pub total_return: Option<f64>,
+}
+
+#[derive(FromRow, Debug)]
+struct DaySheet {
+    day: String,
+    btc_valuation: f64,
+    reporting_valuation: f64,
+    reporting_currency: String,
+}
+
+/// Profit and loss of a pair's fills in its quote asset, at average cost
+#[derive(Serialize, Debug, Default)]
+pub struct Pnl {
+    pub symbol: String,
+    pub quote_asset: String,
+    pub fills: usize,
+    /// Base asset bought by the fills and not sold yet
+    pub position: f64,
+    pub average_cost: Option<f64>,
+    /// Of the sells in the range, net of their commissions in the base and quote asset. A sell
+    /// beyond the position counts with the share of it that was held and the same share of its
+    /// commission, the rest was bought outside of the server.
+    pub realized: f64,
+    /// Of the open position at the last price, net of the fee of selling it
+    pub unrealized: Option<f64>,
+    /// Fee a market sell of the open position would pay at the account's commission rate
+    pub exit_fee: Option<f64>,
+    /// Commissions paid in the base and quote asset by the fills in the range, valued in the
+    /// quote asset, all of them also of sells beyond the position
+    pub commission: f64,
+    /// Commissions paid in other assets (e.g. BNB) by the fills in the range, not part of
+    /// realized PnL
+    pub other_commissions: HashMap<String, f64>,
+    /// Other commissions valued in the quote asset at the last prices, None if one of them
+    /// can't be priced
+    pub other_commissions_value: Option<f64>,
+    /// Realized PnL less the other commissions
+    pub net_realized: Option<f64>,
+    pub last_price: Option<f64>,
+}
+
+/// Range of the equity curve and PnL when none is given
+const DEFAULT_RANGE_DAYS: i64 = 30;
+pub const DEFAULT_POINTS: i64 = 500;
+
+/// Fills in a missing end with now and a missing start with `DEFAULT_RANGE_DAYS` before the end
+pub fn resolve_range(from: Option<i64>, to: Option<i64>) -> (i64, i64) {
+    let to = to.unwrap_or(Utc::now().timestamp_millis());
+    let from = from.unwrap_or(to - Duration::days(DEFAULT_RANGE_DAYS).num_milliseconds());
+    (from, to)
+}
+
+fn change(from: f64, to: f64) -> Option<f64> {
+    if from > 0.0 {
+        Some(to / from - 1.0)
+    } else {
+        None
+    }
+}
+
+/// Valuations of the balance sheets between `from` and `to` (epoch ms), downsampled to at most
+/// `points` by keeping the last sheet of every time bucket
+pub async fn equity_curve(from: i64, to: i64, points: i64) -> Result<EquityCurve, MeshetarError> {
+    if from > to || points <= 0 {
+        return Err(MeshetarError::Validation(String::from(
+            "Equity curve needs from <= to and points above 0.",
+        )));
+    }
+    let connection = DB_POOL.get().unwrap();
+    let bucket_ms = ((to - from) / points).max(1);
+    let mut curve: Vec<EquityPoint> = sqlx::query_as(&format!(
+        "WITH sheets AS (
+            SELECT id, {} AS time, btc_valuation, reporting_valuation, reporting_currency
+            FROM balance_sheets
+        )
+        SELECT time, btc_valuation, reporting_valuation, reporting_currency
+        FROM sheets
+        WHERE id IN (
+            SELECT MAX(id) FROM sheets
+            WHERE time BETWEEN ?1 AND ?2
+            GROUP BY (time - ?1) / ?3
+        )
+        ORDER BY time ASC",
+        SHEET_TIME
+    ))
+    .bind(from)
+    .bind(to)
+    .bind(bucket_ms)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching equity curve. {:?}", e)))
+    .await?;
+
+    // Valuations in another currency are not comparable, the peak starts over after a change
+    let mut peak = f64::MIN;
+    let mut currency = String::new();
+    for point in curve.iter_mut() {
+        if point.reporting_currency != currency {
+            peak = f64::MIN;
+            currency = point.reporting_currency.clone();
+        }
+        peak = peak.max(point.reporting_valuation);
+        point.drawdown = if peak > 0.0 {
+            point.reporting_valuation / peak - 1.0
+        } else {
+            0.0
+        };
+    }
+    let max_drawdown = curve.iter().map(|point| point.drawdown).fold(0.0, f64::min);
+    let total_return = match (curve.first(), curve.last()) {
+        (Some(first), Some(last)) if first.reporting_currency == last.reporting_currency => {
+            change(first.reporting_valuation, last.reporting_valuation)
+        }
+        _ => None,
+    };
+
+    Ok(EquityCurve {
+        from,
+        to,
+        points: curve,
+        daily_returns: daily_returns(from, to).await?,
+        max_drawdown,
+        total_return,
+    })
+}
+
+/// Returns of every UTC day in the range, from the last sheet of a day to the last of the next
+async fn daily_returns(from: i64, to: i64) -> Result<Vec<DailyReturn>, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let days: Vec<DaySheet> = sqlx::query_as(&format!(
+        "WITH sheets AS (
+            SELECT id, {} AS time, date(timestamp) AS day, btc_valuation, reporting_valuation,
+                reporting_currency
+            FROM balance_sheets
+        )
+        SELECT day, btc_valuation, reporting_valuation, reporting_currency
+        FROM sheets
+        WHERE id IN (
+            SELECT MAX(id) FROM sheets
+            WHERE time BETWEEN ?1 AND ?2
+            GROUP BY day
+        )
+        ORDER BY time ASC",
+        SHEET_TIME
+    ))
+    .bind(from)
+    .bind(to)
+    .fetch_all(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching daily valuations. {:?}", e)))
+    .await?;
+    Ok(days
+        .windows(2)
+        .map(|pair| DailyReturn {
+            day: pair[1].day.clone(),
+            btc_return: change(pair[0].btc_valuation, pair[1].btc_valuation),
+            reporting_return: if pair[0].reporting_currency == pair[1].reporting_currency {
+                change(pair[0].reporting_valuation, pair[1].reporting_valuation)
+            } else {
+                None
+            },
+        })
+        .collect())
+}
+
+/// PnL of the pair's fills up to `to` at average cost, realized PnL counts the sells from
+/// `from` on. The cost of what they sold comes from every buy before them, the open position
+/// is valued as if sold at the last price and the account's commission rate.
+pub async fn pnl(pair: Pair, from: i64, to: i64) -> Result<Pnl, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let symbol = pair.to_string();
+    let (base_asset, quote_asset) = pair.assets();
+    let fills = ledger::fills(Some(&symbol), to).await?;
+    let last_price: Option<f64> =
+        sqlx::query_scalar("SELECT last_price FROM asset_ticker WHERE symbol = ?1")
+            .bind(&symbol)
+            .fetch_optional(connection)
+            .map_err(|e| MeshetarError::Database(format!("Error fetching last price. {:?}", e)))
+            .await?;
+    let (open_lots, closed_lots) = ledger::book_pair(pair, &fills, CostBasisMethod::Average)?;
+
+    let mut pnl = Pnl {
+        symbol,
+        quote_asset: quote_asset.to_string(),
+        fills: fills.len(),
+        last_price,
+        ..Default::default()
+    };
+    for fill in fills.iter().filter(|fill| fill.time >= from) {
+        if fill.commission_asset == quote_asset {
+            pnl.commission += fill.commission;
+        } else if fill.commission_asset == base_asset {
+            pnl.commission += fill.commission * fill.price;
+        } else {
+            *pnl.other_commissions
+                .entry(fill.commission_asset.clone())
+                .or_default() += fill.commission;
+        }
+    }
+    pnl.realized = closed_lots
+        .iter()
+        .filter(|closed| closed.closed_at >= from)
+        .map(|closed| closed.gain)
+        .sum();
+    let prices = Prices::load(load_config::config().valuation.bridge_assets).await?;
+    pnl.other_commissions_value = pnl
+        .other_commissions
+        .iter()
+        .map(|(asset, amount)| prices.value(asset, *amount, quote_asset))
+        .sum();
+    pnl.net_realized = pnl
+        .other_commissions_value
+        .map(|value| pnl.realized - value);
+    pnl.position = open_lots.iter().map(|lot| lot.quantity).sum();
+    if pnl.position > 0.0 {
+        let fee_rate = fees::current().await?.market_order();
+        let average_cost = open_lots.iter().map(|lot| lot.cost).sum::<f64>() / pnl.position;
+        pnl.average_cost = Some(average_cost);
+        pnl.exit_fee = last_price.map(|price| price * pnl.position * fee_rate);
+        pnl.unrealized = last_price
+            .map(|price| (price - average_cost) * pnl.position - price * pnl.position * fee_rate);
+    }
+    Ok(pnl)
+}
@@ -1,6 +1,9 @@
+use super::valuation::Prices;
 use crate::utils::{
     binance_client::BINANCE_CLIENT,
     database::DB_POOL,
+    error::MeshetarError,
+    load_config,
     serde_utils::{f64_default, f64_from_string},
 };
 use binance_spot_connector_rust::trade;
@@ -9,6 +12,9 @@ use futures::TryFutureExt;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+/// Epoch ms of a balance sheet, its timestamp is stored as RFC 3339
+pub const SHEET_TIME: &str = "CAST((julianday(timestamp) - 2440587.5) * 86400000 AS INTEGER)";
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct ApiBalance {
     asset: String,
@@ -59,6 +65,10 @@ pub struct Balance {
     balance_sheet_id: i64,
     #[serde(default = "f64_default")]
     btc_valuation: f64,
+    reporting_valuation: f64,
+    /// False when no conversion to BTC or the reporting currency was found, the asset is
+    /// valued at 0 then
+    is_priced: bool,
 }
 
 #[derive(FromRow, Clone, Serialize)]
@@ -66,7 +76,10 @@ pub struct BalanceSheet {
     id: i64,
     timestamp: NaiveDateTime,
     btc_valuation: f64,
-    busd_valuation: f64,
+    reporting_valuation: f64,
+    reporting_currency: String,
+    /// Comma separated assets held that could not be priced
+    unpriced_assets: String,
 }
 
 #[derive(Serialize, Clone)]
@@ -75,14 +88,16 @@ pub struct BalanceSheetWithBalances {
     balances: Vec<Balance>,
 }
 
-pub async fn fetch_account_data() -> Result<(), String> {
+pub async fn fetch_account_data() -> Result<(), MeshetarError> {
     let client = BINANCE_CLIENT.get().unwrap();
     let response = client
         .send(trade::account())
-        .map_err(|e| format!("Error fetching spot wallet {:?}", e))
+        .map_err(|e| MeshetarError::Exchange(format!("Error fetching spot wallet {:?}", e)))
         .await?
         .into_body_str()
-        .map_err(|e| format!("Error parsing spot wallet response, {:?}", e))
+        .map_err(|e| {
+            MeshetarError::Exchange(format!("Error parsing spot wallet response, {:?}", e))
+        })
         .await?;
 
     match serde_json::from_str::<ApiAccount>(&response) {
@@ -95,7 +110,7 @@ pub async fn fetch_account_data() -> Result<(), String> {
     Ok(())
 }
 
-async fn insert_account(account: &ApiAccount) -> Result<(), String> {
+async fn insert_account(account: &ApiAccount) -> Result<(), MeshetarError> {
     let connection = DB_POOL.get().unwrap();
     sqlx::query(
         "INSERT OR REPLACE INTO account (
@@ -128,93 +143,158 @@ async fn insert_account(account: &ApiAccount) -> Result<(), String> {
     .bind(account.account_type.clone())
     .bind(account.uid)
     .execute(connection)
-    .map_err(|e| format!("Error inserting new account data. {:?}", e))
+    .map_err(|e| MeshetarError::Database(format!("Error inserting new account data. {:?}", e)))
     .await?;
     Ok(())
 }
 
-async fn insert_balances(api_balances: Vec<ApiBalance>) -> Result<(), String> {
+/// Whether the balances are the ones of the latest sheet and it was stored less than the
+/// heartbeat ago
+async fn is_unchanged(
+    api_balances: &[ApiBalance],
+    heartbeat_ms: u64,
+) -> Result<bool, MeshetarError> {
+    if heartbeat_ms == 0 {
+        return Ok(false);
+    }
+    let connection = DB_POOL.get().unwrap();
+    let latest: Option<(i64, i64)> = sqlx::query_as(&format!(
+        "SELECT id, {} FROM balance_sheets ORDER BY id DESC LIMIT 1",
+        SHEET_TIME
+    ))
+    .fetch_optional(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching last balance sheet. {:?}", e)))
+    .await?;
+    let Some((id, time)) = latest else {
+        return Ok(false);
+    };
+    if Utc::now().timestamp_millis() - time >= heartbeat_ms as i64 {
+        return Ok(false);
+    }
+    let stored: Vec<(String, f64, f64)> = sqlx::query_as(
+        "SELECT asset, free, locked FROM balances WHERE balance_sheet_id = ?1 ORDER BY asset ASC",
+    )
+    .bind(id)
+    .fetch_all(connection)
+    .map_err(|e| {
+        MeshetarError::Database(format!("Error retrieving balances from database. {:?}", e))
+    })
+    .await?;
+    let mut current: Vec<(&str, f64, f64)> = api_balances
+        .iter()
+        .map(|balance| (balance.asset.as_str(), balance.free, balance.locked))
+        .collect();
+    current.sort_by(|a, b| a.0.cmp(b.0));
+    Ok(stored.len() == current.len()
+        && stored.iter().zip(current.iter()).all(|(stored, current)| {
+            stored.0 == current.0 && stored.1 == current.1 && stored.2 == current.2
+        }))
+}
+
+async fn insert_balances(api_balances: Vec<ApiBalance>) -> Result<(), MeshetarError> {
+    let heartbeat_ms = load_config::config().retention.snapshot_heartbeat_ms;
+    if is_unchanged(&api_balances, heartbeat_ms).await? {
+        return Ok(());
+    }
     let connection = DB_POOL.get().unwrap();
     let mut tx = connection
         .begin()
-        .map_err(|e| format!("Error on creating transaction on balances: {:?}", e))
+        .map_err(|e| {
+            MeshetarError::Database(format!(
+                "Error on creating transaction on balances: {:?}",
+                e
+            ))
+        })
         .await?;
+    let config = load_config::config().valuation;
+    let prices = Prices::load(config.bridge_assets).await?;
+    let valued: Vec<(ApiBalance, Option<f64>, Option<f64>)> = api_balances
+        .into_iter()
+        .map(|balance| {
+            let amount = balance.free + balance.locked;
+            let btc = prices.value(&balance.asset, amount, "BTC");
+            let reporting = prices.value(&balance.asset, amount, &config.reporting_currency);
+            (balance, btc, reporting)
+        })
+        .collect();
+    let unpriced_assets: Vec<&str> = valued
+        .iter()
+        .filter(|(_, btc, reporting)| btc.is_none() || reporting.is_none())
+        .map(|(balance, _, _)| balance.asset.as_str())
+        .collect();
+    if !unpriced_assets.is_empty() {
+        log::debug!("No price found for {}.", unpriced_assets.join(", "));
+    }
+
     let timestamp: String = DateTime::to_rfc3339(&Utc::now());
-    let balance_sheet: BalanceSheet = sqlx::query_as(
-        "INSERT INTO balance_sheets (timestamp, btc_valuation, busd_valuation) VALUES (?1, 0.0, 0.0) RETURNING *",
+    let balance_sheet_id: i64 = sqlx::query_scalar(
+        "INSERT INTO balance_sheets (
+            timestamp,
+            btc_valuation,
+            reporting_valuation,
+            reporting_currency,
+            unpriced_assets
+        ) VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
     )
     .bind(timestamp)
-    .fetch_one(connection)
-    .map_err(|e| format!("Error inserting new balances. {:?}", e))
+    .bind(valued.iter().filter_map(|(_, btc, _)| *btc).sum::<f64>())
+    .bind(
+        valued
+            .iter()
+            .filter_map(|(_, _, reporting)| *reporting)
+            .sum::<f64>(),
+    )
+    .bind(&config.reporting_currency)
+    .bind(unpriced_assets.join(","))
+    .fetch_one(tx.as_mut())
+    .map_err(|e| MeshetarError::Database(format!("Error inserting new balances. {:?}", e)))
     .await?;
 
     // Insert snapshot data
-    for balance in api_balances {
+    for (balance, btc, reporting) in valued.iter() {
         sqlx::query(
-            "INSERT INTO balances (asset, free, locked, balance_sheet_id, btc_valuation) 
-            VALUES (
-                ?1, 
-                ?2, 
-                ?3, 
-                ?4,
-                CASE
-                    WHEN ?1 = 'BTC' THEN ?2 -- Btc valuation for btc is btc free field
-                    ELSE COALESCE((SELECT last_price FROM asset_ticker WHERE symbol = ?5), 0) * ?6
-                END
-            )",
+            "INSERT INTO balances (
+                asset,
+                free,
+                locked,
+                balance_sheet_id,
+                btc_valuation,
+                reporting_valuation,
+                is_priced
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         )
         .bind(&balance.asset)
-        .bind(&balance.free)
-        .bind(&balance.locked)
-        .bind(&balance_sheet.id)
-        .bind(&format!("{}{}", balance.asset.to_string(), "BTC"))
-        .bind(&balance.free)
+        .bind(balance.free)
+        .bind(balance.locked)
+        .bind(balance_sheet_id)
+        .bind(btc.unwrap_or(0.0))
+        .bind(reporting.unwrap_or(0.0))
+        .bind(btc.is_some() && reporting.is_some())
         .execute(tx.as_mut())
         .map_err(|e| {
-            format!(
+            MeshetarError::Database(format!(
                 "Error inserting a balance for {:?}. {:?}",
                 &balance.asset, e
-            )
+            ))
         })
         .await?;
     }
 
-    sqlx::query(
-        "
-        UPDATE balance_sheets
-        SET btc_valuation = (
-            SELECT SUM(btc_valuation)
-            FROM balances
-            WHERE balance_sheet_id = ?1
-        ),
-        busd_valuation = (
-            SELECT SUM(btc_valuation) * asset_ticker.last_price
-            FROM balances
-            LEFT JOIN asset_ticker ON asset_ticker.symbol = 'BTCBUSD'
-            WHERE balance_sheet_id = ?1
-        )
-        WHERE id = ?1",
-    )
-    .bind(&balance_sheet.id)
-    .execute(tx.as_mut())
-    .map_err(|e| format!("Error summing balances on balance_sheet {:?}", e))
-    .await?;
-
     tx.commit()
-        .map_err(|e| format!("Error committing new balances: {:?}", e))
+        .map_err(|e| MeshetarError::Database(format!("Error committing new balances: {:?}", e)))
         .await?;
 
     // Commit transaction
     Ok(())
 }
 
-pub async fn get_balance_sheet() -> Result<BalanceSheetWithBalances, String> {
+pub async fn get_balance_sheet() -> Result<BalanceSheetWithBalances, MeshetarError> {
     let connection = DB_POOL.get().unwrap();
     let balance_sheet: BalanceSheet = sqlx::query_as(
         "SELECT * FROM balance_sheets WHERE id = (SELECT MAX(id) FROM balance_sheets)",
     )
     .fetch_one(connection)
-    .map_err(|e| format!("Error fetching last balance sheet. {:?}", e))
+    .map_err(|e| MeshetarError::Database(format!("Error fetching last balance sheet. {:?}", e)))
     .await?;
     let query = &format!(
         "SELECT * 
@@ -224,7 +304,9 @@ pub async fn get_balance_sheet() -> Result<BalanceSheetWithBalances, String> {
     );
     let balances: Vec<Balance> = sqlx::query_as(query)
         .fetch_all(connection)
-        .map_err(|e| format!("Error retrieving balances from database. {:?}", e))
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error retrieving balances from database. {:?}", e))
+        })
         .await?;
 
     let balance_sheet_with_balances = BalanceSheetWithBalances {
@@ -1,14 +1,22 @@
 use super::{
+    backtesting::{self, BacktestDetail, BacktestRun, BacktestSource},
+    fees::{self, FeeRates},
+    ledger::{self, Book, LedgerEntry},
     meshetar::{Interval, Meshetar, MeshetarStatus, Pair},
+    performance::{self, EquityCurve, Pnl},
     portfolio::{self, BalanceSheetWithBalances},
+    trade::{self, OrderResponse, OrderSide},
+};
+use crate::{
+    assets::book,
+    utils::{
+        error::MeshetarError,
+        load_config::{self, CostBasisMethod},
+    },
+    TaskControl,
 };
-use crate::{assets::book, TaskControl};
 use rocket::{
-    form::Form,
-    http::Status,
-    response::status::{Accepted, Custom},
-    serde::json::Json,
-    State,
+    form::Form, http::Header, response::status::Accepted, serde::json::Json, Responder, State,
 };
 use serde::Deserialize;
 use std::str::FromStr;
@@ -16,21 +24,162 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 
 #[get("/balance_sheet")]
-pub async fn balance_sheet() -> Result<Accepted<Json<BalanceSheetWithBalances>>, Custom<String>> {
-    match portfolio::get_balance_sheet().await {
-        Ok(balance_sheet) => Ok(Accepted(Some(Json(balance_sheet.clone())))),
-        Err(e) => Err(Custom(Status::NotFound, format!("{:?}", e))),
+pub async fn balance_sheet() -> Result<Accepted<Json<BalanceSheetWithBalances>>, MeshetarError> {
+    let balance_sheet = portfolio::get_balance_sheet().await?;
+    Ok(Accepted(Some(Json(balance_sheet))))
+}
+
+/// Valuations of the balance sheets over a range (epoch ms), the last 30 days by default
+#[get("/equity_curve?<from>&<to>&<points>")]
+pub async fn equity_curve(
+    from: Option<i64>,
+    to: Option<i64>,
+    points: Option<i64>,
+) -> Result<Json<EquityCurve>, MeshetarError> {
+    let (from, to) = performance::resolve_range(from, to);
+    let points = points.unwrap_or(performance::DEFAULT_POINTS);
+    Ok(Json(performance::equity_curve(from, to, points).await?))
+}
+
+/// Realized and unrealized PnL of the selected pair's fills
+#[get("/pnl?<from>&<to>")]
+pub async fn pnl(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Json<Pnl>, MeshetarError> {
+    let pair = meshetar.lock().await.pair;
+    let (from, to) = performance::resolve_range(from, to);
+    Ok(Json(performance::pnl(pair, from, to).await?))
+}
+
+/// Commission rates orders, backtests and labelling are charged with
+#[get("/fees")]
+pub async fn fee_rates() -> Result<Json<FeeRates>, MeshetarError> {
+    Ok(Json(fees::current().await?))
+}
+
+#[derive(FromForm, Deserialize)]
+pub struct BacktestPayload<'r> {
+    /// "signals" replays the running model's signals, "labels" the optimal ones
+    #[field(default = "signals")]
+    source: &'r str,
+    from: Option<i64>,
+    to: Option<i64>,
+    /// Overrides the account's commission rate
+    fee_rate: Option<f64>,
+}
+/// Backtests the selected pair and interval over a range (epoch ms), the last 30 days by
+/// default
+#[post("/backtest", data = "<data>")]
+pub async fn start_backtest(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    data: Form<BacktestPayload<'_>>,
+) -> Result<Json<BacktestDetail>, MeshetarError> {
+    let source = BacktestSource::from_str(data.source).map_err(|_| {
+        MeshetarError::Validation(format!("Unknown backtest source {}.", data.source))
+    })?;
+    let (pair, interval) = {
+        let meshetar = meshetar.lock().await;
+        (
+            meshetar.pair.to_string(),
+            meshetar.interval.to_kline_interval().to_string(),
+        )
+    };
+    let (from, to) = performance::resolve_range(data.from, data.to);
+    Ok(Json(
+        backtesting::run(&pair, &interval, source, from, to, data.fee_rate).await?,
+    ))
+}
+
+#[get("/backtest")]
+pub async fn backtest_runs() -> Result<Json<Vec<BacktestRun>>, MeshetarError> {
+    Ok(Json(backtesting::list_runs().await?))
+}
+
+#[get("/backtest/<run_id>")]
+pub async fn backtest_run(run_id: i64) -> Result<Json<BacktestDetail>, MeshetarError> {
+    Ok(Json(backtesting::get_run(run_id).await?))
+}
+
+/// Cost basis method of the query, the configured one by default
+fn cost_basis_method(method: Option<String>) -> Result<CostBasisMethod, MeshetarError> {
+    match method {
+        Some(method) => CostBasisMethod::from_str(&method).map_err(|_| {
+            MeshetarError::Validation(format!("Unknown cost basis method {}.", method))
+        }),
+        None => Ok(load_config::config().ledger.cost_basis),
     }
 }
 
+/// Fills over a range (epoch ms) with their balance changes, of every pair unless one is given
+#[get("/ledger?<from>&<to>&<symbol>")]
+pub async fn ledger_entries(
+    from: Option<i64>,
+    to: Option<i64>,
+    symbol: Option<String>,
+) -> Result<Json<Vec<LedgerEntry>>, MeshetarError> {
+    let (from, to) = performance::resolve_range(from, to);
+    Ok(Json(ledger::entries(symbol.as_deref(), from, to).await?))
+}
+
+/// Lots open at the end of the range and the ones closed in it
+#[get("/ledger/lots?<from>&<to>&<symbol>&<method>")]
+pub async fn ledger_lots(
+    from: Option<i64>,
+    to: Option<i64>,
+    symbol: Option<String>,
+    method: Option<String>,
+) -> Result<Json<Book>, MeshetarError> {
+    let method = cost_basis_method(method)?;
+    let (from, to) = performance::resolve_range(from, to);
+    Ok(Json(
+        ledger::book(symbol.as_deref(), from, to, method).await?,
+    ))
+}
+
+#[derive(Responder)]
+#[response(content_type = "text/csv")]
+pub struct CsvExport {
+    csv: String,
+    disposition: Header<'static>,
+}
+
+/// Realized gains of the lots closed over the range as a CSV download, for tax reporting
+#[get("/ledger/export?<from>&<to>&<symbol>&<method>")]
+pub async fn export_ledger(
+    from: Option<i64>,
+    to: Option<i64>,
+    symbol: Option<String>,
+    method: Option<String>,
+) -> Result<CsvExport, MeshetarError> {
+    let method = cost_basis_method(method)?;
+    let (from, to) = performance::resolve_range(from, to);
+    let book = ledger::book(symbol.as_deref(), from, to, method).await?;
+    Ok(CsvExport {
+        csv: ledger::closed_lots_csv(&book.closed_lots),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"realized_gains_{}_{}_{}.csv\"",
+                method, from, to
+            ),
+        ),
+    })
+}
+
 #[post("/run")]
 pub async fn run(
     meshetar: &State<Arc<Mutex<Meshetar>>>,
     task_control: &State<Arc<Mutex<TaskControl>>>,
-) -> Accepted<Json<Meshetar>> {
+) -> Result<Accepted<Json<Meshetar>>, MeshetarError> {
     // Set state to run
     let meshetar_clone = Arc::clone(&meshetar.inner());
-    meshetar_clone.lock().await.status = MeshetarStatus::Running;
+    {
+        let mut meshetar = meshetar_clone.lock().await;
+        meshetar.ensure_idle()?;
+        meshetar.status = MeshetarStatus::Running;
+    }
     drop(meshetar_clone);
     // Set task control to running
     &task_control.lock().await.sender.send(true);
@@ -53,7 +202,7 @@ pub async fn run(
     });
 
     let summary = meshetar_clone4.lock().await.summerize_json();
-    Accepted(Some(summary))
+    Ok(Accepted(Some(summary)))
 }
 
 #[post("/stop")]
@@ -65,7 +214,9 @@ pub async fn stop_all_operations(
         log::warn!("Failed to stop task. {}", e);
     }
     let mut meshetar = meshetar.lock().await;
-    meshetar.status = MeshetarStatus::Stopping;
+    if meshetar.status != MeshetarStatus::Idle {
+        meshetar.status = MeshetarStatus::Stopping;
+    }
     Accepted(Some(meshetar.summerize_json()))
 }
 
@@ -84,19 +235,12 @@ pub struct IntervalPutPayload<'r> {
 pub async fn interval_put(
     meshetar: &State<Arc<Mutex<Meshetar>>>,
     data: Form<IntervalPutPayload<'_>>,
-) -> Result<Accepted<String>, Custom<String>> {
+) -> Result<Accepted<String>, MeshetarError> {
     let mut meshetar = meshetar.lock().await;
-    if let Ok(value) = Interval::from_str(data.interval) {
-        match meshetar.change_interval(value) {
-            Ok(_) => Ok(Accepted(Some(value.to_string()))),
-            Err(e) => Err(Custom(Status::ServiceUnavailable, e)),
-        }
-    } else {
-        Err(Custom(
-            Status::BadRequest,
-            String::from("Couldnt parse interval."),
-        ))
-    }
+    let value = Interval::from_str(data.interval)
+        .map_err(|_| MeshetarError::Validation(String::from("Couldnt parse interval.")))?;
+    meshetar.change_interval(value)?;
+    Ok(Accepted(Some(value.to_string())))
 }
 
 #[derive(FromForm, Deserialize)]
@@ -107,17 +251,30 @@ pub struct PairPutPayload<'r> {
 pub async fn pair_put(
     meshetar: &State<Arc<Mutex<Meshetar>>>,
     data: Form<PairPutPayload<'_>>,
-) -> Result<Accepted<String>, Custom<String>> {
+) -> Result<Accepted<String>, MeshetarError> {
     let mut meshetar = meshetar.lock().await;
-    if let Ok(value) = Pair::from_str(data.pair) {
-        match meshetar.change_pair(value) {
-            Ok(_) => Ok(Accepted(Some(value.to_string()))),
-            Err(e) => Err(Custom(Status::ServiceUnavailable, e)),
-        }
-    } else {
-        Err(Custom(
-            Status::BadRequest,
-            String::from("Couldnt parse pair."),
-        ))
-    }
+    let value = Pair::from_str(data.pair)
+        .map_err(|_| MeshetarError::Validation(String::from("Couldnt parse pair.")))?;
+    meshetar.change_pair(value)?;
+    Ok(Accepted(Some(value.to_string())))
+}
+
+#[derive(FromForm, Deserialize)]
+pub struct OrderPayload<'r> {
+    side: &'r str,
+    quantity: f64,
+    /// Required for every order on mainnet
+    #[field(default = false)]
+    confirm_mainnet: bool,
+}
+#[post("/order", data = "<data>")]
+pub async fn order(
+    meshetar: &State<Arc<Mutex<Meshetar>>>,
+    data: Form<OrderPayload<'_>>,
+) -> Result<Json<OrderResponse>, MeshetarError> {
+    let side = OrderSide::from_str(data.side)
+        .map_err(|_| MeshetarError::Validation(String::from("Couldnt parse side.")))?;
+    let pair = meshetar.lock().await.pair.to_string();
+    let order = trade::place_market_order(&pair, side, data.quantity, data.confirm_mainnet).await?;
+    Ok(Json(order))
 }
@@ -0,0 +1,297 @@
+use super::{fees, meshetar::Pair};
+use crate::utils::{
+    binance_client::BINANCE_CLIENT,
+    database::DB_POOL,
+    error::MeshetarError,
+    load_config::{self, Environment},
+    serde_utils::f64_from_string,
+};
+use binance_spot_connector_rust::trade::{self, order::Side};
+use chrono::Utc;
+use futures::TryFutureExt;
+use rust_decimal::{prelude::FromPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use strum::{Display, EnumString};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Display, EnumString)]
+#[strum(serialize_all = "UPPERCASE")]
+#[serde(rename_all = "UPPERCASE")]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+impl OrderSide {
+    fn to_side(&self) -> Side {
+        match self {
+            OrderSide::Buy => Side::Buy,
+            OrderSide::Sell => Side::Sell,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderFill {
+    #[serde(deserialize_with = "f64_from_string")]
+    pub price: f64,
+    #[serde(rename = "qty", deserialize_with = "f64_from_string")]
+    pub quantity: f64,
+    #[serde(deserialize_with = "f64_from_string")]
+    pub commission: f64,
+    #[serde(rename = "commissionAsset")]
+    pub commission_asset: String,
+    #[serde(rename = "tradeId")]
+    pub trade_id: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderResponse {
+    pub symbol: String,
+    #[serde(rename = "orderId")]
+    pub order_id: i64,
+    #[serde(rename = "transactTime")]
+    pub transact_time: i64,
+    pub status: String,
+    pub side: OrderSide,
+    #[serde(rename = "executedQty", deserialize_with = "f64_from_string")]
+    pub executed_quantity: f64,
+    #[serde(rename = "cummulativeQuoteQty", deserialize_with = "f64_from_string")]
+    pub cumulative_quote_quantity: f64,
+    #[serde(default)]
+    pub fills: Vec<OrderFill>,
+}
+
+/// Every order has to pass through here. Mainnet orders need to be enabled in config and
+/// explicitly confirmed per order, so a misconfigured environment can't trade real funds.
+pub fn ensure_order_allowed(confirm_mainnet: bool) -> Result<Environment, MeshetarError> {
+    let exchange = load_config::config().exchange;
+    if exchange.environment == Environment::Mainnet {
+        if !exchange.allow_mainnet_orders {
+            return Err(MeshetarError::StateConflict(String::from(
+                "Mainnet orders are disabled, set exchange.allow_mainnet_orders to enable them.",
+            )));
+        }
+        if !confirm_mainnet {
+            return Err(MeshetarError::Validation(String::from(
+                "Mainnet order refused, it has to be confirmed with confirm_mainnet.",
+            )));
+        }
+    }
+    Ok(exchange.environment)
+}
+
+pub async fn place_market_order(
+    symbol: &str,
+    side: OrderSide,
+    quantity: f64,
+    confirm_mainnet: bool,
+) -> Result<OrderResponse, MeshetarError> {
+    let environment = ensure_order_allowed(confirm_mainnet)?;
+    let order_quantity = quantity;
+    let quantity = Decimal::from_f64(quantity)
+        .filter(|quantity| quantity.is_sign_positive() && !quantity.is_zero())
+        .ok_or(MeshetarError::Validation(format!(
+            "Invalid order quantity {}",
+            quantity
+        )))?;
+    log::warn!(
+        "Placing {} market order for {} {} on {}.",
+        side,
+        quantity,
+        symbol,
+        environment
+    );
+    if environment == Environment::Mock {
+        let assets = Pair::from_str(symbol)
+            .map_err(|_| MeshetarError::Validation(format!("Unknown pair {}", symbol)))?
+            .assets();
+        let fee_rate = fees::current().await?.market_order();
+        return simulate_fill(symbol, assets, side, order_quantity, fee_rate).await;
+    }
+    let client = BINANCE_CLIENT.get().unwrap();
+    let request = trade::new_order(symbol, side.to_side(), "MARKET")
+        .quantity(quantity)
+        .new_order_resp_type(trade::order::NewOrderResponseType::Full);
+    let response = client
+        .send(request)
+        .map_err(|e| MeshetarError::Exchange(format!("Error placing order {:?}", e)))
+        .await?
+        .into_body_str()
+        .map_err(|e| MeshetarError::Exchange(format!("Error parsing order response, {:?}", e)))
+        .await?;
+    let order = serde_json::from_str::<OrderResponse>(&response).map_err(|e| {
+        MeshetarError::Exchange(format!("Unexpected order response {:?}: {}", e, response))
+    })?;
+    // The order went through, a failed write must not turn it into an error
+    if let Err(e) = save_fills(&order).await {
+        log::error!("Order {} was filled but not stored: {}", order.order_id, e);
+    }
+    Ok(order)
+}
+
+/// Fills a mock order at the close of the pair's latest stored kline. Ids count down from -1
+/// so simulated fills never collide with exchange ones, the commission is taken from the
+/// received asset like the exchange does.
+async fn simulate_fill(
+    symbol: &str,
+    (base_asset, quote_asset): (&str, &str),
+    side: OrderSide,
+    quantity: f64,
+    fee_rate: f64,
+) -> Result<OrderResponse, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let price: Option<f64> = sqlx::query_scalar(
+        "SELECT close FROM klines WHERE symbol = ?1 ORDER BY open_time DESC LIMIT 1",
+    )
+    .bind(symbol)
+    .fetch_optional(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching the fill price. {:?}", e)))
+    .await?;
+    let price = price.ok_or(MeshetarError::NotFound(format!(
+        "No stored klines of {} to fill a mock order at",
+        symbol
+    )))?;
+    let id: i64 = sqlx::query_scalar(
+        "SELECT MIN(COALESCE(MIN(trade_id), 0), 0) - 1 FROM fills WHERE symbol = ?1",
+    )
+    .bind(symbol)
+    .fetch_one(connection)
+    .map_err(|e| MeshetarError::Database(format!("Error fetching the last mock fill. {:?}", e)))
+    .await?;
+    let quote_quantity = price * quantity;
+    let (commission, commission_asset) = match side {
+        OrderSide::Buy => (quantity * fee_rate, base_asset),
+        OrderSide::Sell => (quote_quantity * fee_rate, quote_asset),
+    };
+    let order = OrderResponse {
+        symbol: symbol.to_string(),
+        order_id: id,
+        transact_time: Utc::now().timestamp_millis(),
+        status: String::from("FILLED"),
+        side,
+        executed_quantity: quantity,
+        cumulative_quote_quantity: quote_quantity,
+        fills: vec![OrderFill {
+            price,
+            quantity,
+            commission,
+            commission_asset: commission_asset.to_string(),
+            trade_id: id,
+        }],
+    };
+    save_fills(&order).await?;
+    Ok(order)
+}
+
+/// Fills are what realized PnL is computed from
+async fn save_fills(order: &OrderResponse) -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let mut tx = connection
+        .begin()
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error on creating transaction on fills: {:?}", e))
+        })
+        .await?;
+    for fill in order.fills.iter() {
+        sqlx::query(
+            "INSERT OR REPLACE INTO fills (
+                symbol,
+                trade_id,
+                order_id,
+                side,
+                time,
+                price,
+                quantity,
+                commission,
+                commission_asset
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )
+        .bind(&order.symbol)
+        .bind(fill.trade_id)
+        .bind(order.order_id)
+        .bind(order.side.to_string())
+        .bind(order.transact_time)
+        .bind(fill.price)
+        .bind(fill.quantity)
+        .bind(fill.commission)
+        .bind(&fill.commission_asset)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error inserting a fill. {:?}", e)))
+        .await?;
+    }
+    tx.commit()
+        .map_err(|e| MeshetarError::Database(format!("Error committing fills: {:?}", e)))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn simulates_fills_at_the_latest_close() {
+        database::run_test(async {
+            let symbol = "MOCKFILLUSDT";
+            let assets = ("MOCKFILL", "USDT");
+            let connection = DB_POOL.get().unwrap();
+            let missing = simulate_fill(symbol, assets, OrderSide::Buy, 1.0, 0.001).await;
+            assert!(matches!(missing, Err(MeshetarError::NotFound(_))));
+            for (open_time, close) in [(0, 100.0), (60000, 200.0)] {
+                sqlx::query(
+                    "INSERT INTO klines (symbol, interval, open_time, open, high, low, close,
+                    volume, close_time, quote_asset_volume, number_of_trades,
+                    taker_buy_base_asset_volume, taker_buy_quote_asset_volume)
+                    VALUES (?1, '1m', ?2, ?3, ?3, ?3, ?3, 1, ?2 + 59999, 1, 1, 1, 1)",
+                )
+                .bind(symbol)
+                .bind(open_time)
+                .bind(close)
+                .execute(connection)
+                .await
+                .unwrap();
+            }
+
+            let buy = simulate_fill(symbol, assets, OrderSide::Buy, 2.0, 0.001)
+                .await
+                .unwrap();
+            assert_eq!(buy.order_id, -1);
+            assert_close(buy.fills[0].price, 200.0);
+            assert_close(buy.cumulative_quote_quantity, 400.0);
+            assert_close(buy.fills[0].commission, 0.002);
+            assert_eq!(buy.fills[0].commission_asset, "MOCKFILL");
+
+            let sell = simulate_fill(symbol, assets, OrderSide::Sell, 1.0, 0.001)
+                .await
+                .unwrap();
+            assert_eq!(sell.order_id, -2);
+            assert_close(sell.fills[0].commission, 0.2);
+            assert_eq!(sell.fills[0].commission_asset, "USDT");
+
+            let stored: Vec<(i64, String, f64)> = sqlx::query_as(
+                "SELECT trade_id, side, price FROM fills WHERE symbol = ?1 ORDER BY trade_id DESC",
+            )
+            .bind(symbol)
+            .fetch_all(connection)
+            .await
+            .unwrap();
+            assert_eq!(
+                stored,
+                vec![
+                    (-1, String::from("BUY"), 200.0),
+                    (-2, String::from("SELL"), 200.0)
+                ]
+            );
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use crate::utils::{database::DB_POOL, error::MeshetarError};
+use futures::TryFutureExt;
+use std::collections::HashMap;
+
+/// Last prices of the tickers, conversions between assets are looked up in them
+pub struct Prices {
+    last_prices: HashMap<String, f64>,
+    /// Assets a conversion may go through when there is no market between two assets
+    bridge_assets: Vec<String>,
+}
+
+impl Prices {
+    pub async fn load(bridge_assets: Vec<String>) -> Result<Self, MeshetarError> {
+        let connection = DB_POOL.get().unwrap();
+        let rows: Vec<(String, f64)> =
+            sqlx::query_as("SELECT symbol, last_price FROM asset_ticker WHERE last_price > 0")
+                .fetch_all(connection)
+                .map_err(|e| MeshetarError::Database(format!("Error fetching tickers. {:?}", e)))
+                .await?;
+        Ok(Prices {
+            last_prices: rows.into_iter().collect(),
+            bridge_assets,
+        })
+    }
+
+    /// Price of `from` in `to` over a single market, e.g. ETHBTC or the inverse of BTCETH
+    fn market_rate(&self, from: &str, to: &str) -> Option<f64> {
+        if from == to {
+            return Some(1.0);
+        }
+        if let Some(price) = self.last_prices.get(&format!("{}{}", from, to)) {
+            return Some(*price);
+        }
+        self.last_prices
+            .get(&format!("{}{}", to, from))
+            .map(|price| 1.0 / price)
+    }
+
+    /// Price of `from` in `to`, directly or through the first bridge asset both have a market
+    /// with
+    pub fn rate(&self, from: &str, to: &str) -> Option<f64> {
+        self.market_rate(from, to).or_else(|| {
+            self.bridge_assets
+                .iter()
+                .filter(|bridge| bridge.as_str() != from && bridge.as_str() != to)
+                .find_map(|bridge| {
+                    Some(self.market_rate(from, bridge)? * self.market_rate(bridge, to)?)
+                })
+        })
+    }
+
+    /// Value of an amount of the asset in `currency`, None if no conversion is found
+    pub fn value(&self, asset: &str, amount: f64, currency: &str) -> Option<f64> {
+        if amount == 0.0 {
+            return Some(0.0);
+        }
+        self.rate(asset, currency).map(|rate| rate * amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices() -> Prices {
+        Prices {
+            last_prices: HashMap::from([
+                (String::from("BTCUSDT"), 40000.0),
+                (String::from("ETHBTC"), 0.05),
+                (String::from("BNBBTC"), 0.01),
+                (String::from("BNBETH"), 0.25),
+                (String::from("SOLBNB"), 0.5),
+            ]),
+            bridge_assets: vec![String::from("BTC"), String::from("USDT")],
+        }
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn direct_and_inverse_markets() {
+        let prices = prices();
+        assert_close(prices.rate("BTC", "BTC"), 1.0);
+        assert_close(prices.rate("BTC", "USDT"), 40000.0);
+        assert_close(prices.rate("USDT", "BTC"), 1.0 / 40000.0);
+        assert_close(prices.rate("BTC", "ETH"), 20.0);
+        // A direct market is preferred over a bridge, BNBETH over BNBBTC and ETHBTC
+        assert_close(prices.rate("BNB", "ETH"), 0.25);
+    }
+
+    #[test]
+    fn bridged_through_the_first_bridge_with_both_markets() {
+        let prices = prices();
+        // ETH to BTC to USDT
+        assert_close(prices.rate("ETH", "USDT"), 0.05 * 40000.0);
+        assert_close(prices.rate("USDT", "ETH"), 1.0 / (0.05 * 40000.0));
+        // BTC has no SOL market, and USDT none of either
+        assert_eq!(prices.rate("SOL", "USDT"), None);
+        assert_close(prices.rate("BNB", "USDT"), 0.01 * 40000.0);
+    }
+
+    #[test]
+    fn values_in_a_currency() {
+        let prices = prices();
+        assert_close(prices.value("ETH", 2.0, "USDT"), 4000.0);
+        assert_close(prices.value("DOGE", 0.0, "USDT"), 0.0);
+        assert_eq!(prices.value("DOGE", 1.0, "USDT"), None);
+        assert_eq!(prices.rate("USDT", "DOGE"), None);
+    }
+}
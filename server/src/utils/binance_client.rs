@@ -1,28 +1,48 @@
-use crate::utils::load_config::{read_config, Config};
-use binance_spot_connector_rust::{http::Credentials, hyper::BinanceHttpClient};
+use crate::utils::{
+    error::MeshetarError,
+    load_config::{self, Config, Environment},
+    secrets,
+};
+use binance_spot_connector_rust::hyper::BinanceHttpClient;
 use hyper::client::HttpConnector;
 use hyper_tls::HttpsConnector;
 use tokio::sync::OnceCell;
 
-pub const BINANCE_WSS_BASE_URL: &str = "wss://stream.binance.com:9443/ws";
-
 pub static BINANCE_CLIENT: OnceCell<BinanceHttpClient<HttpsConnector<HttpConnector>>> =
     OnceCell::const_new();
 
-pub async fn initialize() -> Result<(), String> {
+pub async fn initialize() -> Result<(), MeshetarError> {
     log::info!("Initializing client.");
-    let config: Config = read_config();
-    let credentials = Credentials::from_hmac(
-        config.binance_api_key.to_owned(),
-        config.binance_api_secret.to_owned(),
-    );
-    match BINANCE_CLIENT
-        .set(BinanceHttpClient::with_url("https://testnet.binance.vision").credentials(credentials))
-    {
+    let config: Config = load_config::config();
+    // No client is set up in mock, so nothing can reach the exchange by accident
+    if config.exchange.environment == Environment::Mock {
+        log::info!("Using mock exchange environment, orders are filled from stored klines.");
+        return Ok(());
+    }
+    let credentials = secrets::load_credentials(&config)?;
+    let rest_url = config.exchange.rest_url();
+    match config.exchange.environment {
+        Environment::Mainnet => log::warn!("Using MAINNET exchange environment at {}.", rest_url),
+        environment => log::info!(
+            "Using {} exchange environment at {}.",
+            environment,
+            rest_url
+        ),
+    }
+    match BINANCE_CLIENT.set(BinanceHttpClient::with_url(&rest_url).credentials(credentials)) {
         Ok(_) => Ok(()),
         Err(e) => {
             log::error!("ERROR INITIALIZING BINANCE CLIENT {:?}", e.to_string());
-            Err(e.to_string())
+            Err(MeshetarError::Exchange(e.to_string()))
         }
     }
 }
+
+/// Mock has no exchange behind it, callers skip or simulate their exchange calls
+pub fn is_mock() -> bool {
+    load_config::config().exchange.environment == Environment::Mock
+}
+
+pub fn wss_base_url() -> String {
+    load_config::config().exchange.ws_url()
+}
@@ -1,41 +1,75 @@
+use crate::utils::{error::MeshetarError, load_config};
+use futures::TryFutureExt;
 use sqlx::{Pool, Sqlite, SqlitePool};
 use std::{fs::File, path::Path};
 use tokio::sync::OnceCell;
 
 pub static DB_POOL: OnceCell<Pool<Sqlite>> = OnceCell::const_new();
 
-pub async fn initialize() -> Result<(), String> {
+/// Columns added to tables after their first release as (table, column, definition),
+/// databases created before get them on startup
+const COLUMN_MIGRATIONS: &[(&str, &str, &str)] = &[
+    ("signals", "model_signal", "TEXT"),
+    ("signals", "probability_buy", "REAL"),
+    ("signals", "probability_hold", "REAL"),
+    ("signals", "probability_sell", "REAL"),
+    ("signals", "model_id", "INTEGER"),
+    ("signals", "latency_ms", "INTEGER"),
+    ("models", "backend", "TEXT NOT NULL DEFAULT 'r'"),
+    ("models", "feature_sets", "TEXT"),
+    ("balances", "reporting_valuation", "REAL NOT NULL DEFAULT 0"),
+    ("balances", "is_priced", "INTEGER NOT NULL DEFAULT 1"),
+    // Sheets before the reporting currency were valued in BUSD
+    (
+        "balance_sheets",
+        "reporting_currency",
+        "TEXT NOT NULL DEFAULT 'BUSD'",
+    ),
+    (
+        "balance_sheets",
+        "unpriced_assets",
+        "TEXT NOT NULL DEFAULT ''",
+    ),
+];
+
+/// Columns renamed after their first release as (table, old name, new name)
+const COLUMN_RENAMES: &[(&str, &str, &str)] =
+    &[("balance_sheets", "busd_valuation", "reporting_valuation")];
+
+pub async fn initialize() -> Result<(), MeshetarError> {
     println!("Initializing database.");
     match set_connection().await {
         Ok(_) => {
             setup_tables().await?;
+            rename_columns().await?;
+            migrate_columns().await?;
             Ok(())
         }
         Err(e) => Err(e),
     }
 }
 
-pub async fn set_connection() -> Result<(), String> {
+pub async fn set_connection() -> Result<(), MeshetarError> {
     // Creates the database file if it doesnt exist
-    let database_path = "database.sqlite";
-    if Path::new(database_path).exists() == false {
-        File::create(database_path).map_err(|e| String::from(e.to_string()))?;
+    let database_path = load_config::config().storage.database_path;
+    if Path::new(&database_path).exists() == false {
+        File::create(&database_path).map_err(|e| MeshetarError::Database(e.to_string()))?;
     }
     // Creates a new pool
-    let pool = SqlitePool::connect("database.sqlite").await;
+    let pool = SqlitePool::connect(&database_path).await;
     match pool {
         Ok(pool) => {
             let set_pool_op = DB_POOL.set(pool);
             match set_pool_op {
                 Ok(_) => Ok(()),
-                Err(e) => Err(e.to_string()),
+                Err(e) => Err(MeshetarError::Database(e.to_string())),
             }
         }
-        Err(e) => Err(e.to_string()),
+        Err(e) => Err(MeshetarError::Database(e.to_string())),
     }
 }
 
-pub async fn setup_tables() -> Result<(), String> {
+pub async fn setup_tables() -> Result<(), MeshetarError> {
     let connection = DB_POOL.get();
     match connection {
         Some(connection) => {
@@ -48,13 +82,30 @@ pub async fn setup_tables() -> Result<(), String> {
             locked REAL NOT NULL,
             balance_sheet_id INTEGER,
             btc_valuation REAL NOT NULL,
+            reporting_valuation REAL NOT NULL,
+            is_priced INTEGER NOT NULL,
             FOREIGN KEY (balance_sheet_id) REFERENCES balance_sheets (id)
         );
+        CREATE INDEX IF NOT EXISTS balances_balance_sheet_id ON balances (balance_sheet_id);
         CREATE TABLE IF NOT EXISTS balance_sheets (
             id INTEGER PRIMARY KEY,
             timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
             btc_valuation REAL NOT NULL,
-            busd_valuation REAL NOT NULL
+            reporting_valuation REAL NOT NULL,
+            reporting_currency TEXT NOT NULL,
+            unpriced_assets TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS fills (
+            symbol TEXT NOT NULL,
+            trade_id INTEGER NOT NULL,
+            order_id INTEGER NOT NULL,
+            side TEXT NOT NULL,
+            time INTEGER NOT NULL,
+            price REAL NOT NULL,
+            quantity REAL NOT NULL,
+            commission REAL NOT NULL,
+            commission_asset TEXT NOT NULL,
+            PRIMARY KEY (symbol, trade_id)
         );
         CREATE TABLE IF NOT EXISTS klines (
             symbol TEXT NOT NULL,
@@ -102,8 +153,147 @@ pub async fn setup_tables() -> Result<(), String> {
             interval TEXT NOT NULL,
             time INTEGER NOT NULL,
             signal TEXT NOT NULL,
+            model_signal TEXT,
+            probability_buy REAL,
+            probability_hold REAL,
+            probability_sell REAL,
+            model_id INTEGER,
+            latency_ms INTEGER,
             PRIMARY KEY (symbol, interval, time)
         );
+        CREATE TABLE IF NOT EXISTS labels (
+            symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            open_time INTEGER NOT NULL,
+            label TEXT NOT NULL,
+            model_id INTEGER,
+            PRIMARY KEY (symbol, interval, open_time)
+        );
+        CREATE TABLE IF NOT EXISTS models (
+            id INTEGER PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            status TEXT NOT NULL,
+            train_from INTEGER,
+            train_to INTEGER,
+            test_from INTEGER,
+            test_to INTEGER,
+            features TEXT,
+            parameters TEXT,
+            metrics TEXT,
+            artifact_path TEXT NOT NULL,
+            plot_path TEXT NOT NULL,
+            is_active INTEGER NOT NULL DEFAULT 0,
+            backend TEXT NOT NULL DEFAULT 'r',
+            feature_sets TEXT
+        );
+        CREATE TABLE IF NOT EXISTS feature_sets (
+            name TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            features TEXT NOT NULL,
+            PRIMARY KEY (name, version)
+        );
+        CREATE TABLE IF NOT EXISTS features (
+            symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            feature_set TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            open_time INTEGER NOT NULL,
+            feature_values TEXT NOT NULL,
+            PRIMARY KEY (symbol, interval, feature_set, version, open_time)
+        );
+        CREATE TABLE IF NOT EXISTS sentiment (
+            source TEXT NOT NULL,
+            time INTEGER NOT NULL,
+            value REAL NOT NULL,
+            PRIMARY KEY (source, time)
+        );
+        CREATE TABLE IF NOT EXISTS model_promotions (
+            id INTEGER PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            candidate_id INTEGER NOT NULL,
+            active_id INTEGER,
+            candidate_score REAL,
+            active_score REAL,
+            promoted INTEGER NOT NULL,
+            reason TEXT NOT NULL,
+            FOREIGN KEY (candidate_id) REFERENCES models (id)
+        );
+        CREATE TABLE IF NOT EXISTS walk_forward_runs (
+            id INTEGER PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            status TEXT NOT NULL,
+            train_size INTEGER NOT NULL,
+            test_size INTEGER NOT NULL,
+            step INTEGER NOT NULL,
+            summary TEXT
+        );
+        CREATE TABLE IF NOT EXISTS walk_forward_folds (
+            run_id INTEGER NOT NULL,
+            fold INTEGER NOT NULL,
+            status TEXT NOT NULL,
+            train_from INTEGER NOT NULL,
+            train_to INTEGER,
+            test_from INTEGER NOT NULL,
+            test_to INTEGER NOT NULL,
+            metrics TEXT,
+            PRIMARY KEY (run_id, fold),
+            FOREIGN KEY (run_id) REFERENCES walk_forward_runs (id)
+        );
+        CREATE TABLE IF NOT EXISTS hyperparameter_searches (
+            id INTEGER PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            strategy TEXT NOT NULL,
+            status TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS hyperparameter_trials (
+            search_id INTEGER NOT NULL,
+            trial INTEGER NOT NULL,
+            status TEXT NOT NULL,
+            parameters TEXT NOT NULL,
+            metrics TEXT,
+            score REAL,
+            artifact_path TEXT NOT NULL,
+            PRIMARY KEY (search_id, trial),
+            FOREIGN KEY (search_id) REFERENCES hyperparameter_searches (id)
+        );
+        CREATE TABLE IF NOT EXISTS backtest_runs (
+            id INTEGER PRIMARY KEY,
+            created_at TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            interval TEXT NOT NULL,
+            source TEXT NOT NULL,
+            from_time INTEGER NOT NULL,
+            to_time INTEGER NOT NULL,
+            fee_rate REAL NOT NULL,
+            initial_capital REAL NOT NULL,
+            summary TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS backtest_trades (
+            run_id INTEGER NOT NULL,
+            time INTEGER NOT NULL,
+            side TEXT NOT NULL,
+            price REAL NOT NULL,
+            quantity REAL NOT NULL,
+            fee REAL NOT NULL,
+            PRIMARY KEY (run_id, time),
+            FOREIGN KEY (run_id) REFERENCES backtest_runs (id)
+        );
+        CREATE TABLE IF NOT EXISTS backtest_equity (
+            run_id INTEGER NOT NULL,
+            time INTEGER NOT NULL,
+            equity REAL NOT NULL,
+            buy_and_hold REAL NOT NULL,
+            PRIMARY KEY (run_id, time),
+            FOREIGN KEY (run_id) REFERENCES backtest_runs (id)
+        );
         CREATE TABLE IF NOT EXISTS account(
             maker_commission INTEGER NOT NULL,
             taker_commission INTEGER NOT NULL,
@@ -147,9 +337,93 @@ pub async fn setup_tables() -> Result<(), String> {
 
             match init_statement {
                 Ok(_) => Ok(()),
-                Err(e) => Err(e.to_string()),
+                Err(e) => Err(MeshetarError::Database(e.to_string())),
             }
         }
-        None => Err(String::from("DB pool not ready for operation.")),
+        None => Err(MeshetarError::Database(String::from(
+            "DB pool not ready for operation.",
+        ))),
     }
 }
+
+async fn rename_columns() -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    for (table, old_name, new_name) in COLUMN_RENAMES {
+        let existing: Vec<(String,)> =
+            sqlx::query_as(&format!("SELECT name FROM pragma_table_info('{}')", table))
+                .fetch_all(connection)
+                .map_err(|e| {
+                    MeshetarError::Database(format!("Error inspecting {}: {:?}", table, e))
+                })
+                .await?;
+        if !existing.iter().any(|(name,)| name == old_name) {
+            continue;
+        }
+        sqlx::query(&format!(
+            "ALTER TABLE {} RENAME COLUMN {} TO {}",
+            table, old_name, new_name
+        ))
+        .execute(connection)
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error renaming {}.{}: {:?}", table, old_name, e))
+        })
+        .await?;
+        log::info!("Renamed column {}.{} to {}.", table, old_name, new_name);
+    }
+    Ok(())
+}
+
+async fn migrate_columns() -> Result<(), MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    for (table, column, definition) in COLUMN_MIGRATIONS {
+        let existing: Vec<(String,)> =
+            sqlx::query_as(&format!("SELECT name FROM pragma_table_info('{}')", table))
+                .fetch_all(connection)
+                .map_err(|e| {
+                    MeshetarError::Database(format!("Error inspecting {}: {:?}", table, e))
+                })
+                .await?;
+        if existing.iter().any(|(name,)| name == column) {
+            continue;
+        }
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(connection)
+        .map_err(|e| MeshetarError::Database(format!("Error adding {}.{}: {:?}", table, column, e)))
+        .await?;
+        log::info!("Added column {}.{}.", table, column);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+lazy_static::lazy_static! {
+    /// Tests share one in-memory database, its pool has to stay on the runtime it was opened on
+    static ref TEST_RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+}
+
+/// Runs a test against an in-memory database with all tables. Tests share it, they keep apart
+/// by using symbols of their own.
+#[cfg(test)]
+pub fn run_test<F: std::future::Future>(test: F) -> F::Output {
+    TEST_RUNTIME.block_on(async {
+        DB_POOL
+            .get_or_init(|| async {
+                sqlx::sqlite::SqlitePoolOptions::new()
+                    .max_connections(1)
+                    .idle_timeout(None)
+                    .max_lifetime(None)
+                    .connect("sqlite::memory:")
+                    .await
+                    .unwrap()
+            })
+            .await;
+        setup_tables().await.unwrap();
+        test.await
+    })
+}
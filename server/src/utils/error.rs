@@ -0,0 +1,123 @@
+use rocket::{
+    http::Status,
+    response::{self, Responder},
+    serde::json::Json,
+    Request, Response,
+};
+use serde::Serialize;
+use std::fmt;
+
+/// Error shared by all server modules. Routes return it directly, it responds with a JSON body
+/// `{ "error": <kind>, "message": <text> }` and a status code matching the kind.
+#[derive(Debug, Clone)]
+pub enum MeshetarError {
+    /// Binance request failed or returned something unexpected
+    Exchange(String),
+    Database(String),
+    /// R script (or another model backend) failed
+    ModelRunner(String),
+    /// External data source other than the exchange failed, e.g. a sentiment provider
+    Provider(String),
+    /// Bad input, e.g. unparsable form fields
+    Validation(String),
+    /// Operation not possible in the current state, e.g. starting a task while one is running
+    StateConflict(String),
+    NotFound(String),
+    Config(String),
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl MeshetarError {
+    /// Kind of an error status Rocket answered with itself, e.g. a failed guard
+    pub fn from_status(status: Status, message: String) -> Self {
+        match status.code {
+            404 => MeshetarError::NotFound(message),
+            409 => MeshetarError::StateConflict(message),
+            502 | 504 => MeshetarError::Exchange(message),
+            400..=499 => MeshetarError::Validation(message),
+            _ => MeshetarError::Internal(message),
+        }
+    }
+    pub fn status(&self) -> Status {
+        match self {
+            MeshetarError::Exchange(_) => Status::BadGateway,
+            MeshetarError::Database(_) => Status::InternalServerError,
+            MeshetarError::ModelRunner(_) => Status::InternalServerError,
+            MeshetarError::Provider(_) => Status::BadGateway,
+            MeshetarError::Validation(_) => Status::BadRequest,
+            MeshetarError::StateConflict(_) => Status::Conflict,
+            MeshetarError::NotFound(_) => Status::NotFound,
+            MeshetarError::Config(_) => Status::InternalServerError,
+            MeshetarError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MeshetarError::Exchange(_) => "Exchange",
+            MeshetarError::Database(_) => "Database",
+            MeshetarError::ModelRunner(_) => "ModelRunner",
+            MeshetarError::Provider(_) => "Provider",
+            MeshetarError::Validation(_) => "Validation",
+            MeshetarError::StateConflict(_) => "StateConflict",
+            MeshetarError::NotFound(_) => "NotFound",
+            MeshetarError::Config(_) => "Config",
+            MeshetarError::Internal(_) => "Internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            MeshetarError::Exchange(message)
+            | MeshetarError::Database(message)
+            | MeshetarError::ModelRunner(message)
+            | MeshetarError::Provider(message)
+            | MeshetarError::Validation(message)
+            | MeshetarError::StateConflict(message)
+            | MeshetarError::NotFound(message)
+            | MeshetarError::Config(message)
+            | MeshetarError::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for MeshetarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for MeshetarError {}
+
+impl From<sqlx::Error> for MeshetarError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => MeshetarError::NotFound(String::from("No matching rows.")),
+            e => MeshetarError::Database(e.to_string()),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for MeshetarError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if status.code >= 500 {
+            log::error!("{} {}: {}", request.method(), request.uri(), self);
+        } else {
+            log::warn!("{} {}: {}", request.method(), request.uri(), self);
+        }
+        let body = ErrorBody {
+            error: self.kind(),
+            message: self.message().to_string(),
+        };
+        Response::build_from(Json(body).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
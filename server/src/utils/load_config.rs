@@ -1,11 +1,1007 @@
-#[derive(serde::Deserialize)]
+use crate::{
+    model::feature_store,
+    trading::meshetar::{Interval, Pair},
+    utils::{error::MeshetarError, retention, secrets},
+};
+use chrono::Duration;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap, net::IpAddr, path::Path, str::FromStr, sync::RwLock, time::SystemTime,
+};
+use strum::{Display, EnumString};
+
+pub const CONFIG_PATH: &str = "config.toml";
+/// Env variables starting with this prefix override config values,
+/// nested keys are separated with `__`, e.g. `MESHETAR_POLLING__BALANCE_INTERVAL_MS=10000`
+const ENV_PREFIX: &str = "MESHETAR_";
+const ENV_NESTING_SEPARATOR: &str = "__";
+const RELOAD_CHECK_INTERVAL_MS: u64 = 2000;
+
+lazy_static! {
+    static ref CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum KeyType {
+    #[default]
+    Hmac,
+    Rsa,
+    Ed25519,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Testnet,
+    Mainnet,
+    /// Nothing is sent to Binance, orders are filled at the stored klines' prices
+    Mock,
+}
+
+impl Environment {
+    pub fn rest_url(&self) -> &'static str {
+        match self {
+            Environment::Testnet => "https://testnet.binance.vision",
+            Environment::Mainnet => "https://api.binance.com",
+            Environment::Mock => "mock://rest",
+        }
+    }
+    pub fn ws_url(&self) -> &'static str {
+        match self {
+            Environment::Testnet => "wss://testnet.binance.vision/ws",
+            Environment::Mainnet => "wss://stream.binance.com:9443/ws",
+            Environment::Mock => "mock://ws",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ExchangeConfig {
+    pub environment: Environment,
+    /// Overrides the REST url of the environment
+    pub rest_url: Option<String>,
+    /// Overrides the websocket url of the environment
+    pub ws_url: Option<String>,
+    /// Mainnet orders are refused unless this is set, each order still needs a confirmation
+    pub allow_mainnet_orders: bool,
+}
+
+impl ExchangeConfig {
+    pub fn rest_url(&self) -> String {
+        self.rest_url
+            .to_owned()
+            .unwrap_or(self.environment.rest_url().to_string())
+    }
+    pub fn ws_url(&self) -> String {
+        self.ws_url
+            .to_owned()
+            .unwrap_or(self.environment.ws_url().to_string())
+    }
+    /// Guards against market data and account state coming from different environments
+    fn validate(&self) -> Result<(), MeshetarError> {
+        let production_hosts = ["api.binance.com", "stream.binance.com"];
+        for url in [self.rest_url(), self.ws_url()] {
+            let is_production = production_hosts.iter().any(|host| url.contains(host));
+            if is_production != (self.environment == Environment::Mainnet) {
+                return Err(MeshetarError::Config(format!(
+                    "exchange url {} does not belong to the {} environment.",
+                    url, self.environment
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub database_path: String,
+    /// Directory served by the file server, plots are written here
+    pub static_dir: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            database_path: String::from("database.sqlite"),
+            static_dir: String::from("static"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PollingConfig {
+    pub balance_interval_ms: u64,
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        PollingConfig {
+            balance_interval_ms: 5000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CostBasisMethod {
+    /// Sells close the oldest lots first
+    #[default]
+    Fifo,
+    /// Sells close every open lot at their average cost
+    Average,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct LedgerConfig {
+    pub cost_basis: CostBasisMethod,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FeeConfig {
+    /// Commission rate used until the account's rates have been fetched
+    pub default_rate: f64,
+    /// Whether the account pays its fees in BNB, they are discounted while it holds BNB
+    pub pay_with_bnb: bool,
+    /// Share of the fee that paying in BNB saves
+    pub bnb_discount: f64,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        FeeConfig {
+            default_rate: 0.001,
+            pay_with_bnb: false,
+            bnb_discount: 0.25,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BacktestConfig {
+    /// Quote asset a backtest starts with
+    pub initial_capital: f64,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        BacktestConfig {
+            initial_capital: 1000.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ValuationConfig {
+    /// Currency balance sheets are valued in, besides BTC
+    pub reporting_currency: String,
+    /// Assets a valuation may convert through when an asset has no market with the currency
+    pub bridge_assets: Vec<String>,
+}
+
+impl Default for ValuationConfig {
+    fn default() -> Self {
+        ValuationConfig {
+            reporting_currency: String::from("USDT"),
+            bridge_assets: vec![String::from("USDT"), String::from("BTC")],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// An unchanged balance sheet is stored again only after this long, 0 stores every poll
+    pub snapshot_heartbeat_ms: u64,
+    /// Compacts old balance sheets and klines periodically
+    pub enabled: bool,
+    pub interval_ms: u64,
+    /// Balance sheets older than this are kept one per hour
+    pub hourly_after_days: i64,
+    /// Balance sheets older than this are kept one per day
+    pub daily_after_days: i64,
+    /// Minute klines older than this are replaced by hourly ones, kept forever if not set
+    pub kline_horizon_days: Option<i64>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            snapshot_heartbeat_ms: 60000,
+            enabled: false,
+            interval_ms: 3600000,
+            hourly_after_days: 7,
+            daily_after_days: 90,
+            kline_horizon_days: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TradingConfig {
+    pub default_pair: Pair,
+    pub default_interval: Interval,
+    /// Buy and sell signals with a lower model probability are turned into holds
+    pub min_confidence: f64,
+}
+
+impl Default for TradingConfig {
+    fn default() -> Self {
+        TradingConfig {
+            default_pair: Pair::BTCUSDT,
+            default_interval: Interval::Minutes1,
+            min_confidence: 0.5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ModelBackend {
+    /// Neural net trained and run by the R scripts
+    #[default]
+    R,
+    /// Network and indicators computed in the server, needs no R install
+    Rust,
+}
+
+impl ModelBackend {
+    pub fn artifact_extension(&self) -> &'static str {
+        match self {
+            ModelBackend::R => "rds",
+            ModelBackend::Rust => "json",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ModelConfig {
+    /// Backend new models are trained with, each model runs on the backend it was trained with
+    pub backend: ModelBackend,
+    pub create_script: String,
+    pub run_script: String,
+    /// Number of most recent feature rows the run script reads
+    pub window_size: i64,
+    /// Feature sets new models are trained on, see `feature_store::FEATURE_SETS`
+    pub feature_sets: Vec<String>,
+    /// Pairs whose klines are fetched alongside a pair's and joined into its features
+    pub companions: Vec<Companions>,
+}
+
+impl ModelConfig {
+    /// Companion symbols of a pair, empty when it has none configured
+    pub fn companions_of(&self, symbol: &str) -> Vec<String> {
+        self.companions
+            .iter()
+            .filter(|companions| companions.pair.to_string() == symbol)
+            .flat_map(|companions| companions.symbols.iter().map(|pair| pair.to_string()))
+            .collect()
+    }
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        ModelConfig {
+            backend: ModelBackend::R,
+            create_script: String::from("models/default_create.R"),
+            run_script: String::from("models/default_run.R"),
+            window_size: 50,
+            feature_sets: vec![String::from("technical"), String::from("derived")],
+            companions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Companions {
+    pub pair: Pair,
+    /// e.g. BTCUSDT for ETHUSDT, its moves tend to lead the ones of other coins
+    pub symbols: Vec<Pair>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RetrainJob {
+    /// Cron expression with seconds, e.g. `0 0 3 * * *` for every day at 03:00 UTC
+    pub cron: String,
+    pub pair: Pair,
+    pub interval: Interval,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub enabled: bool,
+    pub jobs: Vec<RetrainJob>,
+    /// How far back history is fetched for a pair without any klines
+    pub history_days: i64,
+    /// Accuracy a retrained model needs above the active one to be promoted
+    pub min_improvement: f64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            enabled: false,
+            jobs: Vec::new(),
+            history_days: 2,
+            min_improvement: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SearchStrategy {
+    /// Every combination of the parameter values
+    #[default]
+    Grid,
+    /// `trials` combinations sampled at random
+    Random,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HyperparameterSearchConfig {
+    pub strategy: SearchStrategy,
+    /// Number of sampled combinations, random strategy only
+    pub trials: usize,
+    /// Training scripts running at the same time
+    pub max_concurrency: usize,
+    /// Candidate values per training script parameter
+    pub space: BTreeMap<String, Vec<serde_json::Value>>,
+}
+
+impl Default for HyperparameterSearchConfig {
+    fn default() -> Self {
+        HyperparameterSearchConfig {
+            strategy: SearchStrategy::Grid,
+            trials: 10,
+            max_concurrency: 2,
+            space: BTreeMap::from([
+                (
+                    String::from("buy_threshold"),
+                    vec![0.005.into(), 0.01.into()],
+                ),
+                (String::from("train_share"), vec![0.6.into(), 0.7.into()]),
+            ]),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WalkForwardConfig {
+    /// Klines each fold trains on
+    pub train_size: usize,
+    /// Klines each fold is tested on, right after its training klines
+    pub test_size: usize,
+    /// Klines the window moves forward between folds
+    pub step: usize,
+    pub max_folds: usize,
+}
+
+impl Default for WalkForwardConfig {
+    fn default() -> Self {
+        WalkForwardConfig {
+            train_size: 2000,
+            test_size: 500,
+            step: 500,
+            max_folds: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EvaluationConfig {
+    /// Numbers of klines after a signal at which its forward return is measured
+    pub horizons: Vec<usize>,
+    /// Absolute forward return within which a hold counts as a hit
+    pub hold_band: f64,
+    pub calibration_bins: usize,
+}
+
+impl Default for EvaluationConfig {
+    fn default() -> Self {
+        EvaluationConfig {
+            horizons: vec![1, 5, 15],
+            hold_band: 0.001,
+            calibration_bins: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum SentimentProviderKind {
+    /// JSON array of time and value objects fetched over HTTP
+    #[default]
+    HttpJson,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum TimeFormat {
+    #[default]
+    EpochMs,
+    EpochS,
+    /// `2023-05-01`, midnight UTC
+    Date,
+    Rfc3339,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SentimentSource {
+    /// Stored values and feature sets `sentiment:<name>` are keyed by it
+    pub name: String,
+    #[serde(default)]
+    pub provider: SentimentProviderKind,
+    pub url: String,
+    /// JSON pointer to the array of values in the response, the response itself if empty
+    #[serde(default)]
+    pub items_pointer: String,
+    pub time_field: String,
+    pub value_field: String,
+    #[serde(default)]
+    pub time_format: TimeFormat,
+    /// Time after its timestamp a value is known, e.g. a day for daily averages. Klines only
+    /// get values that were known at their open time.
+    #[serde(default)]
+    pub lag_ms: i64,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SentimentConfig {
+    pub enabled: bool,
+    pub fetch_interval_ms: u64,
+    pub sources: Vec<SentimentSource>,
+}
+
+impl Default for SentimentConfig {
+    fn default() -> Self {
+        SentimentConfig {
+            enabled: false,
+            fetch_interval_ms: 3600000,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl SentimentConfig {
+    pub fn source(&self, name: &str) -> Option<&SentimentSource> {
+        self.sources.iter().find(|source| source.name == name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ChartOverlay {
+    /// Simple moving average of the closes over 10 klines
+    Sma,
+    /// Exponential moving average of the closes over 10 klines
+    #[default]
+    Ema,
+    /// Bollinger bands, 2 standard deviations around the 20 kline average
+    Bollinger,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ChartPanel {
+    #[default]
+    Volume,
+    Rsi,
+    Macd,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PlotConfig {
+    pub points_per_page: i64,
+    /// Most candles the chart endpoint returns, longer ranges are merged into fewer candles
+    pub max_candles: i64,
+    /// Sentiment source drawn over the chart
+    pub sentiment_source: Option<String>,
+    /// Indicators drawn over the candles of the chart
+    pub overlays: Vec<ChartOverlay>,
+    /// Panels below the candles, in the order given
+    pub panels: Vec<ChartPanel>,
+}
+
+impl Default for PlotConfig {
+    fn default() -> Self {
+        PlotConfig {
+            points_per_page: 180,
+            max_candles: 1000,
+            sentiment_source: None,
+            overlays: vec![ChartOverlay::Ema, ChartOverlay::Bollinger],
+            panels: vec![ChartPanel::Volume],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: IpAddr::from([127, 0, 0, 1]),
+            port: 8000,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Debug, Clone)]
 pub struct Config {
-    pub binance_api_key: String,
-    pub binance_api_secret: String,
+    /// Plaintext credentials, only used when neither env variables nor a keystore provide them
+    pub binance_api_key: Option<String>,
+    pub binance_api_secret: Option<String>,
+    /// Type of the key pair used for signing requests
+    #[serde(default)]
+    pub binance_key_type: KeyType,
+    /// Path to a PEM private key, used with RSA and Ed25519 key types
+    pub binance_private_key_path: Option<String>,
+    /// Path to an encrypted keystore created with `create-keystore`
+    pub keystore_path: Option<String>,
+    #[serde(default)]
+    pub exchange: ExchangeConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub polling: PollingConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub valuation: ValuationConfig,
+    #[serde(default)]
+    pub ledger: LedgerConfig,
+    #[serde(default)]
+    pub fees: FeeConfig,
+    #[serde(default)]
+    pub backtest: BacktestConfig,
+    #[serde(default)]
+    pub trading: TradingConfig,
+    #[serde(default)]
+    pub model: ModelConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub walk_forward: WalkForwardConfig,
+    #[serde(default)]
+    pub hyperparameter_search: HyperparameterSearchConfig,
+    #[serde(default)]
+    pub evaluation: EvaluationConfig,
+    #[serde(default)]
+    pub sentiment: SentimentConfig,
+    #[serde(default)]
+    pub plot: PlotConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
 }
 
-pub fn read_config() -> Config {
-    let config_file = std::fs::read_to_string("config.toml").unwrap();
-    let config: Config = toml::from_str(&config_file).expect("Could not parse config file");
+impl Config {
+    pub fn validate(&self) -> Result<(), MeshetarError> {
+        self.exchange.validate()?;
+        if self.polling.balance_interval_ms == 0 {
+            return Err(MeshetarError::Config(String::from(
+                "polling.balance_interval_ms must be above 0.",
+            )));
+        }
+        if self.valuation.reporting_currency.is_empty() {
+            return Err(MeshetarError::Config(String::from(
+                "valuation.reporting_currency must not be empty.",
+            )));
+        }
+        if !(0.0..1.0).contains(&self.fees.default_rate) {
+            return Err(MeshetarError::Config(String::from(
+                "fees.default_rate must be at least 0 and below 1.",
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.fees.bnb_discount) {
+            return Err(MeshetarError::Config(String::from(
+                "fees.bnb_discount must be between 0 and 1.",
+            )));
+        }
+        if self.backtest.initial_capital <= 0.0 {
+            return Err(MeshetarError::Config(String::from(
+                "backtest.initial_capital must be above 0.",
+            )));
+        }
+        let retention = &self.retention;
+        if retention.interval_ms == 0 {
+            return Err(MeshetarError::Config(String::from(
+                "retention.interval_ms must be above 0.",
+            )));
+        }
+        if retention.hourly_after_days < 0
+            || retention.daily_after_days < retention.hourly_after_days
+        {
+            return Err(MeshetarError::Config(String::from(
+                "retention.hourly_after_days must be at least 0 and daily_after_days at least as many.",
+            )));
+        }
+        if let Some(days) = retention.kline_horizon_days {
+            if days <= 0 {
+                return Err(MeshetarError::Config(String::from(
+                    "retention.kline_horizon_days must be above 0.",
+                )));
+            }
+            // Models of compacted intervals would be trained on fewer klines than configured
+            let window_klines = (self.walk_forward.train_size + self.walk_forward.test_size) as i64;
+            let trained = std::iter::once(self.trading.default_interval)
+                .chain(self.scheduler.jobs.iter().map(|job| job.interval))
+                .filter(|interval| retention::COMPACTED_INTERVALS.contains(interval));
+            for interval in trained {
+                let needed_ms = (window_klines * interval.duration_ms())
+                    .max(Duration::days(self.scheduler.history_days).num_milliseconds());
+                if Duration::days(days).num_milliseconds() < needed_ms {
+                    return Err(MeshetarError::Config(format!(
+                        "retention.kline_horizon_days must cover scheduler.history_days and the {} {} klines walk_forward trains and tests on.",
+                        window_klines, interval
+                    )));
+                }
+            }
+        }
+        if self.plot.points_per_page <= 0 {
+            return Err(MeshetarError::Config(String::from(
+                "plot.points_per_page must be above 0.",
+            )));
+        }
+        if self.plot.max_candles <= 0 {
+            return Err(MeshetarError::Config(String::from(
+                "plot.max_candles must be above 0.",
+            )));
+        }
+        for (i, panel) in self.plot.panels.iter().enumerate() {
+            if self.plot.panels[..i].contains(panel) {
+                return Err(MeshetarError::Config(format!(
+                    "plot.panels lists {} more than once.",
+                    panel
+                )));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.trading.min_confidence) {
+            return Err(MeshetarError::Config(String::from(
+                "trading.min_confidence must be between 0 and 1.",
+            )));
+        }
+        for job in &self.scheduler.jobs {
+            cron::Schedule::from_str(&job.cron).map_err(|e| {
+                MeshetarError::Config(format!(
+                    "scheduler job {} has an invalid cron expression: {}",
+                    job.cron, e
+                ))
+            })?;
+        }
+        if self.scheduler.history_days <= 0 {
+            return Err(MeshetarError::Config(String::from(
+                "scheduler.history_days must be above 0.",
+            )));
+        }
+        // Indicators need a few dozen klines to warm up before anything can be trained
+        if self.walk_forward.train_size < 100 {
+            return Err(MeshetarError::Config(String::from(
+                "walk_forward.train_size must be at least 100.",
+            )));
+        }
+        if self.walk_forward.test_size == 0
+            || self.walk_forward.step == 0
+            || self.walk_forward.max_folds == 0
+        {
+            return Err(MeshetarError::Config(String::from(
+                "walk_forward.test_size, step and max_folds must be above 0.",
+            )));
+        }
+        let search = &self.hyperparameter_search;
+        if search.trials == 0 || search.max_concurrency == 0 {
+            return Err(MeshetarError::Config(String::from(
+                "hyperparameter_search.trials and max_concurrency must be above 0.",
+            )));
+        }
+        if search.space.values().any(|values| values.is_empty()) {
+            return Err(MeshetarError::Config(String::from(
+                "hyperparameter_search.space needs at least one value per parameter.",
+            )));
+        }
+        if self.evaluation.horizons.is_empty() || self.evaluation.horizons.contains(&0) {
+            return Err(MeshetarError::Config(String::from(
+                "evaluation.horizons must be a non-empty list of positive numbers.",
+            )));
+        }
+        if self.evaluation.calibration_bins == 0 {
+            return Err(MeshetarError::Config(String::from(
+                "evaluation.calibration_bins must be above 0.",
+            )));
+        }
+        if self.model.window_size <= 0 {
+            return Err(MeshetarError::Config(String::from(
+                "model.window_size must be above 0.",
+            )));
+        }
+        if self.storage.database_path.is_empty() {
+            return Err(MeshetarError::Config(String::from(
+                "storage.database_path must not be empty.",
+            )));
+        }
+        if !Path::new(&self.storage.static_dir).is_dir() {
+            return Err(MeshetarError::Config(format!(
+                "storage.static_dir {} is not a directory.",
+                self.storage.static_dir
+            )));
+        }
+        if self.model.feature_sets.is_empty() {
+            return Err(MeshetarError::Config(String::from(
+                "model.feature_sets must name at least one feature set.",
+            )));
+        }
+        if let Some(unknown) = self.model.feature_sets.iter().find(|name| {
+            let is_sentiment = name
+                .strip_prefix(feature_store::SENTIMENT_PREFIX)
+                .map_or(false, |source| self.sentiment.source(source).is_some());
+            !is_sentiment
+                && !feature_store::FEATURE_SETS
+                    .iter()
+                    .any(|feature_set| feature_set.name == *name)
+        }) {
+            return Err(MeshetarError::Config(format!(
+                "model.feature_sets names an unknown feature set {}.",
+                unknown
+            )));
+        }
+        for (i, companions) in self.model.companions.iter().enumerate() {
+            if companions.symbols.contains(&companions.pair) {
+                return Err(MeshetarError::Config(format!(
+                    "model.companions of {} must not include the pair itself.",
+                    companions.pair
+                )));
+            }
+            if self.model.companions[..i]
+                .iter()
+                .any(|earlier| earlier.pair == companions.pair)
+            {
+                return Err(MeshetarError::Config(format!(
+                    "model.companions lists {} more than once.",
+                    companions.pair
+                )));
+            }
+        }
+        if self.sentiment.fetch_interval_ms == 0 {
+            return Err(MeshetarError::Config(String::from(
+                "sentiment.fetch_interval_ms must be above 0.",
+            )));
+        }
+        for (i, source) in self.sentiment.sources.iter().enumerate() {
+            if source.time_field.is_empty() || source.value_field.is_empty() {
+                return Err(MeshetarError::Config(String::from(
+                    "sentiment.sources need a time_field and a value_field.",
+                )));
+            }
+            // The name prefixes feature names, the R scripts use them as column names
+            if source.name.is_empty()
+                || !source
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            {
+                return Err(MeshetarError::Config(format!(
+                    "sentiment source name {} must be letters, digits and underscores.",
+                    source.name
+                )));
+            }
+            if self.sentiment.sources[..i]
+                .iter()
+                .any(|earlier| earlier.name == source.name)
+            {
+                return Err(MeshetarError::Config(format!(
+                    "sentiment.sources lists {} more than once.",
+                    source.name
+                )));
+            }
+            if source.lag_ms < 0 {
+                return Err(MeshetarError::Config(format!(
+                    "sentiment source {} must not have a negative lag_ms.",
+                    source.name
+                )));
+            }
+        }
+        if let Some(source) = &self.plot.sentiment_source {
+            if self.sentiment.source(source).is_none() {
+                return Err(MeshetarError::Config(format!(
+                    "plot.sentiment_source {} is not one of sentiment.sources.",
+                    source
+                )));
+            }
+        }
+        let scripts = match self.model.backend {
+            ModelBackend::R => vec![&self.model.create_script, &self.model.run_script],
+            ModelBackend::Rust => Vec::new(),
+        };
+        for script in scripts {
+            if !Path::new(script).is_file() {
+                return Err(MeshetarError::Config(format!(
+                    "Model script {} does not exist.",
+                    script
+                )));
+            }
+        }
+        if self.server.port == 0 {
+            return Err(MeshetarError::Config(String::from(
+                "server.port must be above 0.",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Takes over the fields that are safe to change while running. Returns whether any of the
+    /// fields that need a restart have changed.
+    fn apply_hot_reload(&mut self, new_config: Config) -> bool {
+        let needs_restart = self.exchange != new_config.exchange
+            || self.storage != new_config.storage
+            || self.server != new_config.server;
+        self.polling = new_config.polling;
+        self.retention = new_config.retention;
+        self.valuation = new_config.valuation;
+        self.ledger = new_config.ledger;
+        self.fees = new_config.fees;
+        self.backtest = new_config.backtest;
+        self.trading = new_config.trading;
+        self.model = new_config.model;
+        self.scheduler = new_config.scheduler;
+        self.walk_forward = new_config.walk_forward;
+        self.hyperparameter_search = new_config.hyperparameter_search;
+        self.evaluation = new_config.evaluation;
+        self.sentiment = new_config.sentiment;
+        self.plot = new_config.plot;
+        needs_restart
+    }
+}
+
+/// Parses a raw env value as a toml value (numbers, booleans, ...), falls back to a string.
+fn env_value_to_toml(raw: &str) -> toml::Value {
+    match toml::from_str::<toml::Table>(&format!("value = {}", raw)) {
+        Ok(mut table) => table
+            .remove("value")
+            .unwrap_or(toml::Value::String(raw.to_string())),
+        Err(_) => toml::Value::String(raw.to_string()),
+    }
+}
+
+fn apply_env_overrides(table: &mut toml::Table) -> Result<(), MeshetarError> {
+    for (name, raw_value) in std::env::vars() {
+        let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        // Credentials are taken as they are, never coerced into config values
+        if secrets::SECRET_ENV_VARS.contains(&name.as_str()) {
+            continue;
+        }
+        let keys: Vec<String> = path
+            .split(ENV_NESTING_SEPARATOR)
+            .map(|key| key.to_lowercase())
+            .collect();
+        let (last_key, parent_keys) = keys.split_last().unwrap();
+        let mut current = &mut *table;
+        for key in parent_keys {
+            let entry = current
+                .entry(key.to_owned())
+                .or_insert(toml::Value::Table(toml::Table::new()));
+            current = match entry {
+                toml::Value::Table(nested) => nested,
+                _ => {
+                    return Err(MeshetarError::Config(format!(
+                        "Env override {} points inside a non-table value.",
+                        name
+                    )))
+                }
+            };
+        }
+        current.insert(last_key.to_owned(), env_value_to_toml(&raw_value));
+    }
+    Ok(())
+}
+
+pub fn read_config() -> Result<Config, MeshetarError> {
+    if !Path::new(CONFIG_PATH).exists() {
+        return Err(MeshetarError::Config(format!(
+            "Config file {} not found. Copy config.toml.example to {} and fill it in.",
+            CONFIG_PATH, CONFIG_PATH
+        )));
+    }
+    let config_file = std::fs::read_to_string(CONFIG_PATH).map_err(|e| {
+        MeshetarError::Config(format!("Could not read config file {}: {}", CONFIG_PATH, e))
+    })?;
+    let mut table: toml::Table = toml::from_str(&config_file).map_err(|e| {
+        MeshetarError::Config(format!(
+            "Could not parse config file {}: {}",
+            CONFIG_PATH, e
+        ))
+    })?;
+    apply_env_overrides(&mut table)?;
+    let config: Config = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e| MeshetarError::Config(format!("Invalid config in {}: {}", CONFIG_PATH, e)))?;
     config
+        .validate()
+        .map_err(|e| MeshetarError::Config(format!("Invalid config in {}: {}", CONFIG_PATH, e)))?;
+    Ok(config)
+}
+
+/// Reads, validates and stores the config, has to run before anything reads it.
+pub fn initialize() -> Result<(), MeshetarError> {
+    let config = read_config()?;
+    let mut stored = CONFIG
+        .write()
+        .map_err(|e| MeshetarError::Config(e.to_string()))?;
+    *stored = Some(config);
+    Ok(())
+}
+
+/// Snapshot of the current config
+pub fn config() -> Config {
+    CONFIG
+        .read()
+        .expect("Config lock poisoned.")
+        .clone()
+        .expect("Config read before initialization.")
+}
+
+fn config_modified_at() -> Option<SystemTime> {
+    std::fs::metadata(CONFIG_PATH)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Watches the config file and reloads the fields that can change without a restart.
+pub async fn watch() {
+    let mut last_modified = config_modified_at();
+    loop {
+        tokio::time::sleep(std::time::Duration::from_millis(RELOAD_CHECK_INTERVAL_MS)).await;
+        let modified = config_modified_at();
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+        match read_config() {
+            Ok(new_config) => {
+                let mut stored = CONFIG.write().expect("Config lock poisoned.");
+                if let Some(config) = stored.as_mut() {
+                    if config.apply_hot_reload(new_config) {
+                        log::warn!(
+                            "Config reloaded; exchange, storage and server changes need a restart."
+                        );
+                    } else {
+                        log::info!("Config reloaded.");
+                    }
+                }
+            }
+            Err(e) => log::warn!("Config change ignored: {}", e),
+        }
+    }
 }
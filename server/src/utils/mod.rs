@@ -1,6 +1,9 @@
 pub mod binance_client;
 pub mod database;
+pub mod error;
 pub mod formatting;
 pub mod load_config;
+pub mod retention;
 pub mod rlang_runner;
+pub mod secrets;
 pub mod serde_utils;
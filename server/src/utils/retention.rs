@@ -0,0 +1,303 @@
+use super::{database::DB_POOL, error::MeshetarError, load_config};
+use crate::trading::{meshetar::Interval, portfolio::SHEET_TIME};
+use binance_spot_connector_rust::market::klines::KlineInterval;
+use chrono::{Duration, Utc};
+use futures::TryFutureExt;
+
+const HOUR_MS: i64 = 3600000;
+const DAY_MS: i64 = 86400000;
+
+/// Intervals whose klines beyond `retention.kline_horizon_days` are replaced by hourly ones
+pub const COMPACTED_INTERVALS: [Interval; 2] = [Interval::Minutes1, Interval::Minutes3];
+
+/// Compacts old balance sheets and klines periodically while retention is enabled
+pub async fn run() {
+    loop {
+        let config = load_config::config().retention;
+        if config.enabled {
+            if let Err(e) = compact().await {
+                log::warn!("Error compacting history: {}", e);
+            }
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(config.interval_ms)).await;
+    }
+}
+
+pub async fn compact() -> Result<(), MeshetarError> {
+    let config = load_config::config().retention;
+    let now = Utc::now().timestamp_millis();
+    let hourly_before = now - Duration::days(config.hourly_after_days).num_milliseconds();
+    let daily_before = now - Duration::days(config.daily_after_days).num_milliseconds();
+    let hourly = compact_balance_sheets(hourly_before, HOUR_MS).await?;
+    let daily = compact_balance_sheets(daily_before, DAY_MS).await?;
+    log::info!(
+        "Compacted balance sheets, removed {} to hourly and {} to daily.",
+        hourly,
+        daily
+    );
+    if let Some(days) = config.kline_horizon_days {
+        let before = now - Duration::days(days).num_milliseconds();
+        // Only whole hours are compacted
+        let before = before - before.rem_euclid(HOUR_MS);
+        for interval in COMPACTED_INTERVALS {
+            let interval = interval.to_kline_interval().to_string();
+            let removed = compact_klines(&interval, before).await?;
+            log::info!("Compacted {} {} klines to hourly.", removed, interval);
+        }
+    }
+    Ok(())
+}
+
+/// Keeps the last balance sheet of every bucket among the ones older than `before` and
+/// removes the balances of the others. Returns the number of removed sheets.
+async fn compact_balance_sheets(before: i64, bucket_ms: i64) -> Result<u64, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let mut tx = connection
+        .begin()
+        .map_err(|e| {
+            MeshetarError::Database(format!(
+                "Error on creating transaction on balance sheets: {:?}",
+                e
+            ))
+        })
+        .await?;
+    let removed = sqlx::query(&format!(
+        "DELETE FROM balance_sheets
+        WHERE {time} < ?1 AND id NOT IN (
+            SELECT MAX(id) FROM balance_sheets
+            WHERE {time} < ?1
+            GROUP BY {time} / ?2
+        )",
+        time = SHEET_TIME
+    ))
+    .bind(before)
+    .bind(bucket_ms)
+    .execute(tx.as_mut())
+    .map_err(|e| MeshetarError::Database(format!("Error compacting balance sheets. {:?}", e)))
+    .await?
+    .rows_affected();
+    sqlx::query(
+        "DELETE FROM balances
+        WHERE balance_sheet_id NOT IN (SELECT id FROM balance_sheets)",
+    )
+    .execute(tx.as_mut())
+    .map_err(|e| MeshetarError::Database(format!("Error removing balances. {:?}", e)))
+    .await?;
+    tx.commit()
+        .map_err(|e| {
+            MeshetarError::Database(format!(
+                "Error committing compacted balance sheets: {:?}",
+                e
+            ))
+        })
+        .await?;
+    Ok(removed)
+}
+
+/// Replaces the interval's klines before `before` with hourly klines, an hour that is stored
+/// already is kept. Their indicators, features, labels and signals are removed along. Returns
+/// the number of removed klines.
+async fn compact_klines(interval: &str, before: i64) -> Result<u64, MeshetarError> {
+    let connection = DB_POOL.get().unwrap();
+    let hourly = KlineInterval::Hours1.to_string();
+    let mut tx = connection
+        .begin()
+        .map_err(|e| {
+            MeshetarError::Database(format!("Error on creating transaction on klines: {:?}", e))
+        })
+        .await?;
+    sqlx::query(
+        "WITH hours AS (
+            SELECT
+                symbol,
+                open_time / ?3 * ?3 AS hour,
+                MIN(open_time) AS first_time,
+                MAX(open_time) AS last_time,
+                MAX(high) AS high,
+                MIN(low) AS low,
+                SUM(volume) AS volume,
+                MAX(close_time) AS close_time,
+                SUM(quote_asset_volume) AS quote_asset_volume,
+                SUM(number_of_trades) AS number_of_trades,
+                SUM(taker_buy_base_asset_volume) AS taker_buy_base_asset_volume,
+                SUM(taker_buy_quote_asset_volume) AS taker_buy_quote_asset_volume
+            FROM klines
+            WHERE interval = ?1 AND open_time < ?2
+            GROUP BY symbol, hour
+        )
+        INSERT OR IGNORE INTO klines (
+            symbol,
+            interval,
+            open_time,
+            open,
+            high,
+            low,
+            close,
+            volume,
+            close_time,
+            quote_asset_volume,
+            number_of_trades,
+            taker_buy_base_asset_volume,
+            taker_buy_quote_asset_volume
+        )
+        SELECT
+            hours.symbol,
+            ?4,
+            hours.hour,
+            first.open,
+            hours.high,
+            hours.low,
+            last.close,
+            hours.volume,
+            hours.close_time,
+            hours.quote_asset_volume,
+            hours.number_of_trades,
+            hours.taker_buy_base_asset_volume,
+            hours.taker_buy_quote_asset_volume
+        FROM hours
+        JOIN klines first ON first.symbol = hours.symbol
+            AND first.interval = ?1 AND first.open_time = hours.first_time
+        JOIN klines last ON last.symbol = hours.symbol
+            AND last.interval = ?1 AND last.open_time = hours.last_time",
+    )
+    .bind(interval)
+    .bind(before)
+    .bind(HOUR_MS)
+    .bind(&hourly)
+    .execute(tx.as_mut())
+    .map_err(|e| MeshetarError::Database(format!("Error aggregating klines. {:?}", e)))
+    .await?;
+    let removed = sqlx::query("DELETE FROM klines WHERE interval = ?1 AND open_time < ?2")
+        .bind(interval)
+        .bind(before)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error removing klines. {:?}", e)))
+        .await?
+        .rows_affected();
+    // Labels and signals are of klines that are no more, nothing can be evaluated against them
+    for (table, time) in [
+        ("indicators", "open_time"),
+        ("features", "open_time"),
+        ("labels", "open_time"),
+        ("signals", "time"),
+    ] {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE interval = ?1 AND {} < ?2",
+            table, time
+        ))
+        .bind(interval)
+        .bind(before)
+        .execute(tx.as_mut())
+        .map_err(|e| MeshetarError::Database(format!("Error removing {}. {:?}", table, e)))
+        .await?;
+    }
+    tx.commit()
+        .map_err(|e| MeshetarError::Database(format!("Error committing compacted klines: {:?}", e)))
+        .await?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::database;
+
+    /// Compacting is across symbols, the other tests store 1m klines
+    const INTERVAL_MS: i64 = 180000;
+
+    async fn count(table: &str, time: &str, symbol: &str, interval: &str) -> i64 {
+        sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} WHERE symbol = ?1 AND interval = ?2 AND {} < ?3",
+            table, time
+        ))
+        .bind(symbol)
+        .bind(interval)
+        .bind(2 * HOUR_MS)
+        .fetch_one(DB_POOL.get().unwrap())
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn compacts_klines_with_their_labels_and_signals() {
+        database::run_test(async {
+            let symbol = "RETENTIONUSDT";
+            let connection = DB_POOL.get().unwrap();
+            for i in 0..60 {
+                let open_time = i * INTERVAL_MS;
+                sqlx::query(
+                    "INSERT INTO klines (symbol, interval, open_time, open, high, low, close,
+                        volume, close_time, quote_asset_volume, number_of_trades,
+                        taker_buy_base_asset_volume, taker_buy_quote_asset_volume)
+                    VALUES (?1, '3m', ?2, ?3, ?3 + 1, ?3 - 1, ?3 + 0.5, 1.0, ?4, 2.0, 3, 0.5, 1.0)",
+                )
+                .bind(symbol)
+                .bind(open_time)
+                .bind(100.0 + i as f64)
+                .bind(open_time + INTERVAL_MS - 1)
+                .execute(connection)
+                .await
+                .unwrap();
+                sqlx::query(
+                    "INSERT INTO labels (symbol, interval, open_time, label) VALUES (?1, '3m', ?2, 'hold')",
+                )
+                .bind(symbol)
+                .bind(open_time)
+                .execute(connection)
+                .await
+                .unwrap();
+                sqlx::query(
+                    "INSERT INTO signals (symbol, interval, time, signal) VALUES (?1, '3m', ?2, 'Hold')",
+                )
+                .bind(symbol)
+                .bind(open_time)
+                .execute(connection)
+                .await
+                .unwrap();
+            }
+
+            // The third hour is younger than the horizon
+            assert_eq!(compact_klines("3m", 2 * HOUR_MS).await.unwrap(), 40);
+
+            let hours: Vec<(i64, f64, f64, f64, f64, f64, i64, i64)> = sqlx::query_as(
+                "SELECT open_time, open, high, low, close, volume, close_time, number_of_trades
+                FROM klines WHERE symbol = ?1 AND interval = '1h' ORDER BY open_time ASC",
+            )
+            .bind(symbol)
+            .fetch_all(connection)
+            .await
+            .unwrap();
+            assert_eq!(
+                hours,
+                vec![
+                    (0, 100.0, 120.0, 99.0, 119.5, 20.0, HOUR_MS - 1, 60),
+                    (
+                        HOUR_MS,
+                        120.0,
+                        140.0,
+                        119.0,
+                        139.5,
+                        20.0,
+                        2 * HOUR_MS - 1,
+                        60
+                    ),
+                ]
+            );
+            for (table, time) in [
+                ("klines", "open_time"),
+                ("labels", "open_time"),
+                ("signals", "time"),
+            ] {
+                assert_eq!(count(table, time, symbol, "3m").await, 0, "{}", table);
+            }
+            let kept: i64 = sqlx::query_scalar(
+                "SELECT COUNT(*) FROM labels WHERE symbol = ?1 AND interval = '3m'",
+            )
+            .bind(symbol)
+            .fetch_one(connection)
+            .await
+            .unwrap();
+            assert_eq!(kept, 20);
+        });
+    }
+}
@@ -1,55 +1,101 @@
-use futures::FutureExt;
-use std::{process::Stdio, sync::Arc};
-use tokio::io::AsyncReadExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{collections::VecDeque, process::Stdio, sync::Arc};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::{process::Command, sync::Mutex};
 
-use crate::TaskControl;
+use crate::{utils::error::MeshetarError, TaskControl};
 
-pub async fn run_script(
+/// Scripts print their result as a single stdout line starting with this marker,
+/// everything else on stdout is treated as progress (lifesign) output
+const RESULT_MARKER: &str = "MESHETAR_RESULT ";
+/// How many stderr lines are kept to explain a failed run
+const STDERR_TAIL_LINES: usize = 20;
+
+/// Runs an R script, passing `payload` as JSON on stdin and parsing the JSON result it prints.
+pub async fn run_script<P: Serialize, T: DeserializeOwned>(
     path_to_script: &str,
+    payload: &P,
     task_control: Arc<Mutex<TaskControl>>,
-) -> Result<String, String> {
+) -> Result<T, MeshetarError> {
+    let payload = serde_json::to_vec(payload)
+        .map_err(|e| MeshetarError::ModelRunner(format!("Error serializing payload: {:?}", e)))?;
     // Set the Rscript command and the path to the R script
     let mut child_process = Command::new("Rscript")
         .arg(path_to_script)
+        .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()
-        .map_err(|e| format!("R err: {:?}", e))?;
+        .map_err(|e| MeshetarError::ModelRunner(format!("R err: {:?}", e)))?;
 
-    let mut output = String::new();
-    let mut reader = if let Some(stdout) = child_process.stdout.take() {
-        Some(tokio::io::BufReader::new(stdout))
-    } else {
-        None
-    };
+    // Written alongside reading the output, a script that prints before it has read the whole
+    // payload would otherwise block on a full pipe. Dropping stdin after the payload lets the
+    // script read it until EOF.
+    let mut stdin = child_process.stdin.take().unwrap();
+    let stdin_task = tokio::spawn(async move { stdin.write_all(&payload).await });
 
+    let script_name = path_to_script.to_string();
+    let stderr = child_process.stderr.take().unwrap();
+    let stderr_task = tokio::spawn(async move {
+        let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            log::warn!("[{}] {}", script_name, line);
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        Vec::from(tail).join("\n")
+    });
+
+    let mut stdout = BufReader::new(child_process.stdout.take().unwrap()).lines();
+    let mut result: Option<String> = None;
     let mut receiver = task_control.lock().await.receiver.clone();
     loop {
         tokio::select! {
-            result = reader.as_mut().unwrap().read_to_string(&mut output).fuse() => {
-                result.map_err(|e| format!("Error parsing output: {:?}", e))?;
+            line = stdout.next_line() => {
+                match line.map_err(|e| MeshetarError::ModelRunner(format!("Error reading output: {:?}", e)))? {
+                    Some(line) => match line.strip_prefix(RESULT_MARKER) {
+                        Some(json) => result = Some(json.to_string()),
+                        None => log::info!("[{}] {}", path_to_script, line),
+                    },
+                    None => break,
+                }
             },
             _ = receiver.changed() => {
                 if *receiver.borrow() == false {
-                    child_process.kill().await.map_err(|e| format!("Failed to stop command: {:?}", e))?;
-                    return Err("R Command: execution was stopped".to_owned())
+                    stdin_task.abort();
+                    child_process.kill().await.map_err(|e| MeshetarError::ModelRunner(format!("Failed to stop command: {:?}", e)))?;
+                    return Err(MeshetarError::ModelRunner(String::from("R Command: execution was stopped")))
                 }
             }
         }
+    }
 
-        let exit_status = child_process
-            .try_wait()
-            .map_err(|e| format!("Failed to check child status: {:?}", e))?;
-
-        if let Some(exit_status) = exit_status {
-            if exit_status.success() {
-                return Ok(output);
-            } else {
-                return Err(format!(
-                    "R script returned with error status: {:?}",
-                    exit_status
-                ));
-            }
-        }
+    let exit_status = child_process.wait().await.map_err(|e| {
+        MeshetarError::ModelRunner(format!("Failed to check child status: {:?}", e))
+    })?;
+    let stderr_tail = stderr_task.await.unwrap_or_default();
+    if !exit_status.success() {
+        return Err(MeshetarError::ModelRunner(format!(
+            "R script {} returned with error status {:?}: {}",
+            path_to_script, exit_status, stderr_tail
+        )));
     }
+    stdin_task
+        .await
+        .map_err(|e| MeshetarError::ModelRunner(format!("Error writing payload: {:?}", e)))?
+        .map_err(|e| MeshetarError::ModelRunner(format!("Error writing payload: {:?}", e)))?;
+    let result = result.ok_or(MeshetarError::ModelRunner(format!(
+        "R script {} finished without printing a result.",
+        path_to_script
+    )))?;
+    serde_json::from_str(&result).map_err(|e| {
+        MeshetarError::ModelRunner(format!(
+            "R script {} printed a malformed result: {:?}",
+            path_to_script, e
+        ))
+    })
 }